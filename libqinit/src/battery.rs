@@ -1,10 +1,26 @@
 use anyhow::{Context, Result};
+use log::{info, warn};
 use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::boot_config::BootConfig;
+use crate::notifier;
 
 const CHARGER_ONLINE_PATH: &str = "/sys/class/power_supply/rk817-charger/online";
 const LEVEL_PATH: &str = "/sys/class/power_supply/rk817-battery/capacity";
+// In microvolts
+const VOLTAGE_PATH: &str = "/sys/class/power_supply/rk817-battery/voltage_now";
+// Below this, heavy operations are not started: the PMIC may brown out under sustained CPU load
+const DEFAULT_BROWNOUT_FLOOR_MV: i32 = 3500;
+// Below this, running heavy operations are paused until the voltage recovers above the floor
+const DEFAULT_BROWNOUT_CRITICAL_MV: i32 = 3400;
+const VOLTAGE_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+const BROWNOUT_PAUSE_POLL_INTERVAL: Duration = Duration::from_secs(5);
 
 const MAX_BAR_WIDTH: i32 = 540;
+// At the GUI's sampling rate (100 ms), this is about 3 seconds of consistent readings
+pub const DEFAULT_SMOOTHING_SAMPLES: u32 = 30;
 const BATTERY_BASE_B: &str = r##"<svg xmlns="http://www.w3.org/2000/svg" height="24px" viewBox="0 -960 960 960" width="24px" fill="#000000"><path d="M160-240q-50 0-85-35t-35-85v-240q0-50 35-85t85-35h540q50 0 85 35t35 85v240q0 50-35 85t-85 35H160Zm0-80h540q17 0 28.5-11.5T740-360v-240q0-17-11.5-28.5T700-640H160q-17 0-28.5 11.5T120-600v240q0 17 11.5 28.5T160-320Zm700-60v-200h20q17 0 28.5 11.5T920-540v120q0 17-11.5 28.5T880-380h-20Zm-700 20v-240h"##;
 const BATTERY_BASE_E: &str = r##"v240h-80Z"/></svg>"##;
 
@@ -35,3 +51,225 @@ pub fn charger_plugged_in() -> Result<bool> {
         .with_context(|| "Failed to read charger status")?
         .contains("1"))
 }
+
+pub fn get_voltage_mv() -> Result<i32> {
+    Ok(fs::read_to_string(&VOLTAGE_PATH)
+        .with_context(|| "Failed to read battery voltage")?
+        .trim()
+        .parse::<i32>()
+        .with_context(|| "Failed to parse battery voltage")?
+        / 1000)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BrownoutPolicy {
+    pub floor_mv: i32,
+    pub critical_mv: i32,
+}
+
+impl BrownoutPolicy {
+    pub fn get() -> BrownoutPolicy {
+        let system = BootConfig::peek().map(|boot_config| boot_config.system);
+        let floor_mv = system
+            .as_ref()
+            .and_then(|system| system.brownout_floor_mv)
+            .unwrap_or(DEFAULT_BROWNOUT_FLOOR_MV);
+        let critical_mv = system
+            .as_ref()
+            .and_then(|system| system.brownout_critical_mv)
+            .unwrap_or(DEFAULT_BROWNOUT_CRITICAL_MV);
+
+        BrownoutPolicy {
+            floor_mv,
+            // Otherwise, an operation could be paused right after having been allowed to start
+            critical_mv: critical_mv.min(floor_mv),
+        }
+    }
+
+    // Readings that fail are not held against the operation: a missing fuel gauge should not lock heavy operations out
+    fn sagging_below(&self, threshold_mv: i32) -> Option<i32> {
+        if charger_plugged_in().unwrap_or(false) {
+            return None;
+        }
+        match get_voltage_mv() {
+            Ok(voltage_mv) if voltage_mv < threshold_mv => Some(voltage_mv),
+            Ok(_) => None,
+            Err(e) => {
+                warn!("{}: assuming that the battery voltage is sufficient", &e);
+                None
+            }
+        }
+    }
+
+    // To be called before starting an operation that keeps the CPU busy for a long time
+    pub fn check_before_heavy_operation(&self) -> Result<()> {
+        match self.sagging_below(self.floor_mv) {
+            Some(voltage_mv) => Err(anyhow::anyhow!(
+                "Battery voltage is too low ({} mV): plug in to continue",
+                &voltage_mv
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+// Called between chunks of work, e.g. from a progress callback, which makes every call a safe point to pause at
+pub struct BrownoutGuard {
+    policy: BrownoutPolicy,
+    last_check: Option<Instant>,
+}
+
+impl BrownoutGuard {
+    pub fn new(policy: BrownoutPolicy) -> BrownoutGuard {
+        BrownoutGuard {
+            policy,
+            last_check: None,
+        }
+    }
+
+    // Blocks while the voltage is below the critical threshold, until the charger is plugged in or the voltage recovers above the floor
+    pub fn pause_while_sagging(&mut self) {
+        if self
+            .last_check
+            .is_some_and(|last_check| last_check.elapsed() < VOLTAGE_CHECK_INTERVAL)
+        {
+            return;
+        }
+        self.last_check = Some(Instant::now());
+
+        let Some(voltage_mv) = self.policy.sagging_below(self.policy.critical_mv) else {
+            return;
+        };
+        warn!(
+            "Battery voltage sagged to {} mV: pausing until it recovers",
+            &voltage_mv
+        );
+        notifier::get().sticky_toast("Battery voltage is too low: plug in to continue");
+        while self.policy.sagging_below(self.policy.floor_mv).is_some() {
+            thread::sleep(BROWNOUT_PAUSE_POLL_INTERVAL);
+        }
+        info!("Battery voltage recovered: resuming");
+        notifier::get().toast("Battery voltage recovered: resuming");
+        self.last_check = Some(Instant::now());
+    }
+}
+
+// The rk817 capacity readout oscillates under load: only report a change after the raw value moved consistently in the same direction for a number of samples, and never let the level go up without the charger
+pub struct LevelSmoother {
+    required_samples: u32,
+    smoothed_level: Option<i32>,
+    pending_increase: bool,
+    consistent_samples: u32,
+    charging: bool,
+}
+
+impl LevelSmoother {
+    pub fn new(required_samples: u32) -> LevelSmoother {
+        LevelSmoother {
+            required_samples: required_samples.max(1),
+            smoothed_level: None,
+            pending_increase: false,
+            consistent_samples: 0,
+            charging: false,
+        }
+    }
+
+    pub fn update(&mut self, raw_level: i32, charging: bool) -> i32 {
+        if charging != self.charging {
+            self.charging = charging;
+            self.consistent_samples = 0;
+        }
+
+        let smoothed_level = match self.smoothed_level {
+            Some(level) => level,
+            None => {
+                self.smoothed_level = Some(raw_level);
+                return raw_level;
+            }
+        };
+
+        if raw_level == smoothed_level || (!charging && raw_level > smoothed_level) {
+            self.consistent_samples = 0;
+            return smoothed_level;
+        }
+
+        let increase = raw_level > smoothed_level;
+        if self.consistent_samples == 0 || increase != self.pending_increase {
+            self.pending_increase = increase;
+            self.consistent_samples = 1;
+        } else {
+            self.consistent_samples += 1;
+        }
+
+        if self.consistent_samples >= self.required_samples {
+            self.consistent_samples = 0;
+            self.smoothed_level = Some(raw_level);
+            return raw_level;
+        }
+
+        smoothed_level
+    }
+
+    pub fn get(&self) -> Option<i32> {
+        self.smoothed_level
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_reading_is_taken_as_is() {
+        let mut smoother = LevelSmoother::new(3);
+        assert_eq!(smoother.get(), None);
+        assert_eq!(smoother.update(57, false), 57);
+        assert_eq!(smoother.get(), Some(57));
+    }
+
+    #[test]
+    fn oscillations_are_ignored() {
+        let mut smoother = LevelSmoother::new(3);
+        smoother.update(50, false);
+        for raw_level in [49, 50, 49, 50, 49, 51] {
+            assert_eq!(smoother.update(raw_level, false), 50);
+        }
+    }
+
+    #[test]
+    fn consistent_drop_is_reported() {
+        let mut smoother = LevelSmoother::new(3);
+        smoother.update(50, false);
+        assert_eq!(smoother.update(49, false), 50);
+        assert_eq!(smoother.update(48, false), 50);
+        assert_eq!(smoother.update(48, false), 48);
+        assert_eq!(smoother.get(), Some(48));
+    }
+
+    #[test]
+    fn level_only_goes_up_while_charging() {
+        let mut smoother = LevelSmoother::new(2);
+        smoother.update(50, false);
+        for _ in 0..5 {
+            assert_eq!(smoother.update(55, false), 50);
+        }
+        assert_eq!(smoother.update(55, true), 50);
+        assert_eq!(smoother.update(55, true), 55);
+    }
+
+    #[test]
+    fn plugging_the_charger_restarts_the_count() {
+        let mut smoother = LevelSmoother::new(2);
+        smoother.update(50, false);
+        assert_eq!(smoother.update(49, false), 50);
+        assert_eq!(smoother.update(49, true), 50);
+        assert_eq!(smoother.update(49, true), 49);
+    }
+
+    #[test]
+    fn zero_required_samples_still_needs_one() {
+        let mut smoother = LevelSmoother::new(0);
+        smoother.update(50, false);
+        assert_eq!(smoother.update(49, false), 49);
+    }
+}
@@ -1,22 +1,76 @@
 use crate::eink;
+use crate::rootfs;
 use anyhow::{Context, Result};
 use log::{info, warn};
+use regex::Regex;
+use ron::value::RawValue;
+use serde::de::{Deserializer, MapAccess, Visitor};
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::fs;
+use std::sync::Mutex;
 
 const BOOT_CONFIG_FILE: &str = "boot_config.ron";
 const DEFAULT_BOOT_CONFIG_SUFFIX: &str = ".new";
+const PENDING_CHANGE_FILE: &str = "boot_config_pending.ron";
+
+static WARNED_FOREIGN_SECTIONS: Mutex<Option<HashSet<String>>> = Mutex::new(None);
 
 #[derive(Debug, Default, Serialize, Deserialize, PartialEq, Clone)]
 pub struct BootFlags {
     pub first_boot_done: bool,
+    #[serde(default)]
+    pub skip_filesystem_check: bool,
+    // Set once the first boot provisioning scripts ran, whether they succeeded or not
+    #[serde(default)]
+    pub provisioning_done: bool,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, PartialEq, Clone)]
 pub struct RootFS {
     pub systemd_targets_total: Option<i32>,
+    // Milliseconds into the systemd wait at which each target was reached on the last boot, for the boot splash's time estimate
+    #[serde(default)]
+    pub systemd_target_times_ms: Vec<u64>,
     pub timestamp: i64,
     pub persistent_storage: bool,
+    // Time it took systemd to report startup completion on the last successful boot, used to scale the chroot handoff watchdog
+    #[serde(default)]
+    pub startup_duration_secs: Option<u64>,
+    // Window without any new systemd kernel messages after which the chroot handoff is considered failed
+    #[serde(default)]
+    pub handoff_timeout_secs: Option<u64>,
+    // Mount the root filesystem through dm-verity when the image ships verity metadata, instead of checking its signature over the whole file
+    #[serde(default)]
+    pub verity: bool,
+    // Unset on single-file installs (i.e. without rootfs_a.squashfs and rootfs_b.squashfs)
+    #[serde(default)]
+    pub active_slot: Option<rootfs::Slot>,
+    // Boots of the active slot since systemd last reported startup completion
+    #[serde(default)]
+    pub boot_attempts: u32,
+    // Share of the memory available at boot that the volatile overlay may fill when persistent storage is disabled: 50 % if unset
+    #[serde(default)]
+    pub overlay_memory_percent: Option<u64>,
+    // A warning is shown when the persistent write layer grows beyond this: 2048 MB if unset
+    #[serde(default)]
+    pub write_layer_warning_mb: Option<u64>,
+    // Always use fuse-overlayfs, even when the kernel supports overlayfs
+    #[serde(default)]
+    pub force_fuse: bool,
+    // Mounted into the overlay after the built-in mounts, e.g. to bring a directory of packages into the chroot during development
+    #[serde(default)]
+    pub bind_mounts: Vec<BindMount>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Clone)]
+pub struct BindMount {
+    // Both absolute: the target is relative to the overlay's root
+    pub source: String,
+    pub target: String,
+    #[serde(default)]
+    pub recursive: bool,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, PartialEq, Clone)]
@@ -27,6 +81,164 @@ pub struct System {
     pub recovery_features: bool,
     pub initial_screen_rotation: eink::ScreenRotation,
     pub splash_wallpaper_options: SplashWallpaperOptions,
+    // Generated once on first boot and never regenerated afterwards, even across resets
+    #[serde(default)]
+    pub device_id: Option<String>,
+    // Largest debug QR code size (in modules per side) before diagnostics are split across several codes: derived from the panel's DPI if unset
+    #[serde(default)]
+    pub qr_max_modules: Option<u32>,
+    // How long to wait for the main partition and other block devices to show up during early boot
+    #[serde(default)]
+    pub device_wait_timeout_secs: Option<u64>,
+    // Compressed swap in RAM, set up before the root filesystem is mounted: disabled if unset
+    #[serde(default)]
+    pub zram: Option<Zram>,
+    // Queried in order when connecting to a network: public pools are used if unset
+    #[serde(default)]
+    pub ntp_servers: Option<Vec<String>>,
+    // Applied during init and to the root filesystem: "pinenote" if unset or invalid
+    #[serde(default)]
+    pub hostname: Option<String>,
+    // A warning is shown during boot when the boot or main partition has less free space than this: 200 MB if unset
+    #[serde(default)]
+    pub low_space_threshold_mb: Option<u64>,
+    // Minutes without Wi-Fi activity after which the interface is powered down while Wi-Fi stays enabled: 5 if unset, 0 to disable
+    #[serde(default)]
+    pub wifi_idle_suspend_mins: Option<u64>,
+    // Battery voltage under which heavy operations (e.g. system updates) are not started without the charger: 3500 mV if unset
+    #[serde(default)]
+    pub brownout_floor_mv: Option<i32>,
+    // Battery voltage under which running heavy operations pause until it recovers above the floor: 3400 mV if unset
+    #[serde(default)]
+    pub brownout_critical_mv: Option<i32>,
+    // Minutes without input after which a home unlocked on the login page is locked again, as long as the boot has not finished: disabled if unset or 0
+    #[serde(default)]
+    pub login_idle_lock_mins: Option<u64>,
+    // Minutes without input after which the device powers off while waiting at the boot menu, as long as no boot was started: disabled if unset or 0
+    #[serde(default)]
+    pub idle_power_off_mins: Option<u64>,
+    // Per user: a digest of the recovery key's fingerprint if one was created, None if the user declined to create one
+    #[serde(default)]
+    pub recovery_keys: HashMap<String, Option<String>>,
+    // Language of the boot GUI: English if unset
+    #[serde(default)]
+    pub language: Language,
+    // Wrong PINs in a row after which quick unlock is disabled and the full password is required again: 5 if unset
+    #[serde(default)]
+    pub quick_unlock_max_attempts: Option<u32>,
+    // Size of the boot GUI's text and controls: Medium if unset
+    #[serde(default)]
+    pub ui_scale: UiScale,
+    // Colors of the boot GUI: Light if unset
+    #[serde(default)]
+    pub theme: Theme,
+    // Layout of the on-screen keyboard: QWERTY if unset
+    #[serde(default)]
+    pub keyboard_layout: KeyboardLayout,
+    // Argon2 hash of the passcode asked for before the recovery menu shows up: no passcode if unset
+    #[serde(default)]
+    pub recovery_passcode_hash: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub enum Language {
+    #[default]
+    English,
+    French,
+}
+
+impl Language {
+    pub const ALL: [Language; 2] = [Language::English, Language::French];
+
+    // Matches the directory names of the translation catalogs
+    pub fn code(&self) -> &'static str {
+        match self {
+            Language::English => "en",
+            Language::French => "fr",
+        }
+    }
+
+    // Each language is named in itself
+    pub fn name(&self) -> &'static str {
+        match self {
+            Language::English => "English",
+            Language::French => "Français",
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub enum UiScale {
+    Small,
+    #[default]
+    Medium,
+    Large,
+    ExtraLarge,
+}
+
+impl UiScale {
+    pub const ALL: [UiScale; 4] = [
+        UiScale::Small,
+        UiScale::Medium,
+        UiScale::Large,
+        UiScale::ExtraLarge,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            UiScale::Small => "Small",
+            UiScale::Medium => "Medium",
+            UiScale::Large => "Large",
+            UiScale::ExtraLarge => "Extra large",
+        }
+    }
+}
+
+// Dark swaps the foreground and the background, for when a mostly white screen under the frontlight is too bright
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub enum Theme {
+    #[default]
+    Light,
+    Dark,
+}
+
+impl Theme {
+    pub const ALL: [Theme; 2] = [Theme::Light, Theme::Dark];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Theme::Light => "Light",
+            Theme::Dark => "Dark",
+        }
+    }
+}
+
+// Letters of the on-screen keyboard: the symbols page is the same for all of them
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Clone, Copy)]
+pub enum KeyboardLayout {
+    #[default]
+    Qwerty,
+    Azerty,
+}
+
+impl KeyboardLayout {
+    // Same order as the key sets of the Slint VirtualKeyboardHandler
+    pub const ALL: [KeyboardLayout; 2] = [KeyboardLayout::Qwerty, KeyboardLayout::Azerty];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            KeyboardLayout::Qwerty => "QWERTY",
+            KeyboardLayout::Azerty => "AZERTY",
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Clone)]
+pub struct Zram {
+    pub size_mb: u64,
+    // Falls back to zstd if unset
+    #[serde(default)]
+    pub compression_algorithm: Option<String>,
 }
 
 #[derive(Debug, Default, Serialize, Deserialize, PartialEq, Clone)]
@@ -48,7 +260,11 @@ pub struct BootConfig {
     pub rootfs: RootFS,
     pub system: System,
     #[cfg(feature = "debug")]
+    #[serde(default)]
     pub debug: Debug,
+    // Top-level sections this build does not know about (e.g. 'debug' in a non-debug build), kept verbatim as (name, raw RON value) so that switching builds does not erase them
+    #[serde(skip)]
+    pub foreign_sections: Vec<(String, String)>,
 }
 
 impl BootConfig {
@@ -82,7 +298,7 @@ impl BootConfig {
 
         if fs::exists(&path)? {
             if let Ok(boot_config_str) = fs::read_to_string(&path) {
-                if let Ok(boot_config) = ron::from_str::<BootConfig>(&boot_config_str) {
+                if let Ok(boot_config) = Self::parse(&boot_config_str) {
                     info!("Found valid boot configuration");
                     boot_config_valid = true;
                     boot_config_to_return = boot_config;
@@ -91,6 +307,13 @@ impl BootConfig {
                         "Found invalid boot configuration (possibly corrupted or incomplete?): returning default configuration, but enabling 'first_boot_done'"
                     );
                     let backup_path = format!("{}.bak", &path);
+                    // Has to happen before the backup is overwritten, which may be the only place left holding them
+                    let previous_backup_str = fs::read_to_string(&backup_path).ok();
+                    salvage_persistent_fields(
+                        &mut boot_config_to_return,
+                        std::iter::once(boot_config_str.as_str())
+                            .chain(previous_backup_str.as_deref()),
+                    );
                     info!("Backing old configuration up to path '{}'", &backup_path);
                     fs::copy(&path, &backup_path)?;
 
@@ -116,27 +339,157 @@ impl BootConfig {
         Ok((boot_config_to_return, boot_config_valid))
     }
 
+    // Parses the boot configuration without writing anything back: safe to use on a read-only mount
+    pub fn peek() -> Option<BootConfig> {
+        Self::peek_at(&crate::BOOT_PART_MOUNTPOINT)
+    }
+
+    pub fn peek_at(boot_part_mountpoint: &str) -> Option<BootConfig> {
+        let boot_config_str =
+            fs::read_to_string(Self::get_boot_config_path_at(&boot_part_mountpoint, false)).ok()?;
+        Self::parse(&boot_config_str).ok()
+    }
+
+    pub fn parse(boot_config_str: &str) -> Result<BootConfig> {
+        let mut boot_config = ron::from_str::<BootConfig>(&boot_config_str)?;
+
+        let known_sections: Vec<String> =
+            split_top_level_sections(&Self::serialize(&BootConfig::default())?)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect();
+        if let Some(sections) = split_top_level_sections(&boot_config_str) {
+            boot_config.foreign_sections = sections
+                .into_iter()
+                .filter(|(name, _)| !known_sections.contains(name))
+                .collect();
+        }
+
+        let mut warned_foreign_sections = WARNED_FOREIGN_SECTIONS.lock().unwrap();
+        let warned_foreign_sections = warned_foreign_sections.get_or_insert_with(HashSet::new);
+        for (name, _) in &boot_config.foreign_sections {
+            if warned_foreign_sections.insert(name.to_string()) {
+                warn!(
+                    "Boot configuration section '{}' is not supported by this build: preserving it as is",
+                    &name
+                );
+            }
+        }
+
+        Ok(boot_config)
+    }
+
+    pub fn serialize(boot_config: &BootConfig) -> Result<String> {
+        let mut boot_config_str =
+            ron::ser::to_string_pretty(&boot_config, ron::ser::PrettyConfig::default())?;
+        if boot_config.foreign_sections.is_empty() {
+            return Ok(boot_config_str);
+        }
+
+        // Splice foreign sections back in before the closing parenthesis
+        let trimmed_len = boot_config_str.trim_end().len();
+        boot_config_str.truncate(trimmed_len);
+        if !boot_config_str.ends_with(')') {
+            return Err(anyhow::anyhow!(
+                "Unexpected boot configuration serialization output"
+            ));
+        }
+        boot_config_str.pop();
+        boot_config_str.truncate(boot_config_str.trim_end().len());
+        if !boot_config_str.ends_with(',') && !boot_config_str.ends_with('(') {
+            boot_config_str.push(',');
+        }
+        for (name, value) in &boot_config.foreign_sections {
+            boot_config_str.push_str(&format!("\n    {}: {},", &name, &value));
+        }
+        boot_config_str.push_str("\n)");
+
+        Ok(boot_config_str)
+    }
+
     pub fn write(boot_config: &BootConfig, slated_for_restoration: bool) -> Result<()> {
+        Self::write_at(
+            &crate::BOOT_PART_MOUNTPOINT,
+            &boot_config,
+            slated_for_restoration,
+        )
+    }
+
+    pub(crate) fn write_at(
+        boot_part_mountpoint: &str,
+        boot_config: &BootConfig,
+        slated_for_restoration: bool,
+    ) -> Result<()> {
+        if let Some(issue) = crate::system::boot_partition_write_issue() {
+            warn!("Not writing boot configuration: {:?}", &issue);
+            return Ok(());
+        }
         if !slated_for_restoration {
-            let default_boot_config_file_to_erase = Self::get_boot_config_path(true);
+            let default_boot_config_file_to_erase =
+                Self::get_boot_config_path_at(&boot_part_mountpoint, true);
             if fs::exists(&default_boot_config_file_to_erase)? {
                 fs::remove_file(&default_boot_config_file_to_erase)?;
             }
         }
 
-        let path = Self::get_boot_config_path(slated_for_restoration);
+        let path = Self::get_boot_config_path_at(&boot_part_mountpoint, slated_for_restoration);
         info!("Writing boot configuration at path '{}'", &path);
-        fs::write(
-            &path,
-            ron::ser::to_string_pretty(&boot_config, ron::ser::PrettyConfig::default())?,
-        )
-        .with_context(|| "Failed to write boot configuration")?;
+        fs::write(&path, Self::serialize(&boot_config)?)
+            .with_context(|| "Failed to write boot configuration")?;
 
         Ok(())
     }
 
+    // The boot configuration held in memory is only written back later on, if the boot gets that far: this applies a change to the stored one right away
+    // Returns false if there is no valid stored configuration to update
+    pub fn update_stored(update: impl FnOnce(&mut BootConfig)) -> Result<bool> {
+        let Some(mut stored_boot_config) = Self::peek() else {
+            warn!(
+                "No valid boot configuration stored: the change only applies to the one in memory"
+            );
+            return Ok(false);
+        };
+        update(&mut stored_boot_config);
+        Self::write(&stored_boot_config, false)?;
+
+        Ok(true)
+    }
+
+    // Along with the copy of an invalid configuration, the defaults slated for restoration and any pending change record
+    pub fn remove_stored() -> Result<()> {
+        Self::remove_stored_at(&crate::BOOT_PART_MOUNTPOINT)
+    }
+
+    pub(crate) fn remove_stored_at(boot_part_mountpoint: &str) -> Result<()> {
+        if let Some(issue) = crate::system::boot_partition_write_issue() {
+            return Err(anyhow::anyhow!(
+                "Not removing boot configuration: {}",
+                issue.notice()
+            ));
+        }
+        let path = Self::get_boot_config_path_at(&boot_part_mountpoint, false);
+        for path in [
+            format!("{}.bak", &path),
+            Self::get_boot_config_path_at(&boot_part_mountpoint, true),
+            get_pending_change_path_at(&boot_part_mountpoint),
+            path,
+        ] {
+            if fs::exists(&path)? {
+                info!("Removing '{}'", &path);
+                fs::remove_file(&path).with_context(|| format!("Failed to remove '{}'", &path))?;
+            }
+        }
+
+        crate::system::sync_disks()
+    }
+
     fn get_boot_config_path(slated_for_restoration: bool) -> String {
-        let mut path = format!("{}/{}", &crate::BOOT_PART_MOUNTPOINT, &BOOT_CONFIG_FILE);
+        Self::get_boot_config_path_at(&crate::BOOT_PART_MOUNTPOINT, slated_for_restoration)
+    }
+
+    fn get_boot_config_path_at(boot_part_mountpoint: &str, slated_for_restoration: bool) -> String {
+        let mut path = format!("{}/{}", &boot_part_mountpoint, &BOOT_CONFIG_FILE);
         if slated_for_restoration {
             path.push_str(&DEFAULT_BOOT_CONFIG_SUFFIX);
         }
@@ -144,3 +497,569 @@ impl BootConfig {
         return path;
     }
 }
+
+// Left next to the boot configuration while the action a change depends on is running
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct PendingChange {
+    pub description: String,
+    // For actions that end in a reboot: finding this record on the next boot means that the action went through
+    pub completes_on_reboot: bool,
+    // Serialized, so that foreign sections survive a rollback
+    pub previous_boot_config: String,
+}
+
+// Keeps BootConfig in sync with a dependent side effect: the staged configuration only stays if the action succeeds
+pub struct BootConfigTransaction {
+    pending_change: PendingChange,
+    staged_boot_config: BootConfig,
+}
+
+impl BootConfigTransaction {
+    pub fn stage(
+        previous_boot_config: &BootConfig,
+        staged_boot_config: &BootConfig,
+        description: &str,
+        completes_on_reboot: bool,
+    ) -> Result<BootConfigTransaction> {
+        Ok(BootConfigTransaction {
+            pending_change: PendingChange {
+                description: description.to_string(),
+                completes_on_reboot,
+                previous_boot_config: BootConfig::serialize(&previous_boot_config)?,
+            },
+            staged_boot_config: staged_boot_config.clone(),
+        })
+    }
+
+    pub fn perform<T>(self, action: impl FnOnce() -> Result<T>) -> Result<T> {
+        info!("Performing '{}'", &self.pending_change.description);
+        write_pending_change(&self.pending_change)?;
+        BootConfig::write(&self.staged_boot_config, false)?;
+
+        match action() {
+            Ok(value) => {
+                clear_pending_change()?;
+                Ok(value)
+            }
+            Err(e) => {
+                warn!(
+                    "'{}' failed: restoring previous boot configuration",
+                    &self.pending_change.description
+                );
+                if let Err(rollback_error) = roll_back(&self.pending_change) {
+                    warn!("{:?}", &rollback_error);
+                }
+                Err(e.context(format!(
+                    "'{}' failed and was rolled back",
+                    &self.pending_change.description
+                )))
+            }
+        }
+    }
+}
+
+fn get_pending_change_path() -> String {
+    get_pending_change_path_at(&crate::BOOT_PART_MOUNTPOINT)
+}
+
+fn get_pending_change_path_at(boot_part_mountpoint: &str) -> String {
+    format!("{}/{}", &boot_part_mountpoint, &PENDING_CHANGE_FILE)
+}
+
+fn write_pending_change(pending_change: &PendingChange) -> Result<()> {
+    if crate::system::boot_partition_write_issue().is_some() {
+        return Ok(());
+    }
+    let path = get_pending_change_path();
+    let pending_change_str =
+        ron::ser::to_string_pretty(&pending_change, ron::ser::PrettyConfig::default())
+            .with_context(|| "Failed to serialize pending boot configuration change")?;
+    fs::write(&path, &pending_change_str)
+        .with_context(|| format!("Failed to write pending change to '{}'", &path))?;
+    crate::system::sync_disks()?;
+
+    Ok(())
+}
+
+fn clear_pending_change() -> Result<()> {
+    let path = get_pending_change_path();
+    if fs::exists(&path)? {
+        fs::remove_file(&path)
+            .with_context(|| format!("Failed to remove pending change '{}'", &path))?;
+        crate::system::sync_disks()?;
+    }
+
+    Ok(())
+}
+
+fn roll_back(pending_change: &PendingChange) -> Result<()> {
+    let previous_boot_config = BootConfig::parse(&pending_change.previous_boot_config)
+        .with_context(|| "Failed to parse previous boot configuration")?;
+    BootConfig::write(&previous_boot_config, false)?;
+
+    clear_pending_change()
+}
+
+// Called at every boot, before the boot configuration is read: settles changes whose action was interrupted
+pub fn reconcile_pending_change() -> Result<()> {
+    let path = get_pending_change_path();
+    if !fs::exists(&path)? {
+        return Ok(());
+    }
+    let pending_change = match fs::read_to_string(&path)
+        .map_err(anyhow::Error::from)
+        .and_then(|pending_change_str| Ok(ron::from_str::<PendingChange>(&pending_change_str)?))
+    {
+        Ok(pending_change) => pending_change,
+        Err(e) => {
+            warn!("Discarding unreadable pending change: {:?}", &e);
+            return clear_pending_change();
+        }
+    };
+
+    if pending_change.completes_on_reboot {
+        info!(
+            "'{}' completed with a reboot: keeping the new boot configuration",
+            &pending_change.description
+        );
+        return clear_pending_change();
+    }
+    warn!(
+        "'{}' was interrupted: restoring previous boot configuration",
+        &pending_change.description
+    );
+    roll_back(&pending_change)
+}
+
+// Settings that can be changed from the GUI before booting, along with the fields that change with them
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigSetting {
+    PersistentStorage(bool),
+    // Switching slots also resets the boot attempts counter and the systemd targets count, which undoing has to bring back
+    RootFSSlot {
+        slot: Option<rootfs::Slot>,
+        boot_attempts: u32,
+        systemd_targets_total: Option<i32>,
+    },
+    InitialScreenRotation(eink::ScreenRotation),
+    SplashWallpaper(Option<String>),
+    Timezone(String),
+    Hostname(Option<String>),
+    Language(Language),
+    UiScale(UiScale),
+    Theme(Theme),
+    KeyboardLayout(KeyboardLayout),
+}
+
+impl ConfigSetting {
+    pub fn switch_slot(slot: rootfs::Slot) -> ConfigSetting {
+        ConfigSetting::RootFSSlot {
+            slot: Some(slot),
+            boot_attempts: 0,
+            systemd_targets_total: None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConfigSetting::PersistentStorage(_) => "Persistent root filesystem",
+            ConfigSetting::RootFSSlot { .. } => "Root filesystem slot",
+            ConfigSetting::InitialScreenRotation(_) => "Initial screen rotation",
+            ConfigSetting::SplashWallpaper(_) => "Splash wallpaper model",
+            ConfigSetting::Timezone(_) => "Timezone",
+            ConfigSetting::Hostname(_) => "Hostname",
+            ConfigSetting::Language(_) => "Language",
+            ConfigSetting::UiScale(_) => "GUI scale",
+            ConfigSetting::Theme(_) => "Theme",
+            ConfigSetting::KeyboardLayout(_) => "Keyboard layout",
+        }
+    }
+
+    pub fn value_label(&self) -> String {
+        match self {
+            ConfigSetting::PersistentStorage(enabled) => {
+                if *enabled { "on" } else { "off" }.to_string()
+            }
+            ConfigSetting::RootFSSlot { slot, .. } => slot
+                .map(|slot| format!("{:?}", &slot))
+                .unwrap_or_else(|| "none".to_string()),
+            ConfigSetting::InitialScreenRotation(rotation) => match rotation {
+                eink::ScreenRotation::Cw0 => "0°",
+                eink::ScreenRotation::Cw90 => "90°",
+                eink::ScreenRotation::Cw180 => "180°",
+                eink::ScreenRotation::Cw270 => "270°",
+            }
+            .to_string(),
+            ConfigSetting::SplashWallpaper(wallpaper) => {
+                wallpaper.clone().unwrap_or_else(|| "default".to_string())
+            }
+            ConfigSetting::Timezone(timezone) => timezone.clone(),
+            ConfigSetting::Hostname(hostname) => hostname
+                .clone()
+                .unwrap_or_else(|| crate::system::DEFAULT_HOSTNAME.to_string()),
+            ConfigSetting::Language(language) => language.name().to_string(),
+            ConfigSetting::UiScale(ui_scale) => ui_scale.name().to_string(),
+            ConfigSetting::Theme(theme) => theme.name().to_string(),
+            ConfigSetting::KeyboardLayout(keyboard_layout) => keyboard_layout.name().to_string(),
+        }
+    }
+
+    // The same setting, with the value it currently has in the boot configuration
+    fn current(&self, boot_config: &BootConfig) -> ConfigSetting {
+        match self {
+            ConfigSetting::PersistentStorage(_) => {
+                ConfigSetting::PersistentStorage(boot_config.rootfs.persistent_storage)
+            }
+            ConfigSetting::RootFSSlot { .. } => ConfigSetting::RootFSSlot {
+                slot: boot_config.rootfs.active_slot,
+                boot_attempts: boot_config.rootfs.boot_attempts,
+                systemd_targets_total: boot_config.rootfs.systemd_targets_total,
+            },
+            ConfigSetting::InitialScreenRotation(_) => ConfigSetting::InitialScreenRotation(
+                boot_config.system.initial_screen_rotation.clone(),
+            ),
+            ConfigSetting::SplashWallpaper(_) => ConfigSetting::SplashWallpaper(
+                boot_config
+                    .system
+                    .splash_wallpaper_options
+                    .splash_wallpaper
+                    .clone(),
+            ),
+            ConfigSetting::Timezone(_) => {
+                ConfigSetting::Timezone(boot_config.system.timezone.clone())
+            }
+            ConfigSetting::Hostname(_) => {
+                ConfigSetting::Hostname(boot_config.system.hostname.clone())
+            }
+            ConfigSetting::Language(_) => ConfigSetting::Language(boot_config.system.language),
+            ConfigSetting::UiScale(_) => ConfigSetting::UiScale(boot_config.system.ui_scale),
+            ConfigSetting::Theme(_) => ConfigSetting::Theme(boot_config.system.theme),
+            ConfigSetting::KeyboardLayout(_) => {
+                ConfigSetting::KeyboardLayout(boot_config.system.keyboard_layout)
+            }
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        match self {
+            ConfigSetting::Hostname(Some(hostname)) => crate::system::validate_hostname(&hostname),
+            ConfigSetting::SplashWallpaper(Some(wallpaper))
+                if !crate::splash::WALLPAPER_MODELS_LIST.contains(&wallpaper.as_str()) =>
+            {
+                Err(anyhow::anyhow!(
+                    "Unknown splash wallpaper model '{}'",
+                    &wallpaper
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn apply(&self, boot_config: &mut BootConfig) {
+        match self.clone() {
+            ConfigSetting::PersistentStorage(enabled) => {
+                boot_config.rootfs.persistent_storage = enabled
+            }
+            ConfigSetting::RootFSSlot {
+                slot,
+                boot_attempts,
+                systemd_targets_total,
+            } => {
+                boot_config.rootfs.active_slot = slot;
+                boot_config.rootfs.boot_attempts = boot_attempts;
+                boot_config.rootfs.systemd_targets_total = systemd_targets_total;
+            }
+            ConfigSetting::InitialScreenRotation(rotation) => {
+                boot_config.system.initial_screen_rotation = rotation
+            }
+            ConfigSetting::SplashWallpaper(wallpaper) => {
+                boot_config.system.splash_wallpaper_options.splash_wallpaper = wallpaper
+            }
+            ConfigSetting::Timezone(timezone) => boot_config.system.timezone = timezone,
+            ConfigSetting::Hostname(hostname) => boot_config.system.hostname = hostname,
+            ConfigSetting::Language(language) => boot_config.system.language = language,
+            ConfigSetting::UiScale(ui_scale) => boot_config.system.ui_scale = ui_scale,
+            ConfigSetting::Theme(theme) => boot_config.system.theme = theme,
+            ConfigSetting::KeyboardLayout(keyboard_layout) => {
+                boot_config.system.keyboard_layout = keyboard_layout
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ConfigChange {
+    pub old: ConfigSetting,
+    pub new: ConfigSetting,
+    pub timestamp: chrono::DateTime<chrono::Local>,
+    pub source_page: String,
+}
+
+impl ConfigChange {
+    // e.g. "Timezone: UTC -> Europe/Paris (Boot configuration, 14:02)"
+    pub fn label(&self) -> String {
+        format!(
+            "{}: {} -> {} ({}, {})",
+            self.new.name(),
+            self.old.value_label(),
+            self.new.value_label(),
+            &self.source_page,
+            self.timestamp.format("%H:%M")
+        )
+    }
+
+    // Other settings are picked up by the boot that is about to happen
+    pub fn requires_reboot(&self) -> bool {
+        matches!(self.new, ConfigSetting::PersistentStorage(_))
+    }
+}
+
+// Changes made to the boot configuration since it was last committed, oldest first: they only ever need to live as long as the GUI session
+static SESSION_CHANGES: Mutex<Vec<ConfigChange>> = Mutex::new(Vec::new());
+
+// Every change made from the GUI goes through here so that it can be undone
+// Changing the same setting again updates its entry, which goes away once the setting is back to where it started
+pub fn change_setting(
+    boot_config: &mut BootConfig,
+    setting: ConfigSetting,
+    source_page: &str,
+) -> Result<()> {
+    setting.validate()?;
+    let old = setting.current(&boot_config);
+    if old == setting {
+        return Ok(());
+    }
+    setting.apply(boot_config);
+
+    let mut session_changes = SESSION_CHANGES.lock().unwrap();
+    let previous = session_changes
+        .iter()
+        .position(|change| change.new.name() == setting.name())
+        .map(|index| session_changes.remove(index));
+    let old = previous.map(|change| change.old).unwrap_or(old);
+    if old != setting {
+        session_changes.push(ConfigChange {
+            old,
+            new: setting,
+            timestamp: chrono::Local::now(),
+            source_page: source_page.to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+pub fn session_changes() -> Vec<ConfigChange> {
+    SESSION_CHANGES.lock().unwrap().clone()
+}
+
+// Returns the restored setting, for the GUI to show it again
+pub fn undo_change(boot_config: &mut BootConfig, index: usize) -> Result<ConfigSetting> {
+    let mut session_changes = SESSION_CHANGES.lock().unwrap();
+    let Some(change) = session_changes.get(index) else {
+        return Err(anyhow::anyhow!("No pending change at index {}", index));
+    };
+    change.old.validate()?;
+    let change = session_changes.remove(index);
+    info!("Undoing change: {}", &change.label());
+    change.old.apply(boot_config);
+
+    Ok(change.old)
+}
+
+// Once the boot configuration is committed, its changes cannot be undone anymore
+pub fn clear_session_changes() {
+    SESSION_CHANGES.lock().unwrap().clear();
+}
+
+// Fields that must outlive a corrupted configuration: the device would otherwise get a new identity, and the recovery menu would lose its passcode
+// Each is taken from the first document it can be found in, in order: a configuration that failed to parse as a whole usually still has them intact
+fn salvage_persistent_fields<'a>(
+    boot_config: &mut BootConfig,
+    boot_config_strs: impl Iterator<Item = &'a str> + Clone,
+) {
+    let find = |field: &str| {
+        boot_config_strs
+            .clone()
+            .find_map(|boot_config_str| scan_string_field(&boot_config_str, &field))
+    };
+    if let Some(device_id) = find("device_id") {
+        info!("Recovered device ID from invalid boot configuration");
+        boot_config.system.device_id = Some(device_id);
+    }
+    if let Some(recovery_passcode_hash) = find("recovery_passcode_hash") {
+        info!("Recovered recovery passcode from invalid boot configuration");
+        boot_config.system.recovery_passcode_hash = Some(recovery_passcode_hash);
+    }
+}
+
+// Looks for 'field: Some("value")' anywhere in the text, without requiring the rest of the document to be valid
+fn scan_string_field(ron_str: &str, field: &str) -> Option<String> {
+    let field_pattern = Regex::new(&format!(
+        r#"(?:^|[^A-Za-z0-9_]){}\s*:\s*Some\(\s*"([^"\\]+)"\s*\)"#,
+        regex::escape(&field)
+    ))
+    .ok()?;
+
+    field_pattern
+        .captures(&ron_str)
+        .map(|captures| captures[1].to_string())
+}
+
+// Fields of a RON struct, their values kept as written: ron::Value would turn enum variants like 'Cw270' into '()'
+struct RawSections(Vec<(String, Box<RawValue>)>);
+
+impl<'de> Deserialize<'de> for RawSections {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<RawSections, D::Error> {
+        struct RawSectionsVisitor;
+
+        impl<'de> Visitor<'de> for RawSectionsVisitor {
+            type Value = RawSections;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a struct")
+            }
+
+            fn visit_unit<E>(self) -> Result<RawSections, E> {
+                Ok(RawSections(Vec::new()))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<RawSections, A::Error> {
+                let mut sections = Vec::new();
+                while let Some(section) = map.next_entry::<String, Box<RawValue>>()? {
+                    sections.push(section);
+                }
+
+                Ok(RawSections(sections))
+            }
+        }
+
+        deserializer.deserialize_any(RawSectionsVisitor)
+    }
+}
+
+// Splits the top-level struct of a RON document into (field name, raw value) pairs without interpreting values
+// Returns None if the document does not parse as a struct
+fn split_top_level_sections(ron_str: &str) -> Option<Vec<(String, String)>> {
+    let RawSections(sections) = ron::from_str::<RawSections>(&ron_str).ok()?;
+
+    Some(
+        sections
+            .into_iter()
+            .map(|(name, value)| (name, value.get_ron().trim().to_string()))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn persistent_fields_are_salvaged_from_invalid_configuration() {
+        let mut boot_config = BootConfig::default_boot_config();
+        boot_config.system.device_id = Some("c0ffee".to_string());
+        boot_config.system.recovery_passcode_hash =
+            Some("$argon2id$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA".to_string());
+        let mut boot_config_str = BootConfig::serialize(&boot_config).unwrap();
+        // Truncated by a power loss while being written
+        boot_config_str.truncate(boot_config_str.len() - 3);
+        assert!(BootConfig::parse(&boot_config_str).is_err());
+
+        let mut salvaged_boot_config = BootConfig::default_boot_config();
+        salvage_persistent_fields(
+            &mut salvaged_boot_config,
+            [boot_config_str.as_str()].into_iter(),
+        );
+        assert_eq!(
+            salvaged_boot_config.system.device_id,
+            boot_config.system.device_id
+        );
+        assert_eq!(
+            salvaged_boot_config.system.recovery_passcode_hash,
+            boot_config.system.recovery_passcode_hash
+        );
+    }
+
+    #[test]
+    fn persistent_fields_fall_back_to_backup() {
+        let mut salvaged_boot_config = BootConfig::default_boot_config();
+        salvage_persistent_fields(
+            &mut salvaged_boot_config,
+            [
+                "(system: (device_id: None",
+                "(system: (device_id: Some(\"c0ffee\"),",
+            ]
+            .into_iter(),
+        );
+        assert_eq!(
+            salvaged_boot_config.system.device_id.as_deref(),
+            Some("c0ffee")
+        );
+        assert_eq!(salvaged_boot_config.system.recovery_passcode_hash, None);
+    }
+
+    #[test]
+    fn field_scan_matches_whole_field_names() {
+        assert_eq!(
+            scan_string_field("old_device_id: Some(\"a\")", "device_id"),
+            None
+        );
+        assert_eq!(
+            scan_string_field("(device_id : Some( \"a\" ))", "device_id").as_deref(),
+            Some("a")
+        );
+    }
+
+    #[test]
+    fn sections_are_split_without_interpreting_values() {
+        assert_eq!(
+            split_top_level_sections(
+                "BootConfig(\n    a: (b: 1, c: [2, 3]),\n    d: \"x, ) y\",\n    e: Cw270,\n    f: Some(Debug(g: true)),\n)"
+            ),
+            Some(vec![
+                ("a".to_string(), "(b: 1, c: [2, 3])".to_string()),
+                ("d".to_string(), "\"x, ) y\"".to_string()),
+                ("e".to_string(), "Cw270".to_string()),
+                ("f".to_string(), "Some(Debug(g: true))".to_string()),
+            ])
+        );
+        assert_eq!(split_top_level_sections("()"), Some(Vec::new()));
+    }
+
+    #[test]
+    fn non_struct_documents_are_not_split() {
+        assert_eq!(split_top_level_sections("[1, 2]"), None);
+        assert_eq!(split_top_level_sections("\"a: b\""), None);
+        assert_eq!(split_top_level_sections("(a: (b: 1)"), None);
+    }
+
+    #[test]
+    fn foreign_sections_survive_a_round_trip() {
+        // As written by a build with a section this one lacks
+        let mut boot_config_str =
+            BootConfig::serialize(&BootConfig::default_boot_config()).unwrap();
+        boot_config_str.truncate(boot_config_str.trim_end().len() - 1);
+        boot_config_str.truncate(boot_config_str.trim_end().len());
+        if !boot_config_str.ends_with(',') {
+            boot_config_str.push(',');
+        }
+        boot_config_str.push_str("\n    future: (level: Verbose, targets: [\"a, b\"]),\n)");
+
+        let boot_config = BootConfig::parse(&boot_config_str).unwrap();
+        assert_eq!(
+            boot_config.foreign_sections,
+            vec![(
+                "future".to_string(),
+                "(level: Verbose, targets: [\"a, b\"])".to_string()
+            )]
+        );
+        let rewritten_boot_config_str = BootConfig::serialize(&boot_config).unwrap();
+        let reparsed_boot_config = BootConfig::parse(&rewritten_boot_config_str).unwrap();
+        assert_eq!(reparsed_boot_config, boot_config);
+        assert_eq!(
+            BootConfig::serialize(&reparsed_boot_config).unwrap(),
+            rewritten_boot_config_str
+        );
+    }
+}
@@ -0,0 +1,241 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::fs;
+use std::path::Path;
+use sys_mount::MountFlags;
+use xz2::write::XzEncoder;
+
+use crate::boot_config::BootConfig;
+use crate::secret::{self, REDACTED};
+use crate::system::{self, list_mounts, with_mount};
+
+const REPORTS_DIR: &str = "reports/";
+const SYS_BLOCK_DIR_PATH: &str = "/sys/block";
+const DEV_DIR_PATH: &str = "/dev";
+// Tried in order: USB drives mostly come formatted with one of these
+const REMOVABLE_FILESYSTEM_TYPES: [&str; 3] = ["vfat", "exfat", "ext4"];
+// Unlike QR codes, reports have no size limit: the default preset compresses much faster than 'xz -9 -e'
+const REPORT_XZ_PRESET: u32 = 6;
+
+// Every file of a report is kept whole, unlike in the QR codes of the fatal error page
+pub struct Report<'a> {
+    pub error_reason: Option<&'a str>,
+    pub version_string: &'a str,
+    pub qinit_log_path: &'a str,
+}
+
+// Recovery key digests, the device ID binding PIN unlock data and the recovery passcode hash are left out, on top of every registered secret
+fn redacted_boot_config() -> Result<String> {
+    let Some(mut boot_config) = BootConfig::peek() else {
+        return Err(anyhow::anyhow!("Failed to read boot configuration"));
+    };
+    for digest in boot_config.system.recovery_keys.values_mut() {
+        if digest.is_some() {
+            *digest = Some(REDACTED.to_string());
+        }
+    }
+    if boot_config.system.device_id.is_some() {
+        boot_config.system.device_id = Some(REDACTED.to_string());
+    }
+    if boot_config.system.recovery_passcode_hash.is_some() {
+        boot_config.system.recovery_passcode_hash = Some(REDACTED.to_string());
+    }
+
+    BootConfig::serialize(&boot_config)
+}
+
+// Sources that cannot be read end up as a note in their file rather than failing the whole report
+fn report_files(report: &Report) -> Vec<(&'static str, String)> {
+    let or_note =
+        |result: Result<String>| result.unwrap_or_else(|e| format!("Not available: {:?}", &e));
+    let mut files = Vec::new();
+    if let Some(error_reason) = report.error_reason {
+        files.push(("error_reason.txt", error_reason.to_string()));
+    }
+    files.push(("version.txt", report.version_string.to_string()));
+    files.push((
+        "qinit.log",
+        or_note(
+            fs::read_to_string(&report.qinit_log_path)
+                .with_context(|| format!("Failed to read '{}'", &report.qinit_log_path)),
+        ),
+    ));
+    files.push((
+        "dmesg.txt",
+        or_note(system::read_kernel_buffer_singleshot()),
+    ));
+    files.push(("boot_config.ron", or_note(redacted_boot_config())));
+    #[cfg(not(feature = "init_wrapper"))]
+    files.push(("input.txt", input_report()));
+    files.push((
+        "boot_timing.ron",
+        or_note(
+            ron::ser::to_string_pretty(
+                &system::boot_timing_report(),
+                ron::ser::PrettyConfig::default(),
+            )
+            .with_context(|| "Failed to serialize boot timing report"),
+        ),
+    ));
+
+    files
+        .into_iter()
+        .map(|(file_name, contents)| (file_name, secret::scrub(&contents)))
+        .collect()
+}
+
+#[cfg(not(feature = "init_wrapper"))]
+fn input_report() -> String {
+    match crate::input::last_probe() {
+        Some(input_probe) => format!(
+            "{}\nTouch input available: {}",
+            input_probe.summary(),
+            if input_probe.touch_input_available() {
+                "yes"
+            } else {
+                "no"
+            }
+        ),
+        None => "Input devices: not probed".to_string(),
+    }
+}
+
+fn build_archive(report: &Report, dir_name: &str) -> Result<Vec<u8>> {
+    let mtime = chrono::Local::now().timestamp().max(0) as u64;
+    let mut builder = tar::Builder::new(XzEncoder::new(Vec::new(), REPORT_XZ_PRESET));
+    for (file_name, contents) in report_files(&report) {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(mtime);
+        header.set_cksum();
+        builder
+            .append_data(
+                &mut header,
+                format!("{}/{}", &dir_name, &file_name),
+                contents.as_bytes(),
+            )
+            .with_context(|| format!("Failed to add '{}' to report", &file_name))?;
+    }
+    builder
+        .into_inner()
+        .with_context(|| "Failed to finish report archive")?
+        .finish()
+        .with_context(|| "Failed to finish xz stream")
+}
+
+// Partitions of removable block devices, or the devices themselves if they have none, e.g. '/dev/sda1'
+fn removable_devices() -> Vec<String> {
+    let Ok(entries) = fs::read_dir(&SYS_BLOCK_DIR_PATH) else {
+        warn!("Failed to list '{}'", &SYS_BLOCK_DIR_PATH);
+        return Vec::new();
+    };
+    let mut devices = Vec::new();
+    for entry in entries.flatten() {
+        let device_name = entry.file_name().to_string_lossy().to_string();
+        let sysfs_path = entry.path();
+        let read_attribute = |attribute: &str| {
+            fs::read_to_string(sysfs_path.join(&attribute))
+                .map(|value| value.trim().to_string())
+                .unwrap_or_default()
+        };
+        // Empty card readers report a size of 0
+        if read_attribute("removable") != "1" || read_attribute("size") == "0" {
+            continue;
+        }
+        let mut partitions: Vec<String> = fs::read_dir(&sysfs_path)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .map(|entry| entry.file_name().to_string_lossy().to_string())
+                    .filter(|name| name.starts_with(&device_name) && name != &device_name)
+                    .collect()
+            })
+            .unwrap_or_default();
+        partitions.sort();
+        if partitions.is_empty() {
+            partitions.push(device_name);
+        }
+        devices.extend(
+            partitions
+                .into_iter()
+                .map(|partition| format!("{}/{}", &DEV_DIR_PATH, &partition)),
+        );
+    }
+    devices.sort();
+
+    devices
+}
+
+fn write_to(dir_path: &Path, file_name: &str, archive: &[u8]) -> Result<()> {
+    let report_path = dir_path.join(&file_name);
+    fs::write(&report_path, &archive)
+        .with_context(|| format!("Failed to write '{}'", &report_path.display()))?;
+
+    system::sync_disks()
+}
+
+// Drives already mounted elsewhere (e.g. by the file browser) are written to in place
+fn save_to_removable_device(device: &str, file_name: &str, archive: &[u8]) -> Result<()> {
+    if let Some((_, mountpoint, _, _)) = list_mounts()?
+        .into_iter()
+        .find(|(source, _, _, _)| source == device)
+    {
+        return write_to(Path::new(&mountpoint), &file_name, &archive);
+    }
+
+    let mut last_error = None;
+    for fstype in REMOVABLE_FILESYSTEM_TYPES {
+        match with_mount(
+            &device,
+            &fstype,
+            MountFlags::NOSUID | MountFlags::NODEV | MountFlags::NOEXEC,
+            None,
+            |mountpoint| write_to(Path::new(&mountpoint), &file_name, &archive),
+        ) {
+            Ok(()) => return Ok(()),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No filesystem to try")))
+        .with_context(|| format!("Failed to write report to '{}'", &device))
+}
+
+// Goes to the first removable drive that takes it, then to the boot partition: returns where the report ended up, for display
+pub fn save_report(report: &Report) -> Result<String> {
+    let dir_name = format!(
+        "qinit-report-{}",
+        chrono::Local::now().format("%Y%m%d-%H%M%S")
+    );
+    let file_name = format!("{}.tar.xz", &dir_name);
+    let archive = build_archive(&report, &dir_name)?;
+    info!("Built diagnostic report: {} bytes", archive.len());
+
+    for device in removable_devices() {
+        match save_to_removable_device(&device, &file_name, &archive) {
+            Ok(()) => {
+                let location = format!("{}: {}", &device, &file_name);
+                info!("Saved diagnostic report to '{}'", &location);
+                return Ok(location);
+            }
+            Err(e) => warn!("{:?}", &e),
+        }
+    }
+
+    info!("No removable drive took the diagnostic report: falling back to boot partition");
+    if let Some(issue) = system::boot_partition_write_issue() {
+        return Err(anyhow::anyhow!(
+            "No removable drive available, and boot partition is not writable: {:?}",
+            &issue
+        ));
+    }
+    let reports_dir_path = Path::new(&crate::BOOT_PART_MOUNTPOINT).join(&REPORTS_DIR);
+    fs::create_dir_all(&reports_dir_path)
+        .with_context(|| format!("Failed to create '{}'", &reports_dir_path.display()))?;
+    write_to(&reports_dir_path, &file_name, &archive)?;
+    let location = reports_dir_path.join(&file_name).display().to_string();
+    info!("Saved diagnostic report to '{}'", &location);
+
+    Ok(location)
+}
@@ -1,24 +1,70 @@
 use crate::boot_config::BootConfig;
-use crate::system::{modprobe, run_command, start_service};
+use crate::notifier;
+use crate::openrc;
+use crate::partitions::{self, Partition};
+use crate::system::{kmod, run_command, start_service};
 use anyhow::{Context, Result};
 use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
 use std::process::Command;
+use std::time::{Duration, Instant};
 use std::{
-    fs::{self, File},
+    fs,
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
     thread,
 };
 
-const WAVEFORM_PART: &str = "/dev/mmcblk0p2";
 const WAVEFORM_FILE: &str = "ebc.wbf";
 const CUSTOMWF_FILE: &str = "custom_wf.bin";
 const FIRMWARE_DIR: &str = "waveform/";
+// Shipped in the firmware SquashFS archive, outside of the waveform tmpfs
+const GENERIC_WAVEFORM_DIR: &str = "/lib/firmware/generic_waveform/";
+
+static GENERIC_WAVEFORM_IN_USE: AtomicBool = AtomicBool::new(false);
 
 const UDEV_RULES_PATH: &str = "/etc/udev/rules.d/";
-const LIBINPUT_CW_0: &str = r#"ENV{LIBINPUT_CALIBRATION_MATRIX}="-1 0 1 0 -1 1""#;
-const LIBINPUT_CW_90: &str = r#"ENV{LIBINPUT_CALIBRATION_MATRIX}="0 -1 1 1 0 0""#;
-const LIBINPUT_CW_180: &str = r#"ENV{LIBINPUT_CALIBRATION_MATRIX}="1 0 0 0 1 0""#;
-const LIBINPUT_CW_270: &str = r#"ENV{LIBINPUT_CALIBRATION_MATRIX}="0 1 0 -1 0 1""#;
+const UDEVADM_BINARY_PATH: &str = "/bin/udevadm";
+const DRM_CLASS_PATH: &str = "/sys/class/drm";
+const FB_VIRTUAL_SIZE_PATH: &str = "/sys/class/graphics/fb0/virtual_size";
+// Row-major 2x3 matrices mapping normalized touch coordinates to normalized display coordinates (the digitizer is mounted upside down relative to the panel)
+const TOUCH_MATRIX_CW_0: [f32; 6] = [-1.0, 0.0, 1.0, 0.0, -1.0, 1.0];
+const TOUCH_MATRIX_CW_90: [f32; 6] = [0.0, -1.0, 1.0, 1.0, 0.0, 0.0];
+const TOUCH_MATRIX_CW_180: [f32; 6] = [1.0, 0.0, 0.0, 0.0, 1.0, 0.0];
+const TOUCH_MATRIX_CW_270: [f32; 6] = [0.0, 1.0, 0.0, -1.0, 0.0, 1.0];
+
+// Farthest a calibration tap may land from where its target should be, in fractions of the display size
+const CALIBRATION_TOLERANCE: f32 = 0.2;
+
+static GEOMETRY: Mutex<Option<Geometry>> = Mutex::new(None);
+
+const EBC_MODULE: &str = "rockchip_ebc";
+const EBC_DRIVER_PATH: &str = "/sys/bus/platform/drivers/rockchip-ebc";
+const EBC_DEVICE: &str = "fdec0000.ebc";
+const EBC_FRAME_COUNTER_FILE: &str = "frame_count";
+const REFRESH_WATCHDOG_INTERVAL: Duration = Duration::from_secs(60);
+// Panels that keep wedging would otherwise be reset over and over again
+const DISPLAY_RECOVERY_MIN_INTERVAL: Duration = Duration::from_secs(600);
+const DISPLAY_RECOVERY_HISTORY_FILE: &str = "display_recoveries.ron";
+const DISPLAY_RECOVERY_HISTORY_MAX_RECORDS: usize = 50;
+
+static FRAMES_RENDERED: AtomicU64 = AtomicU64::new(0);
+static LAST_DISPLAY_RECOVERY: Mutex<Option<Instant>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum DisplayRecoveryMethod {
+    Rebind,
+    ModuleReload,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DisplayRecovery {
+    pub timestamp: i64,
+    pub method: DisplayRecoveryMethod,
+    pub succeeded: bool,
+}
 
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize, Default)]
 pub enum ScreenRotation {
@@ -29,32 +75,271 @@ pub enum ScreenRotation {
     Cw270,
 }
 
-pub fn load_waveform() -> Result<()> {
-    info!("Loading waveform from MMC");
-    let waveform_path = format!("{}/{}", &crate::system::WAVEFORM_DIR_PATH, &WAVEFORM_FILE);
-    let waveform_customwf_path =
-        format!("{}/{}", &crate::system::WAVEFORM_DIR_PATH, &CUSTOMWF_FILE);
+impl ScreenRotation {
+    pub const ALL: [ScreenRotation; 4] = [
+        ScreenRotation::Cw0,
+        ScreenRotation::Cw90,
+        ScreenRotation::Cw180,
+        ScreenRotation::Cw270,
+    ];
+
+    pub fn quarter_turns(&self) -> usize {
+        ScreenRotation::ALL
+            .iter()
+            .position(|rotation| rotation == self)
+            .unwrap_or_default()
+    }
+
+    // Clockwise
+    pub fn turned(&self, quarter_turns: usize) -> ScreenRotation {
+        ScreenRotation::ALL[(self.quarter_turns() + quarter_turns) % ScreenRotation::ALL.len()]
+            .clone()
+    }
+}
+
+// Width and height are the panel's native (unrotated) dimensions: use effective_size() for what the user sees
+#[derive(Debug, PartialEq, Clone)]
+pub struct Geometry {
+    pub width: u32,
+    pub height: u32,
+    pub rotation: ScreenRotation,
+    pub dpi: u32,
+}
+
+impl Geometry {
+    pub const fn native(rotation: ScreenRotation) -> Geometry {
+        Geometry {
+            width: crate::SCREEN_W as u32,
+            height: crate::SCREEN_H as u32,
+            rotation,
+            dpi: crate::SCREEN_DPI as u32,
+        }
+    }
+
+    // Falls back to the PineNote's panel dimensions if neither DRM nor the framebuffer report a mode
+    pub fn resolve(rotation: &ScreenRotation) -> Geometry {
+        let mut geometry = Geometry::native(rotation.clone());
+        match read_drm_mode().or_else(read_framebuffer_size) {
+            Some((width, height)) => {
+                geometry.width = width;
+                geometry.height = height;
+            }
+            None => warn!("Could not read display mode: assuming native panel dimensions"),
+        }
+        info!("Screen geometry: {:?}", &geometry);
+
+        geometry
+    }
+
+    pub fn with_rotation(&self, rotation: &ScreenRotation) -> Geometry {
+        Geometry {
+            rotation: rotation.clone(),
+            ..self.clone()
+        }
+    }
+
+    pub const fn is_landscape(&self) -> bool {
+        matches!(self.rotation, ScreenRotation::Cw0 | ScreenRotation::Cw180)
+    }
+
+    pub const fn effective_size(&self) -> (u32, u32) {
+        if self.is_landscape() {
+            (self.width, self.height)
+        } else {
+            (self.height, self.width)
+        }
+    }
+
+    fn touch_matrix(&self) -> [f32; 6] {
+        match self.rotation {
+            ScreenRotation::Cw0 => TOUCH_MATRIX_CW_0,
+            ScreenRotation::Cw90 => TOUCH_MATRIX_CW_90,
+            ScreenRotation::Cw180 => TOUCH_MATRIX_CW_180,
+            ScreenRotation::Cw270 => TOUCH_MATRIX_CW_270,
+        }
+    }
+
+    pub fn libinput_calibration_rule(&self) -> String {
+        let matrix = self
+            .touch_matrix()
+            .iter()
+            .map(|value| value.to_string())
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        format!(r#"ENV{{LIBINPUT_CALIBRATION_MATRIX}}="{}""#, &matrix)
+    }
+
+    // Takes normalized touch coordinates (0 to 1) and returns the matching pixel on the rotated display
+    pub fn translate_touch(&self, x: f32, y: f32) -> (u32, u32) {
+        let matrix = self.touch_matrix();
+        let (width, height) = self.effective_size();
+        let display_x = (matrix[0] * x + matrix[1] * y + matrix[2]).clamp(0.0, 1.0);
+        let display_y = (matrix[3] * x + matrix[4] * y + matrix[5]).clamp(0.0, 1.0);
+
+        (
+            ((display_x * width as f32) as u32).min(width.saturating_sub(1)),
+            ((display_y * height as f32) as u32).min(height.saturating_sub(1)),
+        )
+    }
+}
+
+// Modes are listed as "1872x1404", preferred mode first
+fn read_drm_mode() -> Option<(u32, u32)> {
+    fs::read_dir(&DRM_CLASS_PATH)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| fs::read_to_string(entry.path().join("modes")).ok())
+        .find_map(|modes| {
+            let (width, height) = modes.lines().next()?.split_once('x')?;
+            Some((width.parse().ok()?, height.trim().parse().ok()?))
+        })
+}
+
+fn read_framebuffer_size() -> Option<(u32, u32)> {
+    let virtual_size = fs::read_to_string(&FB_VIRTUAL_SIZE_PATH).ok()?;
+    let (width, height) = virtual_size.trim().split_once(',')?;
+
+    Some((width.parse().ok()?, height.parse().ok()?))
+}
+
+pub fn set_geometry(geometry: Geometry) {
+    *GEOMETRY.lock().unwrap() = Some(geometry);
+}
+
+// Native panel dimensions with the default rotation until resolved at startup
+pub fn geometry() -> Geometry {
+    GEOMETRY
+        .lock()
+        .unwrap()
+        .clone()
+        .unwrap_or(Geometry::native(ScreenRotation::Cw270))
+}
+
+fn waveform_backup_paths() -> (String, String, String) {
     let waveform_backup_dir_path = format!("{}/{}", &crate::BOOT_PART_MOUNTPOINT, &FIRMWARE_DIR);
     let waveform_backup_ebcwbf_path = format!("{}/{}", &waveform_backup_dir_path, &WAVEFORM_FILE);
     let waveform_backup_customwf_path = format!("{}/{}", &waveform_backup_dir_path, &CUSTOMWF_FILE);
 
-    if !fs::exists(&waveform_backup_ebcwbf_path)? || !fs::exists(&waveform_backup_customwf_path)? {
-        info!("Backing waveform file up to data partition");
-        backup_waveform_files(&waveform_backup_dir_path, &waveform_backup_ebcwbf_path)
-            .with_context(|| "Failed to backup waveform files")?;
-    } else {
+    (
+        waveform_backup_dir_path,
+        waveform_backup_ebcwbf_path,
+        waveform_backup_customwf_path,
+    )
+}
+
+// Only touches the data and waveform partitions: unlike load_waveform(), this does not need the firmware archive to be mounted
+pub fn prepare_waveform_backup() -> Result<()> {
+    let (waveform_backup_dir_path, waveform_backup_ebcwbf_path, waveform_backup_customwf_path) =
+        waveform_backup_paths();
+    if fs::exists(&waveform_backup_ebcwbf_path)? && fs::exists(&waveform_backup_customwf_path)? {
         info!("Found existing waveform backup files");
+        return Ok(());
+    }
+
+    if let Some(issue) = crate::system::boot_partition_write_issue() {
+        return Err(anyhow::anyhow!(
+            "Not backing waveform file up: {}",
+            issue.notice()
+        ));
     }
+    info!("Backing waveform file up to data partition");
+    backup_waveform_files(&waveform_backup_dir_path, &waveform_backup_ebcwbf_path)
+        .with_context(|| "Failed to backup waveform files")
+}
+
+// Where waveform files are looked for, in order: only differs in tests
+struct WaveformPaths {
+    live_dir: String,
+    backup_dir: String,
+    generic_dir: String,
+}
+
+#[derive(Debug, PartialEq)]
+enum WaveformSource {
+    Backup,
+    Partition,
+    Generic,
+}
 
-    info!("Copying backup waveform files to live system");
-    fs::copy(&waveform_backup_ebcwbf_path, &waveform_path)
-        .with_context(|| "Failed to copy backup waveform file to live system")?;
-    fs::copy(&waveform_backup_customwf_path, &waveform_customwf_path)
-        .with_context(|| "Failed to copy custom waveform file to live system")?;
+pub fn load_waveform() -> Result<()> {
+    info!("Loading waveform from MMC");
+    let (waveform_backup_dir_path, _, _) = waveform_backup_paths();
+    let source = load_waveform_from(
+        &WaveformPaths {
+            live_dir: crate::system::WAVEFORM_DIR_PATH.to_string(),
+            backup_dir: waveform_backup_dir_path,
+            generic_dir: GENERIC_WAVEFORM_DIR.to_string(),
+        },
+        backup_waveform_files,
+    )?;
+    if source == WaveformSource::Generic {
+        GENERIC_WAVEFORM_IN_USE.store(true, Ordering::SeqCst);
+    }
 
     Ok(())
 }
 
+// Existing backup first, then a fresh read of the waveform partition, then the generic waveform shipped with the firmware
+fn load_waveform_from(
+    paths: &WaveformPaths,
+    read_partition: impl FnOnce(&str, &str) -> Result<()>,
+) -> Result<WaveformSource> {
+    let waveform_path = format!("{}/{}", &paths.live_dir, &WAVEFORM_FILE);
+    let waveform_customwf_path = format!("{}/{}", &paths.live_dir, &CUSTOMWF_FILE);
+    let waveform_backup_ebcwbf_path = format!("{}/{}", &paths.backup_dir, &WAVEFORM_FILE);
+    let waveform_backup_customwf_path = format!("{}/{}", &paths.backup_dir, &CUSTOMWF_FILE);
+
+    let copy_backup_to_live_system = || -> Result<()> {
+        info!("Copying backup waveform files to live system");
+        fs::copy(&waveform_backup_ebcwbf_path, &waveform_path)
+            .with_context(|| "Failed to copy backup waveform file to live system")?;
+        fs::copy(&waveform_backup_customwf_path, &waveform_customwf_path)
+            .with_context(|| "Failed to copy custom waveform file to live system")?;
+        Ok(())
+    };
+
+    if fs::exists(&waveform_backup_ebcwbf_path)? && fs::exists(&waveform_backup_customwf_path)? {
+        info!("Found existing waveform backup files");
+        match copy_backup_to_live_system() {
+            Ok(()) => return Ok(WaveformSource::Backup),
+            Err(e) => warn!("Could not use waveform backup files: {}", &e),
+        }
+    } else {
+        warn!("Could not use waveform backup files: none found");
+    }
+
+    info!("Backing waveform file up to data partition");
+    let e = match read_partition(&paths.backup_dir, &waveform_backup_ebcwbf_path)
+        .with_context(|| "Failed to backup waveform files")
+        .and_then(|()| copy_backup_to_live_system())
+    {
+        Ok(()) => return Ok(WaveformSource::Partition),
+        Err(e) => e,
+    };
+
+    warn!(
+        "!!! Could not load this device's display waveform ({}): falling back to the generic PineNote waveform, display quality may be degraded !!!",
+        &e
+    );
+    fs::copy(
+        &format!("{}/{}", &paths.generic_dir, &WAVEFORM_FILE),
+        &waveform_path,
+    )
+    .with_context(|| "Failed to copy generic waveform file to live system")?;
+    fs::copy(
+        &format!("{}/{}", &paths.generic_dir, &CUSTOMWF_FILE),
+        &waveform_customwf_path,
+    )
+    .with_context(|| "Failed to copy generic custom waveform file to live system")?;
+
+    Ok(WaveformSource::Generic)
+}
+
+pub fn generic_waveform_in_use() -> bool {
+    GENERIC_WAVEFORM_IN_USE.load(Ordering::SeqCst)
+}
+
 pub fn load_modules() -> Result<()> {
     info!("Loading eInk display modules and activating EPDC");
     let modules = [
@@ -65,22 +350,19 @@ pub fn load_modules() -> Result<()> {
         "rockchip_ebc",
     ];
 
-    for module in &modules {
-        modprobe(&[module])?;
-    }
-
-    Ok(())
+    kmod::load_modules(&modules)
 }
 
 pub fn backup_waveform_files(
     waveform_backup_dir_path: &str,
     waveform_backup_ebcwbf_path: &str,
 ) -> Result<()> {
-    let mut waveform = fs::read(&WAVEFORM_PART).with_context(|| "Failed to read waveform")?;
+    let waveform_part_path = partitions::get_path(&Partition::Waveform)?;
+    let mut waveform = fs::read(&waveform_part_path).with_context(|| "Failed to read waveform")?;
     if waveform.is_empty() {
         warn!("Waveform data is empty, trying again with dd");
         waveform = Command::new("/bin/dd")
-            .args(&[&format!("if={}", &WAVEFORM_PART)])
+            .args(&[&format!("if={}", &waveform_part_path)])
             .output()
             .with_context(|| "Failed to collect dd output")?
             .stdout;
@@ -98,24 +380,26 @@ pub fn backup_waveform_files(
     Ok(())
 }
 
-pub fn setup_touchscreen(boot_config: &mut BootConfig) -> Result<()> {
-    info!("Setting up touchscreen input");
-
+fn write_libinput_rules(rotation: &ScreenRotation) -> Result<()> {
     fs::create_dir_all(&UDEV_RULES_PATH)?;
     let libinput_rules_path = format!("{}/libinput.rules", &UDEV_RULES_PATH);
 
-    if boot_config.system.initial_screen_rotation == ScreenRotation::Cw0 {
-        fs::write(&libinput_rules_path, &LIBINPUT_CW_0)?;
-    } else if boot_config.system.initial_screen_rotation == ScreenRotation::Cw90 {
-        fs::write(&libinput_rules_path, &LIBINPUT_CW_90)?;
-    } else if boot_config.system.initial_screen_rotation == ScreenRotation::Cw180 {
-        fs::write(&libinput_rules_path, &LIBINPUT_CW_180)?;
-    } else {
-        fs::write(&libinput_rules_path, &LIBINPUT_CW_270)?;
-    }
+    fs::write(
+        &libinput_rules_path,
+        geometry()
+            .with_rotation(&rotation)
+            .libinput_calibration_rule(),
+    )
+    .with_context(|| format!("Failed to write '{}'", &libinput_rules_path))
+}
+
+pub fn setup_touchscreen(boot_config: &mut BootConfig) -> Result<()> {
+    info!("Setting up touchscreen input");
+    write_libinput_rules(&boot_config.system.initial_screen_rotation)?;
 
-    run_command("/sbin/openrc", &[])?;
-    File::create("/run/openrc/softlevel")?;
+    if !openrc::get().is_up() {
+        warn!("Starting udev without OpenRC");
+    }
     start_service("udev")?;
     start_service("udev-trigger")?;
     start_service("udev-settle")?;
@@ -123,8 +407,58 @@ pub fn setup_touchscreen(boot_config: &mut BootConfig) -> Result<()> {
     Ok(())
 }
 
-pub fn full_refresh() {
-    debug!("Triggering full screen refresh");
+// libinput only reads the calibration matrix when a device shows up: the touchscreen is removed and added back for the new rules to apply
+// The display keeps its rotation until the next boot
+pub fn apply_touchscreen_rotation(rotation: &ScreenRotation) -> Result<()> {
+    info!("Applying touchscreen rotation {:?}", &rotation);
+    write_libinput_rules(&rotation)?;
+    for action in ["--action=remove", "--action=add"] {
+        run_command(
+            &UDEVADM_BINARY_PATH,
+            &[
+                "trigger",
+                &action,
+                "--subsystem-match=input",
+                "--property-match=ID_INPUT_TOUCHSCREEN=1",
+            ],
+        )
+        .with_context(|| "Failed to re-trigger touchscreen")?;
+    }
+
+    run_command(&UDEVADM_BINARY_PATH, &["settle"])
+}
+
+// Each tap is where the user aimed at the matching target, both in fractions of the display size
+// Returns the rotation under which the taps would have landed on their targets, or None if no rotation explains them
+pub fn suggest_touchscreen_rotation(
+    geometry: &Geometry,
+    targets: &[(f32, f32)],
+    taps: &[(f32, f32)],
+) -> Option<ScreenRotation> {
+    if targets.is_empty() || targets.len() != taps.len() {
+        return None;
+    }
+    // The Cw180 touch matrix is the identity: turned from there, it moves taps to where they land after turning the touchscreen rotation as much
+    let quarter_turns = (0..4).find(|quarter_turns| {
+        let turned = geometry.with_rotation(&ScreenRotation::Cw180.turned(*quarter_turns));
+        let (width, height) = turned.effective_size();
+        targets.iter().zip(taps).all(|(target, tap)| {
+            let (x, y) = turned.translate_touch(tap.0, tap.1);
+            (x as f32 / width as f32 - target.0).hypot(y as f32 / height as f32 - target.1)
+                <= CALIBRATION_TOLERANCE
+        })
+    })?;
+    debug!(
+        "Touchscreen calibration taps are off by {} clockwise quarter turns",
+        &quarter_turns
+    );
+
+    Some(geometry.rotation.turned(quarter_turns))
+}
+
+// Returns whether the panel took the refresh, or None when the driver does not tell
+fn trigger_global_refresh() -> Option<bool> {
+    let frames_before = frame_counter();
     // Calling new here is, well, bad (because of possible wrong default values),
     // but we shut down in a second, so no one should care
     let ebc = pinenote_service::drivers::rockchip_ebc::RockchipEbc::new();
@@ -132,4 +466,287 @@ pub fn full_refresh() {
     // TODO: Find a way to interact with EPDC so that it tells us when screen updates
     // are done to avoid doing this kind of horrible things
     thread::sleep(std::time::Duration::from_millis(1000));
+
+    Some(frame_counter()? > frames_before?)
+}
+
+pub fn full_refresh() {
+    debug!("Triggering full screen refresh");
+    if trigger_global_refresh() == Some(false) {
+        warn!("Display did not take the full refresh: EPDC pipeline looks stuck");
+        recover_display();
+    }
+}
+
+// Frames handed to the display so far, as reported by the driver
+fn frame_counter() -> Option<u64> {
+    let frame_counter_path = format!(
+        "{}/{}/{}",
+        &EBC_DRIVER_PATH, &EBC_DEVICE, &EBC_FRAME_COUNTER_FILE
+    );
+    fs::read_to_string(&frame_counter_path)
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+}
+
+// To be called whenever the GUI rendered a frame that should reach the panel
+pub fn note_frame_rendered() {
+    FRAMES_RENDERED.fetch_add(1, Ordering::SeqCst);
+}
+
+// Starts the periodic check for long sessions: the GUI rendering while the driver's frame counter stands still hints at a wedge, which a full refresh then confirms
+pub fn start_refresh_watchdog() {
+    if frame_counter().is_none() {
+        info!("Display driver does not report frames: not watching screen refreshes");
+        return;
+    }
+    thread::spawn(|| {
+        let mut frames_rendered = FRAMES_RENDERED.load(Ordering::SeqCst);
+        let mut frames_displayed = frame_counter();
+        loop {
+            thread::sleep(REFRESH_WATCHDOG_INTERVAL);
+            let last_frames_rendered = frames_rendered;
+            let last_frames_displayed = frames_displayed;
+            frames_rendered = FRAMES_RENDERED.load(Ordering::SeqCst);
+            frames_displayed = frame_counter();
+            if frames_rendered != last_frames_rendered && frames_displayed == last_frames_displayed
+            {
+                debug!("GUI rendered frames that did not reach the display: checking EPDC");
+                full_refresh();
+                frames_displayed = frame_counter();
+            }
+        }
+    });
+}
+
+fn get_display_recovery_history_path() -> String {
+    format!(
+        "{}/{}/{}",
+        &crate::MAIN_PART_MOUNTPOINT,
+        &crate::SYSTEM_DIR,
+        &DISPLAY_RECOVERY_HISTORY_FILE
+    )
+}
+
+pub fn display_recovery_history() -> Vec<DisplayRecovery> {
+    let Ok(history_str) = fs::read_to_string(&get_display_recovery_history_path()) else {
+        return Vec::new();
+    };
+    ron::from_str::<Vec<DisplayRecovery>>(&history_str).unwrap_or_else(|e| {
+        warn!("Ignoring invalid display recovery history: {}", &e);
+        Vec::new()
+    })
+}
+
+fn record_display_recovery(recovery: DisplayRecovery) -> Result<()> {
+    let mut history = display_recovery_history();
+    history.push(recovery);
+    let excess_records = history
+        .len()
+        .saturating_sub(DISPLAY_RECOVERY_HISTORY_MAX_RECORDS);
+    history.drain(0..excess_records);
+    fs::write(
+        &get_display_recovery_history_path(),
+        ron::to_string(&history)?,
+    )
+    .with_context(|| "Failed to write display recovery history")?;
+
+    Ok(())
+}
+
+// Structured section for diagnostics, e.g. "Display recoveries: 2, last on 2026-10-14 09:12 (rebind, succeeded)"
+pub fn display_recovery_summary() -> String {
+    let history = display_recovery_history();
+    match history.last() {
+        Some(last) => format!(
+            "Display recoveries: {}, last on {} ({}, {})",
+            history.len(),
+            chrono::DateTime::from_timestamp(last.timestamp, 0)
+                .map(|timestamp| timestamp.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_default(),
+            format!("{:?}", &last.method).to_lowercase(),
+            if last.succeeded {
+                "succeeded"
+            } else {
+                "failed"
+            }
+        ),
+        None => "Display recoveries: none".to_string(),
+    }
+}
+
+fn rebind_driver() -> Result<()> {
+    for action in ["unbind", "bind"] {
+        let path = format!("{}/{}", &EBC_DRIVER_PATH, &action);
+        fs::write(&path, &EBC_DEVICE)
+            .with_context(|| format!("Failed to {} '{}' display driver", &action, &EBC_DEVICE))?;
+    }
+
+    Ok(())
+}
+
+fn reload_driver_module() -> Result<()> {
+    kmod::unload_module(&EBC_MODULE)?;
+    kmod::load_module(&EBC_MODULE, &[])
+}
+
+// Resets the EPDC pipeline once it stopped taking updates, at most once every DISPLAY_RECOVERY_MIN_INTERVAL
+fn recover_display() {
+    {
+        let mut last_recovery = LAST_DISPLAY_RECOVERY.lock().unwrap();
+        if let Some(last_recovery) = *last_recovery
+            && last_recovery.elapsed() < DISPLAY_RECOVERY_MIN_INTERVAL
+        {
+            warn!("Display was reset recently: not trying again yet");
+            return;
+        }
+        *last_recovery = Some(Instant::now());
+    }
+
+    // The driver reads the waveform when probing the device
+    if let Err(e) = load_waveform() {
+        warn!("Failed to re-apply display waveform: {:?}", &e);
+    }
+    let mut method = DisplayRecoveryMethod::Rebind;
+    info!("Resetting EPDC pipeline by rebinding the display driver");
+    let mut result = rebind_driver();
+    if let Err(e) = &result {
+        warn!("{:?}: reloading the display driver module instead", &e);
+        method = DisplayRecoveryMethod::ModuleReload;
+        result = reload_driver_module();
+    }
+    let succeeded = match result {
+        Ok(()) => trigger_global_refresh() != Some(false),
+        Err(e) => {
+            warn!("Failed to reload display driver: {:?}", &e);
+            false
+        }
+    };
+
+    if succeeded {
+        info!("Display recovered after {:?}", &method);
+        notifier::get().toast("The display stopped updating and was reset");
+    } else {
+        warn!("Display is still not updating after {:?}", &method);
+        notifier::get().toast("The display stopped updating and could not be reset");
+    }
+    if let Err(e) = record_display_recovery(DisplayRecovery {
+        timestamp: chrono::Local::now().timestamp(),
+        method,
+        succeeded,
+    }) {
+        warn!("{:?}", &e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    fn waveform_paths(temp_dir: &TempDir) -> WaveformPaths {
+        let paths = WaveformPaths {
+            live_dir: temp_dir.join("live"),
+            backup_dir: temp_dir.join("backup"),
+            generic_dir: temp_dir.join("generic"),
+        };
+        fs::create_dir_all(&paths.live_dir).unwrap();
+        temp_dir.write("generic/ebc.wbf", b"generic waveform");
+        temp_dir.write("generic/custom_wf.bin", b"generic custom waveform");
+
+        paths
+    }
+
+    #[test]
+    fn touches_are_translated_to_rotated_pixels() {
+        let geometry = Geometry::native(ScreenRotation::Cw180);
+        assert_eq!(geometry.translate_touch(0.5, 0.25), (936, 351));
+        assert_eq!(geometry.translate_touch(1.0, 1.0), (1871, 1403));
+        // Portrait, with the panel's origin in the bottom left corner
+        let geometry = geometry.with_rotation(&ScreenRotation::Cw270);
+        assert_eq!(geometry.effective_size(), (1404, 1872));
+        assert_eq!(geometry.translate_touch(0.0, 0.0), (0, 1871));
+    }
+
+    #[test]
+    fn calibration_taps_suggest_the_matching_rotation() {
+        let targets = [(0.1, 0.1), (0.9, 0.1), (0.9, 0.9), (0.1, 0.9)];
+        let geometry = Geometry::native(ScreenRotation::Cw270);
+        assert_eq!(
+            suggest_touchscreen_rotation(&geometry, &targets, &targets),
+            Some(ScreenRotation::Cw270)
+        );
+        // Each tap lands on the next target clockwise
+        let taps = [(0.9, 0.1), (0.9, 0.9), (0.1, 0.9), (0.1, 0.1)];
+        assert_eq!(
+            suggest_touchscreen_rotation(&geometry, &targets, &taps),
+            Some(ScreenRotation::Cw0)
+        );
+        let scattered = [(0.5, 0.5), (0.9, 0.9), (0.1, 0.1), (0.5, 0.9)];
+        assert_eq!(
+            suggest_touchscreen_rotation(&geometry, &targets, &scattered),
+            None
+        );
+        assert_eq!(suggest_touchscreen_rotation(&geometry, &targets, &[]), None);
+    }
+
+    #[test]
+    fn existing_backup_is_used_first() {
+        let temp_dir = TempDir::new();
+        let paths = waveform_paths(&temp_dir);
+        temp_dir.write("backup/ebc.wbf", b"backup waveform");
+        temp_dir.write("backup/custom_wf.bin", b"backup custom waveform");
+
+        let source = load_waveform_from(&paths, |_, _| panic!("Partition should not be read"));
+        assert_eq!(source.unwrap(), WaveformSource::Backup);
+        assert_eq!(
+            fs::read(&temp_dir.join("live/ebc.wbf")).unwrap(),
+            b"backup waveform"
+        );
+    }
+
+    #[test]
+    fn partition_is_read_without_a_backup() {
+        let temp_dir = TempDir::new();
+        let paths = waveform_paths(&temp_dir);
+        temp_dir.write("backup/custom_wf.bin", b"backup custom waveform");
+
+        let source = load_waveform_from(&paths, |_, waveform_backup_ebcwbf_path| {
+            fs::write(&waveform_backup_ebcwbf_path, b"partition waveform")?;
+            Ok(())
+        });
+        assert_eq!(source.unwrap(), WaveformSource::Partition);
+        assert_eq!(
+            fs::read(&temp_dir.join("live/ebc.wbf")).unwrap(),
+            b"partition waveform"
+        );
+    }
+
+    #[test]
+    fn unreadable_partition_falls_back_to_generic_waveform() {
+        let temp_dir = TempDir::new();
+        let paths = waveform_paths(&temp_dir);
+
+        let source = load_waveform_from(&paths, |_, _| Err(anyhow::anyhow!("I/O error")));
+        assert_eq!(source.unwrap(), WaveformSource::Generic);
+        assert_eq!(
+            fs::read(&temp_dir.join("live/ebc.wbf")).unwrap(),
+            b"generic waveform"
+        );
+        assert_eq!(
+            fs::read(&temp_dir.join("live/custom_wf.bin")).unwrap(),
+            b"generic custom waveform"
+        );
+    }
+
+    #[test]
+    fn missing_generic_waveform_is_an_error() {
+        let temp_dir = TempDir::new();
+        let paths = waveform_paths(&temp_dir);
+        fs::remove_dir_all(&paths.generic_dir).unwrap();
+
+        assert!(load_waveform_from(&paths, |_, _| Err(anyhow::anyhow!("I/O error"))).is_err());
+    }
 }
@@ -0,0 +1,331 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use log::{debug, warn};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::system::list_mounts;
+
+pub const PAGE_SIZE: usize = 50;
+// Where boot timing reports and other diagnostics end up
+const DIAGNOSTICS_DIR_PATH: &str = "/var/log";
+const USB_DEVICE_PREFIX: &str = "/dev/sd";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilePurpose {
+    Any,
+    UpdateArchive,
+    Wallpaper,
+    BackupArchive,
+}
+
+impl FilePurpose {
+    // Empty means that every file is shown
+    pub fn extensions(&self) -> &'static [&'static str] {
+        match self {
+            FilePurpose::Any => &[],
+            FilePurpose::UpdateArchive => &[".squashfs"],
+            FilePurpose::Wallpaper => &[".png", ".jpg", ".jpeg", ".svg"],
+            FilePurpose::BackupArchive => &[".tar", ".tar.gz", ".tgz", ".tar.xz"],
+        }
+    }
+
+    pub fn accepts(&self, file_name: &str) -> bool {
+        let file_name = file_name.to_lowercase();
+        let extensions = self.extensions();
+        extensions.is_empty()
+            || extensions
+                .iter()
+                .any(|extension| file_name.ends_with(extension))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Root {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub name: String,
+    pub path: PathBuf,
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl Entry {
+    pub fn size_label(&self) -> String {
+        if self.is_dir {
+            return String::new();
+        }
+        match self.size {
+            size if size >= 1_000_000_000 => format!("{:.1} GB", size as f64 / 1_000_000_000.0),
+            size if size >= 1_000_000 => format!("{:.1} MB", size as f64 / 1_000_000.0),
+            size if size >= 1_000 => format!("{:.1} kB", size as f64 / 1_000.0),
+            size => format!("{} B", size),
+        }
+    }
+
+    pub fn date_label(&self) -> String {
+        self.modified
+            .map(|modified| {
+                DateTime::<Local>::from(modified)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string()
+            })
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Listing {
+    pub entries: Vec<Entry>,
+    pub page: usize,
+    pub pages_count: usize,
+}
+
+pub fn usb_drives() -> Vec<Root> {
+    match list_mounts() {
+        Ok(mounts) => mounts
+            .into_iter()
+            .filter(|(source, _, _, _)| source.starts_with(&USB_DEVICE_PREFIX))
+            .map(|(source, target, _, _)| Root {
+                name: format!("USB drive ({})", &source),
+                path: PathBuf::from(&target),
+            })
+            .collect(),
+        Err(e) => {
+            warn!("Failed to look for USB drives: {}", &e);
+            Vec::new()
+        }
+    }
+}
+
+// Boot partition, mounted USB drives and diagnostics, in that order: only those that exist are returned
+pub fn roots() -> Vec<Root> {
+    let mut roots = vec![Root {
+        name: "Boot partition".to_string(),
+        path: PathBuf::from(&crate::BOOT_PART_MOUNTPOINT),
+    }];
+    roots.extend(usb_drives());
+    roots.push(Root {
+        name: "Diagnostics".to_string(),
+        path: PathBuf::from(&DIAGNOSTICS_DIR_PATH),
+    });
+
+    roots
+        .into_iter()
+        .filter_map(|root| {
+            // Roots are compared against canonical paths from then on
+            let path = fs::canonicalize(&root.path).ok()?;
+            Some(Root { path, ..root })
+        })
+        .collect()
+}
+
+// Suitable files found directly inside every root and the data partition, newest first, for when walking through folders is not needed
+pub fn find_files(purpose: FilePurpose) -> Vec<Entry> {
+    let mut dirs: Vec<PathBuf> = roots().into_iter().map(|root| root.path).collect();
+    dirs.push(PathBuf::from(&crate::MAIN_PART_MOUNTPOINT));
+
+    let mut files = Vec::new();
+    for dir in dirs {
+        let Ok(read_dir) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in read_dir.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let Ok(metadata) = fs::metadata(&entry.path()) else {
+                continue;
+            };
+            if metadata.is_file() && purpose.accepts(&name) {
+                files.push(Entry {
+                    name,
+                    path: entry.path(),
+                    is_dir: false,
+                    size: metadata.len(),
+                    modified: metadata.modified().ok(),
+                });
+            }
+        }
+    }
+    files.sort_by(|a, b| b.modified.cmp(&a.modified));
+
+    files
+}
+
+// Read-only: nothing here ever writes to, or deletes, what is being browsed
+pub struct FileBrowser {
+    purpose: FilePurpose,
+    roots: Vec<Root>,
+    // Empty when showing the list of roots
+    path_stack: Vec<PathBuf>,
+    page: usize,
+}
+
+impl FileBrowser {
+    pub fn new(purpose: FilePurpose) -> FileBrowser {
+        FileBrowser {
+            purpose,
+            roots: roots(),
+            path_stack: Vec::new(),
+            page: 0,
+        }
+    }
+
+    pub fn purpose(&self) -> FilePurpose {
+        self.purpose
+    }
+
+    pub fn roots(&self) -> &[Root] {
+        &self.roots
+    }
+
+    pub fn current_dir(&self) -> Option<&Path> {
+        self.path_stack.last().map(|path| path.as_path())
+    }
+
+    // Resolves symbolic links, rejecting anything that ends up outside of every root
+    fn resolve(&self, path: &Path) -> Result<PathBuf> {
+        let canonical_path = fs::canonicalize(&path)
+            .with_context(|| format!("Failed to resolve '{}'", &path.display()))?;
+        if !self
+            .roots
+            .iter()
+            .any(|root| canonical_path.starts_with(&root.path))
+        {
+            return Err(anyhow::anyhow!(
+                "'{}' is outside of the allowed locations",
+                &path.display()
+            ));
+        }
+
+        Ok(canonical_path)
+    }
+
+    pub fn enter(&mut self, path: &Path) -> Result<()> {
+        let canonical_path = self.resolve(&path)?;
+        if !canonical_path.is_dir() {
+            return Err(anyhow::anyhow!("'{}' is not a directory", &path.display()));
+        }
+        debug!("Entering '{}'", &canonical_path.display());
+        self.path_stack.push(canonical_path);
+        self.page = 0;
+
+        Ok(())
+    }
+
+    // Returns false when already showing the list of roots
+    pub fn back(&mut self) -> bool {
+        self.page = 0;
+        self.path_stack.pop().is_some()
+    }
+
+    pub fn next_page(&mut self) {
+        self.page += 1;
+    }
+
+    pub fn previous_page(&mut self) {
+        self.page = self.page.saturating_sub(1);
+    }
+
+    // Only names are collected for sorting: metadata is read for the current page alone, which keeps huge directories cheap
+    pub fn list(&mut self) -> Result<Listing> {
+        let Some(current_dir) = self.current_dir().map(Path::to_path_buf) else {
+            let entries = self
+                .roots
+                .iter()
+                .map(|root| Entry {
+                    name: root.name.clone(),
+                    path: root.path.clone(),
+                    is_dir: true,
+                    size: 0,
+                    modified: None,
+                })
+                .collect();
+            return Ok(Listing {
+                entries,
+                page: 0,
+                pages_count: 1,
+            });
+        };
+
+        let mut names: Vec<(bool, String)> = Vec::new();
+        for entry in fs::read_dir(&current_dir)
+            .with_context(|| format!("Failed to list '{}'", &current_dir.display()))?
+        {
+            let Ok(entry) = entry else {
+                continue;
+            };
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let name = entry.file_name().to_string_lossy().to_string();
+            let is_dir = if file_type.is_symlink() {
+                match self.resolve(&entry.path()) {
+                    Ok(target) => target.is_dir(),
+                    Err(_) => continue,
+                }
+            } else {
+                file_type.is_dir()
+            };
+            if is_dir || self.purpose.accepts(&name) {
+                names.push((is_dir, name));
+            }
+        }
+        // Directories first, then case-insensitive alphabetical order
+        names.sort_by_cached_key(|(is_dir, name)| (!is_dir, name.to_lowercase()));
+
+        let pages_count = names.len().div_ceil(PAGE_SIZE).max(1);
+        self.page = self.page.min(pages_count - 1);
+        let entries = names
+            .into_iter()
+            .skip(self.page * PAGE_SIZE)
+            .take(PAGE_SIZE)
+            .map(|(is_dir, name)| {
+                let path = current_dir.join(&name);
+                let metadata = fs::metadata(&path).ok();
+                Entry {
+                    size: metadata
+                        .as_ref()
+                        .map(|metadata| metadata.len())
+                        .unwrap_or(0),
+                    modified: metadata.and_then(|metadata| metadata.modified().ok()),
+                    name,
+                    path,
+                    is_dir,
+                }
+            })
+            .collect();
+
+        Ok(Listing {
+            entries,
+            page: self.page,
+            pages_count,
+        })
+    }
+
+    // Returns the canonical path of the picked file
+    pub fn pick(&self, path: &Path) -> Result<PathBuf> {
+        let canonical_path = self.resolve(&path)?;
+        if !canonical_path.is_file() {
+            return Err(anyhow::anyhow!("'{}' is not a file", &path.display()));
+        }
+        let file_name = canonical_path
+            .file_name()
+            .map(|file_name| file_name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if !self.purpose.accepts(&file_name) {
+            return Err(anyhow::anyhow!(
+                "'{}' is not a suitable file: expected one of {:?}",
+                &file_name,
+                self.purpose.extensions()
+            ));
+        }
+
+        Ok(canonical_path)
+    }
+}
@@ -0,0 +1,234 @@
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use std::fs;
+use std::io::Read;
+use std::sync::Mutex;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::notifier;
+use crate::system::restart_service;
+
+const INPUT_CLASS_PATH: &str = "/sys/class/input";
+// From linux/input-event-codes.h
+const EV_KEY: u16 = 0x01;
+const KEY_POWER: usize = 116;
+const BTN_TOOL_PEN: usize = 0x140;
+const ABS_MT_POSITION_X: usize = 0x35;
+// struct input_event on 64-bit platforms: struct timeval (16 bytes), type (2), code (2), value (4)
+const INPUT_EVENT_SIZE: usize = 24;
+const LONG_PRESS_DURATION: Duration = Duration::from_millis(600);
+
+const TOUCH_MISSING_NOTICE: &str = "Touch input not detected: use the power button to navigate (short press to move to the next item, long press to select it)";
+
+static LAST_PROBE: Mutex<Option<InputProbe>> = Mutex::new(None);
+static LAST_ACTIVITY: Mutex<Option<Instant>> = Mutex::new(None);
+
+#[derive(Debug, Clone)]
+pub struct InputDevice {
+    pub name: String,
+    pub event_path: String,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct InputProbe {
+    pub touchscreen: Option<InputDevice>,
+    pub pen: Option<InputDevice>,
+    pub power_button: Option<InputDevice>,
+}
+
+impl InputProbe {
+    pub fn touch_input_available(&self) -> bool {
+        self.touchscreen.is_some() || self.pen.is_some()
+    }
+
+    pub fn summary(&self) -> String {
+        let describe = |device: &Option<InputDevice>| match device {
+            Some(device) => format!("'{}' ({})", &device.name, &device.event_path),
+            None => "not detected".to_string(),
+        };
+
+        format!(
+            "Input devices: touchscreen {}, pen {}, power button {}",
+            describe(&self.touchscreen),
+            describe(&self.pen),
+            describe(&self.power_button)
+        )
+    }
+}
+
+pub enum NavigationAction {
+    FocusNext,
+    Activate,
+}
+
+// Capabilities are exposed as space-separated hexadecimal words, most significant first
+fn has_capability(bitmask: &str, bit: usize) -> bool {
+    let word_bits = usize::BITS as usize;
+    let words: Vec<&str> = bitmask.split_whitespace().rev().collect();
+    let Some(word) = words.get(bit / word_bits) else {
+        return false;
+    };
+
+    usize::from_str_radix(&word, 16)
+        .map(|word| word & (1 << (bit % word_bits)) != 0)
+        .unwrap_or(false)
+}
+
+pub fn probe() -> Result<InputProbe> {
+    let mut input_probe = InputProbe::default();
+    for entry in fs::read_dir(&INPUT_CLASS_PATH)
+        .with_context(|| format!("Failed to list '{}'", &INPUT_CLASS_PATH))?
+    {
+        let entry = entry?;
+        let event_name = entry.file_name().to_string_lossy().to_string();
+        if !event_name.starts_with("event") {
+            continue;
+        }
+        let device_path = entry.path().join("device");
+        let read_attribute = |attribute: &str| {
+            fs::read_to_string(device_path.join(attribute))
+                .unwrap_or_default()
+                .trim()
+                .to_string()
+        };
+        let device = InputDevice {
+            name: read_attribute("name"),
+            event_path: format!("/dev/input/{}", &event_name),
+        };
+        let abs_capabilities = read_attribute("capabilities/abs");
+        let key_capabilities = read_attribute("capabilities/key");
+        debug!("Found input device {:?}", &device);
+
+        if has_capability(&key_capabilities, BTN_TOOL_PEN) {
+            input_probe.pen.get_or_insert(device);
+        } else if has_capability(&abs_capabilities, ABS_MT_POSITION_X) {
+            input_probe.touchscreen.get_or_insert(device);
+        } else if has_capability(&key_capabilities, KEY_POWER) {
+            input_probe.power_button.get_or_insert(device);
+        }
+    }
+
+    Ok(input_probe)
+}
+
+// Flaky connectors sometimes leave the I2C devices missing: triggering udev again is worth one try
+pub fn probe_with_retry() -> InputProbe {
+    let mut input_probe = probe().unwrap_or_else(|e| {
+        warn!("Failed to probe input devices: {}", &e);
+        InputProbe::default()
+    });
+    if input_probe.touchscreen.is_none() || input_probe.pen.is_none() {
+        warn!("{}: triggering udev again", &input_probe.summary());
+        if let Err(e) =
+            restart_service("udev-trigger").and_then(|()| restart_service("udev-settle"))
+        {
+            warn!("{}", &e);
+        }
+        if let Ok(new_input_probe) = probe() {
+            input_probe = new_input_probe;
+        }
+    }
+    info!("{}", &input_probe.summary());
+    // The GUI does not exist yet: these wait in the early event buffer
+    if input_probe.touchscreen.is_none() {
+        notifier::get().sticky_toast(TOUCH_MISSING_NOTICE);
+    } else if input_probe.pen.is_none() {
+        notifier::get().toast("Pen digitizer not detected");
+    }
+    *LAST_PROBE.lock().unwrap() = Some(input_probe.clone());
+
+    input_probe
+}
+
+// Result of the boot-time probe, if it ran
+pub fn last_probe() -> Option<InputProbe> {
+    LAST_PROBE.lock().unwrap().clone()
+}
+
+// Basic navigation when touch input is unavailable: short presses move the focus, long presses activate the focused item
+pub fn spawn_power_button_navigation(
+    power_button: &InputDevice,
+    action_sender: Sender<NavigationAction>,
+) -> Result<()> {
+    let mut device = fs::File::open(&power_button.event_path)
+        .with_context(|| format!("Failed to open '{}'", &power_button.event_path))?;
+    info!(
+        "Enabling power button navigation through '{}'",
+        &power_button.name
+    );
+
+    thread::spawn(move || {
+        let mut event = [0u8; INPUT_EVENT_SIZE];
+        let mut pressed_at = None;
+        while device.read_exact(&mut event).is_ok() {
+            let event_type = u16::from_ne_bytes([event[16], event[17]]);
+            let code = u16::from_ne_bytes([event[18], event[19]]);
+            let value = i32::from_ne_bytes([event[20], event[21], event[22], event[23]]);
+            if event_type != EV_KEY || code as usize != KEY_POWER {
+                continue;
+            }
+            match value {
+                1 => pressed_at = Some(Instant::now()),
+                0 => {
+                    let Some(pressed_at) = pressed_at.take() else {
+                        continue;
+                    };
+                    let action = if pressed_at.elapsed() >= LONG_PRESS_DURATION {
+                        NavigationAction::Activate
+                    } else {
+                        NavigationAction::FocusNext
+                    };
+                    if action_sender.send(action).is_err() {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+        warn!("Power button navigation stopped");
+    });
+
+    Ok(())
+}
+
+pub fn note_activity() {
+    *LAST_ACTIVITY.lock().unwrap() = Some(Instant::now());
+}
+
+// Zero until the activity monitor started
+pub fn idle_duration() -> Duration {
+    LAST_ACTIVITY
+        .lock()
+        .unwrap()
+        .map(|last_activity| last_activity.elapsed())
+        .unwrap_or_default()
+}
+
+// Any event from the touchscreen, the pen or the power button counts as activity: reading alongside the GUI does not take events away from it
+pub fn spawn_activity_monitor(input_probe: &InputProbe) {
+    note_activity();
+    for device in [
+        &input_probe.touchscreen,
+        &input_probe.pen,
+        &input_probe.power_button,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        let mut file = match fs::File::open(&device.event_path) {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("Not watching '{}' for activity: {}", &device.event_path, &e);
+                continue;
+            }
+        };
+        thread::spawn(move || {
+            let mut event = [0u8; INPUT_EVENT_SIZE];
+            while file.read_exact(&mut event).is_ok() {
+                note_activity();
+            }
+        });
+    }
+}
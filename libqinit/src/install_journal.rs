@@ -0,0 +1,799 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use crate::notifier;
+use crate::system::{ProgressCallback, sha256_digest, sync_disks};
+
+const JOURNAL_FILE: &str = "install_journal.ron";
+const STAGING_SUFFIX: &str = ".partial";
+// Previous companions are set aside under this suffix until the target is replaced
+const PREVIOUS_SUFFIX: &str = ".previous";
+const COPY_CHUNK_SIZE: usize = 1024 * 1024;
+
+// Written before an install starts and removed once it is complete: a leftover record means the install was interrupted
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct InstallIntent {
+    pub target: String,
+    pub source: String,
+    pub expected_sha256: String,
+    pub temp_path: String,
+    // Suffixes of the files that describe the target (e.g. its signature): they are staged next to the staging file and switched along with it
+    #[serde(default)]
+    pub companions: Vec<String>,
+    // Companions the target had before the install, which a rollback restores: the others are removed
+    #[serde(default)]
+    pub previous_companions: Vec<String>,
+}
+
+// Runs on the fully copied and checksummed staging file, right before it replaces the target: an error rolls the install back
+pub type VerifyCallback<'a> = &'a mut dyn FnMut(&InstallIntent) -> Result<()>;
+
+struct Journal {
+    journal_path: String,
+}
+
+impl Journal {
+    fn system() -> Journal {
+        Journal::at(&format!(
+            "{}/{}",
+            &crate::MAIN_PART_MOUNTPOINT,
+            &crate::SYSTEM_DIR
+        ))
+    }
+
+    fn at(dir: &str) -> Journal {
+        Journal {
+            journal_path: format!("{}/{}", &dir, &JOURNAL_FILE),
+        }
+    }
+
+    // Written to a temporary file first so that a crash never leaves a truncated record behind
+    fn write_intent(&self, intent: &InstallIntent) -> Result<()> {
+        let new_journal_path = format!("{}{}", &self.journal_path, &STAGING_SUFFIX);
+        let intent_str = ron::ser::to_string_pretty(&intent, ron::ser::PrettyConfig::default())
+            .with_context(|| "Failed to serialize install intent")?;
+        fs::write(&new_journal_path, &intent_str).with_context(|| {
+            format!("Failed to write install intent to '{}'", &new_journal_path)
+        })?;
+        fs::rename(&new_journal_path, &self.journal_path).with_context(|| {
+            format!("Failed to move install intent to '{}'", &self.journal_path)
+        })?;
+        sync_disks()?;
+
+        Ok(())
+    }
+
+    fn clear_intent(&self) -> Result<()> {
+        if fs::exists(&self.journal_path)? {
+            fs::remove_file(&self.journal_path).with_context(|| {
+                format!("Failed to remove install intent '{}'", &self.journal_path)
+            })?;
+            sync_disks()?;
+        }
+
+        Ok(())
+    }
+
+    fn read_intent(&self) -> Result<Option<InstallIntent>> {
+        if !fs::exists(&self.journal_path)? {
+            return Ok(None);
+        }
+        let intent_str = fs::read_to_string(&self.journal_path)
+            .with_context(|| format!("Failed to read install intent '{}'", &self.journal_path))?;
+        let intent = ron::from_str::<InstallIntent>(&intent_str)
+            .with_context(|| format!("Failed to parse install intent '{}'", &self.journal_path))?;
+
+        Ok(Some(intent))
+    }
+
+    fn roll_back(&self, intent: &InstallIntent) -> Result<()> {
+        info!("Rolling back install of '{}'", &intent.target);
+        restore_companions(&intent)?;
+        if fs::exists(&intent.temp_path)? {
+            fs::remove_file(&intent.temp_path).with_context(|| {
+                format!("Failed to remove staging file '{}'", &intent.temp_path)
+            })?;
+        }
+        remove_companion_leftovers(&intent)?;
+
+        self.clear_intent()
+    }
+
+    fn install(
+        &self,
+        source: &str,
+        target: &str,
+        expected_sha256: &str,
+        companions: &[&str],
+        progress: Option<ProgressCallback>,
+        verify: VerifyCallback,
+    ) -> Result<()> {
+        if let Some(intent) = self.read_intent()? {
+            return Err(anyhow::anyhow!(
+                "Another install of '{}' is pending",
+                &intent.target
+            ));
+        }
+        let mut previous_companions = Vec::new();
+        for suffix in companions {
+            if fs::exists(format!("{}{}", &target, &suffix))? {
+                previous_companions.push(suffix.to_string());
+            }
+        }
+        let intent = InstallIntent {
+            target: target.to_string(),
+            source: source.to_string(),
+            expected_sha256: expected_sha256.to_string(),
+            temp_path: staging_path(&target),
+            companions: companions.iter().map(|suffix| suffix.to_string()).collect(),
+            previous_companions,
+        };
+        info!("Installing '{}' to '{}'", &source, &target);
+        // A stale staging file without an intent record cannot be trusted to match this source
+        if fs::exists(&intent.temp_path)? {
+            fs::remove_file(&intent.temp_path)?;
+        }
+        remove_companion_leftovers(&intent)?;
+        // Before the record exists, so that an interrupted install always has all of them to resume with
+        stage_companions(&intent)?;
+        self.write_intent(&intent)?;
+
+        if let Err(e) = finish(&intent, progress, verify) {
+            if let Err(rollback_error) = self.roll_back(&intent) {
+                warn!("{}", &rollback_error);
+            }
+            return Err(e);
+        }
+
+        self.clear_intent()
+    }
+
+    fn recover_interrupted_install(&self, verify: VerifyCallback) -> Result<()> {
+        let intent = match self.read_intent() {
+            Ok(Some(intent)) => intent,
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                warn!("Discarding unreadable install intent: {:?}", &e);
+                return self.clear_intent();
+            }
+        };
+        warn!("Found interrupted install: {:?}", &intent);
+
+        // Interrupted after the rename: only the record is left to clear
+        if !fs::exists(&intent.temp_path)?
+            && fs::exists(&intent.target)?
+            && sha256_digest(&intent.target, None)?.eq_ignore_ascii_case(&intent.expected_sha256)
+        {
+            info!("Interrupted install of '{}' had completed", &intent.target);
+            remove_companion_leftovers(&intent)?;
+            return self.clear_intent();
+        }
+        // Interrupted while companions were being switched: the previous ones go back next to the previous target first
+        restore_companions(&intent)?;
+
+        if fs::exists(&intent.source)? {
+            notifier::get().toast("Resuming interrupted update");
+            match finish(&intent, None, verify) {
+                Ok(()) => {
+                    self.clear_intent()?;
+                    notifier::get().toast("Interrupted update was completed");
+                    return Ok(());
+                }
+                Err(e) => warn!("Failed to resume install: {:?}", &e),
+            }
+        }
+
+        self.roll_back(&intent)?;
+        notifier::get()
+            .sticky_toast("An update was interrupted and could not be resumed: it was rolled back");
+
+        Ok(())
+    }
+}
+
+// Where the target is copied to before replacing it
+pub fn staging_path(target: &str) -> String {
+    format!("{}{}", &target, &STAGING_SUFFIX)
+}
+
+pub fn read_intent() -> Result<Option<InstallIntent>> {
+    Journal::system().read_intent()
+}
+
+fn companion_paths(intent: &InstallIntent, suffix: &str) -> (String, String, String) {
+    let installed_path = format!("{}{}", &intent.target, &suffix);
+    (
+        format!("{}{}", &intent.source, &suffix),
+        format!("{}{}", &intent.temp_path, &suffix),
+        format!("{}{}", &installed_path, &PREVIOUS_SUFFIX),
+    )
+}
+
+fn remove_if_exists(path: &str) -> Result<()> {
+    if fs::exists(&path)? {
+        fs::remove_file(&path).with_context(|| format!("Failed to remove '{}'", &path))?;
+    }
+
+    Ok(())
+}
+
+// Companions the source does not have are not staged, so that they are removed from the target
+fn stage_companions(intent: &InstallIntent) -> Result<()> {
+    for suffix in &intent.companions {
+        let (source_path, staged_path, _) = companion_paths(&intent, &suffix);
+        if fs::exists(&source_path)? {
+            fs::copy(&source_path, &staged_path)
+                .with_context(|| format!("Failed to copy '{}'", &source_path))?;
+        }
+    }
+
+    Ok(())
+}
+
+// Staged companions are copied rather than moved: an interrupted install still has them to verify the staging file again
+fn switch_companions(intent: &InstallIntent) -> Result<()> {
+    for suffix in &intent.companions {
+        let (_, staged_path, backup_path) = companion_paths(&intent, &suffix);
+        let installed_path = format!("{}{}", &intent.target, &suffix);
+        if fs::exists(&installed_path)? {
+            fs::rename(&installed_path, &backup_path).with_context(|| {
+                format!("Failed to move '{}' to '{}'", &installed_path, &backup_path)
+            })?;
+        }
+        if fs::exists(&staged_path)? {
+            fs::copy(&staged_path, &installed_path)
+                .with_context(|| format!("Failed to copy '{}'", &staged_path))?;
+        }
+    }
+    sync_disks()
+}
+
+// Only valid as long as the target was not replaced
+fn restore_companions(intent: &InstallIntent) -> Result<()> {
+    for suffix in &intent.companions {
+        let (_, _, backup_path) = companion_paths(&intent, &suffix);
+        let installed_path = format!("{}{}", &intent.target, &suffix);
+        if fs::exists(&backup_path)? {
+            fs::rename(&backup_path, &installed_path).with_context(|| {
+                format!(
+                    "Failed to move '{}' back to '{}'",
+                    &backup_path, &installed_path
+                )
+            })?;
+        } else if !intent.previous_companions.contains(&suffix) {
+            remove_if_exists(&installed_path)?;
+        }
+    }
+    sync_disks()
+}
+
+fn remove_companion_leftovers(intent: &InstallIntent) -> Result<()> {
+    for suffix in &intent.companions {
+        let (_, staged_path, backup_path) = companion_paths(&intent, &suffix);
+        remove_if_exists(&staged_path)?;
+        remove_if_exists(&backup_path)?;
+    }
+
+    Ok(())
+}
+
+// Picks up where a previous attempt stopped: whatever was already written is checked along with the rest before the target is replaced
+fn resumable_copy(
+    source: &str,
+    temp_path: &str,
+    mut progress: Option<ProgressCallback>,
+) -> Result<()> {
+    let mut source_file =
+        fs::File::open(&source).with_context(|| format!("Failed to open '{}'", &source))?;
+    let source_size = source_file.metadata()?.len();
+    let mut temp_file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&temp_path)
+        .with_context(|| format!("Failed to open staging file '{}'", &temp_path))?;
+    let mut copied = temp_file.metadata()?.len();
+    if copied > source_size {
+        warn!(
+            "Staging file '{}' is larger than its source: starting over",
+            &temp_path
+        );
+        temp_file.set_len(0)?;
+        copied = 0;
+    }
+    if copied > 0 {
+        info!(
+            "Resuming copy of '{}' at {} of {} bytes",
+            &source, &copied, &source_size
+        );
+    }
+
+    source_file.seek(SeekFrom::Start(copied))?;
+    let mut buffer = vec![0u8; COPY_CHUNK_SIZE];
+    loop {
+        let read = source_file
+            .read(&mut buffer)
+            .with_context(|| format!("Failed to read '{}'", &source))?;
+        if read == 0 {
+            break;
+        }
+        temp_file
+            .write_all(&buffer[..read])
+            .with_context(|| format!("Failed to write staging file '{}'", &temp_path))?;
+        copied += read as u64;
+        if let Some(progress) = progress.as_mut() {
+            progress(copied, source_size);
+        }
+    }
+    temp_file
+        .sync_all()
+        .with_context(|| format!("Failed to sync staging file '{}'", &temp_path))?;
+
+    Ok(())
+}
+
+// The target is only ever replaced through an atomic rename of a fully verified staging file
+// Progress covers the copy, then the checksum of the copy
+fn finish(
+    intent: &InstallIntent,
+    mut progress: Option<ProgressCallback>,
+    verify: VerifyCallback,
+) -> Result<()> {
+    resumable_copy(
+        &intent.source,
+        &intent.temp_path,
+        Some(&mut |done: u64, total: u64| {
+            if let Some(progress) = progress.as_mut() {
+                progress(done, total * 2);
+            }
+        }),
+    )?;
+    let checksum = sha256_digest(
+        &intent.temp_path,
+        Some(&mut |done: u64, total: u64| {
+            if let Some(progress) = progress.as_mut() {
+                progress(total + done, total * 2);
+            }
+        }),
+    )?;
+    if !checksum.eq_ignore_ascii_case(&intent.expected_sha256) {
+        return Err(anyhow::anyhow!(
+            "Checksum mismatch for '{}': expected {}, got {}",
+            &intent.temp_path,
+            &intent.expected_sha256,
+            &checksum
+        ));
+    }
+    verify(&intent)?;
+    switch_companions(&intent)?;
+    fs::rename(&intent.temp_path, &intent.target).with_context(|| {
+        format!(
+            "Failed to move '{}' to '{}'",
+            &intent.temp_path, &intent.target
+        )
+    })?;
+    sync_disks()?;
+
+    remove_companion_leftovers(&intent)
+}
+
+pub fn install(
+    source: &str,
+    target: &str,
+    expected_sha256: &str,
+    companions: &[&str],
+    progress: Option<ProgressCallback>,
+    verify: VerifyCallback,
+) -> Result<()> {
+    Journal::system().install(
+        &source,
+        &target,
+        &expected_sha256,
+        &companions,
+        progress,
+        verify,
+    )
+}
+
+// Called at every boot, before the root filesystem is mounted
+pub fn recover_interrupted_install(verify: VerifyCallback) -> Result<()> {
+    Journal::system().recover_interrupted_install(verify)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::system::to_hex;
+    use crate::test_utils::TempDir;
+
+    const OLD_CONTENTS: &[u8] = b"old root filesystem";
+
+    fn new_contents() -> Vec<u8> {
+        (0..3 * COPY_CHUNK_SIZE + 17)
+            .map(|i| (i % 251) as u8)
+            .collect()
+    }
+
+    fn sha256(contents: &[u8]) -> String {
+        to_hex(&openssl::sha::sha256(&contents))
+    }
+
+    struct Setup {
+        dir: TempDir,
+        journal: Journal,
+        intent: InstallIntent,
+    }
+
+    fn set_up() -> Setup {
+        let dir = TempDir::new();
+        let source = dir.write("source.squashfs", &new_contents());
+        let target = dir.write("rootfs.squashfs", &OLD_CONTENTS);
+        let journal = Journal::at(&dir.path());
+        let intent = InstallIntent {
+            temp_path: staging_path(&target),
+            target,
+            source,
+            expected_sha256: sha256(&new_contents()),
+            companions: Vec::new(),
+            previous_companions: Vec::new(),
+        };
+        Setup {
+            dir,
+            journal,
+            intent,
+        }
+    }
+
+    // Whatever happened, the target is either the previous or the new archive as a whole, never a staging file
+    fn assert_target_is_whole(setup: &Setup) {
+        let target = fs::read(&setup.intent.target).unwrap();
+        assert!(target == OLD_CONTENTS || target == new_contents());
+        assert!(!fs::exists(&setup.intent.temp_path).unwrap());
+        assert!(setup.journal.read_intent().unwrap().is_none());
+    }
+
+    const OLD_SIGNATURE: &[u8] = b"old signature";
+    const NEW_SIGNATURE: &[u8] = b"new signature";
+    const OLD_VERITY: &[u8] = b"old hash tree";
+
+    // The update has a signature but no hash tree, while the archive in place had both
+    fn set_up_with_companions() -> Setup {
+        let mut setup = set_up();
+        setup.dir.write("source.squashfs.dgst", &NEW_SIGNATURE);
+        setup.dir.write("rootfs.squashfs.dgst", &OLD_SIGNATURE);
+        setup.dir.write("rootfs.squashfs.verity", &OLD_VERITY);
+        setup.intent.companions = vec![".dgst".to_string(), ".verity".to_string()];
+        setup.intent.previous_companions = setup.intent.companions.clone();
+        setup
+    }
+
+    // The crash points of interest are right after the intent record was written
+    fn stage(setup: &Setup) {
+        stage_companions(&setup.intent).unwrap();
+        setup.journal.write_intent(&setup.intent).unwrap();
+    }
+
+    // Companions always describe the archive they sit next to, and nothing is left aside
+    fn assert_companions_match_target(setup: &Setup) {
+        let target = &setup.intent.target;
+        let signature = fs::read(format!("{}.dgst", &target)).unwrap();
+        let verity = fs::read(format!("{}.verity", &target)).ok();
+        if fs::read(&target).unwrap() == OLD_CONTENTS {
+            assert_eq!(signature, OLD_SIGNATURE);
+            assert_eq!(verity.as_deref(), Some(OLD_VERITY));
+        } else {
+            assert_eq!(signature, NEW_SIGNATURE);
+            assert_eq!(verity, None);
+        }
+        for suffix in &setup.intent.companions {
+            let (_, staged_path, backup_path) = companion_paths(&setup.intent, &suffix);
+            assert!(!fs::exists(&staged_path).unwrap());
+            assert!(!fs::exists(&backup_path).unwrap());
+        }
+    }
+
+    fn install_with_companions(setup: &Setup, verify: VerifyCallback) -> Result<()> {
+        setup.journal.install(
+            &setup.intent.source,
+            &setup.intent.target,
+            &setup.intent.expected_sha256,
+            &[".dgst", ".verity"],
+            None,
+            verify,
+        )
+    }
+
+    fn accept(_intent: &InstallIntent) -> Result<()> {
+        Ok(())
+    }
+
+    fn reject(_intent: &InstallIntent) -> Result<()> {
+        Err(anyhow::anyhow!("Invalid signature"))
+    }
+
+    #[test]
+    fn install_replaces_target() {
+        let setup = set_up();
+        let mut last_progress = (0, 0);
+        setup
+            .journal
+            .install(
+                &setup.intent.source,
+                &setup.intent.target,
+                &setup.intent.expected_sha256,
+                &[],
+                Some(&mut |done, total| last_progress = (done, total)),
+                &mut accept,
+            )
+            .unwrap();
+        assert_eq!(fs::read(&setup.intent.target).unwrap(), new_contents());
+        assert_eq!(last_progress.0, last_progress.1);
+        assert_target_is_whole(&setup);
+    }
+
+    #[test]
+    fn install_rolls_back_when_verification_fails() {
+        let setup = set_up();
+        let mut verify = |intent: &InstallIntent| {
+            // Fully staged and checksummed, but not in place yet
+            assert_eq!(fs::read(&intent.temp_path).unwrap(), new_contents());
+            assert_eq!(fs::read(&intent.target).unwrap(), OLD_CONTENTS);
+            reject(&intent)
+        };
+        assert!(
+            setup
+                .journal
+                .install(
+                    &setup.intent.source,
+                    &setup.intent.target,
+                    &setup.intent.expected_sha256,
+                    &[],
+                    None,
+                    &mut verify,
+                )
+                .is_err()
+        );
+        assert_eq!(fs::read(&setup.intent.target).unwrap(), OLD_CONTENTS);
+        assert_target_is_whole(&setup);
+    }
+
+    #[test]
+    fn install_rolls_back_on_checksum_mismatch() {
+        let setup = set_up();
+        assert!(
+            setup
+                .journal
+                .install(
+                    &setup.intent.source,
+                    &setup.intent.target,
+                    &sha256(&OLD_CONTENTS),
+                    &[],
+                    None,
+                    &mut accept,
+                )
+                .is_err()
+        );
+        assert_eq!(fs::read(&setup.intent.target).unwrap(), OLD_CONTENTS);
+        assert_target_is_whole(&setup);
+    }
+
+    #[test]
+    fn install_refuses_to_run_while_another_is_pending() {
+        let setup = set_up();
+        setup.journal.write_intent(&setup.intent).unwrap();
+        assert!(
+            setup
+                .journal
+                .install(
+                    &setup.intent.source,
+                    &setup.intent.target,
+                    &setup.intent.expected_sha256,
+                    &[],
+                    None,
+                    &mut accept,
+                )
+                .is_err()
+        );
+        assert_eq!(fs::read(&setup.intent.target).unwrap(), OLD_CONTENTS);
+    }
+
+    #[test]
+    fn crash_before_copy_is_resumed() {
+        let setup = set_up();
+        setup.journal.write_intent(&setup.intent).unwrap();
+        setup
+            .journal
+            .recover_interrupted_install(&mut accept)
+            .unwrap();
+        assert_eq!(fs::read(&setup.intent.target).unwrap(), new_contents());
+        assert_target_is_whole(&setup);
+    }
+
+    #[test]
+    fn crash_during_copy_is_resumed() {
+        let setup = set_up();
+        setup.journal.write_intent(&setup.intent).unwrap();
+        fs::write(
+            &setup.intent.temp_path,
+            &new_contents()[..COPY_CHUNK_SIZE + 5],
+        )
+        .unwrap();
+        setup
+            .journal
+            .recover_interrupted_install(&mut accept)
+            .unwrap();
+        assert_eq!(fs::read(&setup.intent.target).unwrap(), new_contents());
+        assert_target_is_whole(&setup);
+    }
+
+    #[test]
+    fn crash_with_oversized_staging_file_starts_over() {
+        let setup = set_up();
+        setup.journal.write_intent(&setup.intent).unwrap();
+        let mut oversized = new_contents();
+        oversized.extend_from_slice(b"garbage");
+        fs::write(&setup.intent.temp_path, &oversized).unwrap();
+        setup
+            .journal
+            .recover_interrupted_install(&mut accept)
+            .unwrap();
+        assert_eq!(fs::read(&setup.intent.target).unwrap(), new_contents());
+        assert_target_is_whole(&setup);
+    }
+
+    #[test]
+    fn crash_with_corrupted_staging_file_is_rolled_back() {
+        let setup = set_up();
+        setup.journal.write_intent(&setup.intent).unwrap();
+        let mut corrupted = new_contents();
+        corrupted[3] ^= 0xff;
+        fs::write(&setup.intent.temp_path, &corrupted).unwrap();
+        setup
+            .journal
+            .recover_interrupted_install(&mut accept)
+            .unwrap();
+        assert_eq!(fs::read(&setup.intent.target).unwrap(), OLD_CONTENTS);
+        assert_target_is_whole(&setup);
+    }
+
+    #[test]
+    fn crash_before_verification_is_verified_again() {
+        let setup = set_up();
+        setup.journal.write_intent(&setup.intent).unwrap();
+        fs::write(&setup.intent.temp_path, &new_contents()).unwrap();
+        setup
+            .journal
+            .recover_interrupted_install(&mut reject)
+            .unwrap();
+        assert_eq!(fs::read(&setup.intent.target).unwrap(), OLD_CONTENTS);
+        assert_target_is_whole(&setup);
+    }
+
+    #[test]
+    fn crash_after_rename_only_clears_record() {
+        let setup = set_up();
+        setup.journal.write_intent(&setup.intent).unwrap();
+        fs::write(&setup.intent.target, &new_contents()).unwrap();
+        let mut verify = |_intent: &InstallIntent| -> Result<()> {
+            panic!("A completed install must not be verified again")
+        };
+        setup
+            .journal
+            .recover_interrupted_install(&mut verify)
+            .unwrap();
+        assert_eq!(fs::read(&setup.intent.target).unwrap(), new_contents());
+        assert_target_is_whole(&setup);
+    }
+
+    #[test]
+    fn crash_without_source_is_rolled_back() {
+        let setup = set_up();
+        setup.journal.write_intent(&setup.intent).unwrap();
+        fs::write(&setup.intent.temp_path, &new_contents()[..10]).unwrap();
+        fs::remove_file(&setup.intent.source).unwrap();
+        setup
+            .journal
+            .recover_interrupted_install(&mut accept)
+            .unwrap();
+        assert_eq!(fs::read(&setup.intent.target).unwrap(), OLD_CONTENTS);
+        assert_target_is_whole(&setup);
+    }
+
+    #[test]
+    fn unreadable_record_is_discarded() {
+        let setup = set_up();
+        setup.dir.write(&JOURNAL_FILE, b"(target: ");
+        setup
+            .journal
+            .recover_interrupted_install(&mut accept)
+            .unwrap();
+        assert_eq!(fs::read(&setup.intent.target).unwrap(), OLD_CONTENTS);
+        assert_target_is_whole(&setup);
+    }
+
+    #[test]
+    fn install_switches_companions_along_with_target() {
+        let setup = set_up_with_companions();
+        let mut verify = |intent: &InstallIntent| {
+            // Verified against the staged signature, while the one in place still matches the previous archive
+            let staged_signature = fs::read(format!("{}.dgst", &intent.temp_path)).unwrap();
+            assert_eq!(staged_signature, NEW_SIGNATURE);
+            let signature = fs::read(format!("{}.dgst", &intent.target)).unwrap();
+            assert_eq!(signature, OLD_SIGNATURE);
+            Ok(())
+        };
+        install_with_companions(&setup, &mut verify).unwrap();
+        assert_eq!(fs::read(&setup.intent.target).unwrap(), new_contents());
+        assert_companions_match_target(&setup);
+        assert_target_is_whole(&setup);
+    }
+
+    #[test]
+    fn rolled_back_install_keeps_previous_companions() {
+        let setup = set_up_with_companions();
+        assert!(install_with_companions(&setup, &mut reject).is_err());
+        assert_eq!(fs::read(&setup.intent.target).unwrap(), OLD_CONTENTS);
+        assert_companions_match_target(&setup);
+        assert_target_is_whole(&setup);
+    }
+
+    #[test]
+    fn crash_while_switching_companions_is_resumed() {
+        let setup = set_up_with_companions();
+        stage(&setup);
+        fs::write(&setup.intent.temp_path, &new_contents()).unwrap();
+        switch_companions(&setup.intent).unwrap();
+        // The previous archive now sits next to the new signature
+        assert_eq!(fs::read(&setup.intent.target).unwrap(), OLD_CONTENTS);
+        assert_eq!(
+            fs::read(format!("{}.dgst", &setup.intent.target)).unwrap(),
+            NEW_SIGNATURE
+        );
+
+        let mut verify = |intent: &InstallIntent| {
+            assert!(fs::exists(format!("{}.dgst", &intent.temp_path)).unwrap());
+            Ok(())
+        };
+        setup
+            .journal
+            .recover_interrupted_install(&mut verify)
+            .unwrap();
+        assert_eq!(fs::read(&setup.intent.target).unwrap(), new_contents());
+        assert_companions_match_target(&setup);
+        assert_target_is_whole(&setup);
+    }
+
+    #[test]
+    fn crash_while_switching_companions_is_rolled_back() {
+        let setup = set_up_with_companions();
+        stage(&setup);
+        fs::write(&setup.intent.temp_path, &new_contents()).unwrap();
+        switch_companions(&setup.intent).unwrap();
+        fs::remove_file(&setup.intent.source).unwrap();
+        setup
+            .journal
+            .recover_interrupted_install(&mut accept)
+            .unwrap();
+        assert_eq!(fs::read(&setup.intent.target).unwrap(), OLD_CONTENTS);
+        assert_companions_match_target(&setup);
+        assert_target_is_whole(&setup);
+    }
+
+    #[test]
+    fn crash_after_rename_removes_companion_leftovers() {
+        let setup = set_up_with_companions();
+        stage(&setup);
+        fs::write(&setup.intent.temp_path, &new_contents()).unwrap();
+        switch_companions(&setup.intent).unwrap();
+        fs::rename(&setup.intent.temp_path, &setup.intent.target).unwrap();
+        setup
+            .journal
+            .recover_interrupted_install(&mut accept)
+            .unwrap();
+        assert_eq!(fs::read(&setup.intent.target).unwrap(), new_contents());
+        assert_companions_match_target(&setup);
+        assert_target_is_whole(&setup);
+    }
+}
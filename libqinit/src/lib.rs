@@ -6,20 +6,39 @@ cfg_if::cfg_if! {
         pub mod systemd;
         pub mod wifi;
         pub mod brightness;
+        pub mod file_browser;
+        pub mod input;
         pub mod battery;
         pub mod networking;
+        pub mod user_preferences;
+        pub mod users;
     }
 }
 pub mod boot_config;
+pub mod diagnostics;
 pub mod eink;
+pub mod install_journal;
 pub mod netboot;
+pub mod notifier;
+pub mod openrc;
+pub mod partitions;
+pub mod pin;
+pub mod qr;
 pub mod rootfs;
 pub mod rootfs_socket;
+pub mod screenshot;
+pub mod secret;
+pub mod services;
 pub mod signing;
 pub mod splash;
 pub mod storage_encryption;
 pub mod system;
+pub mod usb_gadget;
 
+#[cfg(test)]
+mod test_utils;
+
+// Used unless the kernel command line points elsewhere: use partitions::get_path() instead
 pub const BOOT_PART: &str = "/dev/mmcblk0p7";
 pub const MAIN_PART: &str = "/dev/mmcblk0p9";
 pub const BOOT_PART_MOUNTPOINT: &str = "/boot/";
@@ -38,6 +57,7 @@ pub const READY_PROGRESS_VALUE: f32 = 1.0;
 pub const OPENRC_WORKDIR: &str = "/run/openrc";
 pub const SCREEN_H: u16 = 1404;
 pub const SCREEN_W: u16 = 1872;
+pub const SCREEN_DPI: u16 = 227;
 
 #[derive(PartialEq, Clone)]
 pub enum BootSelection {
@@ -1,8 +1,8 @@
 use anyhow::{Context, Result};
 use log::info;
-use std::process::Command;
 
-use crate::system::{modprobe, run_command};
+use crate::notifier;
+use crate::system::{modprobe, run_command, run_command_capture};
 
 pub const NETBOOT_DEVICE_NODE: &str = "/dev/nbd0";
 
@@ -16,11 +16,10 @@ pub enum NetBootStatus {
 pub fn find_host_ip_addr() -> Result<String> {
     let host_ip_addr;
     loop {
-        let output = Command::new("/usr/bin/dumpleases")
-            .output()
+        let output = run_command_capture("/usr/bin/dumpleases", &[])
             .with_context(|| "Failed to get dumpleases' output")?;
         if output.status.success() {
-            match String::from_utf8_lossy(&output.stdout).lines().last() {
+            match output.stdout.lines().last() {
                 Some(line) => {
                     let ip_addr_vec: Vec<&str> = line.split_whitespace().collect();
                     match ip_addr_vec.get(1).map(|ip| ip.to_string()) {
@@ -45,7 +44,9 @@ pub fn find_host_ip_addr() -> Result<String> {
 pub fn setup() -> Result<()> {
     modprobe(&["nbd"])?;
 
+    notifier::get().sticky_toast("Waiting for NetBoot host");
     let host_ip_addr = find_host_ip_addr()?;
+    notifier::get().toast(&format!("Connecting to NetBoot host {}", &host_ip_addr));
     run_command(
         "/usr/sbin/nbd-client",
         &[&host_ip_addr, "10809", &NETBOOT_DEVICE_NODE],
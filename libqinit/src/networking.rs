@@ -1,6 +1,19 @@
 use anyhow::{Context, Result};
 use local_ip_address::list_afinet_netifas;
 use log::info;
+use regex::Regex;
+use std::fs;
+use std::net::Ipv4Addr;
+
+use crate::system::run_command_capture;
+
+const SYS_CLASS_NET_DIR_PATH: &str = "/sys/class/net";
+const PROC_NET_ROUTE_PATH: &str = "/proc/net/route";
+const RESOLV_CONF_PATH: &str = "/etc/resolv.conf";
+// From linux/route.h
+const RTF_UP: u32 = 0x0001;
+const RTF_GATEWAY: u32 = 0x0002;
+pub const UNKNOWN: &str = "Unknown";
 
 pub fn get_if_ip_address(interface: &str) -> Result<String> {
     let network_interfaces =
@@ -15,3 +28,155 @@ pub fn get_if_ip_address(interface: &str) -> Result<String> {
 
     return Ok("Not found".to_string());
 }
+
+// Anything that could not be determined is None: this is for display only and should never fail as a whole
+#[derive(Debug, Default, Clone)]
+pub struct InterfaceDetails {
+    pub ip_address: Option<String>,
+    pub mac_address: Option<String>,
+    pub gateway: Option<String>,
+    pub dns_servers: Vec<String>,
+    pub link_speed_mbps: Option<u32>,
+    // Wireless interfaces only
+    pub frequency_mhz: Option<u32>,
+}
+
+impl InterfaceDetails {
+    // Label and value pairs, with UNKNOWN for missing values
+    pub fn rows(&self) -> Vec<(&'static str, String)> {
+        let or_unknown = |value: &Option<String>| value.clone().unwrap_or(UNKNOWN.to_string());
+        vec![
+            ("IP address", or_unknown(&self.ip_address)),
+            ("MAC address", or_unknown(&self.mac_address)),
+            ("Gateway", or_unknown(&self.gateway)),
+            (
+                "DNS servers",
+                if self.dns_servers.is_empty() {
+                    UNKNOWN.to_string()
+                } else {
+                    self.dns_servers.join(", ")
+                },
+            ),
+            (
+                "Link speed",
+                or_unknown(
+                    &self
+                        .link_speed_mbps
+                        .map(|speed| format!("{} Mbit/s", speed)),
+                ),
+            ),
+            (
+                "Frequency",
+                or_unknown(
+                    &self
+                        .frequency_mhz
+                        .map(|frequency| format!("{} MHz", frequency)),
+                ),
+            ),
+        ]
+    }
+}
+
+// Default route of the interface, from the kernel's routing table: columns are Iface, Destination, Gateway, Flags, then more
+pub fn parse_default_gateway(route_table: &str, interface: &str) -> Option<Ipv4Addr> {
+    route_table.lines().skip(1).find_map(|line| {
+        let columns: Vec<&str> = line.split_whitespace().collect();
+        let [name, destination, gateway, flags, ..] = columns.as_slice() else {
+            return None;
+        };
+        let flags = u32::from_str_radix(&flags, 16).ok()?;
+        if name != &interface
+            || destination != &"00000000"
+            || flags & (RTF_UP | RTF_GATEWAY) != (RTF_UP | RTF_GATEWAY)
+        {
+            return None;
+        }
+        // Printed as the raw network-order word: its bytes in memory are the address
+        let gateway = u32::from_str_radix(&gateway, 16).ok()?;
+        Some(Ipv4Addr::from(gateway.to_ne_bytes()))
+    })
+}
+
+pub fn parse_dns_servers(resolv_conf: &str) -> Vec<String> {
+    resolv_conf
+        .lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some("nameserver"), Some(address)) => Some(address.to_string()),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn read_interface_attribute(interface: &str, attribute: &str) -> Option<String> {
+    fs::read_to_string(format!(
+        "{}/{}/{}",
+        &SYS_CLASS_NET_DIR_PATH, &interface, &attribute
+    ))
+    .ok()
+    .map(|value| value.trim().to_string())
+    .filter(|value| !value.is_empty())
+}
+
+// iwd reports the frequency in MHz and the transmit bitrate in kbit/s
+fn wireless_link_details(interface: &str) -> (Option<u32>, Option<u32>) {
+    let Ok(output) =
+        run_command_capture(&crate::wifi::IWCTL_PATH, &["station", &interface, "show"])
+    else {
+        return (None, None);
+    };
+    let Ok(ansi_escape) = Regex::new(r"\x1b\[[0-9;]*m") else {
+        return (None, None);
+    };
+    let stdout = ansi_escape.replace_all(&output.stdout, "");
+    let value_of = |property: &str| {
+        stdout.lines().find_map(|line| {
+            let mut words = line.split_whitespace();
+            if words.next() != Some(property) {
+                return None;
+            }
+            words.next()?.parse::<u32>().ok()
+        })
+    };
+
+    (
+        value_of("TxBitrate").map(|bitrate| bitrate / 1000),
+        value_of("Frequency"),
+    )
+}
+
+pub fn get_if_details(interface: &str) -> InterfaceDetails {
+    let (wireless_link_speed_mbps, frequency_mhz) = if fs::exists(format!(
+        "{}/{}/wireless",
+        &SYS_CLASS_NET_DIR_PATH, &interface
+    ))
+    .unwrap_or(false)
+    {
+        wireless_link_details(&interface)
+    } else {
+        (None, None)
+    };
+    let details = InterfaceDetails {
+        ip_address: get_if_ip_address(&interface)
+            .ok()
+            .filter(|ip_address| ip_address != "Not found"),
+        mac_address: read_interface_attribute(&interface, "address"),
+        gateway: fs::read_to_string(&PROC_NET_ROUTE_PATH)
+            .ok()
+            .and_then(|route_table| parse_default_gateway(&route_table, &interface))
+            .map(|gateway| gateway.to_string()),
+        dns_servers: fs::read_to_string(&RESOLV_CONF_PATH)
+            .map(|resolv_conf| parse_dns_servers(&resolv_conf))
+            .unwrap_or_default(),
+        // Wireless drivers mostly report -1 or nothing at all here
+        link_speed_mbps: read_interface_attribute(&interface, "speed")
+            .and_then(|speed| speed.parse::<u32>().ok())
+            .or(wireless_link_speed_mbps),
+        frequency_mhz,
+    };
+    info!("Details of interface {}: {:?}", &interface, &details);
+
+    details
+}
@@ -0,0 +1,305 @@
+use log::{info, warn};
+use std::collections::VecDeque;
+use std::sync::mpsc::{Sender, channel};
+use std::sync::{Mutex, OnceLock};
+
+use crate::secret::Secret;
+
+// Toasts past this are dropped: fatal errors are always kept
+const MAX_EARLY_EVENTS: usize = 32;
+
+static NOTIFIER: OnceLock<Box<dyn UiNotifier>> = OnceLock::new();
+pub static EARLY_EVENTS: EarlyEventBuffer = EarlyEventBuffer::new();
+
+// Lets library code report user-relevant milestones directly instead of relying on the caller to toast them
+pub trait UiNotifier: Send + Sync {
+    fn toast(&self, message: &str);
+    fn sticky_toast(&self, message: &str);
+    // Blocks until the user answers: must never be called from the GUI thread
+    fn ask_confirmation(&self, message: &str) -> bool;
+    // Same constraint as above: returns None if the user cancelled
+    fn ask_pin(&self, request: &PinRequest) -> Option<Secret>;
+}
+
+#[derive(Debug, Clone)]
+pub struct PinRequest {
+    pub message: String,
+    pub max_length: usize,
+    // Shown (along with visual feedback) when re-prompting after a wrong entry
+    pub error_message: Option<String>,
+}
+
+pub enum Notification {
+    Toast(String),
+    StickyToast(String),
+    Confirmation(String, Sender<bool>),
+    PinEntry(PinRequest, Sender<Option<Secret>>),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum EarlyEvent {
+    Toast(String),
+    StickyToast(String),
+    FatalError(String),
+}
+
+// Boot raises some events before the GUI exists: they wait here until it is ready to show them
+pub struct EarlyEventBuffer {
+    // None once drained: events must then go to the GUI directly
+    events: Mutex<Option<VecDeque<EarlyEvent>>>,
+}
+
+impl EarlyEventBuffer {
+    pub(crate) const fn new() -> EarlyEventBuffer {
+        EarlyEventBuffer {
+            events: Mutex::new(Some(VecDeque::new())),
+        }
+    }
+
+    // Hands the event back if the GUI already drained the buffer
+    pub fn push(&self, event: EarlyEvent) -> Result<(), EarlyEvent> {
+        let mut events = self.events.lock().unwrap();
+        let Some(events) = events.as_mut() else {
+            return Err(event);
+        };
+        if events.len() >= MAX_EARLY_EVENTS && !matches!(event, EarlyEvent::FatalError(_)) {
+            warn!("Early event buffer is full: dropping {:?}", &event);
+        } else {
+            events.push_back(event);
+        }
+
+        Ok(())
+    }
+
+    // Called once by the GUI when it is ready: nothing is buffered afterwards
+    pub fn drain(&self) -> Vec<EarlyEvent> {
+        self.events
+            .lock()
+            .unwrap()
+            .take()
+            .map(Vec::from)
+            .unwrap_or_default()
+    }
+}
+
+pub struct ChannelNotifier {
+    sender: Sender<Notification>,
+}
+
+impl ChannelNotifier {
+    pub fn new(sender: Sender<Notification>) -> ChannelNotifier {
+        ChannelNotifier { sender }
+    }
+}
+
+impl UiNotifier for ChannelNotifier {
+    fn toast(&self, message: &str) {
+        info!("{}", &message);
+        let _ = self.sender.send(Notification::Toast(message.to_string()));
+    }
+
+    fn sticky_toast(&self, message: &str) {
+        info!("{}", &message);
+        let _ = self
+            .sender
+            .send(Notification::StickyToast(message.to_string()));
+    }
+
+    fn ask_confirmation(&self, message: &str) -> bool {
+        info!("Asking for confirmation: {}", &message);
+        let (answer_sender, answer_receiver) = channel();
+        if self
+            .sender
+            .send(Notification::Confirmation(
+                message.to_string(),
+                answer_sender,
+            ))
+            .is_err()
+        {
+            warn!("Could not ask for confirmation: assuming the answer is no");
+            return false;
+        }
+
+        answer_receiver.recv().unwrap_or(false)
+    }
+
+    fn ask_pin(&self, request: &PinRequest) -> Option<Secret> {
+        info!("Asking for PIN: {}", &request.message);
+        let (answer_sender, answer_receiver) = channel();
+        if self
+            .sender
+            .send(Notification::PinEntry(request.clone(), answer_sender))
+            .is_err()
+        {
+            warn!("Could not ask for PIN: assuming the prompt was cancelled");
+            return None;
+        }
+
+        answer_receiver.recv().unwrap_or(None)
+    }
+}
+
+// Headless fallback used when no GUI registered itself: toasts are kept in case one shows up later
+pub struct LogNotifier;
+
+impl UiNotifier for LogNotifier {
+    fn toast(&self, message: &str) {
+        info!("{}", &message);
+        // Handed over directly if the GUI already drained the buffer
+        if EARLY_EVENTS
+            .push(EarlyEvent::Toast(message.to_string()))
+            .is_err()
+            && let Some(notifier) = NOTIFIER.get()
+        {
+            notifier.toast(&message);
+        }
+    }
+
+    fn sticky_toast(&self, message: &str) {
+        info!("{}", &message);
+        if EARLY_EVENTS
+            .push(EarlyEvent::StickyToast(message.to_string()))
+            .is_err()
+            && let Some(notifier) = NOTIFIER.get()
+        {
+            notifier.sticky_toast(&message);
+        }
+    }
+
+    fn ask_confirmation(&self, message: &str) -> bool {
+        warn!(
+            "No GUI available to confirm '{}': assuming the answer is no",
+            &message
+        );
+        false
+    }
+
+    fn ask_pin(&self, request: &PinRequest) -> Option<Secret> {
+        warn!(
+            "No GUI available to ask for PIN '{}': assuming the prompt was cancelled",
+            &request.message
+        );
+        None
+    }
+}
+
+pub fn register(notifier: Box<dyn UiNotifier>) {
+    if NOTIFIER.set(notifier).is_err() {
+        warn!("A UI notifier was already registered: ignoring the new one");
+    }
+}
+
+pub fn get() -> &'static dyn UiNotifier {
+    match NOTIFIER.get() {
+        Some(notifier) => notifier.as_ref(),
+        None => &LogNotifier,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::Receiver;
+    use std::thread;
+
+    fn channel_notifier() -> (ChannelNotifier, Receiver<Notification>) {
+        let (sender, receiver) = channel();
+        (ChannelNotifier::new(sender), receiver)
+    }
+
+    #[test]
+    fn toasts_are_forwarded() {
+        let (notifier, receiver) = channel_notifier();
+        notifier.toast("Update installed");
+        notifier.sticky_toast("Update failed");
+        assert!(
+            matches!(receiver.recv().unwrap(), Notification::Toast(message) if message == "Update installed")
+        );
+        assert!(
+            matches!(receiver.recv().unwrap(), Notification::StickyToast(message) if message == "Update failed")
+        );
+    }
+
+    #[test]
+    fn confirmation_waits_for_the_answer() {
+        let (notifier, receiver) = channel_notifier();
+        let answerer = thread::spawn(move || match receiver.recv().unwrap() {
+            Notification::Confirmation(message, answer_sender) => {
+                assert_eq!(message, "Erase everything?");
+                answer_sender.send(true).unwrap();
+            }
+            _ => panic!("Expected a confirmation request"),
+        });
+        assert!(notifier.ask_confirmation("Erase everything?"));
+        answerer.join().unwrap();
+    }
+
+    #[test]
+    fn unanswered_requests_are_declined() {
+        let (notifier, receiver) = channel_notifier();
+        // The answer sender is dropped along with the request
+        let answerer = thread::spawn(move || {
+            receiver.recv().unwrap();
+            receiver.recv().unwrap();
+        });
+        assert!(!notifier.ask_confirmation("Erase everything?"));
+        assert!(
+            notifier
+                .ask_pin(&PinRequest {
+                    message: "Enter PIN".to_string(),
+                    max_length: 6,
+                    error_message: None,
+                })
+                .is_none()
+        );
+        answerer.join().unwrap();
+
+        let (notifier, receiver) = channel_notifier();
+        drop(receiver);
+        assert!(!notifier.ask_confirmation("Erase everything?"));
+    }
+
+    #[test]
+    fn early_events_are_buffered_until_drained() {
+        let early_events = EarlyEventBuffer::new();
+        early_events
+            .push(EarlyEvent::StickyToast(
+                "Touch input not detected".to_string(),
+            ))
+            .unwrap();
+        early_events
+            .push(EarlyEvent::FatalError("Unit failed".to_string()))
+            .unwrap();
+        assert_eq!(
+            early_events.drain(),
+            vec![
+                EarlyEvent::StickyToast("Touch input not detected".to_string()),
+                EarlyEvent::FatalError("Unit failed".to_string()),
+            ]
+        );
+
+        assert_eq!(
+            early_events.push(EarlyEvent::Toast("Too late".to_string())),
+            Err(EarlyEvent::Toast("Too late".to_string()))
+        );
+        assert!(early_events.drain().is_empty());
+    }
+
+    #[test]
+    fn full_early_event_buffer_keeps_fatal_errors() {
+        let early_events = EarlyEventBuffer::new();
+        for i in 0..MAX_EARLY_EVENTS + 1 {
+            early_events.push(EarlyEvent::Toast(i.to_string())).unwrap();
+        }
+        early_events
+            .push(EarlyEvent::FatalError("Unit failed".to_string()))
+            .unwrap();
+
+        let events = early_events.drain();
+        assert_eq!(events.len(), MAX_EARLY_EVENTS + 1);
+        assert_eq!(
+            events.last(),
+            Some(&EarlyEvent::FatalError("Unit failed".to_string()))
+        );
+    }
+}
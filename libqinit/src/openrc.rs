@@ -0,0 +1,310 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::fs::{self, File};
+use std::os::unix::fs::symlink;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::services::{ServiceState, ServiceStatus, Services, read_log_tail};
+use crate::system::{run_command, run_command_capture};
+
+const OPENRC_BINARY_PATH: &str = "/sbin/openrc";
+const RC_SERVICE_BINARY_PATH: &str = "/sbin/rc-service";
+const RC_STATUS_BINARY_PATH: &str = "/bin/rc-status";
+const INIT_SCRIPTS_DIR_PATH: &str = "/etc/init.d";
+const SOFTLEVEL_FILE: &str = "softlevel";
+const STARTED_DIR: &str = "started";
+// Read by diagnostics and by anything started later that needs to know which service manager is in charge
+const STATUS_DIR_PATH: &str = "/run/qinit";
+const STATUS_FILE: &str = "openrc";
+
+static OPENRC: OnceLock<OpenRc> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpenRcState {
+    Up,
+    // Initialization failed or OpenRC is not installed: services are supervised directly instead
+    Unavailable,
+    // Simulator builds never touch the host's service manager
+    Stub,
+}
+
+pub struct OpenRc {
+    state: OpenRcState,
+}
+
+// Parses `rc-status --format ini` output, i.e. a "[runlevel]" header followed by "service = state" lines
+fn parse_rc_status(output: &str) -> Vec<ServiceStatus> {
+    output
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(name, state)| {
+            let name = name.trim().to_string();
+            let state = match state.trim() {
+                "started" => ServiceState::Started,
+                "crashed" | "failed" => ServiceState::Crashed,
+                _ => ServiceState::Stopped,
+            };
+            ServiceStatus {
+                log_tail: read_log_tail(&name),
+                name,
+                state,
+            }
+        })
+        .collect()
+}
+
+fn write_status_file(status_dir_path: &str, state: OpenRcState) {
+    let status_file_path = format!("{}/{}", &status_dir_path, &STATUS_FILE);
+    let result = fs::create_dir_all(&status_dir_path)
+        .and_then(|()| fs::write(&status_file_path, format!("{:?}\n", &state).to_lowercase()));
+    if let Err(e) = result {
+        warn!("Failed to write '{}': {}", &status_file_path, &e);
+    }
+}
+
+impl OpenRc {
+    fn initialize() -> OpenRc {
+        if cfg!(feature = "gui_only") {
+            info!("OpenRC is not initialized: supervising services directly");
+            return OpenRc {
+                state: OpenRcState::Stub,
+            };
+        }
+        Self::initialize_at(
+            &OPENRC_BINARY_PATH,
+            &crate::OPENRC_WORKDIR,
+            &STATUS_DIR_PATH,
+        )
+    }
+
+    // Another process (e.g. the previous boot stage) may have initialized OpenRC already: the work directory tells
+    fn initialize_at(openrc_binary_path: &str, workdir: &str, status_dir_path: &str) -> OpenRc {
+        let state = if Path::new(&workdir).exists() {
+            OpenRcState::Up
+        } else if !Path::new(&openrc_binary_path).exists() {
+            OpenRcState::Unavailable
+        } else {
+            info!("Initializing OpenRC");
+            let result = run_command(&openrc_binary_path, &[]).and_then(|()| {
+                let softlevel_path = format!("{}/{}", &workdir, &SOFTLEVEL_FILE);
+                File::create(&softlevel_path)
+                    .with_context(|| format!("Failed to create '{}'", &softlevel_path))?;
+                Ok(())
+            });
+            match result {
+                Ok(()) => OpenRcState::Up,
+                Err(e) => {
+                    warn!("Failed to initialize OpenRC: {:?}", &e);
+                    OpenRcState::Unavailable
+                }
+            }
+        };
+
+        match state {
+            OpenRcState::Up => info!("Managing services through OpenRC"),
+            _ => info!("OpenRC is not initialized: supervising services directly"),
+        }
+        write_status_file(&status_dir_path, state);
+
+        OpenRc { state }
+    }
+
+    pub fn state(&self) -> OpenRcState {
+        self.state
+    }
+
+    pub fn is_up(&self) -> bool {
+        self.state == OpenRcState::Up
+    }
+
+    // For services whose work was done by hand, so that OpenRC does not try to start them again (e.g. networking in the debug framework)
+    pub fn mark_started(&self, service: &str) -> Result<()> {
+        if !self.is_up() {
+            return Ok(());
+        }
+        let started_dir_path = format!("{}/{}", &crate::OPENRC_WORKDIR, &STARTED_DIR);
+        fs::create_dir_all(&started_dir_path)?;
+        symlink(
+            format!("{}/{}", &INIT_SCRIPTS_DIR_PATH, &service),
+            format!("{}/{}", &started_dir_path, &service),
+        )
+        .with_context(|| format!("Failed to mark OpenRC service '{}' as started", &service))
+    }
+}
+
+impl Services for OpenRc {
+    fn start(&self, service: &str) -> Result<()> {
+        run_command(&RC_SERVICE_BINARY_PATH, &[&service, "start"])
+            .with_context(|| format!("Failed to start '{}' service", &service))
+    }
+
+    fn stop(&self, service: &str) -> Result<()> {
+        run_command(&RC_SERVICE_BINARY_PATH, &[&service, "stop"])
+            .with_context(|| format!("Failed to stop '{}' service", &service))
+    }
+
+    fn restart(&self, service: &str) -> Result<()> {
+        run_command(&RC_SERVICE_BINARY_PATH, &[&service, "restart"])
+            .with_context(|| format!("Failed to restart '{}' service", &service))
+    }
+
+    fn status(&self) -> Result<Vec<ServiceStatus>> {
+        let output = run_command_capture(&RC_STATUS_BINARY_PATH, &["--nocolor", "--format", "ini"])
+            .with_context(|| "Failed to query OpenRC services status")?;
+        if !output.status.success() {
+            return Err(anyhow::anyhow!(
+                "rc-status exited with status {}: {}",
+                &output.status,
+                output.stderr.trim()
+            ));
+        }
+
+        Ok(parse_rc_status(&output.stdout))
+    }
+}
+
+fn get_or_initialize(openrc: &OnceLock<OpenRc>, initialize: impl FnOnce() -> OpenRc) -> &OpenRc {
+    openrc.get_or_init(initialize)
+}
+
+// Initialized exactly once by whichever module asks first: concurrent callers wait for that initialization to finish
+pub fn get() -> &'static OpenRc {
+    get_or_initialize(&OPENRC, OpenRc::initialize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+    use std::os::unix::fs::PermissionsExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    // Stands in for the real binary: creates the work directory as OpenRC would, and counts how many times it ran
+    fn fake_openrc(dir: &TempDir) -> String {
+        let openrc_binary_path = dir.write(
+            "sbin/openrc",
+            format!(
+                "#!/bin/sh\necho run >> '{}'\nmkdir -p '{}'\nsleep 0.1\n",
+                &dir.join("runs"),
+                &dir.join("run/openrc")
+            )
+            .as_bytes(),
+        );
+        fs::set_permissions(&openrc_binary_path, fs::Permissions::from_mode(0o755)).unwrap();
+        openrc_binary_path
+    }
+
+    fn initialize_in(dir: &TempDir) -> OpenRc {
+        OpenRc::initialize_at(
+            &dir.join("sbin/openrc"),
+            &dir.join("run/openrc"),
+            &dir.join("run/qinit"),
+        )
+    }
+
+    #[test]
+    fn concurrent_first_calls_write_state_once() {
+        let dir = &TempDir::new();
+        fake_openrc(&dir);
+        let openrc = &OnceLock::new();
+        thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(move || get_or_initialize(openrc, || initialize_in(&dir)));
+            }
+        });
+        assert!(openrc.get().unwrap().is_up());
+        assert_eq!(fs::read_to_string(dir.join("runs")).unwrap(), "run\n");
+        assert!(fs::exists(dir.join("run/openrc/softlevel")).unwrap());
+        assert_eq!(
+            fs::read_to_string(dir.join("run/qinit/openrc")).unwrap(),
+            "up\n"
+        );
+
+        // Later calls leave both files alone
+        fs::remove_file(dir.join("run/openrc/softlevel")).unwrap();
+        fs::remove_file(dir.join("run/qinit/openrc")).unwrap();
+        get_or_initialize(openrc, || initialize_in(&dir));
+        assert!(!fs::exists(dir.join("run/openrc/softlevel")).unwrap());
+        assert!(!fs::exists(dir.join("run/qinit/openrc")).unwrap());
+        assert_eq!(fs::read_to_string(dir.join("runs")).unwrap(), "run\n");
+    }
+
+    #[test]
+    fn openrc_initialized_elsewhere_is_not_run_again() {
+        let dir = TempDir::new();
+        fake_openrc(&dir);
+        fs::create_dir_all(dir.join("run/openrc")).unwrap();
+        assert!(initialize_in(&dir).is_up());
+        assert!(!fs::exists(dir.join("runs")).unwrap());
+        assert!(!fs::exists(dir.join("run/openrc/softlevel")).unwrap());
+        assert_eq!(
+            fs::read_to_string(dir.join("run/qinit/openrc")).unwrap(),
+            "up\n"
+        );
+    }
+
+    #[test]
+    fn missing_openrc_is_unavailable() {
+        let dir = TempDir::new();
+        assert_eq!(initialize_in(&dir).state(), OpenRcState::Unavailable);
+        assert_eq!(
+            fs::read_to_string(dir.join("run/qinit/openrc")).unwrap(),
+            "unavailable\n"
+        );
+    }
+
+    #[test]
+    fn concurrent_first_calls_initialize_once() {
+        let openrc = &OnceLock::new();
+        let initializations = &AtomicUsize::new(0);
+        let handles: Vec<&OpenRc> = thread::scope(|scope| {
+            let threads: Vec<_> = (0..8)
+                .map(|_| {
+                    scope.spawn(move || {
+                        get_or_initialize(openrc, || {
+                            initializations.fetch_add(1, Ordering::SeqCst);
+                            // Long enough for the other threads to ask in the meantime
+                            thread::sleep(Duration::from_millis(100));
+                            OpenRc {
+                                state: OpenRcState::Unavailable,
+                            }
+                        })
+                    })
+                })
+                .collect();
+            threads
+                .into_iter()
+                .map(|thread| thread.join().unwrap())
+                .collect()
+        });
+
+        assert_eq!(initializations.load(Ordering::SeqCst), 1);
+        assert!(
+            handles
+                .iter()
+                .all(|handle| std::ptr::eq(*handle, handles[0]))
+        );
+        assert_eq!(openrc.get().unwrap().state(), OpenRcState::Unavailable);
+    }
+
+    #[test]
+    fn rc_status_states_are_parsed() {
+        let statuses =
+            parse_rc_status("[default]\niwd = started\nsshd = crashed\n[manual]\nudev = stopped\n");
+        let states: Vec<(&str, ServiceState)> = statuses
+            .iter()
+            .map(|status| (status.name.as_str(), status.state))
+            .collect();
+        assert_eq!(
+            states,
+            vec![
+                ("iwd", ServiceState::Started),
+                ("sshd", ServiceState::Crashed),
+                ("udev", ServiceState::Stopped),
+            ]
+        );
+    }
+}
@@ -0,0 +1,274 @@
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::system::{get_cmdline_str, wait_for};
+
+const BY_PARTLABEL_DIR_PATH: &str = "/dev/disk/by-partlabel/";
+const BY_PARTUUID_DIR_PATH: &str = "/dev/disk/by-partuuid/";
+// udev may not be running yet: the kernel exposes partition names here regardless
+const SYSFS_BLOCK_DIR_PATH: &str = "/sys/class/block/";
+const PARTLABEL_PREFIX: &str = "PARTLABEL=";
+const PARTUUID_PREFIX: &str = "PARTUUID=";
+const WAVEFORM_PART: &str = "/dev/mmcblk0p2";
+
+// find_path() is polled while waiting for partitions: an override which does not match anything is only reported once
+static WARNED_OVERRIDES: Mutex<Option<HashSet<String>>> = Mutex::new(None);
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Partition {
+    Waveform,
+    Boot,
+    Main,
+}
+
+impl Partition {
+    // As named in the PineNote's stock partition table
+    // The boot and main partitions are created when installing, with neither an agreed upon label nor a known PARTUUID: looking them up under a made-up label could pick another system's partition on the same device
+    // They are only found by label or PARTUUID when the kernel command line gives one (see cmdline_property()), and otherwise at their usual device paths
+    fn label(&self) -> Option<&str> {
+        match self {
+            Partition::Waveform => Some("waveform"),
+            Partition::Boot | Partition::Main => None,
+        }
+    }
+
+    fn fallback_path(&self) -> &str {
+        match self {
+            Partition::Waveform => WAVEFORM_PART,
+            Partition::Boot => crate::BOOT_PART,
+            Partition::Main => crate::MAIN_PART,
+        }
+    }
+
+    // Kernel command line property allowing to override the partition: accepts a device path, 'PARTLABEL=<label>' or 'PARTUUID=<uuid>'
+    fn cmdline_property(&self) -> &str {
+        match self {
+            Partition::Waveform => "quill_waveform_part",
+            Partition::Boot => "quill_boot_part",
+            Partition::Main => "quill_main_part",
+        }
+    }
+}
+
+fn find_by_partlabel(label: &str) -> Result<Option<String>> {
+    let by_partlabel_path = format!("{}{}", &BY_PARTLABEL_DIR_PATH, &label);
+    if fs::exists(&by_partlabel_path)? {
+        return Ok(Some(
+            fs::canonicalize(&by_partlabel_path)?
+                .to_string_lossy()
+                .to_string(),
+        ));
+    }
+
+    if !fs::exists(&SYSFS_BLOCK_DIR_PATH)? {
+        return Ok(None);
+    }
+    for entry in fs::read_dir(&SYSFS_BLOCK_DIR_PATH)
+        .with_context(|| "Failed to read sysfs block devices directory")?
+    {
+        let uevent_path = entry?.path().join("uevent");
+        let Ok(uevent) = fs::read_to_string(&uevent_path) else {
+            continue;
+        };
+        let mut device_name = None;
+        let mut partition_name = None;
+        for line in uevent.lines() {
+            if let Some(value) = line.strip_prefix("DEVNAME=") {
+                device_name = Some(value.to_string());
+            } else if let Some(value) = line.strip_prefix("PARTNAME=") {
+                partition_name = Some(value.to_string());
+            }
+        }
+        if partition_name.as_deref() == Some(label)
+            && let Some(device_name) = device_name
+        {
+            return Ok(Some(format!("/dev/{}", &device_name)));
+        }
+    }
+
+    Ok(None)
+}
+
+fn find_by_partuuid(partuuid: &str) -> Result<Option<String>> {
+    let by_partuuid_path = format!("{}{}", &BY_PARTUUID_DIR_PATH, &partuuid.to_lowercase());
+    if fs::exists(&by_partuuid_path)? {
+        return Ok(Some(
+            fs::canonicalize(&by_partuuid_path)?
+                .to_string_lossy()
+                .to_string(),
+        ));
+    }
+
+    Ok(None)
+}
+
+// Catches overrides which could never match anything, instead of waiting for them until the timeout
+fn check_specifier(specifier: &str) -> Result<()> {
+    let is_valid = if let Some(label) = specifier.strip_prefix(&PARTLABEL_PREFIX) {
+        !label.is_empty() && !label.contains('/')
+    } else if let Some(partuuid) = specifier.strip_prefix(&PARTUUID_PREFIX) {
+        !partuuid.is_empty()
+            && partuuid
+                .chars()
+                .all(|character| character.is_ascii_hexdigit() || character == '-')
+    } else {
+        specifier.starts_with('/')
+    };
+    if !is_valid {
+        return Err(anyhow::anyhow!(
+            "'{}' is neither a device path, '{}<label>' nor '{}<uuid>'",
+            &specifier,
+            &PARTLABEL_PREFIX,
+            &PARTUUID_PREFIX
+        ));
+    }
+
+    Ok(())
+}
+
+fn cmdline_override(partition: &Partition) -> Result<Option<String>> {
+    get_cmdline_str(&partition.cmdline_property())
+}
+
+fn warn_unresolved_override(partition: &Partition, specifier: &str) {
+    let override_str = format!("{}={}", &partition.cmdline_property(), &specifier);
+    if WARNED_OVERRIDES
+        .lock()
+        .unwrap()
+        .get_or_insert_with(HashSet::new)
+        .insert(override_str.clone())
+    {
+        warn!(
+            "'{}' on kernel command line does not match any partition yet: {:?} partition will only be looked up there",
+            &override_str, &partition
+        );
+    }
+}
+
+fn resolve_specifier(specifier: &str) -> Result<Option<String>> {
+    if let Some(label) = specifier.strip_prefix(&PARTLABEL_PREFIX) {
+        find_by_partlabel(&label)
+    } else if let Some(partuuid) = specifier.strip_prefix(&PARTUUID_PREFIX) {
+        find_by_partuuid(&partuuid)
+    } else if fs::exists(&specifier)? {
+        Ok(Some(specifier.to_string()))
+    } else {
+        Ok(None)
+    }
+}
+
+// Returns None if the partition could not be found (yet)
+pub fn find_path(partition: &Partition) -> Result<Option<String>> {
+    if let Some(specifier) = cmdline_override(&partition)? {
+        check_specifier(&specifier).with_context(|| {
+            format!(
+                "Invalid {:?} partition override '{}' on kernel command line",
+                &partition,
+                &partition.cmdline_property()
+            )
+        })?;
+        if let Some(path) = resolve_specifier(&specifier)? {
+            debug!(
+                "Resolved {:?} partition to '{}' from kernel command line",
+                &partition, &path
+            );
+            return Ok(Some(path));
+        }
+        warn_unresolved_override(&partition, &specifier);
+        return Ok(None);
+    }
+
+    if let Some(label) = partition.label()
+        && let Some(path) = find_by_partlabel(&label)?
+    {
+        debug!(
+            "Resolved {:?} partition to '{}' from its partition label",
+            &partition, &path
+        );
+        return Ok(Some(path));
+    }
+
+    if Path::new(&partition.fallback_path()).exists() {
+        return Ok(Some(partition.fallback_path().to_string()));
+    }
+
+    Ok(None)
+}
+
+// Location of a file stored on the boot partition, once mount_base_partitions() mounted it
+pub fn boot_file_path(file: &str) -> String {
+    format!("{}/{}", &crate::BOOT_PART_MOUNTPOINT, &file)
+}
+
+pub fn get_path(partition: &Partition) -> Result<String> {
+    if let Some(path) = find_path(&partition)? {
+        Ok(path)
+    } else {
+        warn!(
+            "Could not resolve {:?} partition: falling back to '{}'",
+            &partition,
+            &partition.fallback_path()
+        );
+        Ok(partition.fallback_path().to_string())
+    }
+}
+
+pub fn wait_for_partition(partition: &Partition, timeout: Duration) -> Result<String> {
+    let path = wait_for(
+        || find_path(&partition),
+        timeout,
+        &format!(
+            "the {} partition",
+            format!("{:?}", &partition).to_lowercase()
+        ),
+    )
+    .with_context(|| match cmdline_override(&partition) {
+        Ok(Some(specifier)) => format!(
+            "Failed to find {:?} partition given as '{}={}' on kernel command line",
+            &partition,
+            &partition.cmdline_property(),
+            &specifier
+        ),
+        _ => format!("Failed to find {:?} partition", &partition),
+    })?;
+    info!("Using '{}' as {:?} partition", &path, &partition);
+
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides_which_cannot_match_are_rejected() {
+        for specifier in [
+            "/dev/mmcblk1p2",
+            "PARTLABEL=quill_main",
+            "PARTUUID=6b3f1a2c-05",
+            "PARTUUID=1B2C3D4E-0000-4A1B-8C2D-3E4F5A6B7C8D",
+        ] {
+            check_specifier(&specifier).unwrap();
+        }
+        for specifier in [
+            "mmcblk1p2",
+            "PARTLABEL=",
+            "PARTLABEL=../sda1",
+            "PARTUUID=",
+            "PARTUUID=not-a-uuid",
+            "LABEL=quill_main",
+        ] {
+            assert!(
+                check_specifier(&specifier)
+                    .unwrap_err()
+                    .to_string()
+                    .contains(&specifier)
+            );
+        }
+    }
+}
@@ -0,0 +1,605 @@
+use anyhow::{Context, Result};
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::prelude::*;
+use log::{info, warn};
+use openssl::symm::{Cipher, decrypt_aead, encrypt_aead};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::notifier::{self, PinRequest};
+use crate::secret::{self, Secret};
+
+pub const DEFAULT_PIN_MAX_LENGTH: usize = 8;
+pub const DEFAULT_FREE_ATTEMPTS: u32 = 3;
+pub const DEFAULT_BASE_LOCKOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_MAX_LOCKOUT: Duration = Duration::from_secs(15 * 60);
+// The RK3566's Cortex-A55 cores are slow: aim for a hash taking about half a second there
+const ARGON2_TARGET_DURATION: Duration = Duration::from_millis(500);
+const ARGON2_MEMORY_KIB: u32 = 19 * 1024;
+const ARGON2_MAX_ITERATIONS: u32 = 10;
+const SALT_LEN: usize = 16;
+// At least a million PINs at half a second per guess: days of hashing offline, rather than the hour four digits would take
+pub const QUICK_UNLOCK_PIN_MIN_LENGTH: usize = 6;
+pub const DEFAULT_QUICK_UNLOCK_MAX_ATTEMPTS: u32 = 5;
+const QUICK_UNLOCK_DIR: &str = "quick_unlock/";
+const QUICK_UNLOCK_FILE_EXT: &str = ".ron";
+const QUICK_UNLOCK_KEY_LEN: usize = 32;
+const QUICK_UNLOCK_NONCE_LEN: usize = 12;
+const QUICK_UNLOCK_TAG_LEN: usize = 16;
+const QUICK_UNLOCK_HARDWARE_SECRET_CONTEXT: &[u8] = b"qinit quick unlock";
+
+// Shared by every PIN prompt: switching to another one does not reset the lockout
+static RATE_LIMITER: Mutex<RateLimiter> = Mutex::new(RateLimiter::new(
+    DEFAULT_FREE_ATTEMPTS,
+    DEFAULT_BASE_LOCKOUT,
+    DEFAULT_MAX_LOCKOUT,
+));
+
+// The storage password of a user, encrypted with a key derived from their PIN: kept on the boot partition, next to the boot configuration
+// Threat model: anyone holding the storage can copy the blob and try PINs offline, where the attempts limit does not apply
+// The key also depends on the SoC serial number, which is only readable from the running device: a copied blob (and boot partition) is not enough
+// This does not protect against someone who can run code on the device itself, or who can guess the serial number
+#[derive(Debug, Serialize, Deserialize)]
+struct QuickUnlockBlob {
+    iterations: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+    tag: String,
+    // Survives reboots: otherwise, powering off would reset the count
+    failed_attempts: u32,
+}
+
+// What a PIN prompt's check made of an entry
+pub enum PinCheck<T> {
+    Accepted(T),
+    // Asked again, along with this message
+    Rejected(String),
+    // No attempts left: the prompt gives up
+    Exhausted,
+}
+
+pub enum PinPromptOutcome<T> {
+    Accepted(T),
+    Cancelled,
+    Exhausted,
+}
+
+pub enum QuickUnlockOutcome {
+    Unlocked(Secret),
+    WrongPin { remaining_attempts: u32 },
+    // Too many wrong PINs: the blob is gone and the full password is required again
+    Disabled,
+}
+
+// Lockout after repeated failures: every failure past the free attempts doubles the lockout, up to a maximum
+// Meant to be shared by every credential check (PINs, encrypted storage passwords) so they follow the same policy
+pub struct RateLimiter {
+    free_attempts: u32,
+    base_lockout: Duration,
+    max_lockout: Duration,
+    failures: u32,
+    locked_until: Option<Instant>,
+}
+
+impl RateLimiter {
+    pub const fn new(
+        free_attempts: u32,
+        base_lockout: Duration,
+        max_lockout: Duration,
+    ) -> RateLimiter {
+        RateLimiter {
+            free_attempts,
+            base_lockout,
+            max_lockout,
+            failures: 0,
+            locked_until: None,
+        }
+    }
+
+    pub fn remaining_lockout(&self) -> Option<Duration> {
+        self.locked_until
+            .map(|locked_until| locked_until.saturating_duration_since(Instant::now()))
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    // Returns the lockout started by this failure, if any
+    pub fn record_failure(&mut self) -> Option<Duration> {
+        self.failures += 1;
+        if self.failures <= self.free_attempts {
+            return None;
+        }
+
+        let exponent = (self.failures - self.free_attempts - 1).min(16);
+        let lockout = self
+            .base_lockout
+            .saturating_mul(1 << exponent)
+            .min(self.max_lockout);
+        warn!(
+            "{} failed attempt(s): locking out for {} seconds",
+            &self.failures,
+            lockout.as_secs()
+        );
+        self.locked_until = Some(Instant::now() + lockout);
+
+        Some(lockout)
+    }
+
+    pub fn record_success(&mut self) {
+        self.failures = 0;
+        self.locked_until = None;
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> RateLimiter {
+        RateLimiter::new(
+            DEFAULT_FREE_ATTEMPTS,
+            DEFAULT_BASE_LOCKOUT,
+            DEFAULT_MAX_LOCKOUT,
+        )
+    }
+}
+
+fn argon2_with_iterations(iterations: u32) -> Result<Argon2<'static>> {
+    let params = Params::new(ARGON2_MEMORY_KIB, iterations, 1, None)
+        .map_err(|e| anyhow::anyhow!("Invalid argon2 parameters: {}", e))?;
+
+    Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+}
+
+// Measured once when a PIN is set: parameters are stored in the resulting PHC string, so verification does not need to measure again
+fn calibrate_iterations() -> Result<u32> {
+    let salt = SaltString::encode_b64(&[0u8; SALT_LEN])
+        .map_err(|e| anyhow::anyhow!("Failed to encode salt: {}", e))?;
+    for iterations in 1..=ARGON2_MAX_ITERATIONS {
+        let start = Instant::now();
+        argon2_with_iterations(iterations)?
+            .hash_password(b"calibration", &salt)
+            .map_err(|e| anyhow::anyhow!("Failed to hash calibration input: {}", e))?;
+        if start.elapsed() >= ARGON2_TARGET_DURATION {
+            info!(
+                "Using {} argon2 iteration(s) ({} ms per hash)",
+                &iterations,
+                start.elapsed().as_millis()
+            );
+            return Ok(iterations);
+        }
+    }
+
+    Ok(ARGON2_MAX_ITERATIONS)
+}
+
+pub fn hash_pin(pin: &Secret) -> Result<String> {
+    let salt_bytes: [u8; SALT_LEN] = rand::rng().random();
+    let salt = SaltString::encode_b64(&salt_bytes)
+        .map_err(|e| anyhow::anyhow!("Failed to encode salt: {}", e))?;
+    let hash = argon2_with_iterations(calibrate_iterations()?)?
+        .hash_password(pin.expose().as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!("Failed to hash PIN: {}", e))?;
+
+    Ok(hash.to_string())
+}
+
+pub fn verify_pin(pin: &Secret, stored_hash: &str) -> Result<bool> {
+    let stored_hash = PasswordHash::new(&stored_hash)
+        .map_err(|e| anyhow::anyhow!("Invalid stored PIN hash: {}", e))?;
+
+    Ok(Argon2::default()
+        .verify_password(pin.expose().as_bytes(), &stored_hash)
+        .is_ok())
+}
+
+// Blocks until answered: must never be called from the GUI thread
+// Every rejected entry counts towards the shared lockout, whatever the check's own attempt limit is
+pub fn prompt_and_verify<T>(
+    message: &str,
+    mut check: impl FnMut(&Secret) -> Result<PinCheck<T>>,
+) -> Result<PinPromptOutcome<T>> {
+    let notifier = notifier::get();
+    let mut error_message = None;
+    loop {
+        let lockout = RATE_LIMITER.lock().unwrap().remaining_lockout();
+        if let Some(lockout) = lockout {
+            notifier.sticky_toast(&format!(
+                "Too many attempts: try again in {} seconds",
+                lockout.as_secs() + 1
+            ));
+            thread::sleep(lockout);
+        }
+
+        let Some(pin) = notifier.ask_pin(&PinRequest {
+            message: message.to_string(),
+            max_length: DEFAULT_PIN_MAX_LENGTH,
+            error_message: error_message.take(),
+        }) else {
+            info!("PIN prompt was cancelled");
+            return Ok(PinPromptOutcome::Cancelled);
+        };
+
+        match check(&pin)? {
+            PinCheck::Accepted(value) => {
+                RATE_LIMITER.lock().unwrap().record_success();
+                return Ok(PinPromptOutcome::Accepted(value));
+            }
+            PinCheck::Rejected(message) => {
+                RATE_LIMITER.lock().unwrap().record_failure();
+                error_message = Some(message);
+            }
+            PinCheck::Exhausted => {
+                RATE_LIMITER.lock().unwrap().record_failure();
+                return Ok(PinPromptOutcome::Exhausted);
+            }
+        }
+    }
+}
+
+fn quick_unlock_path_in(boot_part_mountpoint: &str, user: &str) -> String {
+    format!(
+        "{}/{}/{}{}",
+        &boot_part_mountpoint, &QUICK_UNLOCK_DIR, &user, &QUICK_UNLOCK_FILE_EXT
+    )
+}
+
+fn quick_unlock_path(user: &str) -> String {
+    quick_unlock_path_in(&crate::BOOT_PART_MOUNTPOINT, &user)
+}
+
+pub fn has_quick_unlock(user: &str) -> bool {
+    fs::exists(&quick_unlock_path(&user)).unwrap_or(false)
+}
+
+fn decode(value: &str) -> Result<Vec<u8>> {
+    BASE64_STANDARD
+        .decode(&value)
+        .with_context(|| "Corrupted quick unlock data")
+}
+
+// Read every time rather than stored: see QuickUnlockBlob
+fn read_hardware_secret() -> Result<[u8; 32]> {
+    let serial = crate::system::read_soc_serial_number().ok_or_else(|| {
+        anyhow::anyhow!(
+            "Quick unlock needs the SoC serial number, which this device does not expose"
+        )
+    })?;
+    let mut hasher = openssl::sha::Sha256::new();
+    hasher.update(&QUICK_UNLOCK_HARDWARE_SECRET_CONTEXT);
+    hasher.update(serial.as_bytes());
+
+    Ok(hasher.finish())
+}
+
+// The device ID is mixed into the salt and authenticated along with the ciphertext: a blob copied to another device cannot be decrypted there
+fn derive_quick_unlock_key(
+    pin: &Secret,
+    salt: &[u8],
+    device_id: &str,
+    hardware_secret: &[u8],
+    iterations: u32,
+) -> Result<[u8; QUICK_UNLOCK_KEY_LEN]> {
+    let mut bound_salt = salt.to_vec();
+    bound_salt.extend_from_slice(device_id.as_bytes());
+    bound_salt.extend_from_slice(&hardware_secret);
+    let mut key = [0u8; QUICK_UNLOCK_KEY_LEN];
+    argon2_with_iterations(iterations)?
+        .hash_password_into(pin.expose().as_bytes(), &bound_salt, &mut key)
+        .map_err(|e| anyhow::anyhow!("Failed to derive quick unlock key: {}", e))?;
+
+    Ok(key)
+}
+
+fn write_quick_unlock_blob(user: &str, blob: &QuickUnlockBlob) -> Result<()> {
+    let path = quick_unlock_path(&user);
+    let dir_path = format!("{}/{}", &crate::BOOT_PART_MOUNTPOINT, &QUICK_UNLOCK_DIR);
+    fs::create_dir_all(&dir_path).with_context(|| format!("Failed to create '{}'", &dir_path))?;
+    fs::write(&path, ron::to_string(&blob)?)
+        .with_context(|| format!("Failed to write '{}'", &path))?;
+
+    crate::system::sync_disks()
+}
+
+pub fn validate_quick_unlock_pin(pin: &Secret) -> Result<()> {
+    let pin_length = pin.expose().chars().count();
+    if pin_length < QUICK_UNLOCK_PIN_MIN_LENGTH
+        || pin_length > DEFAULT_PIN_MAX_LENGTH
+        || !pin
+            .expose()
+            .chars()
+            .all(|character| character.is_ascii_digit())
+    {
+        return Err(anyhow::anyhow!(
+            "PIN must be made of {} to {} digits",
+            &QUICK_UNLOCK_PIN_MIN_LENGTH,
+            &DEFAULT_PIN_MAX_LENGTH
+        ));
+    }
+
+    Ok(())
+}
+
+// The password must have been checked beforehand, e.g. with storage_encryption::check_password()
+pub fn enable_quick_unlock(
+    user: &str,
+    pin: &Secret,
+    password: &Secret,
+    device_id: &str,
+) -> Result<()> {
+    validate_quick_unlock_pin(&pin)?;
+    info!("Enabling quick unlock for user '{}'", &user);
+    let salt: [u8; SALT_LEN] = rand::rng().random();
+    let nonce: [u8; QUICK_UNLOCK_NONCE_LEN] = rand::rng().random();
+    let hardware_secret = read_hardware_secret()?;
+    let iterations = calibrate_iterations()?;
+    let mut key = derive_quick_unlock_key(&pin, &salt, &device_id, &hardware_secret, iterations)?;
+    let mut tag = [0u8; QUICK_UNLOCK_TAG_LEN];
+    let ciphertext = encrypt_aead(
+        Cipher::aes_256_gcm(),
+        &key,
+        Some(&nonce),
+        device_id.as_bytes(),
+        password.expose().as_bytes(),
+        &mut tag,
+    );
+    secret::zeroize(&mut key);
+    let ciphertext = ciphertext.with_context(|| "Failed to encrypt password")?;
+
+    write_quick_unlock_blob(
+        &user,
+        &QuickUnlockBlob {
+            iterations,
+            salt: BASE64_STANDARD.encode(&salt),
+            nonce: BASE64_STANDARD.encode(&nonce),
+            ciphertext: BASE64_STANDARD.encode(&ciphertext),
+            tag: BASE64_STANDARD.encode(&tag),
+            failed_attempts: 0,
+        },
+    )
+}
+
+fn disable_quick_unlock_in(boot_part_mountpoint: &str, user: &str) -> Result<()> {
+    let path = quick_unlock_path_in(&boot_part_mountpoint, &user);
+    if !fs::exists(&path)? {
+        return Ok(());
+    }
+    info!("Disabling quick unlock for user '{}'", &user);
+    crate::storage_encryption::shred_file(&path)
+        .with_context(|| format!("Failed to remove '{}'", &path))?;
+
+    crate::system::sync_disks()
+}
+
+// Also whenever the password it holds stops being valid (e.g. it was changed, or the storage was destroyed)
+pub fn disable_quick_unlock(user: &str) -> Result<()> {
+    disable_quick_unlock_in(&crate::BOOT_PART_MOUNTPOINT, &user)
+}
+
+// The blob is bound to the device, not to the user name: it keeps working under the new one
+// Whatever was left behind under the new name belonged to someone else
+fn rename_quick_unlock_in(boot_part_mountpoint: &str, user: &str, new_user: &str) -> Result<()> {
+    disable_quick_unlock_in(&boot_part_mountpoint, &new_user)?;
+    let path = quick_unlock_path_in(&boot_part_mountpoint, &user);
+    if !fs::exists(&path)? {
+        return Ok(());
+    }
+    let new_path = quick_unlock_path_in(&boot_part_mountpoint, &new_user);
+    fs::rename(&path, &new_path)
+        .with_context(|| format!("Failed to rename '{}' to '{}'", &path, &new_path))?;
+
+    crate::system::sync_disks()
+}
+
+pub fn rename_quick_unlock(user: &str, new_user: &str) -> Result<()> {
+    rename_quick_unlock_in(&crate::BOOT_PART_MOUNTPOINT, &user, &new_user)
+}
+
+// One wrong PIN counts as one attempt, whatever the reason decryption failed
+pub fn quick_unlock(
+    user: &str,
+    pin: &Secret,
+    device_id: &str,
+    max_attempts: u32,
+) -> Result<QuickUnlockOutcome> {
+    let path = quick_unlock_path(&user);
+    let blob_str =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read '{}'", &path))?;
+    let mut blob = ron::from_str::<QuickUnlockBlob>(&blob_str)
+        .with_context(|| format!("Failed to parse '{}'", &path))?;
+
+    let mut key = derive_quick_unlock_key(
+        &pin,
+        &decode(&blob.salt)?,
+        &device_id,
+        &read_hardware_secret()?,
+        blob.iterations,
+    )?;
+    let plaintext = decrypt_aead(
+        Cipher::aes_256_gcm(),
+        &key,
+        Some(&decode(&blob.nonce)?),
+        device_id.as_bytes(),
+        &decode(&blob.ciphertext)?,
+        &decode(&blob.tag)?,
+    );
+    secret::zeroize(&mut key);
+
+    match plaintext {
+        Ok(plaintext) => {
+            let password = match String::from_utf8(plaintext) {
+                Ok(password) => Secret::new(password),
+                Err(e) => {
+                    let mut plaintext = e.into_bytes();
+                    secret::zeroize(&mut plaintext);
+                    return Err(anyhow::anyhow!("Corrupted quick unlock data"));
+                }
+            };
+            if blob.failed_attempts > 0 {
+                blob.failed_attempts = 0;
+                write_quick_unlock_blob(&user, &blob)?;
+            }
+            Ok(QuickUnlockOutcome::Unlocked(password))
+        }
+        Err(_) => {
+            blob.failed_attempts += 1;
+            warn!(
+                "Wrong quick unlock PIN for user '{}' ({} of {} attempts)",
+                &user, &blob.failed_attempts, &max_attempts
+            );
+            if blob.failed_attempts >= max_attempts {
+                disable_quick_unlock(&user)?;
+                return Ok(QuickUnlockOutcome::Disabled);
+            }
+            write_quick_unlock_blob(&user, &blob)?;
+            Ok(QuickUnlockOutcome::WrongPin {
+                remaining_attempts: max_attempts - blob.failed_attempts,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    fn write_blob(boot_part: &TempDir, user: &str, failed_attempts: u32) {
+        let blob = QuickUnlockBlob {
+            iterations: 1,
+            salt: String::new(),
+            nonce: String::new(),
+            ciphertext: String::new(),
+            tag: String::new(),
+            failed_attempts,
+        };
+        boot_part.write(
+            &format!("{}{}{}", &QUICK_UNLOCK_DIR, &user, &QUICK_UNLOCK_FILE_EXT),
+            ron::to_string(&blob).unwrap().as_bytes(),
+        );
+    }
+
+    fn has_blob(boot_part: &TempDir, user: &str) -> bool {
+        fs::exists(quick_unlock_path_in(&boot_part.path(), &user)).unwrap()
+    }
+
+    // After a password change: the blob would only hold the old one
+    #[test]
+    fn disabled_quick_unlock_leaves_no_blob() {
+        let boot_part = TempDir::new();
+        write_blob(&boot_part, "alice", 0);
+        disable_quick_unlock_in(&boot_part.path(), "alice").unwrap();
+        assert!(!has_blob(&boot_part, "alice"));
+        // Nothing to do the second time around
+        disable_quick_unlock_in(&boot_part.path(), "alice").unwrap();
+    }
+
+    // After the storage was destroyed: a new user of the same name must not inherit a PIN
+    #[test]
+    fn destroyed_user_quick_unlock_is_not_inherited() {
+        let boot_part = TempDir::new();
+        write_blob(&boot_part, "alice", 0);
+        write_blob(&boot_part, "bob", 0);
+        disable_quick_unlock_in(&boot_part.path(), "alice").unwrap();
+        assert!(!has_blob(&boot_part, "alice"));
+        assert!(has_blob(&boot_part, "bob"));
+    }
+
+    #[test]
+    fn renamed_user_keeps_quick_unlock() {
+        let boot_part = TempDir::new();
+        write_blob(&boot_part, "alice", 2);
+        // Left behind by a previous user of the new name
+        write_blob(&boot_part, "carol", 0);
+        rename_quick_unlock_in(&boot_part.path(), "alice", "carol").unwrap();
+        assert!(!has_blob(&boot_part, "alice"));
+        let blob_str =
+            fs::read_to_string(quick_unlock_path_in(&boot_part.path(), "carol")).unwrap();
+        assert_eq!(
+            ron::from_str::<QuickUnlockBlob>(&blob_str)
+                .unwrap()
+                .failed_attempts,
+            2
+        );
+    }
+
+    #[test]
+    fn renamed_user_without_quick_unlock_gets_none() {
+        let boot_part = TempDir::new();
+        write_blob(&boot_part, "carol", 0);
+        rename_quick_unlock_in(&boot_part.path(), "alice", "carol").unwrap();
+        assert!(!has_blob(&boot_part, "alice"));
+        assert!(!has_blob(&boot_part, "carol"));
+    }
+
+    #[test]
+    fn stored_pin_hashes_verify() {
+        let hash = hash_pin(&Secret::new("1234".to_string())).unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_pin(&Secret::new("1234".to_string()), &hash).unwrap());
+        assert!(!verify_pin(&Secret::new("4321".to_string()), &hash).unwrap());
+        // Salted: the same PIN never hashes the same way twice
+        assert_ne!(hash, hash_pin(&Secret::new("1234".to_string())).unwrap());
+    }
+
+    #[test]
+    fn invalid_stored_hash_is_an_error() {
+        assert!(verify_pin(&Secret::new("1234".to_string()), "1234").is_err());
+    }
+
+    #[test]
+    fn quick_unlock_key_depends_on_every_input() {
+        let pin = Secret::new("123456".to_string());
+        let device_id = "4f1c2a9e-0b7d-4c3e-9a21-5d6e7f809a1b";
+        let key = derive_quick_unlock_key(&pin, &[1; SALT_LEN], &device_id, &[2; 32], 1).unwrap();
+        assert_eq!(
+            key,
+            derive_quick_unlock_key(&pin, &[1; SALT_LEN], &device_id, &[2; 32], 1).unwrap()
+        );
+        for other_key in [
+            derive_quick_unlock_key(
+                &Secret::new("123457".to_string()),
+                &[1; SALT_LEN],
+                &device_id,
+                &[2; 32],
+                1,
+            ),
+            derive_quick_unlock_key(&pin, &[1; SALT_LEN], "other", &[2; 32], 1),
+            // Same blob and boot partition, on another device or without the serial number
+            derive_quick_unlock_key(&pin, &[1; SALT_LEN], &device_id, &[3; 32], 1),
+        ] {
+            assert_ne!(key, other_key.unwrap());
+        }
+    }
+
+    #[test]
+    fn short_or_non_numeric_quick_unlock_pins_are_rejected() {
+        assert!(validate_quick_unlock_pin(&Secret::new("123456".to_string())).is_ok());
+        assert!(validate_quick_unlock_pin(&Secret::new("12345678".to_string())).is_ok());
+        assert!(validate_quick_unlock_pin(&Secret::new("1234".to_string())).is_err());
+        assert!(validate_quick_unlock_pin(&Secret::new("123456789".to_string())).is_err());
+        assert!(validate_quick_unlock_pin(&Secret::new("12345a".to_string())).is_err());
+    }
+
+    #[test]
+    fn lockout_doubles_up_to_the_maximum() {
+        let mut rate_limiter =
+            RateLimiter::new(2, Duration::from_secs(30), Duration::from_secs(100));
+        assert_eq!(rate_limiter.record_failure(), None);
+        assert_eq!(rate_limiter.record_failure(), None);
+        assert!(rate_limiter.remaining_lockout().is_none());
+        assert_eq!(rate_limiter.record_failure(), Some(Duration::from_secs(30)));
+        assert!(rate_limiter.remaining_lockout().is_some());
+        assert_eq!(rate_limiter.record_failure(), Some(Duration::from_secs(60)));
+        assert_eq!(
+            rate_limiter.record_failure(),
+            Some(Duration::from_secs(100))
+        );
+
+        rate_limiter.record_success();
+        assert!(rate_limiter.remaining_lockout().is_none());
+        assert_eq!(rate_limiter.record_failure(), None);
+    }
+}
@@ -0,0 +1,239 @@
+use anyhow::Result;
+use rand::Rng;
+use std::collections::BTreeMap;
+
+// Compressed diagnostics too large for a single QR code are split into parts, each starting with this header:
+// magic (2 bytes), index (1 byte, starting at 0), total (1 byte), session ID (4 bytes, big endian)
+// A payload shown as a single QR code carries no header and is the bare XZ stream
+pub const PART_MAGIC: &[u8; 2] = b"QQ";
+pub const PART_HEADER_LEN: usize = 8;
+pub const MAX_PARTS: usize = 16;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct PartHeader {
+    pub index: u8,
+    pub total: u8,
+    pub session_id: u32,
+}
+
+#[derive(Debug)]
+pub struct MissingPartsError {
+    pub session_id: u32,
+    pub total: u8,
+    pub missing: Vec<u8>,
+}
+
+impl std::fmt::Display for MissingPartsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let missing: Vec<String> = self
+            .missing
+            .iter()
+            .map(|index| (index + 1).to_string())
+            .collect();
+        write!(
+            f,
+            "Session {:08x} is incomplete: missing part(s) {} out of {}",
+            &self.session_id,
+            missing.join(", "),
+            &self.total
+        )
+    }
+}
+
+impl std::error::Error for MissingPartsError {}
+
+pub fn new_session_id() -> u32 {
+    rand::rng().random()
+}
+
+pub fn split_payload(data: &[u8], session_id: u32, parts_count: usize) -> Result<Vec<Vec<u8>>> {
+    if parts_count == 0 || parts_count > MAX_PARTS {
+        return Err(anyhow::anyhow!(
+            "Invalid number of QR code parts: {}",
+            &parts_count
+        ));
+    }
+    if parts_count == 1 {
+        return Ok(vec![data.to_vec()]);
+    }
+
+    let chunk_size = data.len().div_ceil(parts_count).max(1);
+    let chunks: Vec<&[u8]> = data.chunks(chunk_size).collect();
+    let total = chunks.len() as u8;
+    let mut parts = Vec::new();
+    for (index, chunk) in chunks.iter().enumerate() {
+        let mut part = Vec::with_capacity(PART_HEADER_LEN + chunk.len());
+        part.extend_from_slice(PART_MAGIC);
+        part.push(index as u8);
+        part.push(total);
+        part.extend_from_slice(&session_id.to_be_bytes());
+        part.extend_from_slice(chunk);
+        parts.push(part);
+    }
+
+    Ok(parts)
+}
+
+// Returns None for headerless (single QR code) payloads
+pub fn parse_part(part: &[u8]) -> Option<(PartHeader, &[u8])> {
+    if part.len() < PART_HEADER_LEN || !part.starts_with(PART_MAGIC) {
+        return None;
+    }
+    let header = PartHeader {
+        index: part[2],
+        total: part[3],
+        session_id: u32::from_be_bytes([part[4], part[5], part[6], part[7]]),
+    };
+    if header.total == 0 || header.index >= header.total {
+        return None;
+    }
+
+    Some((header, &part[PART_HEADER_LEN..]))
+}
+
+// Parts may be scanned in any order and more than once
+pub fn reassemble_parts(parts: &[Vec<u8>]) -> Result<Vec<u8>> {
+    if parts.len() == 1 && parse_part(&parts[0]).is_none() {
+        return Ok(parts[0].clone());
+    }
+
+    let mut expected: Option<(u32, u8)> = None;
+    let mut chunks: BTreeMap<u8, &[u8]> = BTreeMap::new();
+    for part in parts {
+        let (header, chunk) =
+            parse_part(&part).ok_or_else(|| anyhow::anyhow!("Invalid QR code part header"))?;
+        match expected {
+            None => expected = Some((header.session_id, header.total)),
+            Some((session_id, total)) => {
+                if header.session_id != session_id || header.total != total {
+                    return Err(anyhow::anyhow!(
+                        "QR code parts belong to different sessions ({:08x} and {:08x})",
+                        &session_id,
+                        &header.session_id
+                    ));
+                }
+            }
+        }
+        chunks.insert(header.index, chunk);
+    }
+
+    let Some((session_id, total)) = expected else {
+        return Err(anyhow::anyhow!("No QR code parts to reassemble"));
+    };
+    let missing: Vec<u8> = (0..total)
+        .filter(|index| !chunks.contains_key(index))
+        .collect();
+    if !missing.is_empty() {
+        return Err(MissingPartsError {
+            session_id,
+            total,
+            missing,
+        }
+        .into());
+    }
+
+    Ok(chunks.into_values().flatten().copied().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SESSION_ID: u32 = 0x1234abcd;
+
+    fn payload() -> Vec<u8> {
+        (0..100u8).collect()
+    }
+
+    #[test]
+    fn parts_start_with_their_header() {
+        let parts = split_payload(&payload(), SESSION_ID, 3).unwrap();
+        assert_eq!(parts.len(), 3);
+        for (index, part) in parts.iter().enumerate() {
+            assert_eq!(
+                &part[..PART_HEADER_LEN],
+                &[b'Q', b'Q', index as u8, 3, 0x12, 0x34, 0xab, 0xcd]
+            );
+        }
+        assert_eq!(parts[0].len(), PART_HEADER_LEN + 34);
+        assert_eq!(parts[2].len(), PART_HEADER_LEN + 32);
+    }
+
+    #[test]
+    fn single_part_is_the_bare_payload() {
+        let parts = split_payload(&payload(), SESSION_ID, 1).unwrap();
+        assert_eq!(parts, vec![payload()]);
+        assert_eq!(reassemble_parts(&parts).unwrap(), payload());
+    }
+
+    #[test]
+    fn parts_count_is_bounded() {
+        assert!(split_payload(&payload(), SESSION_ID, 0).is_err());
+        assert!(split_payload(&payload(), SESSION_ID, MAX_PARTS + 1).is_err());
+        assert_eq!(
+            split_payload(&[0; MAX_PARTS * 10], SESSION_ID, MAX_PARTS)
+                .unwrap()
+                .len(),
+            MAX_PARTS
+        );
+    }
+
+    #[test]
+    fn short_payload_gets_fewer_parts() {
+        let parts = split_payload(&[1, 2], SESSION_ID, 4).unwrap();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parse_part(&parts[1]).unwrap().0.total, 2);
+    }
+
+    #[test]
+    fn invalid_headers_are_not_parts() {
+        assert_eq!(parse_part(b"QQ"), None);
+        assert_eq!(parse_part(b"XZ\x00\x02\x00\x00\x00\x00data"), None);
+        // Index out of range, then no parts at all
+        assert_eq!(parse_part(b"QQ\x02\x02\x00\x00\x00\x00data"), None);
+        assert_eq!(parse_part(b"QQ\x00\x00\x00\x00\x00\x00data"), None);
+        assert_eq!(
+            parse_part(b"QQ\x01\x02\x12\x34\xab\xcddata"),
+            Some((
+                PartHeader {
+                    index: 1,
+                    total: 2,
+                    session_id: SESSION_ID,
+                },
+                &b"data"[..]
+            ))
+        );
+    }
+
+    #[test]
+    fn parts_are_reassembled_in_any_order() {
+        let mut parts = split_payload(&payload(), SESSION_ID, 4).unwrap();
+        parts.reverse();
+        let scanned_again = parts[1].clone();
+        parts.push(scanned_again);
+        assert_eq!(reassemble_parts(&parts).unwrap(), payload());
+    }
+
+    #[test]
+    fn missing_parts_are_reported() {
+        let parts = split_payload(&payload(), SESSION_ID, 4).unwrap();
+        let error = reassemble_parts(&[parts[1].clone(), parts[2].clone()]).unwrap_err();
+        let missing_parts = error.downcast_ref::<MissingPartsError>().unwrap();
+        assert_eq!(missing_parts.session_id, SESSION_ID);
+        assert_eq!(missing_parts.total, 4);
+        assert_eq!(missing_parts.missing, vec![0, 3]);
+        // Numbered from 1 for whoever scans them
+        assert_eq!(
+            missing_parts.to_string(),
+            "Session 1234abcd is incomplete: missing part(s) 1, 4 out of 4"
+        );
+    }
+
+    #[test]
+    fn parts_of_different_sessions_are_not_mixed() {
+        let parts = split_payload(&payload(), SESSION_ID, 2).unwrap();
+        let other_parts = split_payload(&payload(), SESSION_ID + 1, 2).unwrap();
+        assert!(reassemble_parts(&[parts[0].clone(), other_parts[1].clone()]).is_err());
+        assert!(reassemble_parts(&[]).is_err());
+    }
+}
@@ -1,29 +1,261 @@
 use anyhow::{Context, Result};
 use log::info;
 
-use crate::{boot_config::BootConfig, system::rm_dir_all};
+use crate::secret::Secret;
+use crate::{
+    boot_config::{self, BootConfig},
+    notifier, pin, storage_encryption,
+    system::rm_dir_all,
+};
 
+use std::fs;
 use std::sync::{Arc, Mutex};
 
+// Wrong passcodes in a row after which the recovery menu stays locked until the next boot
+pub const PASSCODE_MAX_ATTEMPTS: u32 = 3;
+const PASSCODE_MIN_LENGTH: usize = 4;
+
 pub fn soft_reset(boot_config: Arc<Mutex<BootConfig>>) -> Result<()> {
     info!("Starting soft reset process");
+    let notifier = notifier::get();
+
+    notifier.toast("Removing root filesystem changes");
+    reset_system_changes()?;
+
+    notifier.toast("Removing user data");
+
+    rm_dir_all(&system_home_dir_path(&crate::MAIN_PART_MOUNTPOINT))
+        .with_context(|| "Failed to remove system home directory")?;
+
+    reset_to_defaults(&mut boot_config.lock().unwrap());
+
+    Ok(())
+}
+
+fn system_home_dir_path(main_part_mountpoint: &str) -> String {
+    format!("{}{}", &main_part_mountpoint, &crate::SYSTEM_HOME_DIR)
+}
+
+// The device ID is never regenerated, and the recovery passcode would otherwise be removed by anyone getting past it once
+fn reset_to_defaults(boot_config: &mut BootConfig) {
+    let device_id = boot_config.system.device_id.take();
+    let recovery_passcode_hash = boot_config.system.recovery_passcode_hash.take();
+    *boot_config = BootConfig::default_boot_config();
+    boot_config.system.device_id = device_id;
+    boot_config.system.recovery_passcode_hash = recovery_passcode_hash;
+}
+
+// Only the root filesystem's write layer: user data and settings are left alone
+pub fn reset_system_changes() -> Result<()> {
+    reset_system_changes_in(&crate::MAIN_PART_MOUNTPOINT)
+}
 
+fn reset_system_changes_in(main_part_mountpoint: &str) -> Result<()> {
+    info!("Removing root filesystem changes");
     rm_dir_all(&format!(
         "{}{}{}",
-        &crate::MAIN_PART_MOUNTPOINT,
+        &main_part_mountpoint,
         &crate::SYSTEM_DIR,
         &crate::ROOTFS_DIR
     ))
-    .with_context(|| "Failed to remove rootfs write cache directory")?;
+    .with_context(|| "Failed to remove rootfs write cache directory")
+}
 
-    rm_dir_all(&format!(
-        "{}{}",
-        &crate::MAIN_PART_MOUNTPOINT,
-        &crate::SYSTEM_HOME_DIR
-    ))
-    .with_context(|| "Failed to remove system home directory")?;
+// Defaults are written right away: the device ID would otherwise only survive in memory until the next reboot
+pub fn reset_boot_config(boot_config: &mut BootConfig) -> Result<()> {
+    reset_boot_config_in(&crate::BOOT_PART_MOUNTPOINT, boot_config)
+}
+
+fn reset_boot_config_in(boot_part_mountpoint: &str, boot_config: &mut BootConfig) -> Result<()> {
+    info!("Resetting boot configuration");
+    BootConfig::remove_stored_at(&boot_part_mountpoint)?;
+    reset_to_defaults(boot_config);
+    BootConfig::write_at(&boot_part_mountpoint, &boot_config, false)
+        .with_context(|| "Failed to write default boot configuration")?;
+    boot_config::clear_session_changes();
+
+    crate::system::sync_disks()
+}
+
+// Encrypted storage is destroyed user by user first, configuration files included, before whatever else is in the home directory
+pub fn erase_user_homes(boot_config: &mut BootConfig) -> Result<()> {
+    erase_user_homes_in(&crate::MAIN_PART_MOUNTPOINT, |user| {
+        storage_encryption::destroy_vault(boot_config, &user)
+    })
+}
+
+// Nothing else is removed once destroying a user's storage failed
+fn erase_user_homes_in(
+    main_part_mountpoint: &str,
+    mut destroy_vault: impl FnMut(&str) -> Result<()>,
+) -> Result<()> {
+    let system_home_dir_path = system_home_dir_path(&main_part_mountpoint);
+    if !fs::exists(&system_home_dir_path)? {
+        return Ok(());
+    }
+    for user in storage_encryption::get_users_in(&system_home_dir_path)? {
+        destroy_vault(&user)?;
+    }
+    rm_dir_all(&system_home_dir_path).with_context(|| "Failed to remove system home directory")?;
+
+    crate::system::sync_disks()
+}
 
-    *boot_config.lock().unwrap() = BootConfig::default_boot_config();
+// Entered on the PIN keypad
+fn validate_passcode(passcode: &Secret) -> Result<()> {
+    let passcode_length = passcode.expose().chars().count();
+    if passcode_length < PASSCODE_MIN_LENGTH
+        || passcode_length > pin::DEFAULT_PIN_MAX_LENGTH
+        || !passcode
+            .expose()
+            .chars()
+            .all(|character| character.is_ascii_digit())
+    {
+        return Err(anyhow::anyhow!(
+            "Recovery passcode must be made of {} to {} digits",
+            &PASSCODE_MIN_LENGTH,
+            &pin::DEFAULT_PIN_MAX_LENGTH
+        ));
+    }
 
     Ok(())
 }
+
+pub fn verify_passcode(passcode: &Secret, stored_hash: &str) -> Result<bool> {
+    pin::verify_pin(&passcode, &stored_hash).with_context(|| "Failed to verify recovery passcode")
+}
+
+// The current passcode must have been checked beforehand: None removes it
+pub fn set_passcode(boot_config: &mut BootConfig, passcode: Option<&Secret>) -> Result<()> {
+    let passcode_hash = match passcode {
+        Some(passcode) => {
+            validate_passcode(&passcode)?;
+            info!("Setting recovery passcode");
+            Some(pin::hash_pin(&passcode).with_context(|| "Failed to hash recovery passcode")?)
+        }
+        None => {
+            info!("Removing recovery passcode");
+            None
+        }
+    };
+    boot_config.system.recovery_passcode_hash = passcode_hash.clone();
+    BootConfig::update_stored(|stored_boot_config| {
+        stored_boot_config.system.recovery_passcode_hash = passcode_hash;
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    // Same layout as MAIN_PART: one user with encrypted storage, one without, and the root filesystem's write layer
+    fn main_part() -> TempDir {
+        let main_part = TempDir::new();
+        main_part.write("home/.alice/gocryptfs.conf", b"{}");
+        main_part.write("home/.alice/ciphertext", b"encrypted");
+        main_part.write("home/.bob/encryption_disabled", b"");
+        main_part.write("home/alice/notes.txt", b"plain");
+        main_part.write("home/.not-a-user/file", b"");
+        main_part.write("system/rootfs/etc/hostname", b"pinenote");
+        main_part.write("system/journal.ron", b"()");
+
+        main_part
+    }
+
+    fn main_part_mountpoint(main_part: &TempDir) -> String {
+        format!("{}/", &main_part.path())
+    }
+
+    #[test]
+    fn system_changes_reset_leaves_the_rest_alone() {
+        let main_part = main_part();
+        reset_system_changes_in(&main_part_mountpoint(&main_part)).unwrap();
+        assert!(!fs::exists(main_part.join("system/rootfs")).unwrap());
+        assert!(fs::exists(main_part.join("system/journal.ron")).unwrap());
+        assert!(fs::exists(main_part.join("home/alice/notes.txt")).unwrap());
+        // Nothing left to remove is not an error
+        reset_system_changes_in(&main_part_mountpoint(&main_part)).unwrap();
+    }
+
+    #[test]
+    fn every_user_is_destroyed_before_the_home_directory_goes() {
+        let main_part = main_part();
+        let mut destroyed = Vec::new();
+        erase_user_homes_in(&main_part_mountpoint(&main_part), |user| {
+            // Storage is still there while each user is destroyed
+            assert!(fs::exists(main_part.join(&format!("home/.{}", &user))).unwrap());
+            destroyed.push(user.to_string());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(destroyed, vec!["alice", "bob"]);
+        assert!(!fs::exists(main_part.join("home")).unwrap());
+        assert!(fs::exists(main_part.join("system/rootfs")).unwrap());
+    }
+
+    #[test]
+    fn failed_user_erase_stops_the_reset() {
+        let main_part = main_part();
+        let mut attempts = 0;
+        assert!(
+            erase_user_homes_in(&main_part_mountpoint(&main_part), |_user| {
+                attempts += 1;
+                Err(anyhow::anyhow!("Encrypted storage is in use"))
+            })
+            .is_err()
+        );
+        assert_eq!(attempts, 1);
+        assert!(fs::exists(main_part.join("home/.bob/encryption_disabled")).unwrap());
+        assert!(fs::exists(main_part.join("home/alice/notes.txt")).unwrap());
+    }
+
+    #[test]
+    fn missing_home_directory_is_already_erased() {
+        let main_part = TempDir::new();
+        erase_user_homes_in(&main_part_mountpoint(&main_part), |_user| {
+            panic!("There is no user to destroy")
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn defaults_keep_the_device_id_and_recovery_passcode() {
+        let mut boot_config = BootConfig::default_boot_config();
+        boot_config.system.device_id = Some("device".to_string());
+        boot_config.system.recovery_passcode_hash = Some("hash".to_string());
+        boot_config.system.timezone = "Europe/Paris".to_string();
+        boot_config.flags.first_boot_done = true;
+        reset_to_defaults(&mut boot_config);
+        assert_eq!(boot_config.system.device_id.as_deref(), Some("device"));
+        assert_eq!(
+            boot_config.system.recovery_passcode_hash.as_deref(),
+            Some("hash")
+        );
+        assert_eq!(boot_config.system.timezone, "UTC");
+        assert!(!boot_config.flags.first_boot_done);
+    }
+
+    #[test]
+    fn reset_boot_config_is_stored_with_the_device_id() {
+        let boot_part = TempDir::new();
+        let mut boot_config = BootConfig::default_boot_config();
+        boot_config.system.device_id = Some("device".to_string());
+        boot_config.system.recovery_passcode_hash = Some("hash".to_string());
+        boot_config.system.timezone = "Europe/Paris".to_string();
+        BootConfig::write_at(&boot_part.path(), &boot_config, false).unwrap();
+        boot_part.write("boot_config.ron.bak", b"()");
+
+        reset_boot_config_in(&boot_part.path(), &mut boot_config).unwrap();
+        let stored = BootConfig::peek_at(&boot_part.path()).unwrap();
+        assert_eq!(stored.system.device_id.as_deref(), Some("device"));
+        assert_eq!(
+            stored.system.recovery_passcode_hash.as_deref(),
+            Some("hash")
+        );
+        assert_eq!(stored.system.timezone, "UTC");
+        assert!(!fs::exists(boot_part.join("boot_config.ron.bak")).unwrap());
+    }
+}
@@ -1,12 +1,30 @@
 use anyhow::{Context, Result};
-use log::{debug, info};
+use log::{debug, error, info, warn};
 use openssl::pkey::PKey;
 use openssl::pkey::Public;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
 use std::fs;
-use sys_mount::Mount;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, symlink};
+use std::path::{Component, Path};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::Mutex;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+use sys_mount::{Mount, MountFlags};
+use walkdir::WalkDir;
 
-use crate::signing::check_signature;
-use crate::system::{self, bind_mount, bulletproof_unmount, rm_dir_all, run_command};
+use crate::boot_config::{BindMount, BootConfig, RootFS};
+use crate::install_journal::{self, InstallIntent};
+use crate::notifier::{self, UiNotifier};
+use crate::secret::Secret;
+use crate::signing::{self, check_signature_with_progress};
+use crate::system::{
+    self, OutputTail, ProgressCallback, bind_mount, bulletproof_unmount, command_failed,
+    rm_dir_all, run_command, run_command_with_stdin, run_command_with_timeout, sha256_digest,
+    sync_disks,
+};
 
 pub const ROOTFS_MOUNTED_PROGRESS_VALUE: f32 = 0.1;
 const RO_DIR: &str = "read/";
@@ -16,21 +34,738 @@ const RW_FIRMWARE_WRITE_DIR: &str = "write-firmware/";
 const RW_WORK_DIR: &str = "work/";
 const RW_MODULES_WORK_DIR: &str = "work-modules/";
 const RW_FIRMWARE_WORK_DIR: &str = "work-firmware/";
+const VERIFICATION_PROGRESS_STEP: u64 = 25;
+// A slot that failed to complete this many boots in a row is abandoned for the other one
+pub const MAX_BOOT_ATTEMPTS: u32 = 3;
+const VERITY_METADATA_SUFFIX: &str = ".verity";
+const VERITYSETUP_BINARY_PATH: &str = "/sbin/veritysetup";
+const VERITY_DEVICE_NAME: &str = "rootfs";
+const VERITY_DEVICE_PATH: &str = "/dev/mapper/rootfs";
+const WHITEOUT_PREFIX: &str = ".wh.";
+const OPAQUE_MARKER: &str = ".wh..wh..opq";
+// Next to the persistent write layer: what is carried over to volatile storage waits there until the next boot
+const STAGED_WRITE_LAYER_DIR: &str = "staged-write/";
+const FUSE_OVERLAYFS_BINARY_PATH: &str = "/usr/bin/fuse-overlayfs";
+const FUSE_MODULE: &str = "fuse";
+const PROC_FILESYSTEMS_PATH: &str = "/proc/filesystems";
+const CHROOT_BINARY_PATH: &str = "/usr/sbin/chroot";
+const CHPASSWD_BINARY_PATH: &str = "/usr/sbin/chpasswd";
+// Filesystem type (also used as source), target relative to the overlay's root and mount options, in mounting order
+const OVERLAY_MOUNTS: &[(&str, &str, &str)] = &[
+    ("proc", "proc", ""),
+    ("sysfs", "sys", ""),
+    ("cgroup2", "sys/fs/cgroup", "nsdelegate"),
+    ("tmpfs", "tmp", ""),
+    ("tmpfs", "run", ""),
+    ("devtmpfs", "dev", ""),
+    // Matches what systemd would mount itself
+    ("devpts", "dev/pts", "gid=5,mode=620,ptmxmode=000"),
+    ("tmpfs", "dev/shm", "mode=1777"),
+];
+// On the boot partition: every script needs a detached signature next to it, like any other archive
+const FIRST_BOOT_SCRIPTS_DIR: &str = "first_boot.d";
+// Inside the overlay, where verified copies of the scripts are run from
+const FIRST_BOOT_SCRIPTS_RUN_DIR: &str = "run/first_boot.d";
+const DEFAULT_OVERLAY_MEMORY_PERCENT: u64 = 50;
+const DEFAULT_WRITE_LAYER_WARNING_MB: u64 = 2048;
+const WRITE_LAYER_CHECK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+// Shipped alongside an archive: they are copied when the update has them, and removed otherwise so that they never describe the wrong archive
+const UPDATE_COMPANION_SUFFIXES: &[&str] = &[".dgst", ".verity", ".verity.dgst"];
 
-pub fn setup(pubkey: &PKey<Public>, persistent: bool) -> Result<()> {
-    info!("Mounting root filesystem SquashFS archive");
-    let rootfs_file_path = format!(
+static ACTIVE_SLOT: Mutex<Option<Slot>> = Mutex::new(None);
+// Slot that was abandoned during this boot, if any
+static SLOT_FALLBACK: Mutex<Option<Slot>> = Mutex::new(None);
+// Backend of the root filesystem overlay currently mounted, if any
+static OVERLAY_BACKEND: Mutex<Option<OverlayBackend>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum OverlayBackend {
+    Kernel,
+    Fuse,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum Slot {
+    A,
+    B,
+}
+
+impl Slot {
+    pub fn other(&self) -> Slot {
+        match self {
+            Slot::A => Slot::B,
+            Slot::B => Slot::A,
+        }
+    }
+
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            Slot::A => "rootfs_a.squashfs",
+            Slot::B => "rootfs_b.squashfs",
+        }
+    }
+}
+
+// Falls back to the single rootfs.squashfs file when there is no slot
+pub fn rootfs_file_path(slot: Option<Slot>) -> String {
+    format!(
         "{}/{}/{}",
         &crate::MAIN_PART_MOUNTPOINT,
         &crate::SYSTEM_DIR,
-        &crate::ROOTFS_FILE
+        slot.map(|slot| slot.file_name())
+            .unwrap_or(&crate::ROOTFS_FILE)
+    )
+}
+
+pub fn active_rootfs_file_path() -> String {
+    rootfs_file_path(*ACTIVE_SLOT.lock().unwrap())
+}
+
+pub fn available_slots() -> Vec<Slot> {
+    [Slot::A, Slot::B]
+        .into_iter()
+        .filter(|slot| fs::exists(&rootfs_file_path(Some(*slot))).unwrap_or(false))
+        .collect()
+}
+
+// Called early in every boot: counts this boot as an attempt of the active slot, switching slots first if it failed too many times
+// The attempt is written right away, since a boot that hangs never gets another chance to record it
+pub fn select_slot(boot_config: &mut BootConfig) -> Result<Option<Slot>> {
+    let slots = available_slots();
+    let Some(first_slot) = slots.first() else {
+        boot_config.rootfs.active_slot = None;
+        *ACTIVE_SLOT.lock().unwrap() = None;
+        return Ok(None);
+    };
+
+    let mut active_slot = boot_config
+        .rootfs
+        .active_slot
+        .filter(|slot| slots.contains(&slot))
+        .unwrap_or(*first_slot);
+    if boot_config.rootfs.boot_attempts >= MAX_BOOT_ATTEMPTS && slots.contains(&active_slot.other())
+    {
+        warn!(
+            "Root filesystem slot {:?} failed to boot {} times: switching to slot {:?}",
+            &active_slot,
+            &boot_config.rootfs.boot_attempts,
+            &active_slot.other()
+        );
+        *SLOT_FALLBACK.lock().unwrap() = Some(active_slot);
+        active_slot = active_slot.other();
+        boot_config.rootfs.boot_attempts = 0;
+        // Counted for the slot that used to be active
+        boot_config.rootfs.systemd_targets_total = None;
+    }
+    info!("Booting root filesystem slot {:?}", &active_slot);
+    boot_config.rootfs.active_slot = Some(active_slot);
+    boot_config.rootfs.boot_attempts += 1;
+    *ACTIVE_SLOT.lock().unwrap() = Some(active_slot);
+    BootConfig::write(&boot_config, false)?;
+
+    Ok(Some(active_slot))
+}
+
+pub fn slot_fallback_notice() -> Option<String> {
+    SLOT_FALLBACK.lock().unwrap().map(|failed_slot| {
+        format!(
+            "Root filesystem slot {:?} failed to boot {} times: now using slot {:?}",
+            &failed_slot,
+            &MAX_BOOT_ATTEMPTS,
+            &failed_slot.other()
+        )
+    })
+}
+
+// Image generation: mksquashfs pads the archive to 4 KiB blocks, after which the hash tree is appended to the same file with
+//     veritysetup format rootfs.squashfs rootfs.squashfs --hash-offset=<archive size> --data-blocks=<archive size / 4096>
+// The resulting root hash goes into 'rootfs.squashfs.verity' (this structure, as RON), which is signed like any other artifact ('rootfs.squashfs.verity.dgst')
+// The archive itself must still be signed as a whole: that signature is what is checked when verity is disabled
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct VerityMetadata {
+    // Hexadecimal
+    pub root_hash: String,
+    // Size of the SquashFS data in bytes, i.e. where the hash tree starts
+    pub hash_offset: u64,
+    // In 4096-byte blocks
+    pub data_blocks: u64,
+}
+
+// Returns None if the image has no verity metadata; metadata that is present but untrusted is an error
+fn read_verity_metadata(
+    pubkey: &PKey<Public>,
+    rootfs_file_path: &str,
+) -> Result<Option<VerityMetadata>> {
+    let verity_metadata_path = format!("{}{}", &rootfs_file_path, &VERITY_METADATA_SUFFIX);
+    if !fs::exists(&verity_metadata_path)? {
+        return Ok(None);
+    }
+    // The root hash is only as trustworthy as this signature: checking it is cheap since the file is tiny
+    if !signing::check_signature(&pubkey, &verity_metadata_path)? {
+        return Err(anyhow::anyhow!(
+            "Root filesystem verity metadata is not trusted: invalid signature for '{}'",
+            &verity_metadata_path
+        ));
+    }
+    let verity_metadata = ron::from_str::<VerityMetadata>(
+        &fs::read_to_string(&verity_metadata_path)
+            .with_context(|| format!("Failed to read '{}'", &verity_metadata_path))?,
+    )
+    .with_context(|| format!("Failed to parse '{}'", &verity_metadata_path))?;
+    if verity_metadata.root_hash.is_empty()
+        || !verity_metadata
+            .root_hash
+            .chars()
+            .all(|c| c.is_ascii_hexdigit())
+    {
+        return Err(anyhow::anyhow!(
+            "Invalid root hash in '{}'",
+            &verity_metadata_path
+        ));
+    }
+
+    Ok(Some(verity_metadata))
+}
+
+// veritysetup checks the hash tree against the signed root hash before creating the device; blocks are then verified as they are read
+fn mount_verity(
+    rootfs_file_path: &str,
+    verity_metadata: &VerityMetadata,
+    mountpoint: &str,
+) -> Result<()> {
+    info!("Setting up dm-verity device for root filesystem");
+    let loop_device_path = system::attach_loop_device(&rootfs_file_path)?;
+    if let Err(e) = run_command_with_timeout(
+        &VERITYSETUP_BINARY_PATH,
+        &[
+            "open",
+            &loop_device_path,
+            &VERITY_DEVICE_NAME,
+            &loop_device_path,
+            &verity_metadata.root_hash,
+            &format!("--hash-offset={}", &verity_metadata.hash_offset),
+            &format!("--data-blocks={}", &verity_metadata.data_blocks),
+        ],
+        system::MOUNT_TIMEOUT,
+    ) {
+        let _ = system::detach_loop_device(&loop_device_path);
+        return Err(e).with_context(|| "Failed to open root filesystem verity device");
+    }
+
+    Mount::builder()
+        .fstype("squashfs")
+        .flags(MountFlags::RDONLY)
+        .mount(&VERITY_DEVICE_PATH, &mountpoint)
+        .with_context(|| {
+            format!(
+                "Failed to mount '{}' at '{}'",
+                &VERITY_DEVICE_PATH, &mountpoint
+            )
+        })?;
+
+    Ok(())
+}
+
+fn close_verity() -> Result<()> {
+    if fs::exists(&VERITY_DEVICE_PATH)? {
+        run_command(&VERITYSETUP_BINARY_PATH, &["close", &VERITY_DEVICE_NAME])
+            .with_context(|| "Failed to close root filesystem verity device")?;
+    }
+
+    Ok(())
+}
+
+fn overlay_tmpfs_options(rootfs_config: &RootFS) -> String {
+    let percent = rootfs_config
+        .overlay_memory_percent
+        .unwrap_or(DEFAULT_OVERLAY_MEMORY_PERCENT)
+        .clamp(1, 100);
+    match system::available_memory() {
+        Ok(available_memory) => format!("size={}k", available_memory * percent / 100 / 1024),
+        Err(e) => {
+            // tmpfs understands percentages, of total memory instead
+            warn!("{}: limiting overlay to {} % of total memory", &e, &percent);
+            format!("size={}%", &percent)
+        }
+    }
+}
+
+fn persistent_rw_dir_path_base() -> String {
+    persistent_rw_dir_path_base_in(&crate::MAIN_PART_MOUNTPOINT)
+}
+
+fn persistent_rw_dir_path_base_in(main_part_mountpoint: &str) -> String {
+    format!(
+        "{}/{}/{}",
+        &main_part_mountpoint,
+        &crate::SYSTEM_DIR,
+        &crate::ROOTFS_DIR,
+    )
+}
+
+fn write_layer_dir_path() -> String {
+    format!("{}/{}", &persistent_rw_dir_path_base(), &RW_WRITE_DIR)
+}
+
+// Whiteouts and opaque markers are always empty regular files (fuse-overlayfs) or 0:0 character devices (kernel overlayfs)
+fn is_corrupted_whiteout(name: &str, metadata: &fs::Metadata) -> bool {
+    name.starts_with(&WHITEOUT_PREFIX)
+        && !(metadata.is_file() && metadata.len() == 0)
+        && !(metadata.file_type().is_char_device() && metadata.rdev() == 0)
+}
+
+// A whiteout sitting next to the very entry it hides comes from a rename that was interrupted halfway: the entry is kept
+fn is_stale_whiteout(path: &Path, name: &str) -> bool {
+    name != OPAQUE_MARKER
+        && name
+            .strip_prefix(&WHITEOUT_PREFIX)
+            .is_some_and(|hidden_name| path.with_file_name(&hidden_name).symlink_metadata().is_ok())
+}
+
+// Only meant for the persistent write layer, before it is mounted: overlayfs expects empty work directories anyway, so clearing them is always safe
+pub fn check_write_layer() -> Result<()> {
+    info!("Checking persistent write layer");
+    let rw_dir_path_base = persistent_rw_dir_path_base();
+    let mut repairs = Vec::new();
+
+    for work_dir in [RW_WORK_DIR, RW_MODULES_WORK_DIR, RW_FIRMWARE_WORK_DIR] {
+        let work_dir_path = format!("{}/{}", &rw_dir_path_base, &work_dir);
+        let is_empty = fs::read_dir(&work_dir_path)
+            .map(|mut entries| entries.next().is_none())
+            .unwrap_or(true);
+        if !is_empty {
+            debug!("Clearing overlay work directory '{}'", &work_dir_path);
+            rm_dir_all(&work_dir_path)
+                .with_context(|| format!("Failed to clear '{}'", &work_dir_path))?;
+            fs::create_dir_all(&work_dir_path)?;
+            repairs.push(format!("cleared {}", work_dir.trim_end_matches('/')));
+        }
+    }
+
+    let mut corrupted_entries = Vec::new();
+    for write_dir in [RW_WRITE_DIR, RW_MODULES_WRITE_DIR, RW_FIRMWARE_WRITE_DIR] {
+        let write_dir_path = format!("{}/{}", &rw_dir_path_base, &write_dir);
+        if !Path::new(&write_dir_path).exists() {
+            continue;
+        }
+        let mut walker = WalkDir::new(&write_dir_path)
+            .follow_links(false)
+            .into_iter();
+        while let Some(entry) = walker.next() {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    warn!("Failed to check write layer entry: {}", &e);
+                    continue;
+                }
+            };
+            let name = entry.file_name().to_string_lossy();
+            let Ok(metadata) = entry.path().symlink_metadata() else {
+                continue;
+            };
+            if is_corrupted_whiteout(&name, &metadata) || is_stale_whiteout(entry.path(), &name) {
+                if metadata.is_dir() {
+                    walker.skip_current_dir();
+                }
+                corrupted_entries.push(entry.into_path());
+            }
+        }
+    }
+    // Removed once walking is done, so that entries do not vanish from under the walker
+    let mut removed_entries = 0;
+    for path in &corrupted_entries {
+        warn!("Removing corrupted write layer entry '{}'", &path.display());
+        let result = if path
+            .symlink_metadata()
+            .is_ok_and(|metadata| metadata.is_dir())
+        {
+            fs::remove_dir_all(&path)
+        } else {
+            fs::remove_file(&path)
+        };
+        match result {
+            Ok(()) => removed_entries += 1,
+            Err(e) => warn!("Failed to remove '{}': {}", &path.display(), &e),
+        }
+    }
+    if removed_entries > 0 {
+        repairs.push(format!("removed {} corrupted entries", &removed_entries));
+    }
+
+    if !repairs.is_empty() {
+        info!("Repaired persistent write layer: {}", repairs.join(", "));
+        notifier::get().toast(&format!(
+            "Repaired persistent storage: {}",
+            repairs.join(", ")
+        ));
+    }
+
+    Ok(())
+}
+
+// Persistent storage is only bounded by the main partition: periodically warns when the write layer gets larger than configured
+// A warning is only sent again once the write layer went back under the threshold in the meantime
+pub fn monitor_write_layer(rootfs_config: &RootFS, toast_sender: Sender<String>) -> Result<()> {
+    let threshold_mb = rootfs_config
+        .write_layer_warning_mb
+        .unwrap_or(DEFAULT_WRITE_LAYER_WARNING_MB);
+    let write_dir_path = write_layer_dir_path();
+    let mut warned = false;
+    loop {
+        match system::dir_size(&write_dir_path) {
+            Ok(size) => {
+                let size_mb = size / 1_000_000;
+                debug!("Persistent write layer size: {} MB", &size_mb);
+                if size_mb > threshold_mb {
+                    if !warned {
+                        warn!(
+                            "Persistent write layer is {} MB, more than the {} MB threshold",
+                            &size_mb, &threshold_mb
+                        );
+                        toast_sender.send(format!(
+                            "Persistent storage uses {} MB: consider cleaning it up",
+                            &size_mb
+                        ))?;
+                        warned = true;
+                    }
+                } else {
+                    warned = false;
+                }
+            }
+            Err(e) => warn!("Failed to measure persistent write layer: {}", &e),
+        }
+        thread::sleep(WRITE_LAYER_CHECK_INTERVAL);
+    }
+}
+
+fn write_layer_dir_paths(persistent: bool) -> Vec<(&'static str, String)> {
+    write_layer_dir_paths_in(
+        &crate::MAIN_PART_MOUNTPOINT,
+        &crate::OVERLAY_WORKDIR,
+        persistent,
+    )
+}
+
+fn write_layer_dir_paths_in(
+    main_part_mountpoint: &str,
+    overlay_workdir: &str,
+    persistent: bool,
+) -> Vec<(&'static str, String)> {
+    let base = if persistent {
+        persistent_rw_dir_path_base_in(&main_part_mountpoint)
+    } else {
+        overlay_workdir.to_string()
+    };
+    [RW_WRITE_DIR, RW_MODULES_WRITE_DIR, RW_FIRMWARE_WRITE_DIR]
+        .into_iter()
+        .map(|write_dir| (write_dir, format!("{}/{}", &base, &write_dir)))
+        .collect()
+}
+
+fn staged_write_layer_dir_path() -> String {
+    staged_write_layer_dir_path_in(&crate::MAIN_PART_MOUNTPOINT)
+}
+
+fn staged_write_layer_dir_path_in(main_part_mountpoint: &str) -> String {
+    format!(
+        "{}/{}",
+        &persistent_rw_dir_path_base_in(&main_part_mountpoint),
+        &STAGED_WRITE_LAYER_DIR
+    )
+}
+
+// Lets the user choose whether root filesystem changes follow the persistent storage toggle, instead of silently leaving them behind
+pub fn offer_write_layer_migration(from_persistent: bool, to_persistent: bool) {
+    offer_write_layer_migration_in(
+        &crate::MAIN_PART_MOUNTPOINT,
+        &crate::OVERLAY_WORKDIR,
+        from_persistent,
+        to_persistent,
+        notifier::get(),
+    )
+}
+
+fn offer_write_layer_migration_in(
+    main_part_mountpoint: &str,
+    overlay_workdir: &str,
+    from_persistent: bool,
+    to_persistent: bool,
+    notifier: &dyn UiNotifier,
+) {
+    let size = match check_write_layer_migration_in(
+        &main_part_mountpoint,
+        &overlay_workdir,
+        from_persistent,
+        to_persistent,
+    ) {
+        Ok(0) => return,
+        Ok(size) => size,
+        Err(e) => {
+            error!("{:?}", &e);
+            notifier.toast(&format!(
+                "Root filesystem changes cannot be carried over: {}",
+                &e
+            ));
+            return;
+        }
+    };
+    let destination = if to_persistent {
+        "persistent storage"
+    } else {
+        "memory"
+    };
+    if notifier.ask_confirmation(&format!(
+        "Carry the {} MB of root filesystem changes over to {}? Otherwise, they will be dropped on the next boot.",
+        size / 1_000_000,
+        &destination
+    )) {
+        if let Err(e) = migrate_write_layer_in(
+            &main_part_mountpoint,
+            &overlay_workdir,
+            from_persistent,
+            to_persistent,
+            notifier,
+        ) {
+            error!("{:?}", &e);
+            notifier.toast("Failed to carry over root filesystem changes");
+        }
+    } else {
+        info!("Root filesystem changes will not be carried over");
+    }
+}
+
+// Returns how much would be carried over, refusing when it does not fit where it would go
+// The volatile write layer only exists while its tmpfs is mounted, i.e. not before the overlay is set up
+fn check_write_layer_migration_in(
+    main_part_mountpoint: &str,
+    overlay_workdir: &str,
+    from_persistent: bool,
+    to_persistent: bool,
+) -> Result<u64> {
+    if from_persistent == to_persistent {
+        return Ok(0);
+    }
+    let mut size = 0;
+    for (_, write_dir_path) in
+        write_layer_dir_paths_in(&main_part_mountpoint, &overlay_workdir, from_persistent)
+    {
+        if Path::new(&write_dir_path).exists() {
+            size += system::dir_size(&write_dir_path)?;
+        }
+    }
+    if size == 0 {
+        return Ok(0);
+    }
+
+    if !to_persistent {
+        let available_memory = system::available_memory()?;
+        if size > available_memory {
+            return Err(anyhow::anyhow!(
+                "Root filesystem changes ({} MB) are larger than available memory ({} MB)",
+                size / 1_000_000,
+                available_memory / 1_000_000
+            ));
+        }
+    }
+    // Carrying over to volatile storage goes through a staged copy on the main partition too
+    let available_space = system::disk_usage(&main_part_mountpoint)?.available;
+    if size > available_space {
+        return Err(anyhow::anyhow!(
+            "Root filesystem changes ({} MB) are larger than available space on the main partition ({} MB)",
+            size / 1_000_000,
+            available_space / 1_000_000
+        ));
+    }
+
+    Ok(size)
+}
+
+// The persistent write layer is left as is when carrying it over to volatile storage: it is staged, then copied to memory by the next boot's setup()
+// Kernel overlayfs whiteouts are special files, which are not copied: files deleted from the root filesystem may show up again
+fn migrate_write_layer_in(
+    main_part_mountpoint: &str,
+    overlay_workdir: &str,
+    from_persistent: bool,
+    to_persistent: bool,
+    notifier: &dyn UiNotifier,
+) -> Result<()> {
+    let size = check_write_layer_migration_in(
+        &main_part_mountpoint,
+        &overlay_workdir,
+        from_persistent,
+        to_persistent,
+    )?;
+    if size == 0 {
+        info!("Write layer is empty: nothing to carry over");
+        return Ok(());
+    }
+    notifier.toast(&format!(
+        "Carrying over root filesystem changes ({} MB)",
+        size / 1_000_000
+    ));
+
+    let target_base = if to_persistent {
+        persistent_rw_dir_path_base_in(&main_part_mountpoint)
+    } else {
+        staged_write_layer_dir_path_in(&main_part_mountpoint)
+    };
+    let copy_options = system::CopyOptions {
+        mode: system::CopyMode::Merge,
+        preserve_xattrs: true,
+    };
+    for (write_dir, write_dir_path) in
+        write_layer_dir_paths_in(&main_part_mountpoint, &overlay_workdir, from_persistent)
+    {
+        if !Path::new(&write_dir_path).exists() {
+            continue;
+        }
+        let target_path = format!("{}/{}", &target_base, &write_dir);
+        fs::create_dir_all(&target_path)?;
+        system::copy_dir_recursively(&write_dir_path, &target_path, &copy_options)
+            .with_context(|| format!("Failed to carry over '{}'", &write_dir_path))?;
+    }
+    sync_disks()?;
+    notifier.toast("Root filesystem changes were carried over");
+
+    Ok(())
+}
+
+// Consumes what migrate_write_layer_in() staged for the volatile write layer, once its tmpfs is mounted
+fn apply_staged_write_layer() -> Result<()> {
+    let staged_dir_path = staged_write_layer_dir_path();
+    if !Path::new(&staged_dir_path).exists() {
+        return Ok(());
+    }
+    info!("Applying staged root filesystem changes");
+    let copy_options = system::CopyOptions {
+        mode: system::CopyMode::Merge,
+        preserve_xattrs: true,
+    };
+    let result =
+        write_layer_dir_paths(false)
+            .into_iter()
+            .try_for_each(|(write_dir, write_dir_path)| {
+                let staged_path = format!("{}/{}", &staged_dir_path, &write_dir);
+                if !Path::new(&staged_path).exists() {
+                    return Ok(());
+                }
+                system::copy_dir_recursively(&staged_path, &write_dir_path, &copy_options)
+            });
+    // Applied once, whether it worked or not: a staged copy that does not fit would fail the same way on every boot
+    rm_dir_all(&staged_dir_path)
+        .with_context(|| format!("Failed to remove '{}'", &staged_dir_path))?;
+
+    result.with_context(|| "Failed to apply staged root filesystem changes")
+}
+
+fn kernel_overlayfs_supported() -> bool {
+    match fs::read_to_string(&PROC_FILESYSTEMS_PATH) {
+        // e.g. "nodev\toverlay"
+        Ok(filesystems) => filesystems
+            .lines()
+            .any(|line| line.split_whitespace().last() == Some("overlay")),
+        Err(e) => {
+            warn!("Failed to read '{}': {}", &PROC_FILESYSTEMS_PATH, &e);
+            false
+        }
+    }
+}
+
+// Kernel overlayfs avoids the I/O overhead of a userspace daemon, which would also have to survive the chroot handoff
+// fuse-overlayfs remains the fallback, e.g. when the upper directory lives on a filesystem without proper d_type support, which kernel overlayfs refuses
+fn mount_overlay(
+    lower_dir: &str,
+    upper_dir: &str,
+    work_dir: &str,
+    mountpoint: &str,
+    force_fuse: bool,
+) -> Result<OverlayBackend> {
+    let options = format!(
+        "lowerdir={},upperdir={},workdir={}",
+        &lower_dir, &upper_dir, &work_dir
     );
-    if fs::exists(&rootfs_file_path)? && check_signature(&pubkey, &rootfs_file_path)? {
+    if force_fuse {
+        info!("Kernel overlayfs is disabled in boot configuration");
+    } else if !kernel_overlayfs_supported() {
+        info!("Kernel overlayfs is not supported");
+    } else {
+        match Mount::builder()
+            .fstype("overlay")
+            .data(&options)
+            .mount("overlay", &mountpoint)
+        {
+            Ok(_) => return Ok(OverlayBackend::Kernel),
+            Err(e) => warn!(
+                "Failed to mount kernel overlayfs at '{}': {}: falling back to fuse-overlayfs",
+                &mountpoint, &e
+            ),
+        }
+    }
+
+    // May be built into the kernel
+    if let Err(e) = system::kmod::load_module(&FUSE_MODULE, &[]) {
+        warn!("{:?}", &e);
+    }
+    run_command_with_timeout(
+        &FUSE_OVERLAYFS_BINARY_PATH,
+        &["-o", &options, &mountpoint],
+        system::MOUNT_TIMEOUT,
+    )
+    .with_context(|| format!("Failed to mount fuse-overlayfs at '{}'", &mountpoint))?;
+
+    Ok(OverlayBackend::Fuse)
+}
+
+pub fn overlay_backend() -> Option<OverlayBackend> {
+    *OVERLAY_BACKEND.lock().unwrap()
+}
+
+pub fn setup(pubkey: &PKey<Public>, rootfs_config: &RootFS) -> Result<()> {
+    info!("Mounting root filesystem SquashFS archive");
+    // The slot may have been changed from the GUI since it was selected
+    *ACTIVE_SLOT.lock().unwrap() = rootfs_config.active_slot;
+    let rootfs_file_path = rootfs_file_path(rootfs_config.active_slot);
+    let persistent = rootfs_config.persistent_storage;
+    // Verifying a large archive takes a while on this hardware: keep the user informed
+    let mut next_reported_percent = VERIFICATION_PROGRESS_STEP;
+    let mut report_progress = |done: u64, total: u64| {
+        let percent = done * 100 / total.max(1);
+        if percent >= next_reported_percent && percent < 100 {
+            notifier::get().toast(&format!("Verifying root filesystem: {}%", &percent));
+            while next_reported_percent <= percent {
+                next_reported_percent += VERIFICATION_PROGRESS_STEP;
+            }
+        }
+    };
+    let verity_metadata = if rootfs_config.verity && fs::exists(&rootfs_file_path)? {
+        let verity_metadata = read_verity_metadata(&pubkey, &rootfs_file_path)?;
+        if verity_metadata.is_none() {
+            warn!("No verity metadata found for root filesystem: checking its signature instead");
+        }
+        verity_metadata
+    } else {
+        None
+    };
+    if fs::exists(&rootfs_file_path)?
+        && (verity_metadata.is_some()
+            || check_signature_with_progress(
+                &pubkey,
+                &rootfs_file_path,
+                Some(&mut report_progress),
+            )?)
+    {
         fs::create_dir_all(&crate::OVERLAY_WORKDIR)
             .with_context(|| "Failed to create overlay's work directory")?;
         // Necessary to make disk space checks work in chroot (e.g. for package managers)
+        // Without persistent storage, this is also where everything written inside the chroot ends up: a size limit keeps a runaway writer from eating all memory
+        let tmpfs_options = overlay_tmpfs_options(&rootfs_config);
+        info!(
+            "Mounting overlay work directory's tmpfs with options '{}'",
+            &tmpfs_options
+        );
         Mount::builder()
             .fstype("tmpfs")
+            .data(&tmpfs_options)
             .mount("tmpfs", &crate::OVERLAY_WORKDIR)
             .with_context(|| "Failed to mount tmpfs at overlay work directory")?;
 
@@ -44,12 +779,7 @@ pub fn setup(pubkey: &PKey<Public>, persistent: bool) -> Result<()> {
         let rw_modules_work_dir_path;
         let rw_firmware_work_dir_path;
         if persistent {
-            rw_dir_path_base = format!(
-                "{}/{}/{}",
-                &crate::MAIN_PART_MOUNTPOINT,
-                &crate::SYSTEM_DIR,
-                &crate::ROOTFS_DIR,
-            );
+            rw_dir_path_base = persistent_rw_dir_path_base();
             rw_write_dir_path = format!("{}/{}", &rw_dir_path_base, &RW_WRITE_DIR);
             rw_modules_write_dir_path = format!("{}/{}", &rw_dir_path_base, &RW_MODULES_WRITE_DIR);
             rw_firmware_write_dir_path =
@@ -76,73 +806,70 @@ pub fn setup(pubkey: &PKey<Public>, persistent: bool) -> Result<()> {
         fs::create_dir_all(&rw_work_dir_path)?;
         fs::create_dir_all(&rw_modules_work_dir_path)?;
         fs::create_dir_all(&rw_firmware_work_dir_path)?;
+        if !persistent && let Err(e) = apply_staged_write_layer() {
+            error!("{:?}", &e);
+            notifier::get().toast("Failed to carry over root filesystem changes");
+        }
         fs::create_dir_all(&crate::OVERLAY_MOUNTPOINT)
             .with_context(|| "Failed to create overlay mountpoint's directory")?;
 
-        run_command("/bin/mount", &[&rootfs_file_path, &ro_mountpoint])
-            .with_context(|| "Failed to mount root filesystem's SquashFS archive")?;
+        match &verity_metadata {
+            Some(verity_metadata) => {
+                mount_verity(&rootfs_file_path, &verity_metadata, &ro_mountpoint)
+            }
+            None => system::mount_squashfs(&rootfs_file_path, &ro_mountpoint),
+        }
+        .with_context(|| "Failed to mount root filesystem's SquashFS archive")?;
 
+        let force_fuse = rootfs_config.force_fuse;
         info!("Setting up overlay filesystem");
-        run_command(
-            "/bin/mount",
-            &[
-                "-t",
-                "overlay",
-                "-o",
-                &format!(
-                    "lowerdir={},upperdir={},workdir={}",
-                    &ro_mountpoint, &rw_write_dir_path, &rw_work_dir_path
-                ),
-                "none",
-                &crate::OVERLAY_MOUNTPOINT,
-            ],
+        let backend = mount_overlay(
+            &ro_mountpoint,
+            &rw_write_dir_path,
+            &rw_work_dir_path,
+            &crate::OVERLAY_MOUNTPOINT,
+            force_fuse,
         )
         .with_context(|| "Failed to mount overlay filesystem at overlay's mountpoint")?;
+        info!(
+            "Root filesystem overlay is backed by {:?} overlayfs",
+            &backend
+        );
+        *OVERLAY_BACKEND.lock().unwrap() = Some(backend);
         info!("Setting up modules overlay filesystem");
-        run_command(
-            "/bin/mount",
-            &[
-                "-t",
-                "overlay",
-                "-o",
-                &format!(
-                    "lowerdir={},upperdir={},workdir={}",
-                    &system::MODULES_DIR_PATH,
-                    &rw_modules_write_dir_path,
-                    &rw_modules_work_dir_path
-                ),
-                "none",
-                &format!(
-                    "{}/{}",
-                    &crate::OVERLAY_MOUNTPOINT,
-                    &system::MODULES_DIR_PATH
-                ),
-            ],
+        mount_overlay(
+            &system::MODULES_DIR_PATH,
+            &rw_modules_write_dir_path,
+            &rw_modules_work_dir_path,
+            &format!(
+                "{}/{}",
+                &crate::OVERLAY_MOUNTPOINT,
+                &system::MODULES_DIR_PATH
+            ),
+            force_fuse,
         )
         .with_context(|| "Failed to mount overlay filesystem at modules overlay's mountpoint")?;
         info!("Setting up firmware overlay filesystem");
-        run_command(
-            "/bin/mount",
-            &[
-                "-t",
-                "overlay",
-                "-o",
-                &format!(
-                    "lowerdir={},upperdir={},workdir={}",
-                    &system::FIRMWARE_DIR_PATH,
-                    &rw_firmware_write_dir_path,
-                    &rw_firmware_work_dir_path
-                ),
-                "none",
-                &format!(
-                    "{}/{}",
-                    &crate::OVERLAY_MOUNTPOINT,
-                    &system::FIRMWARE_DIR_PATH
-                ),
-            ],
+        mount_overlay(
+            &system::FIRMWARE_DIR_PATH,
+            &rw_firmware_write_dir_path,
+            &rw_firmware_work_dir_path,
+            &format!(
+                "{}/{}",
+                &crate::OVERLAY_MOUNTPOINT,
+                &system::FIRMWARE_DIR_PATH
+            ),
+            force_fuse,
         )
         .with_context(|| "Failed to mount overlay filesystem at firmware overlay's mountpoint")?;
-        setup_mounts()?;
+        setup_mounts(&rootfs_config)?;
+    } else if let Some(failure) = signing::last_signature_failure()
+        && failure.path == rootfs_file_path
+    {
+        return Err(anyhow::anyhow!(
+            "Root filesystem SquashFS archive is not trusted: {}",
+            &failure
+        ));
     } else {
         return Err(anyhow::anyhow!(
             "Either root filesystem SquashFS archive was not found, either its signature was invalid"
@@ -152,13 +879,125 @@ pub fn setup(pubkey: &PKey<Public>, persistent: bool) -> Result<()> {
     Ok(())
 }
 
+// The copy is what ends up being booted, so that is what gets checked, rather than the source
+// Its companion files are staged next to it: the journal only switches them along with the archive once this passed
+fn verify_staged_update(
+    pubkey: &PKey<Public>,
+    intent: &InstallIntent,
+    progress: Option<ProgressCallback>,
+) -> Result<()> {
+    if !check_signature_with_progress(&pubkey, &intent.temp_path, progress)? {
+        return Err(anyhow::anyhow!(
+            "System update '{}' has an invalid signature",
+            &intent.source
+        ));
+    }
+
+    Ok(())
+}
+
+// Installs a new root filesystem archive into the inactive slot, or over the single archive on installs without slots
+// Goes through the install journal: an interrupted or invalid update never replaces what is in place, and is picked up again on the next boot
+// Progress covers the checksum of the source, the copy, its checksum, then the signature check of the copied archive
+pub fn install_update(
+    path: &str,
+    pubkey: &PKey<Public>,
+    rootfs_config: &mut RootFS,
+    progress: Option<ProgressCallback>,
+) -> Result<()> {
+    let digest_path = format!("{}{}", &path, &crate::GENERIC_DIGEST_EXT);
+    if !fs::exists(&digest_path)? {
+        return Err(anyhow::anyhow!(
+            "No signature found for system update '{}'",
+            &path
+        ));
+    }
+    let target_slot = rootfs_config.active_slot.map(|slot| slot.other());
+    let target_path = rootfs_file_path(target_slot);
+    info!("Installing system update '{}' to '{}'", &path, &target_path);
+
+    // The copy and the signature check report progress one after the other
+    let progress = RefCell::new(progress);
+    let report_progress = |done: u64, total: u64| {
+        if let Some(progress) = progress.borrow_mut().as_mut() {
+            progress(done, total);
+        }
+    };
+    // Pins the archive that was picked, so that a copy interrupted by a crash can be resumed from the same source
+    let expected_sha256 = sha256_digest(
+        &path,
+        Some(&mut |done: u64, total: u64| report_progress(done, total * 4)),
+    )?;
+    install_journal::install(
+        &path,
+        &target_path,
+        &expected_sha256,
+        &UPDATE_COMPANION_SUFFIXES,
+        Some(&mut |done: u64, total: u64| report_progress(total / 2 + done, total * 2)),
+        &mut |intent: &InstallIntent| {
+            verify_staged_update(
+                &pubkey,
+                &intent,
+                Some(&mut |done: u64, total: u64| report_progress(total * 3 + done, total * 4)),
+            )
+        },
+    )?;
+    signing::invalidate_cached_verdicts(&target_path);
+
+    rootfs_config.timestamp = fs::metadata(&target_path)
+        .with_context(|| "Failed to retrieve root filesystem SquashFS archive's metadata")?
+        .mtime();
+    // The new archive may not reach the same number of systemd targets: the progress bar recalibrates on its first boot
+    rootfs_config.systemd_targets_total = None;
+    if let Some(target_slot) = target_slot {
+        info!("Root filesystem slot {:?} will be used", &target_slot);
+        rootfs_config.active_slot = Some(target_slot);
+        rootfs_config.boot_attempts = 0;
+    }
+    info!("System update '{}' was installed", &path);
+
+    Ok(())
+}
+
+// Called at every boot, before the root filesystem is mounted
+// A resumed update is checked like any other, but the slot it went to is not switched to: installing it again does that
+pub fn recover_interrupted_update(pubkey: &PKey<Public>) -> Result<()> {
+    install_journal::recover_interrupted_install(&mut |intent: &InstallIntent| {
+        verify_staged_update(&pubkey, &intent, None)
+    })
+}
+
 pub fn tear_down() -> Result<()> {
     info!("Unmounting root filesystem overlay and cleaning up");
+    // Homes are mounted inside the overlay
+    crate::storage_encryption::unmount_all();
 
+    // Detaching the root overlay alone would leave the nested overlays' fuse-overlayfs daemons running until nothing references them anymore
+    if OVERLAY_BACKEND.lock().unwrap().take() == Some(OverlayBackend::Fuse) {
+        for dir in [&system::FIRMWARE_DIR_PATH, &system::MODULES_DIR_PATH] {
+            let mountpoint = format!("{}/{}", &crate::OVERLAY_MOUNTPOINT, &dir);
+            if let Err(e) = bulletproof_unmount(&mountpoint) {
+                warn!("Failed to unmount '{}': {}", &mountpoint, &e);
+            }
+        }
+    }
     bulletproof_unmount(&crate::OVERLAY_MOUNTPOINT)
         .with_context(|| "Failed to unmount root filesystem overlay directory")?;
+    // Along with its loop device: the verity device can only be closed once nothing holds it anymore
+    let ro_mountpoint = format!("{}/{}", &crate::OVERLAY_WORKDIR, &RO_DIR);
+    if system::is_mountpoint(&ro_mountpoint)?
+        && let Err(e) = system::unmount_squashfs(&ro_mountpoint)
+    {
+        warn!(
+            "Failed to unmount root filesystem's SquashFS archive: {:?}",
+            &e
+        );
+    }
     bulletproof_unmount(&format!("{}", &crate::OVERLAY_WORKDIR))
         .with_context(|| "Failed to unmount root filesystem overlay's work directory")?;
+    if let Err(e) = close_verity() {
+        warn!("{:?}", &e);
+    }
     rm_dir_all(&crate::OVERLAY_MOUNTPOINT)
         .with_context(|| "Failed to remove overlay mountpoint's directory")?;
     rm_dir_all(&crate::OVERLAY_WORKDIR)
@@ -167,32 +1006,54 @@ pub fn tear_down() -> Result<()> {
     Ok(())
 }
 
-pub fn setup_mounts() -> Result<()> {
+// Rejects anything that could end up outside of the overlay, or that depends on the current directory
+fn check_bind_mount(bind_mount: &BindMount) -> Result<()> {
+    for path in [&bind_mount.source, &bind_mount.target] {
+        let path = Path::new(&path);
+        if !path.is_absolute()
+            || path
+                .components()
+                .any(|component| component == Component::ParentDir)
+        {
+            return Err(anyhow::anyhow!(
+                "'{}' is not an absolute path",
+                &path.display()
+            ));
+        }
+    }
+    if !fs::exists(&bind_mount.source)? {
+        return Err(anyhow::anyhow!(
+            "Source '{}' does not exist",
+            &bind_mount.source
+        ));
+    }
+
+    Ok(())
+}
+
+pub fn setup_mounts(rootfs_config: &RootFS) -> Result<()> {
     info!("Mounting filesystems in overlay");
 
-    Mount::builder()
-        .fstype("proc")
-        .mount("proc", &format!("{}/proc", &crate::OVERLAY_MOUNTPOINT))
-        .with_context(|| "Failed to mount proc filesystem at overlay's mountpoint")?;
-    Mount::builder()
-        .fstype("sysfs")
-        .mount("sysfs", &format!("{}/sys", &crate::OVERLAY_MOUNTPOINT))
-        .with_context(|| "Failed to mount sysfs at overlay's mountpoint")?;
-    Mount::builder()
-        .fstype("tmpfs")
-        .mount("tmpfs", &format!("{}/tmp", &crate::OVERLAY_MOUNTPOINT))
-        .with_context(|| "Failed to mount tmpfs at overlay's mountpoint ('/tmp')")?;
-    Mount::builder()
-        .fstype("tmpfs")
-        .mount("tmpfs", &format!("{}/run", &crate::OVERLAY_MOUNTPOINT))
-        .with_context(|| "Failed to mount tmpfs at overlay's mountpoint ('/run')")?;
-    Mount::builder()
-        .fstype("devtmpfs")
-        .mount("devtmpfs", &format!("{}/dev", &crate::OVERLAY_MOUNTPOINT))
-        .with_context(|| "Failed to mount devtmpfs at overlay's mountpoint")?;
+    for (fstype, target, data) in OVERLAY_MOUNTS {
+        let mountpoint = format!("{}/{}", &crate::OVERLAY_MOUNTPOINT, &target);
+        // Nested mountpoints (e.g. 'dev/pts') live on freshly mounted filesystems
+        fs::create_dir_all(&mountpoint)
+            .with_context(|| format!("Failed to create mountpoint '{}'", &mountpoint))?;
+        Mount::builder()
+            .fstype(*fstype)
+            .data(&data)
+            .mount(&fstype, &mountpoint)
+            .with_context(|| {
+                format!(
+                    "Failed to mount {} at overlay's mountpoint ('/{}')",
+                    &fstype, &target
+                )
+            })?;
+    }
     bind_mount(
         &format!("{}", &crate::BOOT_PART_MOUNTPOINT),
         &format!("{}/{}", &crate::OVERLAY_MOUNTPOINT, &crate::BOOT_DIR),
+        true,
     )
     .with_context(|| "Failed to bind-mount boot partition to overlay")?;
     bind_mount(
@@ -202,29 +1063,331 @@ pub fn setup_mounts() -> Result<()> {
             &crate::SYSTEM_HOME_DIR
         ),
         &format!("{}/{}", &crate::OVERLAY_MOUNTPOINT, &crate::SYSTEM_HOME_DIR),
+        true,
     )
     .with_context(|| "Failed to bind-mount system home directory to overlay")?;
 
+    // Development setups only: a bad entry is skipped rather than failing the boot
+    for bind_mount_config in &rootfs_config.bind_mounts {
+        if let Err(e) = check_bind_mount(&bind_mount_config) {
+            warn!(
+                "Ignoring bind mount of '{}' at '{}': {}",
+                &bind_mount_config.source, &bind_mount_config.target, &e
+            );
+            continue;
+        }
+        let mountpoint = format!(
+            "{}{}",
+            &crate::OVERLAY_MOUNTPOINT,
+            &bind_mount_config.target
+        );
+        info!(
+            "Bind-mounting '{}' at '{}' in overlay",
+            &bind_mount_config.source, &bind_mount_config.target
+        );
+        fs::create_dir_all(&mountpoint)
+            .with_context(|| format!("Failed to create mountpoint '{}'", &mountpoint))?;
+        bind_mount(
+            &bind_mount_config.source,
+            &mountpoint,
+            bind_mount_config.recursive,
+        )
+        .with_context(|| {
+            format!(
+                "Failed to bind-mount '{}' to overlay",
+                &bind_mount_config.source
+            )
+        })?;
+    }
+
     Ok(())
 }
 
-pub fn run_chroot_command(command: &[&str]) -> Result<()> {
-    debug!("Running command in chroot: {:?}", &command);
+#[derive(Debug)]
+pub struct ChrootCommandResult {
+    pub command: Vec<String>,
+    pub status: ExitStatus,
+    // Both only hold the last lines of output
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl ChrootCommandResult {
+    // A non-zero exit status becomes an error carrying the end of the command's error output
+    pub fn check(self) -> Result<ChrootCommandResult> {
+        if self.status.success() {
+            return Ok(self);
+        }
+        let args: Vec<&str> = self.command.iter().skip(1).map(String::as_str).collect();
+
+        Err(command_failed(
+            self.command.first().map(String::as_str).unwrap_or_default(),
+            &args,
+            self.status,
+            self.stderr,
+        )
+        .into())
+    }
+}
+
+// Only failing to run the command is an error: callers decide what a non-zero exit status means, e.g. with ChrootCommandResult::check()
+pub fn run_chroot_command(command: &[&str]) -> Result<ChrootCommandResult> {
+    debug!(
+        "Running command in chroot: '{}'",
+        crate::secret::scrub(&command.join(" "))
+    );
+
+    let mut child = Command::new(&CHROOT_BINARY_PATH)
+        .arg(&crate::OVERLAY_MOUNTPOINT)
+        .args(command)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to execute command in chroot: {:?}", &command))?;
+    let stdout_tail = OutputTail::capture_stdout(&mut child);
+    let stderr_tail = OutputTail::capture(&mut child);
+    let status = child
+        .wait()
+        .with_context(|| format!("Failed to wait for command in chroot: {:?}", &command))?;
+    let result = ChrootCommandResult {
+        command: command
+            .iter()
+            .map(|arg| crate::secret::scrub(&arg))
+            .collect(),
+        status,
+        stdout: stdout_tail.collect(),
+        stderr: stderr_tail.collect(),
+    };
+
+    debug!("Exit status is {}", &status);
+    if !status.success() {
+        error!(
+            "Command '{}' in chroot exited with status: {}",
+            &result.command.join(" "),
+            &status
+        );
+        if !result.stdout.is_empty() {
+            error!("Last lines of its output:\n{}", &result.stdout);
+        }
+        if !result.stderr.is_empty() {
+            error!("Last lines of its error output:\n{}", &result.stderr);
+        }
+    }
+
+    Ok(result)
+}
+
+// Leaves the encrypted storage alone: see storage_encryption::change_password()
+pub fn change_user_password(user: &str, new_password: &Secret) -> Result<()> {
+    info!("Changing system password for user '{}'", &user);
+    if crate::storage_encryption::is_storage_mounted(&user)? {
+        return Err(anyhow::anyhow!("Home of user '{}' is in use", &user));
+    }
+    // chpasswd reads "user:password" lines
+    let credentials = Secret::new(format!("{}:{}\n", &user, new_password.expose()));
+    run_command_with_stdin(
+        &CHROOT_BINARY_PATH,
+        &[&crate::OVERLAY_MOUNTPOINT, &CHPASSWD_BINARY_PATH],
+        credentials.expose().as_bytes(),
+    )
+    .with_context(|| format!("Failed to change system password for user '{}'", &user))
+}
+
+// Lexical order, without the signatures themselves
+fn list_first_boot_scripts(scripts_dir: &str) -> Result<Vec<String>> {
+    let mut scripts: Vec<String> = fs::read_dir(&scripts_dir)
+        .with_context(|| format!("Failed to list '{}'", &scripts_dir))?
+        .flatten()
+        .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_file()))
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| !name.ends_with(&crate::GENERIC_DIGEST_EXT))
+        .collect();
+    scripts.sort();
+
+    Ok(scripts)
+}
+
+// Copies the script along with its signature into the overlay, then checks the copy: what runs is exactly what was verified
+fn run_first_boot_script(pubkey: &PKey<Public>, scripts_dir: &str, script: &str) -> Result<()> {
+    let run_dir = format!(
+        "{}/{}",
+        &crate::OVERLAY_MOUNTPOINT,
+        &FIRST_BOOT_SCRIPTS_RUN_DIR
+    );
+    fs::create_dir_all(&run_dir).with_context(|| format!("Failed to create '{}'", &run_dir))?;
+    let copy_path = format!("{}/{}", &run_dir, &script);
+    for suffix in ["", crate::GENERIC_DIGEST_EXT] {
+        let source_path = format!("{}/{}{}", &scripts_dir, &script, &suffix);
+        fs::copy(&source_path, format!("{}{}", &copy_path, &suffix))
+            .with_context(|| format!("Failed to copy '{}'", &source_path))?;
+    }
+
+    let result = if signing::check_signature(&pubkey, &copy_path)? {
+        run_chroot_command(&[
+            "/bin/sh",
+            &format!("/{}/{}", &FIRST_BOOT_SCRIPTS_RUN_DIR, &script),
+        ])?
+        .check()
+        .map(|_| ())
+    } else {
+        Err(anyhow::anyhow!("Invalid signature"))
+    };
+    rm_dir_all(&run_dir)?;
+
+    result
+}
+
+// Runs the first boot provisioning scripts found on the boot partition, if any, before systemd starts
+// Failures do not abort the boot: they are returned, one line per script, for a single warning to be shown
+pub fn run_first_boot_scripts(pubkey: &PKey<Public>) -> Vec<String> {
+    let scripts_dir = format!(
+        "{}/{}",
+        &crate::BOOT_PART_MOUNTPOINT,
+        &FIRST_BOOT_SCRIPTS_DIR
+    );
+    if !fs::exists(&scripts_dir).unwrap_or(false) {
+        return Vec::new();
+    }
+    let scripts = match list_first_boot_scripts(&scripts_dir) {
+        Ok(scripts) => scripts,
+        Err(e) => return vec![format!("{}", &e)],
+    };
+
+    let mut failures = Vec::new();
+    for (i, script) in scripts.iter().enumerate() {
+        info!("Running first boot setup script '{}'", &script);
+        notifier::get().toast(&format!(
+            "First boot setup ({}/{}): {}",
+            i + 1,
+            scripts.len(),
+            &script
+        ));
+        if let Err(e) = run_first_boot_script(&pubkey, &scripts_dir, &script) {
+            error!("First boot setup script '{}' failed: {:?}", &script, &e);
+            failures.push(format!("{}: {}", &script, &e));
+        }
+    }
+
+    failures
+}
+
+// Before the chroot is entered, so that early units already run in the right timezone: set_timezone() makes it official later on
+pub fn write_localtime(timezone: &str) -> Result<()> {
+    info!("Setting overlay filesystem's localtime to '{}'", &timezone);
+    let localtime_path = format!("{}/etc/localtime", &crate::OVERLAY_MOUNTPOINT);
+    let timezone_data = format!("/usr/share/zoneinfo/{}", &timezone);
+    if fs::symlink_metadata(&localtime_path).is_ok() {
+        fs::remove_file(&localtime_path)
+            .with_context(|| format!("Failed to remove '{}'", &localtime_path))?;
+    }
+    // Resolved inside the root filesystem
+    symlink(&timezone_data, &localtime_path).with_context(|| {
+        format!(
+            "Failed to symlink '{}' to '{}'",
+            &localtime_path, &timezone_data
+        )
+    })?;
 
-    let mut args: Vec<&str> = Vec::with_capacity(1 + command.len());
-    args.push(&crate::OVERLAY_MOUNTPOINT);
-    args.extend_from_slice(&command);
+    Ok(())
+}
 
-    run_command("/usr/sbin/chroot", &args)?;
+// Keeps the root filesystem in agreement with the hostname set during init
+pub fn write_hostname(hostname: &str) -> Result<()> {
+    info!("Setting overlay filesystem's hostname to '{}'", &hostname);
+    let hostname_file_path = format!("{}/etc/hostname", &crate::OVERLAY_MOUNTPOINT);
+    fs::write(&hostname_file_path, format!("{}\n", &hostname))
+        .with_context(|| format!("Failed to write hostname to '{}'", &hostname_file_path))?;
 
     Ok(())
 }
 
 pub fn set_timezone(timezone: &str) -> Result<()> {
     info!("Setting overlay filesystem's timezone to '{}'", &timezone);
-    Ok(run_chroot_command(&[
-        "/usr/sbin/timedatectl",
-        "set-timezone",
-        &timezone,
-    ])?)
+    run_chroot_command(&["/usr/sbin/timedatectl", "set-timezone", &timezone])?
+        .check()
+        .with_context(|| format!("Failed to set timezone to '{}'", &timezone))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{RecordingNotifier, TempDir};
+
+    const CONFIRMATION: &str = "confirmation: Carry the 2 MB of root filesystem changes over to memory? Otherwise, they will be dropped on the next boot.";
+
+    // Main partition holding a persistent write layer, next to an empty volatile one
+    fn write_layers() -> (TempDir, TempDir) {
+        let main_part = TempDir::new();
+        // Sizes come from allocated blocks: this must not be compressed away
+        let incompressible: Vec<u8> = (0..2_000_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect();
+        main_part.write("system/rootfs/write/usr/lib/big", &incompressible);
+        main_part.write("system/rootfs/write-modules/module.ko", b"module");
+        (main_part, TempDir::new())
+    }
+
+    fn offer_migration_to_volatile(
+        main_part: &TempDir,
+        overlay_workdir: &TempDir,
+        notifier: &RecordingNotifier,
+    ) {
+        offer_write_layer_migration_in(
+            &main_part.path(),
+            &overlay_workdir.path(),
+            true,
+            false,
+            notifier,
+        );
+    }
+
+    #[test]
+    fn accepted_migration_reports_its_milestones() {
+        let (main_part, overlay_workdir) = write_layers();
+        let notifier = RecordingNotifier::new(true);
+        offer_migration_to_volatile(&main_part, &overlay_workdir, &notifier);
+        assert_eq!(
+            notifier.events(),
+            vec![
+                CONFIRMATION,
+                "toast: Carrying over root filesystem changes (2 MB)",
+                "toast: Root filesystem changes were carried over",
+            ]
+        );
+        assert!(
+            fs::exists(main_part.join("system/rootfs/staged-write/write/usr/lib/big")).unwrap()
+        );
+        assert!(
+            fs::exists(main_part.join("system/rootfs/staged-write/write-modules/module.ko"))
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn declined_migration_only_asks() {
+        let (main_part, overlay_workdir) = write_layers();
+        let notifier = RecordingNotifier::new(false);
+        offer_migration_to_volatile(&main_part, &overlay_workdir, &notifier);
+        assert_eq!(notifier.events(), vec![CONFIRMATION]);
+        assert!(!fs::exists(main_part.join("system/rootfs/staged-write")).unwrap());
+    }
+
+    #[test]
+    fn empty_write_layer_is_not_offered() {
+        let main_part = TempDir::new();
+        let overlay_workdir = TempDir::new();
+        let notifier = RecordingNotifier::new(true);
+        offer_migration_to_volatile(&main_part, &overlay_workdir, &notifier);
+        // Nor is anything offered when persistent storage stays as it was
+        offer_write_layer_migration_in(
+            &main_part.path(),
+            &overlay_workdir.path(),
+            true,
+            true,
+            &notifier,
+        );
+        assert!(notifier.events().is_empty());
+    }
 }
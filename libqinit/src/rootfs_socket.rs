@@ -1,11 +1,13 @@
 use anyhow::{Context, Result};
 use core::ops::Deref;
-use libquillcom::socket::{self, AnswerFromQinit, CommandToQinit, LoginForm};
-use log::{debug, info};
+use libquillcom::socket::{self, AnswerFromQinit, CommandToQinit, ErrorDetails, LoginForm};
+use log::{debug, info, warn};
 use postcard::to_allocvec;
 use socket::PrimitiveShutDownType;
 use std::io::Write;
 use std::{
+    fs, mem,
+    os::unix::net::UnixListener,
     sync::{
         Arc, Mutex,
         atomic::{AtomicBool, Ordering},
@@ -14,30 +16,76 @@ use std::{
     thread,
 };
 
+use crate::notifier::{EarlyEvent, EarlyEventBuffer};
+use crate::secret::{self, Secret};
+
 pub const ROOTFS_SOCKET_PATH: &str = "/overlay/run/qinit_rootfs.sock";
 
+// Unlike libquillcom's LoginForm, the password is wiped from memory as soon as it is dropped
+#[derive(Clone)]
+struct LoginCredentials {
+    username: String,
+    password: Secret,
+}
+
+#[derive(Default)]
+struct LoginSlot {
+    credentials: Option<LoginCredentials>,
+    // Set once credentials were consumed: later ones are ignored until the root filesystem asks for the login page again
+    closed: bool,
+}
+
+impl LoginSlot {
+    fn store(&mut self, login_form: LoginForm) {
+        let password = Secret::new(login_form.password);
+        if login_form.username.is_empty() || password.is_empty() {
+            self.credentials = None;
+        } else if self.closed {
+            warn!("Ignoring login credentials: the root filesystem did not ask for the login page");
+        } else {
+            self.credentials = Some(LoginCredentials {
+                username: login_form.username,
+                password,
+            });
+        }
+    }
+
+    fn serve(&mut self, consume_once: bool) -> Option<LoginCredentials> {
+        if consume_once {
+            // The root filesystem may ask before anyone logged in: the slot only closes once something was served
+            let credentials = self.credentials.take();
+            self.closed |= credentials.is_some();
+            credentials
+        } else {
+            self.credentials.clone()
+        }
+    }
+}
+
 pub fn initialize(
     login_credentials_receiver: Receiver<LoginForm>,
     splash_sender: Sender<PrimitiveShutDownType>,
     splash_ready_receiver: Receiver<()>,
     can_shut_down: Arc<AtomicBool>,
     login_page_trigger_sender: Sender<()>,
+    consume_once: bool,
 ) -> Result<()> {
-    let login_form_mutex = Arc::new(Mutex::new(None));
+    let login_slot_mutex = Arc::new(Mutex::new(LoginSlot::default()));
     thread::spawn({
-        let login_form_mutex = login_form_mutex.clone();
-        move || listen_for_login_credentials(login_credentials_receiver, login_form_mutex)
+        let login_slot_mutex = login_slot_mutex.clone();
+        move || listen_for_login_credentials(login_credentials_receiver, login_slot_mutex)
     });
 
     thread::spawn({
-        let login_form_mutex = login_form_mutex.clone();
+        let login_slot_mutex = login_slot_mutex.clone();
         move || {
             listen_for_commands(
-                login_form_mutex,
+                login_slot_mutex,
                 splash_sender,
                 splash_ready_receiver,
                 can_shut_down,
                 login_page_trigger_sender,
+                consume_once,
             )
         }
     });
@@ -45,31 +93,84 @@ pub fn initialize(
     Ok(())
 }
 
-pub fn listen_for_login_credentials(
+// Used by binaries inside the chroot wishing to invoke a 'Fatal error' splash
+// Bound before returning: units inside the chroot may fail as soon as the first stage execs /sbin/init
+pub fn listen_for_fatal_errors(
+    socket_path: &str,
+    early_events: &'static EarlyEventBuffer,
+    interrupt_sender: Sender<String>,
+) -> Result<()> {
+    let unix_listener = socket::bind(&socket_path)
+        .with_context(|| format!("Failed to bind fatal error socket at '{}'", &socket_path))?;
+    let socket_path = socket_path.to_string();
+    thread::spawn(move || {
+        // This is a one-time call: any more fatal errors are useless since we already block the UI until the next boot
+        match read_fatal_error(&unix_listener) {
+            Ok(error_reason) => {
+                info!("Received request to show fatal error splash: proceeding");
+                // Held until the GUI is ready to show it
+                if let Err(EarlyEvent::FatalError(error_reason)) =
+                    early_events.push(EarlyEvent::FatalError(error_reason))
+                {
+                    let _ = interrupt_sender.send(error_reason);
+                }
+                let _ = fs::remove_file(&socket_path);
+            }
+            Err(e) => warn!("{:?}", &e),
+        }
+    });
+
+    Ok(())
+}
+
+fn read_fatal_error(unix_listener: &UnixListener) -> Result<String> {
+    let frame =
+        socket::read(&unix_listener).with_context(|| "Failed to read from fatal error socket")?;
+    let error_details = postcard::from_bytes::<ErrorDetails>(&frame)
+        .with_context(|| "Failed to decode fatal error details")?;
+
+    Ok(error_details.error_reason)
+}
+
+fn listen_for_login_credentials(
     login_credentials_receiver: Receiver<LoginForm>,
-    login_form_mutex: Arc<Mutex<Option<LoginForm>>>,
+    login_slot_mutex: Arc<Mutex<LoginSlot>>,
 ) -> Result<()> {
     loop {
         if let Ok(login_form) = login_credentials_receiver.recv() {
-            let mut login_form_guard = login_form_mutex.lock().unwrap();
-            if login_form.username.is_empty() || login_form.password.is_empty() {
-                *login_form_guard = None;
-            } else {
-                *login_form_guard = Some(LoginForm {
-                    username: login_form.username,
-                    password: login_form.password,
-                });
-            }
+            login_slot_mutex.lock().unwrap().store(login_form);
         }
     }
 }
 
-pub fn listen_for_commands(
-    login_form_mutex: Arc<Mutex<Option<LoginForm>>>,
+// Every copy of the password made on the way out is wiped once sent
+fn send_login_credentials(
+    unix_stream: &mut impl Write,
+    credentials: Option<LoginCredentials>,
+) -> Result<()> {
+    let mut answer = AnswerFromQinit::Login(credentials.map(|credentials| LoginForm {
+        username: credentials.username,
+        password: credentials.password.expose().to_string(),
+    }));
+    let result = to_allocvec(&answer);
+    if let AnswerFromQinit::Login(Some(login_form)) = &mut answer {
+        secret::zeroize_string(&mut login_form.password);
+    }
+    let mut login_form_vec =
+        result.with_context(|| "Failed to create vector with login credentials")?;
+    let result = unix_stream.write_all(&login_form_vec);
+    secret::zeroize(&mut login_form_vec);
+
+    result.with_context(|| "Failed to send login credentials")
+}
+
+fn listen_for_commands(
+    login_slot_mutex: Arc<Mutex<LoginSlot>>,
     splash_sender: Sender<PrimitiveShutDownType>,
     splash_ready_receiver: Receiver<()>,
     can_shut_down: Arc<AtomicBool>,
     login_page_trigger_sender: Sender<()>,
+    consume_once: bool,
 ) -> Result<()> {
     info!("Listening for commands");
     let unix_listener = socket::bind(&ROOTFS_SOCKET_PATH)?;
@@ -81,20 +182,19 @@ pub fn listen_for_commands(
             CommandToQinit::GetLoginCredentials => {
                 debug!("Sending login credentials to root filesystem");
 
-                let login_form_vec = to_allocvec(&AnswerFromQinit::Login(
-                    login_form_mutex.lock().unwrap().take(),
-                ))
-                .with_context(|| "Failed to create vector with login credentials")?;
-
-                unix_stream
-                    .write_all(&login_form_vec)
-                    .with_context(|| "Failed to send login credentials")?;
+                let credentials = login_slot_mutex.lock().unwrap().serve(consume_once);
+                send_login_credentials(&mut unix_stream, credentials)?;
             }
             CommandToQinit::TriggerSplash(shut_down_type) => {
                 info!(
                     "Displaying splash screen for shut down type '{:?}'",
                     shut_down_type
                 );
+                // The root filesystem is going down: nobody is left to serve credentials to afterwards
+                let going_down = matches!(
+                    shut_down_type,
+                    PrimitiveShutDownType::PowerOff | PrimitiveShutDownType::Reboot
+                );
                 splash_sender
                     .send(shut_down_type)
                     .with_context(|| "Failed to send splash type from socket call")?;
@@ -113,8 +213,14 @@ pub fn listen_for_commands(
                 unix_stream
                     .write_all(&reply)
                     .with_context(|| "Failed to send splash readiness status")?;
+
+                if going_down {
+                    break;
+                }
             }
             CommandToQinit::TriggerSwitchToLoginPage => {
+                // Logging out: whatever is typed next is meant for the root filesystem again
+                mem::take(&mut *login_slot_mutex.lock().unwrap());
                 let _ = login_page_trigger_sender.send(());
 
                 let reply = to_allocvec(&AnswerFromQinit::LoginPageReady)?;
@@ -128,6 +234,134 @@ pub fn listen_for_commands(
         }
     }
 
+    // Pending credentials are wiped, and later ones ignored, once nothing can ask for them anymore
+    *login_slot_mutex.lock().unwrap() = LoginSlot {
+        credentials: None,
+        closed: true,
+    };
     info!("Stopped listening for commands");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    fn login_form(username: &str, password: &str) -> LoginForm {
+        LoginForm {
+            username: username.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    #[test]
+    fn credentials_are_served_once_when_consumed() {
+        let mut login_slot = LoginSlot::default();
+        assert!(login_slot.serve(true).is_none());
+        assert!(!login_slot.closed);
+
+        login_slot.store(login_form("alice", "hunter22"));
+        let credentials = login_slot.serve(true).unwrap();
+        assert_eq!(credentials.username, "alice");
+        assert_eq!(credentials.password.expose(), "hunter22");
+        assert!(login_slot.serve(true).is_none());
+
+        // Closed until the root filesystem asks for the login page again
+        login_slot.store(login_form("alice", "hunter22"));
+        assert!(login_slot.serve(true).is_none());
+        mem::take(&mut login_slot);
+        login_slot.store(login_form("alice", "hunter22"));
+        assert!(login_slot.serve(true).is_some());
+    }
+
+    #[test]
+    fn credentials_are_kept_when_not_consumed() {
+        let mut login_slot = LoginSlot::default();
+        login_slot.store(login_form("alice", "hunter22"));
+        assert!(login_slot.serve(false).is_some());
+        assert!(login_slot.serve(false).is_some());
+        assert!(!login_slot.closed);
+    }
+
+    #[test]
+    fn empty_credentials_clear_the_slot() {
+        let mut login_slot = LoginSlot::default();
+        login_slot.store(login_form("alice", "hunter22"));
+        login_slot.store(login_form("alice", ""));
+        assert!(login_slot.serve(false).is_none());
+    }
+
+    #[test]
+    fn sent_credentials_decode_as_login_answer() {
+        let mut sent = Vec::new();
+        send_login_credentials(
+            &mut sent,
+            Some(LoginCredentials {
+                username: "alice".to_string(),
+                password: Secret::new("hunter22".to_string()),
+            }),
+        )
+        .unwrap();
+        match postcard::from_bytes::<AnswerFromQinit>(&sent).unwrap() {
+            AnswerFromQinit::Login(Some(login_form)) => {
+                assert_eq!(login_form.username, "alice");
+                assert_eq!(login_form.password, "hunter22");
+            }
+            _ => panic!("Unexpected answer"),
+        }
+
+        let mut sent = Vec::new();
+        send_login_credentials(&mut sent, None).unwrap();
+        assert!(matches!(
+            postcard::from_bytes::<AnswerFromQinit>(&sent).unwrap(),
+            AnswerFromQinit::Login(None)
+        ));
+    }
+
+    #[test]
+    fn fatal_error_sent_right_after_bind_is_not_lost() {
+        static EARLY_EVENTS: EarlyEventBuffer = EarlyEventBuffer::new();
+        let temp_dir = TempDir::new();
+        let socket_path = temp_dir.join("qinit.sock");
+        let (interrupt_sender, interrupt_receiver) = channel();
+        listen_for_fatal_errors(&socket_path, &EARLY_EVENTS, interrupt_sender).unwrap();
+
+        let client = thread::spawn({
+            let socket_path = socket_path.clone();
+            move || {
+                let frame = to_allocvec(&ErrorDetails {
+                    error_reason: "Unit failed".to_string(),
+                })
+                .unwrap();
+                socket::write(&socket_path, &frame).unwrap();
+            }
+        });
+        // Races with the client: the error either was buffered in time or goes straight to the GUI, never both
+        let mut fatal_errors: Vec<String> = EARLY_EVENTS
+            .drain()
+            .into_iter()
+            .filter_map(|event| match event {
+                EarlyEvent::FatalError(error_reason) => Some(error_reason),
+                _ => None,
+            })
+            .collect();
+        client.join().unwrap();
+        if fatal_errors.is_empty() {
+            fatal_errors.push(
+                interrupt_receiver
+                    .recv_timeout(Duration::from_secs(5))
+                    .unwrap(),
+            );
+        }
+
+        assert_eq!(fatal_errors, vec!["Unit failed".to_string()]);
+        assert!(
+            interrupt_receiver
+                .recv_timeout(Duration::from_millis(100))
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,141 @@
+use anyhow::{Context, Result};
+use log::info;
+use std::fs;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+const FRAMEBUFFER_PATH: &str = "/dev/fb0";
+const FB_SYSFS_PATH: &str = "/sys/class/graphics/fb0";
+const SCREENSHOTS_DIR: &str = "screenshots/";
+
+// rockchip_ebc exposes either 4 or 8 bits grayscale, or RGB565 (and XRGB8888 through DRM's fbdev emulation)
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PixelFormat {
+    Gray4,
+    Gray8,
+    Rgb565,
+    Xrgb8888,
+}
+
+impl PixelFormat {
+    fn from_bits_per_pixel(bits_per_pixel: u32) -> Result<PixelFormat> {
+        match bits_per_pixel {
+            4 => Ok(PixelFormat::Gray4),
+            8 => Ok(PixelFormat::Gray8),
+            16 => Ok(PixelFormat::Rgb565),
+            32 => Ok(PixelFormat::Xrgb8888),
+            _ => Err(anyhow::anyhow!(
+                "Unsupported framebuffer pixel format: {} bits per pixel",
+                bits_per_pixel
+            )),
+        }
+    }
+
+    fn luma(&self, row: &[u8], x: usize) -> u8 {
+        match self {
+            PixelFormat::Gray4 => {
+                let nibble = (row[x / 2] >> ((x % 2) * 4)) & 0x0f;
+                nibble * 0x11
+            }
+            PixelFormat::Gray8 => row[x],
+            PixelFormat::Rgb565 => {
+                let pixel = u16::from_le_bytes([row[x * 2], row[x * 2 + 1]]);
+                let red = ((pixel >> 11) & 0x1f) as u32 * 255 / 31;
+                let green = ((pixel >> 5) & 0x3f) as u32 * 255 / 63;
+                let blue = (pixel & 0x1f) as u32 * 255 / 31;
+                rgb_to_luma(red, green, blue)
+            }
+            PixelFormat::Xrgb8888 => rgb_to_luma(
+                row[x * 4 + 2] as u32,
+                row[x * 4 + 1] as u32,
+                row[x * 4] as u32,
+            ),
+        }
+    }
+}
+
+// ITU-R BT.601: the panel only shows grays anyway
+fn rgb_to_luma(red: u32, green: u32, blue: u32) -> u8 {
+    ((red * 299 + green * 587 + blue * 114) / 1000) as u8
+}
+
+fn read_fb_attribute(attribute: &str) -> Option<String> {
+    fs::read_to_string(format!("{}/{}", &FB_SYSFS_PATH, &attribute))
+        .ok()
+        .map(|value| value.trim().to_string())
+}
+
+// Falls back to the geometry resolved at startup if the framebuffer does not report its dimensions
+fn framebuffer_geometry() -> (usize, usize) {
+    read_fb_attribute("virtual_size")
+        .and_then(|virtual_size| {
+            let (width, height) = virtual_size.split_once(',')?;
+            Some((width.parse().ok()?, height.parse().ok()?))
+        })
+        .unwrap_or_else(|| {
+            // The framebuffer is never rotated
+            let geometry = crate::eink::geometry();
+            (geometry.width as usize, geometry.height as usize)
+        })
+}
+
+fn read_framebuffer() -> Result<(Vec<u8>, usize, usize)> {
+    let (width, height) = framebuffer_geometry();
+    let bits_per_pixel = read_fb_attribute("bits_per_pixel")
+        .and_then(|bits_per_pixel| bits_per_pixel.parse().ok())
+        .unwrap_or(8);
+    let pixel_format = PixelFormat::from_bits_per_pixel(bits_per_pixel)?;
+    // Rows may be padded
+    let stride = read_fb_attribute("stride")
+        .and_then(|stride| stride.parse().ok())
+        .unwrap_or((width * bits_per_pixel as usize).div_ceil(8));
+    let framebuffer = fs::read(&FRAMEBUFFER_PATH)
+        .with_context(|| format!("Failed to read '{}'", &FRAMEBUFFER_PATH))?;
+    if framebuffer.len() < stride * height {
+        return Err(anyhow::anyhow!(
+            "Framebuffer is smaller than expected: {} bytes for {}x{} pixels",
+            framebuffer.len(),
+            width,
+            height
+        ));
+    }
+
+    let mut luma = Vec::with_capacity(width * height);
+    for row in framebuffer.chunks_exact(stride).take(height) {
+        luma.extend((0..width).map(|x| pixel_format.luma(&row, x)));
+    }
+
+    Ok((luma, width, height))
+}
+
+fn write_png(path: &Path, luma: &[u8], width: usize, height: usize) -> Result<()> {
+    let file = fs::File::create(&path)
+        .with_context(|| format!("Failed to create '{}'", &path.display()))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, height as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()?
+        .write_image_data(&luma)
+        .with_context(|| format!("Failed to write '{}'", &path.display()))?;
+
+    Ok(())
+}
+
+// Grabs whatever the panel currently shows, in the framebuffer's orientation
+// Only asked for from the GUI: test rigs cannot ask for one over the root filesystem socket until libquillcom's CommandToQinit has a screenshot command, which this tree cannot add
+pub fn take() -> Result<PathBuf> {
+    let (luma, width, height) = read_framebuffer()?;
+    let screenshots_dir_path = Path::new(&crate::BOOT_PART_MOUNTPOINT).join(&SCREENSHOTS_DIR);
+    fs::create_dir_all(&screenshots_dir_path)
+        .with_context(|| format!("Failed to create '{}'", &screenshots_dir_path.display()))?;
+    let screenshot_path = screenshots_dir_path.join(format!(
+        "screenshot-{}.png",
+        chrono::Local::now().format("%Y%m%d-%H%M%S-%3f")
+    ));
+    write_png(&screenshot_path, &luma, width, height)?;
+    crate::system::sync_disks()?;
+    info!("Saved screenshot to '{}'", &screenshot_path.display());
+
+    Ok(screenshot_path)
+}
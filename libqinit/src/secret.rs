@@ -0,0 +1,165 @@
+use std::fmt;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::{LazyLock, Mutex};
+
+pub const REDACTED: &str = "<redacted>";
+// Shorter values would also match unrelated words and numbers all over the logs
+pub const SCRUB_MIN_LENGTH: usize = 6;
+
+// Every secret created during this session, used to scrub diagnostics as defense in depth: only their length and a keyed hash are kept
+static REGISTERED_SECRETS: LazyLock<Mutex<RegisteredSecrets>> =
+    LazyLock::new(|| Mutex::new(RegisteredSecrets::default()));
+
+#[derive(Default)]
+struct RegisteredSecrets {
+    hasher: RandomState,
+    // Length in bytes and hash
+    entries: Vec<(usize, u64)>,
+}
+
+#[derive(PartialEq, Clone)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: String) -> Secret {
+        register(&value);
+        Secret(value)
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", &REDACTED)
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", &REDACTED)
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        zeroize_string(&mut self.0);
+    }
+}
+
+// For copies that cannot live in a Secret, e.g. serialized buffers or structures defined by libquillcom
+pub fn zeroize(bytes: &mut [u8]) {
+    for byte in bytes {
+        // SAFETY: the pointer comes from a mutable reference
+        unsafe {
+            std::ptr::write_volatile(byte, 0);
+        }
+    }
+}
+
+pub fn zeroize_string(value: &mut String) {
+    // SAFETY: a string made of null bytes is still valid UTF-8
+    zeroize(unsafe { value.as_bytes_mut() });
+}
+
+pub fn register(value: &str) {
+    if value.len() < SCRUB_MIN_LENGTH || value == crate::storage_encryption::DISABLED_MODE_PASSWORD
+    {
+        return;
+    }
+    let mut registered_secrets = REGISTERED_SECRETS.lock().unwrap();
+    let entry = (
+        value.len(),
+        registered_secrets.hasher.hash_one(value.as_bytes()),
+    );
+    if !registered_secrets.entries.contains(&entry) {
+        registered_secrets.entries.push(entry);
+    }
+}
+
+// Secrets are valid UTF-8 on their own, so a match always starts and ends on character boundaries
+pub fn scrub(text: &str) -> String {
+    let registered_secrets = REGISTERED_SECRETS.lock().unwrap();
+    if registered_secrets.entries.is_empty() {
+        return text.to_string();
+    }
+    let bytes = text.as_bytes();
+    let mut scrubbed = String::with_capacity(text.len());
+    let mut copied_until = 0;
+    let mut position = 0;
+    while position < bytes.len() {
+        let matched_length = registered_secrets
+            .entries
+            .iter()
+            .filter(|(length, hash)| {
+                bytes.len() - position >= *length
+                    && registered_secrets
+                        .hasher
+                        .hash_one(&bytes[position..position + length])
+                        == *hash
+            })
+            .map(|(length, _hash)| *length)
+            .max();
+        match matched_length {
+            Some(length) => {
+                scrubbed.push_str(&text[copied_until..position]);
+                scrubbed.push_str(&REDACTED);
+                position += length;
+                copied_until = position;
+            }
+            None => position += 1,
+        }
+    }
+    scrubbed.push_str(&text[copied_until..]);
+
+    scrubbed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registered_secrets_are_scrubbed() {
+        register("correct horse battery");
+        register("correct horse");
+        assert_eq!(
+            scrub("iwctl --passphrase correct horse battery, then correct horse"),
+            format!("iwctl --passphrase {}, then {}", &REDACTED, &REDACTED)
+        );
+        assert_eq!(scrub("correct"), "correct");
+    }
+
+    #[test]
+    fn multibyte_text_around_secrets_is_kept() {
+        register("mötdepässe");
+        assert_eq!(scrub("é mötdepässe ü"), format!("é {} ü", &REDACTED));
+    }
+
+    #[test]
+    fn short_secrets_and_sentinel_are_not_registered() {
+        register("1234");
+        register(crate::storage_encryption::DISABLED_MODE_PASSWORD);
+        assert_eq!(scrub("PIN 1234 accepted"), "PIN 1234 accepted");
+        assert_eq!(
+            scrub(crate::storage_encryption::DISABLED_MODE_PASSWORD),
+            crate::storage_encryption::DISABLED_MODE_PASSWORD
+        );
+    }
+
+    #[test]
+    fn secrets_are_redacted_when_formatted() {
+        let secret = Secret::new("hunter2222".to_string());
+        assert_eq!(
+            format!("{} {:?}", &secret, &secret),
+            format!("{} {}", &REDACTED, &REDACTED)
+        );
+        assert_eq!(secret.expose(), "hunter2222");
+    }
+}
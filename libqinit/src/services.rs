@@ -0,0 +1,261 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use nix::sys::signal::{Signal, kill};
+use nix::unistd::Pid;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::process::{Child, Command};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::system::{keep_last_lines, run_command};
+
+// Where OpenRC's output logger writes when enabled for a service
+const SERVICE_LOG_DIR_PATH: &str = "/var/log";
+const SERVICE_LOG_TAIL_LINES: usize = 10;
+const DIRECT_PID_DIR_PATH: &str = "/run/qinit/services";
+const DIRECT_STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+static DIRECT: OnceLock<Direct> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ServiceState {
+    Started,
+    Stopped,
+    Crashed,
+}
+
+#[derive(Debug, Clone)]
+pub struct ServiceStatus {
+    pub name: String,
+    pub state: ServiceState,
+    pub log_tail: Option<String>,
+}
+
+pub trait Services: Send + Sync {
+    fn start(&self, service: &str) -> Result<()>;
+    fn stop(&self, service: &str) -> Result<()>;
+    fn restart(&self, service: &str) -> Result<()> {
+        self.stop(&service)?;
+        self.start(&service)
+    }
+    fn status(&self) -> Result<Vec<ServiceStatus>>;
+}
+
+pub(crate) fn read_log_tail(service: &str) -> Option<String> {
+    let log_path = format!("{}/{}.log", &SERVICE_LOG_DIR_PATH, &service);
+    fs::read_to_string(&log_path)
+        .ok()
+        .map(|log| crate::secret::scrub(&keep_last_lines(&log, SERVICE_LOG_TAIL_LINES)))
+}
+
+struct DirectService {
+    name: &'static str,
+    command: &'static str,
+    args: &'static [&'static str],
+    // Runs to completion instead of staying in the background (e.g. udev-trigger)
+    oneshot: bool,
+}
+
+// Mirrors what the OpenRC init scripts would run, in the foreground so that daemons can be tracked
+const DIRECT_SERVICES: &[DirectService] = &[
+    DirectService {
+        name: "udev",
+        command: "/sbin/udevd",
+        args: &[],
+        oneshot: false,
+    },
+    DirectService {
+        name: "udev-trigger",
+        command: "/bin/udevadm",
+        args: &["trigger", "--type=devices", "--action=add"],
+        oneshot: true,
+    },
+    DirectService {
+        name: "udev-settle",
+        command: "/bin/udevadm",
+        args: &["settle"],
+        oneshot: true,
+    },
+    DirectService {
+        name: "iwd",
+        command: "/usr/libexec/iwd",
+        args: &[],
+        oneshot: false,
+    },
+    DirectService {
+        name: "redsocks",
+        command: "/usr/bin/redsocks",
+        args: &["-c", "/etc/redsocks.conf"],
+        oneshot: false,
+    },
+];
+
+// Spawns and supervises daemons itself, for when OpenRC is not initialized
+#[derive(Default)]
+pub struct Direct {
+    children: Mutex<HashMap<String, Child>>,
+    // Services that exited unsuccessfully without being asked to stop
+    crashed: Mutex<HashSet<String>>,
+}
+
+impl Direct {
+    pub fn new() -> Direct {
+        Direct::default()
+    }
+
+    fn pid_file_path(service: &str) -> String {
+        format!("{}/{}.pid", &DIRECT_PID_DIR_PATH, &service)
+    }
+
+    // Returns whether the child exited successfully if it exited, reaping it
+    // A child may already have been reaped by a SIGCHLD handler calling waitpid(-1) (e.g. in the init wrapper): this counts as a successful exit
+    fn reap(service: &str, child: &mut Child) -> Option<bool> {
+        match child.try_wait() {
+            Ok(Some(status)) => {
+                info!("Service '{}' exited with {}", &service, &status);
+                Some(status.success())
+            }
+            Ok(None) => None,
+            Err(_) => Some(true),
+        }
+    }
+
+    pub fn is_running(&self, service: &str) -> bool {
+        let mut children = self.children.lock().unwrap();
+        let Some(child) = children.get_mut(service) else {
+            return false;
+        };
+        if let Some(success) = Self::reap(&service, child) {
+            if !success {
+                self.crashed.lock().unwrap().insert(service.to_string());
+            }
+            children.remove(service);
+            let _ = fs::remove_file(Self::pid_file_path(&service));
+            return false;
+        }
+
+        true
+    }
+}
+
+impl Services for Direct {
+    fn start(&self, service: &str) -> Result<()> {
+        let definition = DIRECT_SERVICES
+            .iter()
+            .find(|definition| definition.name == service)
+            .ok_or_else(|| anyhow::anyhow!("Unknown service '{}'", &service))?;
+        if definition.oneshot {
+            return run_command(&definition.command, &definition.args)
+                .with_context(|| format!("Failed to start '{}' service", &service));
+        }
+        if self.is_running(&service) {
+            info!("Service '{}' is already running", &service);
+            return Ok(());
+        }
+
+        let child = Command::new(&definition.command)
+            .args(definition.args)
+            .spawn()
+            .with_context(|| format!("Failed to start '{}' service", &service))?;
+        info!("Started service '{}' with PID {}", &service, child.id());
+        self.crashed.lock().unwrap().remove(service);
+        fs::create_dir_all(&DIRECT_PID_DIR_PATH)?;
+        if let Err(e) = fs::write(Self::pid_file_path(&service), child.id().to_string()) {
+            warn!(
+                "Failed to write PID file for service '{}': {}",
+                &service, &e
+            );
+        }
+        self.children
+            .lock()
+            .unwrap()
+            .insert(service.to_string(), child);
+
+        Ok(())
+    }
+
+    fn stop(&self, service: &str) -> Result<()> {
+        let Some(mut child) = self.children.lock().unwrap().remove(service) else {
+            info!("Service '{}' is not running", &service);
+            return Ok(());
+        };
+        let _ = fs::remove_file(Self::pid_file_path(&service));
+        if Self::reap(&service, &mut child).is_some() {
+            return Ok(());
+        }
+
+        info!("Stopping service '{}'", &service);
+        kill(Pid::from_raw(child.id() as i32), Signal::SIGTERM)
+            .with_context(|| format!("Failed to stop '{}' service", &service))?;
+        let start = Instant::now();
+        while start.elapsed() < DIRECT_STOP_TIMEOUT {
+            if Self::reap(&service, &mut child).is_some() {
+                return Ok(());
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        warn!(
+            "Service '{}' did not stop after {} seconds: killing it",
+            &service,
+            DIRECT_STOP_TIMEOUT.as_secs()
+        );
+        let _ = child.kill();
+        let _ = child.wait();
+
+        Ok(())
+    }
+
+    fn status(&self) -> Result<Vec<ServiceStatus>> {
+        Ok(DIRECT_SERVICES
+            .iter()
+            .filter(|definition| !definition.oneshot)
+            .map(|definition| {
+                let state = if self.is_running(&definition.name) {
+                    ServiceState::Started
+                } else if self.crashed.lock().unwrap().contains(definition.name) {
+                    ServiceState::Crashed
+                } else {
+                    ServiceState::Stopped
+                };
+                ServiceStatus {
+                    name: definition.name.to_string(),
+                    state,
+                    log_tail: read_log_tail(&definition.name),
+                }
+            })
+            .collect())
+    }
+}
+
+// OpenRC gets initialized on first use if needed: services are only supervised directly when that is not possible
+pub fn get() -> &'static dyn Services {
+    let openrc = crate::openrc::get();
+    if openrc.is_up() {
+        openrc
+    } else {
+        DIRECT.get_or_init(Direct::new)
+    }
+}
+
+// Structured section for diagnostics, e.g. "Services: udev started, iwd crashed"
+pub fn status_summary() -> String {
+    match get().status() {
+        Ok(statuses) if !statuses.is_empty() => format!(
+            "Services: {}",
+            statuses
+                .iter()
+                .map(|status| format!(
+                    "{} {}",
+                    &status.name,
+                    format!("{:?}", &status.state).to_lowercase()
+                ))
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+        Ok(_) => "Services: none".to_string(),
+        Err(e) => format!("Services: not available ({})", &e),
+    }
+}
@@ -7,44 +7,166 @@ cfg_if::cfg_if! {
         use openssl::sign::Verifier;
         use openssl::hash::MessageDigest;
         use log::error;
+        use crate::system::{for_each_file_chunk, to_hex};
     }
 }
 
 use openssl::pkey::PKey;
 use openssl::pkey::Public;
 use std::fs;
+use std::sync::{Mutex, OnceLock};
 
+use crate::system::ProgressCallback;
+
+// Shipped in the initramfs, read-only
 const PUBKEY_PATH: &str = "/opt/key/public.pem";
 
+// Cached in memory once read: qinit never writes the key to disk, unless explicitly dumped in developer mode
+static PUBLIC_KEY: OnceLock<PKey<Public>> = OnceLock::new();
+
+// Every signature failure of this session, oldest first
+static SIGNATURE_FAILURES: Mutex<Vec<SignatureFailure>> = Mutex::new(Vec::new());
+
+#[derive(Debug, Clone)]
+pub struct SignatureFailure {
+    pub path: String,
+    pub sha256: String,
+}
+
+impl std::fmt::Display for SignatureFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid signature for '{}' (sha256 {})",
+            &self.path, &self.sha256
+        )
+    }
+}
+
 pub fn read_public_key() -> Result<PKey<Public>> {
+    if let Some(pubkey) = PUBLIC_KEY.get() {
+        return Ok(pubkey.clone());
+    }
+    // The command line only overrides the key shipped in the initramfs
+    let pubkey = match decode_public_key_from_cmdline()? {
+        Some(pubkey) => pubkey,
+        None if fs::exists(&PUBKEY_PATH)? => read_shipped_public_key()?,
+        None => {
+            cfg_if::cfg_if! {
+                if #[cfg(feature = "free_roam")] {
+                    // Signatures are not verified in this mode: the key only has to exist
+                    warn!("Free roam mode: no public key found, using a placeholder");
+                    let placeholder = PKey::generate_ed25519()?;
+                    PKey::public_key_from_raw_bytes(&placeholder.raw_public_key()?, openssl::pkey::Id::ED25519)?
+                } else {
+                    return Err(anyhow::anyhow!(
+                        "No public key found on kernel command line or at '{}'",
+                        &PUBKEY_PATH
+                    ));
+                }
+            }
+        }
+    };
+
+    Ok(PUBLIC_KEY.get_or_init(|| pubkey).clone())
+}
+
+fn read_shipped_public_key() -> Result<PKey<Public>> {
     info!("Reading embedded kernel public key");
     let pubkey_bytes =
         fs::read(&PUBKEY_PATH).with_context(|| "Failed to read public key from file")?;
-    let pubkey = PKey::public_key_from_pem(&pubkey_bytes)
-        .with_context(|| "Failed to read public key from PEM bytes")?;
+    PKey::public_key_from_pem(&pubkey_bytes)
+        .with_context(|| "Failed to read public key from PEM bytes")
+}
 
-    Ok(pubkey)
+fn decode_public_key_from_cmdline() -> Result<Option<PKey<Public>>> {
+    match crate::system::Cmdline::get()?.pubkey()? {
+        Some(pubkey_bytes) => {
+            info!("Decoding public key from kernel command line");
+            let pubkey = PKey::public_key_from_pem(&pubkey_bytes)
+                .or_else(|_| PKey::public_key_from_der(&pubkey_bytes))
+                .with_context(|| "Failed to read public key from kernel command line")?;
+            Ok(Some(pubkey))
+        }
+        None => Ok(None),
+    }
+}
+
+// For external verification of artifacts, e.g. with `openssl dgst -sha256 -verify public.pem`
+#[cfg(feature = "debug")]
+pub fn dump_public_key(path: &str) -> Result<()> {
+    let pubkey_pem = read_public_key()?
+        .public_key_to_pem()
+        .with_context(|| "Failed to encode public key as PEM")?;
+    fs::write(&path, &pubkey_pem)
+        .with_context(|| format!("Failed to write public key to '{}'", &path))?;
+    info!("Dumped public key to '{}'", &path);
+
+    Ok(())
+}
+
+pub fn check_signature(pubkey: &PKey<Public>, file: &str) -> Result<bool> {
+    check_signature_with_progress(&pubkey, &file, None)
 }
 
-pub fn check_signature(_pubkey: &PKey<Public>, file: &str) -> Result<bool> {
+pub fn check_signature_with_progress(
+    _pubkey: &PKey<Public>,
+    file: &str,
+    _progress: Option<ProgressCallback>,
+) -> Result<bool> {
     cfg_if::cfg_if! {
         if #[cfg(feature = "free_roam")] {
             warn!("Free roam mode: signature of file '{}' was not verified", &file);
             return Ok(true);
         } else {
             let digest_file = format!("{}{}", &file, &crate::GENERIC_DIGEST_EXT);
-            let data = fs::read(&file).with_context(|| format!("Could not read file '{}' for signature verification", &file))?;
             let signature = fs::read(&digest_file).with_context(|| format!("Could not read digest file '{}' for signature verification", &digest_file))?;
             let mut verifier = Verifier::new(MessageDigest::sha256(), &_pubkey)?;
-            verifier.update(&data)?;
+            // Computed in the same pass so that a failure can be reported with the offending file's hash
+            let mut hasher = openssl::sha::Sha256::new();
+            for_each_file_chunk(&file, _progress, |chunk| {
+                verifier.update(&chunk)?;
+                hasher.update(&chunk);
+                Ok(())
+            })
+            .with_context(|| format!("Could not read file '{}' for signature verification", &file))?;
             let pass = verifier.verify(&signature)?;
             if pass {
                 info!("File '{}': signature verified successfully", &file);
             } else {
-                error!("File '{}': invalid signature", &file);
+                let failure = SignatureFailure {
+                    path: file.to_string(),
+                    sha256: to_hex(&hasher.finish()),
+                };
+                error!("{}", &failure);
+                invalidate_cached_verdicts(&file);
+                SIGNATURE_FAILURES.lock().unwrap().push(failure);
             }
 
             Ok(pass)
         }
     }
 }
+
+// A replacement file must be checked from scratch rather than matched against a checksum recorded for the invalid one
+pub fn invalidate_cached_verdicts(file: &str) {
+    let checksum_file_path = format!("{}.sha256", &file);
+    if fs::exists(&checksum_file_path).unwrap_or(false) {
+        info!("Removing cached checksum '{}'", &checksum_file_path);
+        if let Err(e) = fs::remove_file(&checksum_file_path) {
+            log::warn!(
+                "Failed to remove cached checksum '{}': {}",
+                &checksum_file_path,
+                &e
+            );
+        }
+    }
+}
+
+pub fn signature_failures() -> Vec<SignatureFailure> {
+    SIGNATURE_FAILURES.lock().unwrap().clone()
+}
+
+pub fn last_signature_failure() -> Option<SignatureFailure> {
+    SIGNATURE_FAILURES.lock().unwrap().last().cloned()
+}
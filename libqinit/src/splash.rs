@@ -1,4 +1,5 @@
 use crate::boot_config::BootConfig;
+use crate::eink;
 use crate::system::{self, QINIT_BINARIES_DIR_PATH, run_command};
 
 use anyhow::Result;
@@ -75,6 +76,8 @@ pub fn generate_wallpaper(boot_config_mutex: &Arc<Mutex<BootConfig>>) -> Result<
         }
     }
 
+    // The wallpaper is shown in the current session's orientation
+    let (width, height) = eink::geometry().effective_size();
     system::mount_qinit_binaries()?;
 
     let mut count = 0;
@@ -88,9 +91,9 @@ pub fn generate_wallpaper(boot_config_mutex: &Arc<Mutex<BootConfig>>) -> Result<
                 "--output",
                 &WALLPAPER_OUT_FILE_PATH,
                 "-w",
-                &format!("{}", crate::SCREEN_W),
+                &width.to_string(),
                 "-h",
-                &format!("{}", crate::SCREEN_H),
+                &height.to_string(),
                 "-s",
                 &rand::random::<i32>().unsigned_abs().to_string(),
                 "-f",
@@ -1,20 +1,72 @@
 use anyhow::{Context, Result};
-use log::info;
+use chrono::{DateTime, Local};
+use log::{info, warn};
 use serde_json;
-use std::{fs, thread};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::chown;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
 
 cfg_if::cfg_if! {
     if #[cfg(feature = "debug")] {
-        use log::warn;
         use std::path::Path;
     }
 }
 
-use crate::system::{bulletproof_unmount, is_mountpoint, run_command};
+use crate::boot_config::BootConfig;
+use crate::secret::Secret;
+use crate::system::{
+    bulletproof_unmount, is_mountpoint, run_command, run_command_with_stdin, to_hex, wait_for,
+};
 
 pub const GOCRYPTFS_BINARY: &str = "/usr/bin/gocryptfs";
 pub const DISABLED_MODE_FILE: &str = "encryption_disabled";
 pub const DISABLED_MODE_PASSWORD: &str = "ENCRYPTION DISABLED";
+const GOCRYPTFS_CONFIG_FILE: &str = "gocryptfs.conf";
+// Next to the encrypted directories rather than inside them, where gocryptfs would complain about a file name it cannot decrypt
+const GOCRYPTFS_CONFIG_BACKUP_SUFFIX: &str = ".gocryptfs.conf.backup";
+// A copy of the configuration whose master key is wrapped by the recovery key instead of the password: gocryptfs only has one password per configuration file
+const GOCRYPTFS_RECOVERY_CONFIG_SUFFIX: &str = ".gocryptfs.recovery.conf";
+const RECOVERY_KEY_BYTES: usize = 20;
+const RECOVERY_KEY_GROUP_LENGTH: usize = 4;
+const GOCRYPTFS_DIRIV_FILE: &str = "gocryptfs.diriv";
+const NOT_FOUND: &str = "Not found";
+pub const PASSWORD_MIN_LENGTH: usize = 8;
+// Users are defined by the root filesystem
+const PASSWD_FILE_PATH: &str = "etc/passwd";
+// Scratch mountpoint for checking a password without touching the user's home
+const PASSWORD_CHECK_MOUNTPOINT: &str = "/run/qinit/gocryptfs-check";
+// The root filesystem bind-mounts home directories while booting
+const HOME_MOUNT_TIMEOUT: Duration = Duration::from_secs(60);
+// gocryptfs flushes and exits on its own once unmounted
+const GOCRYPTFS_EXIT_TIMEOUT: Duration = Duration::from_secs(10);
+const PROC_DIR_PATH: &str = "/proc";
+
+// Encrypted storage mounted by mount_storage(), by user: several homes may be unlocked in the same boot session, and all of them are unmounted cleanly on the way down
+static MOUNTS: LazyLock<Mutex<HashMap<String, MountState>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+// Why mounting a user's encrypted storage failed, as far as can be told without the password
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VaultStatus {
+    WrongPassword,
+    CorruptConfig,
+    MissingVault,
+}
+
+pub enum Credential {
+    Password(Secret),
+    RecoveryKey(Secret),
+}
+
+#[derive(Debug, Clone)]
+pub struct MountState {
+    pub mountpoint: String,
+    pub mounted_at: DateTime<Local>,
+    pub with_recovery_key: bool,
+}
 
 pub struct UserDetails {
     pub encryption_enabled: bool,
@@ -22,32 +74,69 @@ pub struct UserDetails {
     pub salt: String,
 }
 
-// In Quill OS, either the user sets a password and is forced to use storage encryption, either it does not set a password and cannot use storage encryption.
-pub fn get_users_using_storage_encryption() -> Result<Vec<String>> {
-    info!("Building list of users using storage encryption");
-    let users = fs::read_dir(&format!(
-        "{}/{}",
-        &crate::MAIN_PART_MOUNTPOINT,
-        &crate::SYSTEM_HOME_DIR
-    ))
-    .with_context(|| "Failed to read system home directory")?;
-    let mut users_using_storage_encryption: Vec<String> = Vec::new();
+// Users are the dotted directories holding one of the given files
+fn scan_users(marker_files: &[&str]) -> Result<Vec<String>> {
+    scan_users_in(
+        &format!(
+            "{}/{}",
+            &crate::MAIN_PART_MOUNTPOINT,
+            &crate::SYSTEM_HOME_DIR
+        ),
+        &marker_files,
+    )
+}
+
+fn scan_users_in(system_home_dir_path: &str, marker_files: &[&str]) -> Result<Vec<String>> {
+    let users = fs::read_dir(&system_home_dir_path)
+        .with_context(|| "Failed to read system home directory")?;
+    let mut found_users: Vec<String> = Vec::new();
     for user in users {
         let user = user?;
         if !user.metadata()?.is_dir() {
             continue;
         }
+        // Encrypted directories are the dotted ones: anything else is not a user's storage
+        let file_name = user.file_name().to_string_lossy().to_string();
+        let Some(username) = file_name.strip_prefix('.') else {
+            continue;
+        };
         let user_path = user.path().to_string_lossy().to_string();
-        if fs::exists(&format!("{}/gocryptfs.conf", &user_path))? {
-            users_using_storage_encryption
-                .push(user.file_name().to_string_lossy()[1..].to_string());
+        if username.is_empty() {
+            continue;
+        }
+        for marker_file in marker_files {
+            if fs::exists(&format!("{}/{}", &user_path, &marker_file))? {
+                found_users.push(username.to_string());
+                break;
+            }
         }
     }
+    found_users.sort();
+
+    Ok(found_users)
+}
+
+// In Quill OS, either the user sets a password and is forced to use storage encryption, either it does not set a password and cannot use storage encryption.
+pub fn get_users_using_storage_encryption() -> Result<Vec<String>> {
+    info!("Building list of users using storage encryption");
+    let users_using_storage_encryption = scan_users(&[GOCRYPTFS_CONFIG_FILE])?;
     info!("List is as follows: {:?}", &users_using_storage_encryption);
 
     Ok(users_using_storage_encryption)
 }
 
+// Whether they use storage encryption or not
+pub fn get_users() -> Result<Vec<String>> {
+    scan_users(&[GOCRYPTFS_CONFIG_FILE, DISABLED_MODE_FILE])
+}
+
+pub(crate) fn get_users_in(system_home_dir_path: &str) -> Result<Vec<String>> {
+    scan_users_in(
+        &system_home_dir_path,
+        &[GOCRYPTFS_CONFIG_FILE, DISABLED_MODE_FILE],
+    )
+}
+
 pub fn get_user_storage_encryption_status(user: &str) -> Result<bool> {
     Ok(!fs::exists(format!(
         "{}/{}/.{}/{}",
@@ -83,7 +172,7 @@ pub fn get_encryption_user_details(user: &str) -> Result<UserDetails> {
             &user
         )
     })?;
-    let not_found = "Not found";
+    let not_found = NOT_FOUND;
     if let Some(encrypted_key) = json.get("EncryptedKey")
         && let Some(salt_str) = json["ScryptObject"]["Salt"].as_str()
     {
@@ -109,18 +198,17 @@ pub fn get_encryption_user_details(user: &str) -> Result<UserDetails> {
     }
 }
 
-pub fn mount_storage(user: &str, password: &str) -> Result<()> {
+pub fn mount_storage(user: &str, credential: &Credential) -> Result<()> {
     info!("Attempting to mount encrypted storage for user '{}'", &user);
     let home_path_base = format!("{}/{}", &crate::OVERLAY_MOUNTPOINT, &crate::SYSTEM_HOME_DIR);
     let home_path_encrypted = format!("{}/.{}", &home_path_base, &user);
     let home_mountpoint_path = format!("{}/{}", &home_path_base, &user);
 
-    loop {
-        if is_mountpoint(&home_path_base)? {
-            break;
-        }
-        thread::sleep(std::time::Duration::from_millis(250));
-    }
+    wait_for(
+        || Ok(is_mountpoint(&home_path_base)?.then_some(())),
+        HOME_MOUNT_TIMEOUT,
+        "the home directories to be mounted",
+    )?;
 
     cfg_if::cfg_if! {
         if #[cfg(feature = "debug")] {
@@ -136,17 +224,36 @@ pub fn mount_storage(user: &str, password: &str) -> Result<()> {
         }
     }
 
+    if is_mounted(&user) {
+        return Err(anyhow::anyhow!(
+            "Encrypted storage of user '{}' is already mounted",
+            &user
+        ));
+    }
     if !is_mountpoint(&home_mountpoint_path)? {
-        run_command(
-            "/bin/sh",
-            &[
-                "-c",
-                &format!(
-                    "printf '{}' | {} -allow_other {} {}",
-                    &password, &GOCRYPTFS_BINARY, &home_path_encrypted, &home_mountpoint_path,
-                ),
-            ],
-        )?;
+        let recovery_config_path = recovery_config_path(&user);
+        let (mut args, password) = match credential {
+            Credential::Password(password) => (vec!["-allow_other"], password.clone()),
+            Credential::RecoveryKey(recovery_key) => (
+                vec!["-allow_other", "-config", recovery_config_path.as_str()],
+                normalize_recovery_key(&recovery_key),
+            ),
+        };
+        args.extend([home_path_encrypted.as_str(), home_mountpoint_path.as_str()]);
+        // gocryptfs reads the password from standard input when it is not a terminal
+        run_command_with_stdin(&GOCRYPTFS_BINARY, &args, password.expose().as_bytes())
+            .with_context(|| format!("Failed to mount encrypted storage for user '{}'", &user))?;
+        MOUNTS.lock().unwrap().insert(
+            user.to_string(),
+            MountState {
+                mountpoint: home_mountpoint_path.clone(),
+                mounted_at: Local::now(),
+                with_recovery_key: matches!(credential, Credential::RecoveryKey(_)),
+            },
+        );
+        if let Err(e) = back_up_config(&user) {
+            warn!("{:?}", &e);
+        }
     } else {
         return Err(anyhow::anyhow!(
             "User home directory seems to be already mounted"
@@ -156,6 +263,33 @@ pub fn mount_storage(user: &str, password: &str) -> Result<()> {
     Ok(())
 }
 
+// Only knows about what mount_storage() did: cheap enough to be asked on every login
+pub fn is_mounted(user: &str) -> bool {
+    MOUNTS.lock().unwrap().contains_key(user)
+}
+
+// Sorted, so that users are always unmounted in the same order
+// The root filesystem cannot ask for this until libquillcom's CommandToQinit has a variant for it, which this tree cannot add
+pub fn mounted_users() -> Vec<String> {
+    let mut users: Vec<String> = MOUNTS.lock().unwrap().keys().cloned().collect();
+    users.sort();
+    users
+}
+
+pub fn mount_state(user: &str) -> Option<MountState> {
+    MOUNTS.lock().unwrap().get(user).cloned()
+}
+
+// Asks the kernel instead: also catches mounts made behind qinit's back, e.g. by the root filesystem
+pub fn is_storage_mounted(user: &str) -> Result<bool> {
+    is_mountpoint(&format!(
+        "{}/{}/{}",
+        &crate::OVERLAY_MOUNTPOINT,
+        &crate::SYSTEM_HOME_DIR,
+        &user
+    ))
+}
+
 pub fn unmount_storage(user: &str) -> Result<()> {
     info!("Unmounting encrypted storage for user '{}'", &user);
     bulletproof_unmount(&format!(
@@ -164,6 +298,523 @@ pub fn unmount_storage(user: &str) -> Result<()> {
         &crate::SYSTEM_HOME_DIR,
         &user
     ))?;
+    if let Some(mount_state) = MOUNTS.lock().unwrap().remove(user) {
+        info!(
+            "Unmounted '{}', mounted since {}{}",
+            &mount_state.mountpoint,
+            mount_state.mounted_at.format("%Y-%m-%d %H:%M:%S"),
+            if mount_state.with_recovery_key {
+                " with the recovery key"
+            } else {
+                ""
+            }
+        );
+    }
+
+    Ok(())
+}
+
+// The daemon keeps the encrypted directory's path in its command line
+fn gocryptfs_running(user: &str) -> bool {
+    let encrypted_dir = format!("/.{}", &user);
+    let Ok(processes) = fs::read_dir(&PROC_DIR_PATH) else {
+        return false;
+    };
+    processes.flatten().any(|process| {
+        fs::read(process.path().join("cmdline")).is_ok_and(|cmdline| {
+            let args: Vec<String> = cmdline
+                .split(|byte| *byte == 0)
+                .map(|arg| String::from_utf8_lossy(&arg).to_string())
+                .collect();
+            args.first()
+                .is_some_and(|command| command.ends_with("gocryptfs"))
+                && args.iter().any(|arg| arg.ends_with(&encrypted_dir))
+        })
+    })
+}
+
+// Never fails: shutdown goes on regardless, with whatever is still mounted logged
+pub fn unmount_all() {
+    for user in &mounted_users() {
+        if let Err(e) = unmount_storage(&user) {
+            warn!("{:?}", &e);
+            continue;
+        }
+        if let Err(e) = wait_for(
+            || Ok((!gocryptfs_running(&user)).then_some(())),
+            GOCRYPTFS_EXIT_TIMEOUT,
+            &format!("gocryptfs to exit for user '{}'", &user),
+        ) {
+            warn!("{}", &e);
+        }
+    }
+
+    let still_mounted = mounted_users();
+    if !still_mounted.is_empty() {
+        warn!(
+            "Encrypted storage still mounted for users: {:?}",
+            &still_mounted
+        );
+    }
+}
+
+fn encrypted_dir_path(user: &str) -> String {
+    format!(
+        "{}/{}/.{}",
+        &crate::MAIN_PART_MOUNTPOINT,
+        &crate::SYSTEM_HOME_DIR,
+        &user
+    )
+}
+
+fn config_path(user: &str) -> String {
+    format!("{}/{}", &encrypted_dir_path(&user), &GOCRYPTFS_CONFIG_FILE)
+}
+
+fn config_backup_path(user: &str) -> String {
+    format!(
+        "{}/{}/.{}{}",
+        &crate::MAIN_PART_MOUNTPOINT,
+        &crate::SYSTEM_HOME_DIR,
+        &user,
+        &GOCRYPTFS_CONFIG_BACKUP_SUFFIX
+    )
+}
+
+// Only a configuration that just worked is worth keeping
+fn back_up_config(user: &str) -> Result<()> {
+    let backup_path = config_backup_path(&user);
+    fs::copy(&config_path(&user), &backup_path).with_context(|| {
+        format!(
+            "Failed to back up gocryptfs configuration file for user '{}'",
+            &user
+        )
+    })?;
+
+    Ok(())
+}
+
+// Along with the configuration backup and the recovery configuration: the plain home directory is the system's business
+pub fn rename_storage(user: &str, new_user: &str) -> Result<()> {
+    if is_mounted(&user) || is_storage_mounted(&user)? {
+        return Err(anyhow::anyhow!("Home of user '{}' is in use", &user));
+    }
+    if fs::exists(&encrypted_dir_path(&new_user))? {
+        return Err(anyhow::anyhow!(
+            "Storage of user '{}' already exists",
+            &new_user
+        ));
+    }
+    for (path, new_path) in [
+        (encrypted_dir_path(&user), encrypted_dir_path(&new_user)),
+        (config_backup_path(&user), config_backup_path(&new_user)),
+        (recovery_config_path(&user), recovery_config_path(&new_user)),
+    ] {
+        if fs::exists(&path)? {
+            fs::rename(&path, &new_path)
+                .with_context(|| format!("Failed to rename '{}' to '{}'", &path, &new_path))?;
+        }
+    }
+    info!("Renamed storage of user '{}' to '{}'", &user, &new_user);
+
+    crate::system::sync_disks()
+}
+
+// Marks a new user as not using storage encryption: see get_user_storage_encryption_status()
+pub fn init_disabled_mode(user: &str) -> Result<()> {
+    let encrypted_dir_path = encrypted_dir_path(&user);
+    fs::create_dir_all(&encrypted_dir_path)
+        .with_context(|| format!("Failed to create '{}'", &encrypted_dir_path))?;
+    let disabled_mode_file_path = format!("{}/{}", &encrypted_dir_path, &DISABLED_MODE_FILE);
+    fs::File::create(&disabled_mode_file_path)
+        .with_context(|| format!("Failed to create '{}'", &disabled_mode_file_path))?;
+
+    crate::system::sync_disks()
+}
+
+pub fn has_config_backup(user: &str) -> bool {
+    fs::exists(&config_backup_path(&user)).unwrap_or(false)
+}
+
+pub fn restore_config_backup(user: &str) -> Result<()> {
+    info!(
+        "Restoring gocryptfs configuration file for user '{}' from its backup",
+        &user
+    );
+    let path = config_path(&user);
+    // Kept around in case the backup turns out to be the wrong one
+    if fs::exists(&path)? {
+        fs::rename(&path, format!("{}.corrupted", &path))?;
+    }
+    fs::copy(&config_backup_path(&user), &path).with_context(|| {
+        format!(
+            "Failed to restore gocryptfs configuration file for user '{}'",
+            &user
+        )
+    })?;
+    crate::system::sync_disks()?;
 
     Ok(())
 }
+
+// Meant to be called after mount_storage() failed: a password problem is only assumed once the vault itself looks fine
+pub fn check_vault(user: &str) -> VaultStatus {
+    if !fs::exists(&encrypted_dir_path(&user)).unwrap_or(false)
+        || !fs::exists(&config_path(&user)).unwrap_or(false)
+    {
+        warn!("Encrypted storage for user '{}' is missing", &user);
+        return VaultStatus::MissingVault;
+    }
+    match get_encryption_user_details(&user) {
+        Ok(user_details)
+            if user_details.encrypted_key != NOT_FOUND && user_details.salt != NOT_FOUND => {}
+        Ok(_) => {
+            warn!(
+                "gocryptfs configuration file for user '{}' lacks its key",
+                &user
+            );
+            return VaultStatus::CorruptConfig;
+        }
+        Err(e) => {
+            warn!("{:?}", &e);
+            return VaultStatus::CorruptConfig;
+        }
+    }
+    if let Err(e) = run_command(&GOCRYPTFS_BINARY, &["-info", &encrypted_dir_path(&user)]) {
+        warn!(
+            "gocryptfs rejected configuration file for user '{}': {:?}",
+            &user, &e
+        );
+        return VaultStatus::CorruptConfig;
+    }
+
+    VaultStatus::WrongPassword
+}
+
+fn home_mountpoint_path(user: &str) -> String {
+    format!(
+        "{}/{}/{}",
+        &crate::MAIN_PART_MOUNTPOINT,
+        &crate::SYSTEM_HOME_DIR,
+        &user
+    )
+}
+
+// Checked by the GUI before anything is written: init_vault() does not ask twice
+pub fn validate_new_password(password: &Secret, confirmation: &Secret) -> Result<()> {
+    if password.expose().chars().count() < PASSWORD_MIN_LENGTH {
+        return Err(anyhow::anyhow!(
+            "Password must be at least {} characters long",
+            &PASSWORD_MIN_LENGTH
+        ));
+    }
+    if password != confirmation {
+        return Err(anyhow::anyhow!("Passwords do not match"));
+    }
+    // Passwords are sent line by line to gocryptfs and chpasswd
+    if password.expose().contains('\n') {
+        return Err(anyhow::anyhow!("Password must not contain line breaks"));
+    }
+
+    Ok(())
+}
+
+// Each line reads "name:password:uid:gid:gecos:home:shell"
+fn user_ids(user: &str) -> Result<(u32, u32)> {
+    let passwd_path = format!("{}/{}", &crate::OVERLAY_MOUNTPOINT, &PASSWD_FILE_PATH);
+    let passwd = fs::read_to_string(&passwd_path)
+        .with_context(|| format!("Failed to read '{}'", &passwd_path))?;
+    for line in passwd.lines() {
+        let fields: Vec<&str> = line.split(':').collect();
+        if fields.len() >= 4 && fields[0] == user {
+            return Ok((
+                fields[2]
+                    .parse()
+                    .with_context(|| format!("Invalid user ID for user '{}'", &user))?,
+                fields[3]
+                    .parse()
+                    .with_context(|| format!("Invalid group ID for user '{}'", &user))?,
+            ));
+        }
+    }
+
+    Err(anyhow::anyhow!("User '{}' does not exist", &user))
+}
+
+pub fn init_vault(user: &str, password: &Secret) -> Result<()> {
+    info!("Initializing encrypted storage for user '{}'", &user);
+    let encrypted_dir_path = encrypted_dir_path(&user);
+    let home_mountpoint_path = home_mountpoint_path(&user);
+    if fs::exists(&config_path(&user))? {
+        return Err(anyhow::anyhow!(
+            "User '{}' already has encrypted storage",
+            &user
+        ));
+    }
+    let (uid, gid) = user_ids(&user)?;
+    // gocryptfs refuses to mount over files, and they would end up hidden anyway
+    if fs::exists(&home_mountpoint_path)? && fs::read_dir(&home_mountpoint_path)?.next().is_some() {
+        return Err(anyhow::anyhow!(
+            "Home directory of user '{}' is not empty",
+            &user
+        ));
+    }
+
+    let created_encrypted_dir = !fs::exists(&encrypted_dir_path)?;
+    let disabled_mode_file_path = format!("{}/{}", &encrypted_dir_path, &DISABLED_MODE_FILE);
+    let disabled_mode_was_set = fs::exists(&disabled_mode_file_path)?;
+    let result = (|| -> Result<()> {
+        fs::create_dir_all(&encrypted_dir_path)?;
+        // gocryptfs only initializes empty directories
+        if disabled_mode_was_set {
+            fs::remove_file(&disabled_mode_file_path)?;
+        }
+        run_command_with_stdin(
+            &GOCRYPTFS_BINARY,
+            &["-init", "-q", &encrypted_dir_path],
+            password.expose().as_bytes(),
+        )?;
+        fs::create_dir_all(&home_mountpoint_path)?;
+        chown(&home_mountpoint_path, Some(uid), Some(gid))?;
+        crate::system::sync_disks()
+    })();
+
+    if let Err(e) = result {
+        // Whatever was there before is put back as it was
+        let cleanup = if created_encrypted_dir {
+            fs::remove_dir_all(&encrypted_dir_path)
+        } else {
+            [GOCRYPTFS_CONFIG_FILE, GOCRYPTFS_DIRIV_FILE]
+                .iter()
+                .map(|file| format!("{}/{}", &encrypted_dir_path, &file))
+                .filter(|path| fs::exists(&path).unwrap_or(false))
+                .try_for_each(fs::remove_file)
+                .and_then(|()| match disabled_mode_was_set {
+                    true => fs::File::create(&disabled_mode_file_path).map(|_| ()),
+                    false => Ok(()),
+                })
+        };
+        if let Err(cleanup_error) = cleanup {
+            warn!(
+                "Failed to clean up encrypted storage of user '{}': {}",
+                &user, &cleanup_error
+            );
+        }
+        return Err(e).with_context(|| {
+            format!(
+                "Failed to initialize encrypted storage for user '{}'",
+                &user
+            )
+        });
+    }
+
+    Ok(())
+}
+
+// A read-only mount is the only way gocryptfs checks a password without handing out the master key
+pub fn check_password(user: &str, password: &Secret) -> Result<()> {
+    fs::create_dir_all(&PASSWORD_CHECK_MOUNTPOINT)?;
+    run_command_with_stdin(
+        &GOCRYPTFS_BINARY,
+        &[
+            "-ro",
+            &encrypted_dir_path(&user),
+            &PASSWORD_CHECK_MOUNTPOINT,
+        ],
+        password.expose().as_bytes(),
+    )
+    .with_context(|| format!("Wrong password for encrypted storage of user '{}'", &user))?;
+
+    bulletproof_unmount(&PASSWORD_CHECK_MOUNTPOINT)
+}
+
+pub fn change_password(user: &str, old_password: &Secret, new_password: &Secret) -> Result<()> {
+    info!("Changing encrypted storage password for user '{}'", &user);
+    if is_storage_mounted(&user)? {
+        return Err(anyhow::anyhow!(
+            "Encrypted storage of user '{}' is in use",
+            &user
+        ));
+    }
+    // Old password first, then the new one
+    let passwords = Secret::new(format!(
+        "{}\n{}\n",
+        old_password.expose(),
+        new_password.expose()
+    ));
+    run_command_with_stdin(
+        &GOCRYPTFS_BINARY,
+        &["-passwd", "-q", &encrypted_dir_path(&user)],
+        passwords.expose().as_bytes(),
+    )
+    .with_context(|| {
+        format!(
+            "Failed to change encrypted storage password for user '{}'",
+            &user
+        )
+    })?;
+    // Otherwise, the backup would still only open with the old password
+    if let Err(e) = back_up_config(&user) {
+        warn!("{:?}", &e);
+    }
+
+    crate::system::sync_disks()
+}
+
+fn recovery_config_path(user: &str) -> String {
+    format!(
+        "{}/{}/.{}{}",
+        &crate::MAIN_PART_MOUNTPOINT,
+        &crate::SYSTEM_HOME_DIR,
+        &user,
+        &GOCRYPTFS_RECOVERY_CONFIG_SUFFIX
+    )
+}
+
+pub fn has_recovery_key(user: &str) -> bool {
+    fs::exists(&recovery_config_path(&user)).unwrap_or(false)
+}
+
+// Recovery keys are shown as groups of uppercase hexadecimal digits (e.g. "1A2B-3C4D-..."), but may be typed in any case, with or without separators
+fn normalize_recovery_key(recovery_key: &Secret) -> Secret {
+    let digits: Vec<char> = recovery_key
+        .expose()
+        .chars()
+        .filter(|character| character.is_ascii_alphanumeric())
+        .map(|character| character.to_ascii_uppercase())
+        .collect();
+    Secret::new(
+        digits
+            .chunks(RECOVERY_KEY_GROUP_LENGTH)
+            .map(|group| group.iter().collect::<String>())
+            .collect::<Vec<String>>()
+            .join("-"),
+    )
+}
+
+// Wraps the vault's master key with a fresh recovery key in a second configuration file: the password keeps working as before
+pub fn create_recovery_key(user: &str, password: &Secret) -> Result<Secret> {
+    info!("Creating recovery key for user '{}'", &user);
+    let mut random_bytes = [0u8; RECOVERY_KEY_BYTES];
+    openssl::rand::rand_bytes(&mut random_bytes)
+        .with_context(|| "Failed to generate recovery key")?;
+    let recovery_key = normalize_recovery_key(&Secret::new(to_hex(&random_bytes)));
+    random_bytes.fill(0);
+
+    let recovery_config_path = recovery_config_path(&user);
+    let result = fs::copy(&config_path(&user), &recovery_config_path)
+        .with_context(|| "Failed to copy gocryptfs configuration file")
+        .and_then(|_| {
+            // Old password first, then the new one
+            let passwords = Secret::new(format!(
+                "{}\n{}\n",
+                password.expose(),
+                recovery_key.expose()
+            ));
+            run_command_with_stdin(
+                &GOCRYPTFS_BINARY,
+                &[
+                    "-passwd",
+                    "-q",
+                    "-config",
+                    &recovery_config_path,
+                    &encrypted_dir_path(&user),
+                ],
+                passwords.expose().as_bytes(),
+            )
+        })
+        .and_then(|()| crate::system::sync_disks());
+    if let Err(e) = result {
+        // A copy still wrapped by the password would pass for a recovery key
+        if let Err(e) = fs::remove_file(&recovery_config_path) {
+            warn!("Failed to remove '{}': {}", &recovery_config_path, &e);
+        }
+        return Err(e)
+            .with_context(|| format!("Failed to create recovery key for user '{}'", &user));
+    }
+
+    Ok(recovery_key)
+}
+
+// Only a digest of the recovery key's fingerprint is kept: enough to tell that a recovery key exists, useless for unlocking anything
+pub fn record_recovery_key(
+    boot_config: &mut BootConfig,
+    user: &str,
+    recovery_key: Option<&Secret>,
+) -> Result<()> {
+    let digest = recovery_key.map(|recovery_key| {
+        let fingerprint = to_hex(&openssl::sha::sha256(recovery_key.expose().as_bytes()));
+        to_hex(&openssl::sha::sha256(fingerprint.as_bytes()))[0..16].to_string()
+    });
+    boot_config
+        .system
+        .recovery_keys
+        .insert(user.to_string(), digest.clone());
+    BootConfig::update_stored(|stored_boot_config| {
+        stored_boot_config
+            .system
+            .recovery_keys
+            .insert(user.to_string(), digest);
+    })?;
+
+    Ok(())
+}
+
+// Overwritten in place before being unlinked: merely unlinking would leave the wrapped master key readable in free blocks
+pub fn shred_file(path: &str) -> Result<()> {
+    let length = fs::metadata(&path)?.len() as usize;
+    let mut random_bytes = vec![0u8; length];
+    openssl::rand::rand_bytes(&mut random_bytes)?;
+    let mut file = fs::OpenOptions::new().write(true).open(&path)?;
+    file.write_all(&random_bytes)?;
+    file.sync_all()?;
+    fs::remove_file(&path)?;
+
+    Ok(())
+}
+
+// Configuration files go first: without them, whatever ciphertext survives an interrupted erase can never be decrypted again
+pub fn destroy_vault(boot_config: &mut BootConfig, user: &str) -> Result<()> {
+    warn!("Destroying encrypted storage of user '{}'", &user);
+    // The user name ends up in a path that is removed recursively
+    if user.is_empty() || user.starts_with('.') || user.contains('/') {
+        return Err(anyhow::anyhow!("Invalid user name '{}'", &user));
+    }
+    let encrypted_dir_path = encrypted_dir_path(&user);
+    if !fs::exists(&encrypted_dir_path)? {
+        return Err(anyhow::anyhow!(
+            "Encrypted storage of user '{}' does not exist",
+            &user
+        ));
+    }
+    if is_mounted(&user) || is_storage_mounted(&user).unwrap_or(false) {
+        unmount_storage(&user)?;
+    }
+
+    for path in [
+        config_path(&user),
+        config_backup_path(&user),
+        recovery_config_path(&user),
+    ] {
+        if fs::exists(&path)? {
+            shred_file(&path).with_context(|| format!("Failed to destroy '{}'", &path))?;
+        }
+    }
+    // Holds the password of the storage that is going away
+    crate::pin::disable_quick_unlock(&user)?;
+    crate::system::sync_disks()?;
+    fs::remove_dir_all(&encrypted_dir_path)
+        .with_context(|| format!("Failed to remove '{}'", &encrypted_dir_path))?;
+
+    let forget_user = |boot_config: &mut BootConfig| {
+        if boot_config.system.default_user.as_deref() == Some(user) {
+            boot_config.system.default_user = None;
+        }
+        boot_config.system.recovery_keys.remove(user);
+    };
+    forget_user(boot_config);
+    BootConfig::update_stored(forget_user)?;
+    info!("Destroyed encrypted storage of user '{}'", &user);
+
+    crate::system::sync_disks()
+}
@@ -1,30 +1,43 @@
 use anyhow::{Context, Result};
 use base64::prelude::*;
 use libquillcom::socket::PrimitiveShutDownType;
-use log::{debug, info, warn};
+use log::{debug, error, info, warn};
+use nix::sys::stat::{Mode, SFlag, makedev, mknod};
+use nix::time::{ClockId, clock_gettime};
 use openssl::pkey::PKey;
 use openssl::pkey::Public;
 use rand::Rng;
 use rand::distr::Alphanumeric;
-use regex::Regex;
 use rmesg;
+use serde::{Deserialize, Serialize};
 use sha256;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::env;
-use std::os::unix::fs::symlink;
-use std::path::Path;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::{MetadataExt, PermissionsExt, chown, lchown, symlink};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ExitStatus, Stdio};
 use std::sync::{
-    Arc,
+    Arc, Condvar, Mutex, OnceLock,
     atomic::{AtomicBool, Ordering},
+    mpsc,
 };
 use std::{fs, process::Command, thread, time::Duration};
-use sys_mount::{Mount, UnmountFlags, unmount};
+use sys_mount::{Mount, MountFlags, UnmountFlags, unmount};
 use walkdir::WalkDir;
+use xz2::stream::{Check, Stream};
+use xz2::write::XzEncoder;
 
 use crate::boot_config::BootConfig;
 use crate::netboot::{NETBOOT_DEVICE_NODE, NetBootStatus};
+use crate::partitions::{self, Partition};
 use crate::rootfs::run_chroot_command;
+use crate::services;
 use crate::signing::check_signature;
 
+pub mod kmod;
+
 pub const MODULES_DIR_PATH: &str = "/lib/modules";
 pub const MODULES_ARCHIVE: &str = "modules.squashfs";
 pub const FIRMWARE_DIR_PATH: &str = "/lib/firmware";
@@ -34,8 +47,48 @@ pub const QINIT_BINARIES_ARCHIVE: &str = "qinit_binaries.squashfs";
 pub const QINIT_BINARIES_DIR_PATH: &str = "/qinit_binaries/";
 
 const REBOOT_BINARY_PATH: &str = "/sbin/reboot";
+const E2FSCK_BINARY_PATH: &str = "/sbin/e2fsck";
+const FSCK_TIMEOUT: Duration = Duration::from_secs(300);
+const MODPROBE_TIMEOUT: Duration = Duration::from_secs(30);
+pub const MOUNT_TIMEOUT: Duration = Duration::from_secs(60);
+pub const DEFAULT_DEVICE_WAIT_TIMEOUT_SECS: u64 = 60;
 const POWER_OFF_BINARY_PATH: &str = "/sbin/poweroff";
+const POWER_STATE_PATH: &str = "/sys/power/state";
 const TIMEZONE_FILES_DIR_PATH: &str = "/usr/share/zoneinfo/";
+const CMDLINE_PATH: &str = "/proc/cmdline";
+const MOUNTINFO_PATH: &str = "/proc/self/mountinfo";
+const ZRAM_DEVICE_PATH: &str = "/dev/zram0";
+const ZRAM_SYSFS_PATH: &str = "/sys/block/zram0";
+// Swap on zram should always be preferred over any disk-backed swap the root filesystem may set up
+const ZRAM_SWAP_PRIORITY: &str = "100";
+pub const DEFAULT_ZRAM_ALGORITHM: &str = "zstd";
+pub const DEFAULT_HOSTNAME: &str = "pinenote";
+const HOSTNAME_MAX_LENGTH: usize = 253;
+const HOSTNAME_LABEL_MAX_LENGTH: usize = 63;
+const CMDLINE_PUBKEY_PROPERTY: &str = "quill_pubkey";
+const SOC_SERIAL_NUMBER_PATH: &str = "/proc/device-tree/serial-number";
+const FILE_CHUNK_SIZE: usize = 1024 * 1024;
+const STDERR_TAIL_LINES: usize = 20;
+// Longer lines are split: keeps memory bounded for commands that write huge amounts of stderr without newlines
+const STDERR_LINE_MAX_BYTES: u64 = 512;
+// Grace period for the last stderr lines to come in once the command exited
+const STDERR_DRAIN_TIMEOUT: Duration = Duration::from_millis(200);
+// Waits for hardware that last longer than this are reported, rather than looking like a hang
+const WAIT_REPORT_THRESHOLD: Duration = Duration::from_secs(2);
+const WAIT_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+const WAIT_INITIAL_DELAY: Duration = Duration::from_millis(50);
+const WAIT_MAX_DELAY: Duration = Duration::from_secs(1);
+const WRITE_TEST_FILE: &str = ".qinit_write_test";
+// Enough for the boot configuration and the waveform backup
+const MIN_WRITABLE_FREE_SPACE_BYTES: u64 = 4 * 1024 * 1024;
+pub const DEFAULT_LOW_SPACE_THRESHOLD_MB: u64 = 200;
+const MEMINFO_PATH: &str = "/proc/meminfo";
+const LOADAVG_PATH: &str = "/proc/loadavg";
+const SELF_STATUS_PATH: &str = "/proc/self/status";
+// Boot phases that use up more available memory than this are flagged in the boot timing summary
+const PHASE_MEMORY_DELTA_WARNING_KB: i64 = 32 * 1024;
+// LZMA_PRESET_EXTREME from liblzma
+const XZ_PRESET_EXTREME: u32 = 0x8000_0000;
 const EXCLUDED_TIMEZONE_FILES: [&str; 5] = [
     "posixrules",
     "zone.tab",
@@ -43,6 +96,36 @@ const EXCLUDED_TIMEZONE_FILES: [&str; 5] = [
     "iso3166.tab",
     "leap-seconds.list",
 ];
+// Copies of the whole database with other leap second rules
+const EXCLUDED_TIMEZONE_DIRS: [&str; 2] = ["posix/", "right/"];
+const TIMEZONE_FILE_MAGIC: &[u8; 4] = b"TZif";
+const LOCALTIME_PATH: &str = "/etc/localtime";
+
+pub const BOOT_TIMING_REPORT_PATH: &str = "/var/log/boot_timing.ron";
+// Relative to the main partition's system directory
+const BOOT_HISTORY_FILE: &str = "boot_history.ron";
+const BOOT_HISTORY_MAX_RECORDS: usize = 50;
+const BOOT_ESTIMATE_RECORDS: usize = 5;
+const RTC_WAKEALARM_PATH: &str = "/sys/class/rtc/rtc0/wakealarm";
+const POWER_ON_ALARM_MAX_DELAY_SECS: i64 = 30 * 24 * 60 * 60;
+// Written next to the boot configuration when powering off with an alarm set, so that the next boot can tell why it happened
+const POWER_ON_ALARM_FILE: &str = "power_on_alarm";
+// How late after the alarm time a boot still counts as woken up by it
+const POWER_ON_ALARM_WAKE_SLACK_SECS: i64 = 10 * 60;
+// The first stage is a separate process: it hands its phases over to the second stage through this file
+const FIRST_STAGE_BOOT_PHASES_PATH: &str = "/boot_phases.ron";
+// Likewise for unfixable filesystem corruption: the first stage has no GUI to offer recovery with
+const FIRST_STAGE_FILESYSTEM_CORRUPTION_PATH: &str = "/filesystem_corruption.ron";
+
+static CMDLINE: OnceLock<Cmdline> = OnceLock::new();
+static BOOT_PHASES: Mutex<Vec<BootPhase>> = Mutex::new(Vec::new());
+static ZRAM_SWAP_ACTIVE: AtomicBool = AtomicBool::new(false);
+static BOOT_PARTITION_WRITE_ISSUE: Mutex<Option<PartitionWriteIssue>> = Mutex::new(None);
+static FILESYSTEM_CORRUPTION: Mutex<Vec<FilesystemCorruptedError>> = Mutex::new(Vec::new());
+static DISK_USAGE: Mutex<Vec<PartitionUsage>> = Mutex::new(Vec::new());
+// Seconds since the UNIX epoch, programmed into the RTC on the next power off
+static POWER_ON_ALARM: Mutex<Option<i64>> = Mutex::new(None);
+static WAKE_REASON: Mutex<Option<WakeReason>> = Mutex::new(None);
 
 #[derive(PartialEq)]
 pub enum BootCommand {
@@ -54,17 +137,389 @@ pub enum BootCommand {
     BootFinished,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PartitionWriteIssue {
+    ReadOnly,
+    Full,
+}
+
+impl PartitionWriteIssue {
+    pub fn notice(&self) -> &'static str {
+        match self {
+            PartitionWriteIssue::ReadOnly => {
+                "Boot partition is read-only — settings will not be saved"
+            }
+            PartitionWriteIssue::Full => "Boot partition is full — settings will not be saved",
+        }
+    }
+}
+
 pub struct BootCommandForm {
     pub command: BootCommand,
     pub can_shut_down: Option<Arc<AtomicBool>>,
 }
 
+#[derive(Debug, PartialEq)]
+pub enum FilesystemCheckOutcome {
+    Clean,
+    Repaired,
+    RebootRequired,
+    TimedOut,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilesystemCorruptedError {
+    pub device: String,
+    pub exit_code: i32,
+}
+
+impl std::fmt::Display for FilesystemCorruptedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Unfixable filesystem corruption on '{}' (e2fsck exit code {}): a recovery boot is required to restore this partition",
+            &self.device, &self.exit_code
+        )
+    }
+}
+
+impl std::error::Error for FilesystemCorruptedError {}
+
+#[derive(Debug)]
+pub struct TimedOutError {
+    pub command: String,
+    pub timeout: Duration,
+}
+
+impl std::fmt::Display for TimedOutError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Command `{}` did not finish within {} seconds and was killed",
+            &self.command,
+            self.timeout.as_secs()
+        )
+    }
+}
+
+impl std::error::Error for TimedOutError {}
+
+#[derive(Debug)]
+pub struct WaitTimedOut {
+    pub description: String,
+    pub timeout: Duration,
+}
+
+impl std::fmt::Display for WaitTimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Gave up waiting for {} after {} seconds",
+            &self.description,
+            self.timeout.as_secs()
+        )
+    }
+}
+
+impl std::error::Error for WaitTimedOut {}
+
+#[derive(Debug)]
+pub struct CommandFailed {
+    pub program: String,
+    pub args: Vec<String>,
+    pub status: ExitStatus,
+    pub stderr_tail: String,
+}
+
+impl std::fmt::Display for CommandFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Command `{}` exited with status: {}",
+            &self.program, &self.status
+        )?;
+        if !self.stderr_tail.is_empty() {
+            write!(
+                f,
+                "\nLast lines of its error output:\n{}",
+                &self.stderr_tail
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for CommandFailed {}
+
+// Forwards a child's output to ours while keeping its last lines for error reports
+pub(crate) struct OutputTail {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    done_receiver: mpsc::Receiver<()>,
+}
+
+impl OutputTail {
+    pub(crate) fn capture(child: &mut Child) -> OutputTail {
+        OutputTail::spawn(child.stderr.take(), |line| {
+            let _ = std::io::stderr().write_all(&line);
+        })
+    }
+
+    pub(crate) fn capture_stdout(child: &mut Child) -> OutputTail {
+        OutputTail::spawn(child.stdout.take(), |line| {
+            let _ = std::io::stdout().write_all(&line);
+        })
+    }
+
+    fn spawn(stream: Option<impl Read + Send + 'static>, forward: fn(&[u8])) -> OutputTail {
+        let lines = Arc::new(Mutex::new(VecDeque::with_capacity(STDERR_TAIL_LINES)));
+        let (done_sender, done_receiver) = mpsc::channel();
+        if let Some(stream) = stream {
+            let lines = lines.clone();
+            thread::spawn(move || {
+                let mut reader = BufReader::new(stream);
+                let mut line = Vec::new();
+                loop {
+                    line.clear();
+                    match (&mut reader)
+                        .take(STDERR_LINE_MAX_BYTES)
+                        .read_until(b'\n', &mut line)
+                    {
+                        Ok(0) | Err(_) => break,
+                        Ok(_) => {}
+                    }
+                    forward(&line);
+                    let mut lines = lines.lock().unwrap();
+                    if lines.len() == STDERR_TAIL_LINES {
+                        lines.pop_front();
+                    }
+                    lines.push_back(crate::secret::scrub(
+                        &String::from_utf8_lossy(&line).trim_end(),
+                    ));
+                }
+                let _ = done_sender.send(());
+            });
+        }
+
+        OutputTail {
+            lines,
+            done_receiver,
+        }
+    }
+
+    // Daemons forked by the command may keep the pipe open: do not wait for them
+    pub(crate) fn collect(self) -> String {
+        let _ = self.done_receiver.recv_timeout(STDERR_DRAIN_TIMEOUT);
+        let lines = self.lines.lock().unwrap();
+
+        lines.iter().cloned().collect::<Vec<String>>().join("\n")
+    }
+}
+
+pub(crate) fn command_failed(
+    command: &str,
+    args: &[&str],
+    status: ExitStatus,
+    stderr_tail: String,
+) -> CommandFailed {
+    CommandFailed {
+        program: command.to_string(),
+        args: args.iter().map(|arg| crate::secret::scrub(&arg)).collect(),
+        status,
+        stderr_tail,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BootPhase {
+    pub name: String,
+    // Milliseconds on the monotonic clock, i.e. since the kernel started
+    pub start_ms: u64,
+    pub end_ms: Option<u64>,
+    #[serde(default)]
+    pub start_sample: Option<ResourceSample>,
+    // Set along with end_ms
+    #[serde(default)]
+    pub resource_delta: Option<ResourceDelta>,
+}
+
+// Fields are unset when their source could not be read
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ResourceSample {
+    pub available_memory_kb: Option<u64>,
+    // Over the last minute
+    pub load_average: Option<f32>,
+    // Of the process that recorded the sample: the first and second stages are separate processes
+    pub rss_kb: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ResourceDelta {
+    // Positive when the phase used up available memory
+    pub memory_used_kb: Option<i64>,
+    pub rss_kb: Option<i64>,
+    pub load_average: Option<f32>,
+}
+
+impl ResourceSample {
+    // A few small reads from procfs: cheap enough to be taken around every boot phase
+    // The minimal init_wrapper build does not sample anything
+    pub fn take() -> Option<ResourceSample> {
+        if cfg!(feature = "init_wrapper") {
+            return None;
+        }
+        Some(ResourceSample {
+            available_memory_kb: fs::read_to_string(&MEMINFO_PATH)
+                .ok()
+                .and_then(|meminfo| parse_meminfo_available_kb(&meminfo)),
+            load_average: fs::read_to_string(&LOADAVG_PATH)
+                .ok()
+                .and_then(|loadavg| loadavg.split_whitespace().next()?.parse::<f32>().ok()),
+            rss_kb: fs::read_to_string(&SELF_STATUS_PATH)
+                .ok()
+                .and_then(|status| parse_kb_field(&status, "VmRSS:")),
+        })
+    }
+
+    pub fn delta(&self, end: &ResourceSample) -> ResourceDelta {
+        let difference = |start: Option<u64>, end: Option<u64>| Some(end? as i64 - start? as i64);
+
+        ResourceDelta {
+            memory_used_kb: difference(end.available_memory_kb, self.available_memory_kb),
+            rss_kb: difference(self.rss_kb, end.rss_kb),
+            load_average: self
+                .load_average
+                .zip(end.load_average)
+                .map(|(start, end)| end - start),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BootTimingReport {
+    pub phases: Vec<BootPhase>,
+    pub total_ms: u64,
+    // Unset when no root filesystem overlay was mounted
+    #[serde(default)]
+    pub overlay_backend: Option<crate::rootfs::OverlayBackend>,
+    // Unset when the boot partition could not be checked for a power-on alarm marker
+    #[serde(default)]
+    pub wake_reason: Option<WakeReason>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum WakeReason {
+    // Power button, reboot, charger plugged in...: the kernel does not tell them apart
+    Other,
+    PowerOnAlarm,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BootRecord {
+    pub rootfs_identity: String,
+    pub total_ms: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: Option<u32>,
+    pub max_duration: Option<Duration>,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub backoff_multiplier: f64,
+    pub jitter: bool,
+}
+
+pub struct CommandOutput {
+    pub status: ExitStatus,
+    pub stdout: String,
+    pub stderr: String,
+}
+
 #[derive(PartialEq)]
 pub enum PowerDownMode {
     Normal,
     RootFS,
 }
 
+#[derive(Default)]
+struct ShutdownSchedule {
+    deadline: Option<std::time::Instant>,
+    // Bumped on every (re)scheduling or cancellation so that stale timer threads know to stop
+    generation: u64,
+}
+
+// Cancellable "power off in X minutes" timer
+#[derive(Clone, Default)]
+pub struct ShutdownScheduler {
+    schedule: Arc<(Mutex<ShutdownSchedule>, Condvar)>,
+}
+
+impl ShutdownScheduler {
+    pub fn new() -> ShutdownScheduler {
+        ShutdownScheduler::default()
+    }
+
+    // Replaces any previously scheduled shutdown: on_expiry runs on the timer thread
+    pub fn schedule(&self, delay: Duration, on_expiry: impl FnOnce() + Send + 'static) {
+        let (lock, condvar) = &*self.schedule;
+        let generation = {
+            let mut schedule = lock.lock().unwrap();
+            schedule.generation += 1;
+            schedule.deadline = Some(std::time::Instant::now() + delay);
+            schedule.generation
+        };
+        condvar.notify_all();
+        info!("Scheduled power off in {} minute(s)", delay.as_secs() / 60);
+
+        let schedule = self.schedule.clone();
+        thread::spawn(move || {
+            let (lock, condvar) = &*schedule;
+            let mut guard = lock.lock().unwrap();
+            loop {
+                if guard.generation != generation {
+                    return;
+                }
+                let Some(deadline) = guard.deadline else {
+                    return;
+                };
+                let now = std::time::Instant::now();
+                if now >= deadline {
+                    break;
+                }
+                guard = condvar.wait_timeout(guard, deadline - now).unwrap().0;
+            }
+            guard.deadline = None;
+            drop(guard);
+
+            info!("Scheduled power off time reached");
+            on_expiry();
+        });
+    }
+
+    // Returns false if nothing was scheduled
+    pub fn cancel(&self) -> bool {
+        let (lock, condvar) = &*self.schedule;
+        let mut schedule = lock.lock().unwrap();
+        schedule.generation += 1;
+        let was_scheduled = schedule.deadline.take().is_some();
+        condvar.notify_all();
+        if was_scheduled {
+            info!("Cancelled scheduled power off");
+        }
+
+        was_scheduled
+    }
+
+    pub fn remaining(&self) -> Option<Duration> {
+        let (lock, _) = &*self.schedule;
+        lock.lock()
+            .unwrap()
+            .deadline
+            .map(|deadline| deadline.saturating_duration_since(std::time::Instant::now()))
+    }
+}
+
 pub fn mount_base_filesystems() -> Result<()> {
     Mount::builder()
         .fstype("proc")
@@ -95,117 +550,1113 @@ pub fn mount_base_filesystems() -> Result<()> {
     Ok(())
 }
 
-pub fn get_cmdline_bool(property: &str) -> Result<bool> {
-    info!(
-        "Trying to extract boolean value for property '{}' in kernel command line",
-        &property
-    );
-    let cmdline = fs::read_to_string("/proc/cmdline")?;
-    let re_str = format!(r"{}=(\w+)", regex::escape(&property));
-    let re = Regex::new(&re_str)?;
-    if let Some(captures) = re.captures(&cmdline) {
-        if let Some(value_match) = captures.get(1) {
-            let value = value_match.as_str();
-            if value == "1" || value == "true" {
-                info!("Property '{}' is true", &property);
-                return Ok(true);
-            } else {
-                info!("Property '{}' is false", &property);
-                return Ok(false);
+// Kernel command line parsed once into key/value pairs and flags
+pub struct Cmdline {
+    parameters: HashMap<String, String>,
+    flags: HashSet<String>,
+}
+
+impl Cmdline {
+    pub fn parse(cmdline: &str) -> Cmdline {
+        let mut parameters = HashMap::new();
+        let mut flags = HashSet::new();
+
+        let mut tokens: Vec<String> = Vec::new();
+        let mut current = String::new();
+        let mut in_quotes = false;
+        for c in cmdline.trim().chars() {
+            if c == '"' {
+                in_quotes = !in_quotes;
+            } else if c.is_whitespace() && !in_quotes {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            } else {
+                current.push(c);
+            }
+        }
+        if !current.is_empty() {
+            tokens.push(current);
+        }
+
+        for token in tokens {
+            if let Some((key, value)) = token.split_once('=') {
+                parameters.insert(key.to_string(), value.to_string());
+            } else {
+                flags.insert(token);
+            }
+        }
+
+        Cmdline { parameters, flags }
+    }
+
+    pub fn get() -> Result<&'static Cmdline> {
+        if let Some(cmdline) = CMDLINE.get() {
+            return Ok(cmdline);
+        }
+        let cmdline_str = fs::read_to_string(&CMDLINE_PATH)
+            .with_context(|| "Failed to read kernel command line")?;
+        let _ = CMDLINE.set(Cmdline::parse(&cmdline_str));
+
+        Ok(CMDLINE.get().unwrap())
+    }
+
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.flags.contains(flag)
+    }
+
+    pub fn get_str(&self, property: &str) -> Option<&str> {
+        self.parameters.get(property).map(|value| value.as_str())
+    }
+
+    pub fn get_bool(&self, property: &str) -> bool {
+        match self.get_str(&property) {
+            Some(value) => value == "1" || value == "true",
+            None => self.has_flag(&property),
+        }
+    }
+
+    pub fn get_u32(&self, property: &str) -> Result<Option<u32>> {
+        match self.get_str(&property) {
+            Some(value) => Ok(Some(value.parse::<u32>().with_context(|| {
+                format!(
+                    "Failed to parse kernel command line property '{}' as an integer",
+                    &property
+                )
+            })?)),
+            None => Ok(None),
+        }
+    }
+
+    // Base64-encoded DER or PEM public key, wherever it is located in the command line
+    pub fn pubkey(&self) -> Result<Option<Vec<u8>>> {
+        match self.get_str(&CMDLINE_PUBKEY_PROPERTY) {
+            Some(value) => Ok(Some(BASE64_STANDARD.decode(value).with_context(
+                || "Failed to decode public key from kernel command line",
+            )?)),
+            None => Ok(None),
+        }
+    }
+}
+
+pub fn get_cmdline_bool(property: &str) -> Result<bool> {
+    let value = Cmdline::get()?.get_bool(&property);
+    info!(
+        "Property '{}' is {} in kernel command line",
+        &property, &value
+    );
+
+    Ok(value)
+}
+
+pub fn get_cmdline_str(property: &str) -> Result<Option<String>> {
+    let value = Cmdline::get()?.get_str(&property).map(|v| v.to_string());
+    if let Some(value) = &value {
+        debug!(
+            "Property '{}' is '{}' in kernel command line",
+            &property, &value
+        );
+    }
+
+    Ok(value)
+}
+
+pub fn set_workdir(path: &str) -> Result<()> {
+    let root = Path::new(path);
+    env::set_current_dir(&root)?;
+
+    Ok(())
+}
+
+impl RetryPolicy {
+    pub fn fixed(delay: Duration, max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: Some(max_attempts),
+            max_duration: None,
+            initial_delay: delay,
+            max_delay: delay,
+            backoff_multiplier: 1.0,
+            jitter: false,
+        }
+    }
+
+    // Starts with quick checks for devices that are usually about to show up, then settles down to one per second
+    pub fn polling(timeout: Duration) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: None,
+            max_duration: Some(timeout),
+            initial_delay: WAIT_INITIAL_DELAY,
+            max_delay: WAIT_MAX_DELAY,
+            backoff_multiplier: 1.5,
+            jitter: false,
+        }
+    }
+}
+
+// Used for block devices and other nodes showing up during early boot: slow SD cards may need a longer timeout
+pub fn device_wait_timeout(boot_config: Option<&BootConfig>) -> Duration {
+    Duration::from_secs(
+        boot_config
+            .and_then(|boot_config| boot_config.system.device_wait_timeout_secs)
+            .unwrap_or(DEFAULT_DEVICE_WAIT_TIMEOUT_SECS),
+    )
+}
+
+pub fn retry<T, F: FnMut() -> Result<T>>(policy: &RetryPolicy, mut operation: F) -> Result<T> {
+    let start = std::time::Instant::now();
+    let mut attempts = 0;
+    let mut delay = policy.initial_delay;
+    loop {
+        attempts += 1;
+        let error = match operation() {
+            Ok(value) => return Ok(value),
+            Err(error) => error,
+        };
+
+        let elapsed = start.elapsed();
+        let attempts_exhausted = policy
+            .max_attempts
+            .is_some_and(|max_attempts| attempts >= max_attempts);
+        let time_exhausted = policy
+            .max_duration
+            .is_some_and(|max_duration| elapsed >= max_duration);
+        if attempts_exhausted || time_exhausted {
+            return Err(error.context(format!(
+                "Giving up after {} attempt(s) over {} ms",
+                &attempts,
+                elapsed.as_millis()
+            )));
+        }
+
+        let mut sleep_duration = delay;
+        if policy.jitter && !delay.is_zero() {
+            sleep_duration += delay.mul_f64(rand::rng().random_range(0.0..0.25));
+        }
+        if let Some(max_duration) = policy.max_duration {
+            sleep_duration = sleep_duration.min(max_duration.saturating_sub(elapsed));
+        }
+        thread::sleep(sleep_duration);
+        delay = delay
+            .mul_f64(policy.backoff_multiplier)
+            .min(policy.max_delay);
+    }
+}
+
+// Polls the condition with backoff until it yields a value, letting the user know when hardware takes a while to show up (e.g. "Still waiting for the main partition…")
+// Errors from the condition end the wait right away; running out of time is a WaitTimedOut error
+pub fn wait_for<T>(
+    mut condition: impl FnMut() -> Result<Option<T>>,
+    timeout: Duration,
+    description: &str,
+) -> Result<T> {
+    let start = std::time::Instant::now();
+    let mut next_report = WAIT_REPORT_THRESHOLD;
+    // Only "not there yet" is retried: errors from the condition are handed back as a successful outcome so that retry() stops
+    let outcome = retry(&RetryPolicy::polling(timeout), || match condition() {
+        Ok(Some(value)) => Ok(Ok(value)),
+        Ok(None) => {
+            let elapsed = start.elapsed();
+            if elapsed >= next_report && elapsed < timeout {
+                crate::notifier::get().toast(&format!("Still waiting for {}…", &description));
+                next_report = elapsed + WAIT_REPORT_INTERVAL;
+            }
+            Err(anyhow::anyhow!("{} is not available yet", &description))
+        }
+        Err(e) => Ok(Err(e)),
+    });
+
+    match outcome {
+        Ok(Ok(value)) => {
+            if start.elapsed() >= WAIT_REPORT_THRESHOLD {
+                info!(
+                    "Done waiting for {} after {} ms",
+                    &description,
+                    start.elapsed().as_millis()
+                );
+            }
+            Ok(value)
+        }
+        Ok(Err(e)) => Err(e),
+        Err(_) => Err(WaitTimedOut {
+            description: description.to_string(),
+            timeout,
+        }
+        .into()),
+    }
+}
+
+pub fn wait_for_path(path: &str, timeout: Duration, description: &str) -> Result<()> {
+    wait_for(
+        || Ok(fs::exists(&path)?.then_some(())),
+        timeout,
+        &description,
+    )
+}
+
+pub fn run_command(command: &str, args: &[&str]) -> Result<()> {
+    debug!(
+        "Running command '{}' with arguments '{}'",
+        &command,
+        crate::secret::scrub(&args.join(" "))
+    );
+    let mut child = Command::new(&command)
+        .args(args)
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to execute command: {}", &command))?;
+    let stderr_tail = OutputTail::capture(&mut child);
+    let status = child
+        .wait()
+        .with_context(|| format!("Failed to wait for command: {}", &command))?;
+
+    debug!("Exit status is {}", &status);
+    if status.success() {
+        Ok(())
+    } else {
+        return Err(command_failed(&command, &args, status, stderr_tail.collect()).into());
+    }
+}
+
+// For passwords and other secrets: unlike arguments, standard input does not show up in /proc/<pid>/cmdline
+pub fn run_command_with_stdin(command: &str, args: &[&str], stdin: &[u8]) -> Result<()> {
+    debug!(
+        "Running command '{}' with arguments '{}' and data on standard input",
+        &command,
+        crate::secret::scrub(&args.join(" "))
+    );
+    let mut child = Command::new(&command)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to execute command: {}", &command))?;
+    let stderr_tail = OutputTail::capture(&mut child);
+    // Dropping the pipe closes it, which marks the end of input
+    if let Some(mut child_stdin) = child.stdin.take()
+        && let Err(e) = child_stdin.write_all(&stdin)
+    {
+        // Left alone, the child could wait forever for the rest of its input
+        let _ = child.kill();
+        let _ = child.wait();
+        return Err(e).with_context(|| {
+            format!("Failed to write to standard input of command: {}", &command)
+        });
+    }
+    let status = child
+        .wait()
+        .with_context(|| format!("Failed to wait for command: {}", &command))?;
+
+    debug!("Exit status is {}", &status);
+    if status.success() {
+        Ok(())
+    } else {
+        return Err(command_failed(&command, &args, status, stderr_tail.collect()).into());
+    }
+}
+
+// Returns None if the child had to be killed
+fn wait_with_timeout(child: &mut Child, timeout: Duration) -> Result<Option<ExitStatus>> {
+    let start = std::time::Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if start.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+pub fn run_command_with_timeout(command: &str, args: &[&str], timeout: Duration) -> Result<()> {
+    debug!(
+        "Running command '{}' with arguments '{}' and a timeout of {} seconds",
+        &command,
+        crate::secret::scrub(&args.join(" ")),
+        timeout.as_secs()
+    );
+    let mut child = Command::new(&command)
+        .args(args)
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to execute command: {}", &command))?;
+    let stderr_tail = OutputTail::capture(&mut child);
+
+    let Some(status) = wait_with_timeout(&mut child, timeout)? else {
+        return Err(TimedOutError {
+            command: command.to_string(),
+            timeout,
+        }
+        .into());
+    };
+
+    debug!("Exit status is {}", &status);
+    if status.success() {
+        Ok(())
+    } else {
+        return Err(command_failed(&command, &args, status, stderr_tail.collect()).into());
+    }
+}
+
+// Unlike run_command(), a non-zero exit status is not an error: callers inspect CommandOutput's status themselves
+pub fn run_command_capture(command: &str, args: &[&str]) -> Result<CommandOutput> {
+    debug!(
+        "Running command '{}' with arguments '{}' and capturing its output",
+        &command,
+        crate::secret::scrub(&args.join(" "))
+    );
+    let output = Command::new(&command)
+        .args(args)
+        .output()
+        .with_context(|| format!("Failed to execute command: {}", &command))?;
+
+    debug!("Exit status is {}", &output.status);
+    Ok(CommandOutput {
+        status: output.status,
+        stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+        stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+    })
+}
+
+pub fn modprobe(args: &[&str]) -> Result<()> {
+    run_command_with_timeout("/sbin/modprobe", &args, MODPROBE_TIMEOUT)
+        .with_context(|| format!("Failed to load module; modprobe arguments: {:?}\n", &args))?;
+
+    Ok(())
+}
+
+pub fn check_filesystem(device: &str) -> Result<FilesystemCheckOutcome> {
+    info!("Checking filesystem on '{}'", &device);
+    // The completion bar goes to the console: that is all there is to show progress with in the first stage
+    let mut child = Command::new(&E2FSCK_BINARY_PATH)
+        .args(&["-p", "-C", "0", &device])
+        .spawn()
+        .with_context(|| format!("Failed to execute command: {}", &E2FSCK_BINARY_PATH))?;
+
+    let Some(status) = wait_with_timeout(&mut child, FSCK_TIMEOUT)? else {
+        warn!(
+            "Filesystem check on '{}' did not finish within {} seconds: aborting it",
+            &device,
+            FSCK_TIMEOUT.as_secs()
+        );
+        return Ok(FilesystemCheckOutcome::TimedOut);
+    };
+
+    // See e2fsck(8)
+    match status.code() {
+        Some(0) => Ok(FilesystemCheckOutcome::Clean),
+        Some(1) => {
+            warn!("Filesystem errors on '{}' were corrected", &device);
+            Ok(FilesystemCheckOutcome::Repaired)
+        }
+        Some(2) | Some(3) => {
+            warn!(
+                "Filesystem errors on '{}' were corrected, but a reboot is required",
+                &device
+            );
+            Ok(FilesystemCheckOutcome::RebootRequired)
+        }
+        Some(exit_code) => Err(FilesystemCorruptedError {
+            device: device.to_string(),
+            exit_code,
+        }
+        .into()),
+        None => Err(anyhow::anyhow!(
+            "Filesystem check on '{}' was killed by a signal",
+            &device
+        )),
+    }
+}
+
+// The boot configuration lives on the boot partition itself: peek at it through a temporary read-only mount
+fn peek_boot_config(boot_part_path: &str) -> Option<BootConfig> {
+    match with_mount(
+        &boot_part_path,
+        "ext4",
+        MountFlags::RDONLY,
+        None,
+        |mountpoint| Ok(BootConfig::peek_at(&mountpoint)),
+    ) {
+        Ok(boot_config) => boot_config,
+        Err(e) => {
+            warn!(
+                "Failed to read boot configuration from read-only boot partition: {}",
+                &e
+            );
+            None
+        }
+    }
+}
+
+fn filesystem_check_skipped(boot_config: Option<&BootConfig>) -> Result<bool> {
+    if get_cmdline_bool("quill_skip_fsck")? {
+        return Ok(true);
+    }
+
+    Ok(boot_config.is_some_and(|boot_config| boot_config.flags.skip_filesystem_check))
+}
+
+// Runs in the first stage, before any GUI exists: progress is only reported on the console
+// Unfixable corruption does not stop the boot, so that the second stage can offer recovery
+fn check_filesystems(devices: &[&str]) -> Result<Vec<FilesystemCorruptedError>> {
+    info!(
+        "Checking filesystems (up to {} seconds per partition)…",
+        FSCK_TIMEOUT.as_secs()
+    );
+
+    let mut reboot_required = false;
+    let mut corruption = Vec::new();
+    for device in devices {
+        match check_filesystem(&device) {
+            Ok(FilesystemCheckOutcome::RebootRequired) => reboot_required = true,
+            Ok(_) => {}
+            Err(e) => match e.downcast::<FilesystemCorruptedError>() {
+                Ok(corrupted) => {
+                    error!("{}", &corrupted);
+                    corruption.push(corrupted);
+                }
+                Err(e) => return Err(e),
+            },
+        }
+    }
+
+    if reboot_required {
+        info!("Filesystems were repaired: rebooting");
+        sync_disks()?;
+        run_command(&REBOOT_BINARY_PATH, &["-f"])?;
+    }
+    info!("Filesystems checked");
+
+    Ok(corruption)
+}
+
+pub fn save_first_stage_filesystem_corruption() -> Result<()> {
+    let corruption = FILESYSTEM_CORRUPTION.lock().unwrap();
+    if corruption.is_empty() {
+        return Ok(());
+    }
+    fs::write(
+        &FIRST_STAGE_FILESYSTEM_CORRUPTION_PATH,
+        ron::to_string(&*corruption)?,
+    )
+    .with_context(|| "Failed to save first stage filesystem corruption")?;
+
+    Ok(())
+}
+
+pub fn load_first_stage_filesystem_corruption() -> Result<()> {
+    if !fs::exists(&FIRST_STAGE_FILESYSTEM_CORRUPTION_PATH)? {
+        return Ok(());
+    }
+    let corruption = ron::from_str::<Vec<FilesystemCorruptedError>>(
+        &fs::read_to_string(&FIRST_STAGE_FILESYSTEM_CORRUPTION_PATH)
+            .with_context(|| "Failed to read first stage filesystem corruption")?,
+    )
+    .with_context(|| "Failed to parse first stage filesystem corruption")?;
+    *FILESYSTEM_CORRUPTION.lock().unwrap() = corruption;
+
+    Ok(())
+}
+
+// Partitions that e2fsck could not fix during this boot: they are mounted read-only
+pub fn filesystem_corruption() -> Vec<FilesystemCorruptedError> {
+    FILESYSTEM_CORRUPTION.lock().unwrap().clone()
+}
+
+fn is_corrupted(device: &str) -> bool {
+    FILESYSTEM_CORRUPTION
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|corrupted| corrupted.device == device)
+}
+
+// Whatever e2fsck could not fix must not get any worse
+fn mount_base_partition(device: &str, mountpoint: &str) -> Result<()> {
+    let builder = Mount::builder().fstype("ext4");
+    let builder = if is_corrupted(&device) {
+        warn!("Mounting corrupted partition '{}' read-only", &device);
+        builder.flags(MountFlags::RDONLY)
+    } else {
+        builder.data("rw")
+    };
+    builder.mount(&device, &mountpoint)?;
+
+    Ok(())
+}
+
+// ext4 remounts read-only after errors: this catches it before writes fail deep into the boot process
+pub fn check_partition_writable(mountpoint: &str) -> Option<PartitionWriteIssue> {
+    match nix::sys::statvfs::statvfs(mountpoint) {
+        Ok(stat) => {
+            if stat.flags().contains(nix::sys::statvfs::FsFlags::ST_RDONLY) {
+                warn!("Partition mounted at '{}' is read-only", &mountpoint);
+                return Some(PartitionWriteIssue::ReadOnly);
+            }
+            let free_space = stat.blocks_available() as u64 * stat.fragment_size() as u64;
+            if free_space < MIN_WRITABLE_FREE_SPACE_BYTES {
+                warn!(
+                    "Partition mounted at '{}' only has {} bytes left",
+                    &mountpoint, &free_space
+                );
+                return Some(PartitionWriteIssue::Full);
+            }
+        }
+        Err(e) => warn!("Failed to query filesystem at '{}': {}", &mountpoint, &e),
+    }
+
+    let write_test_file_path = format!("{}/{}", &mountpoint, &WRITE_TEST_FILE);
+    if let Err(e) =
+        fs::write(&write_test_file_path, b"").and_then(|()| fs::remove_file(&write_test_file_path))
+    {
+        warn!(
+            "Failed to write to partition mounted at '{}': {}",
+            &mountpoint, &e
+        );
+        if e.raw_os_error() == Some(nix::errno::Errno::ENOSPC as i32) {
+            return Some(PartitionWriteIssue::Full);
+        }
+        return Some(PartitionWriteIssue::ReadOnly);
+    }
+
+    None
+}
+
+pub fn check_boot_partition_writable() -> Option<PartitionWriteIssue> {
+    let issue = check_partition_writable(&crate::BOOT_PART_MOUNTPOINT);
+    *BOOT_PARTITION_WRITE_ISSUE.lock().unwrap() = issue;
+
+    issue
+}
+
+// Result of the last check_boot_partition_writable() call
+pub fn boot_partition_write_issue() -> Option<PartitionWriteIssue> {
+    *BOOT_PARTITION_WRITE_ISSUE.lock().unwrap()
+}
+
+// In bytes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DiskUsage {
+    pub total: u64,
+    pub used: u64,
+    pub available: u64,
+}
+
+impl DiskUsage {
+    // e.g. "1.2 GB free of 14.6 GB"
+    pub fn summary(&self) -> String {
+        let gigabytes = |bytes: u64| bytes as f64 / 1_000_000_000.0;
+        format!(
+            "{:.1} GB free of {:.1} GB",
+            gigabytes(self.available),
+            gigabytes(self.total)
+        )
+    }
+}
+
+// "Available" excludes blocks reserved for root, which is what unprivileged writers (e.g. gocryptfs) actually get
+pub fn disk_usage(path: &str) -> Result<DiskUsage> {
+    let stat = nix::sys::statvfs::statvfs(path)
+        .with_context(|| format!("Failed to query filesystem at '{}'", &path))?;
+    let fragment_size = stat.fragment_size() as u64;
+
+    Ok(DiskUsage {
+        total: stat.blocks() as u64 * fragment_size,
+        used: (stat.blocks() as u64).saturating_sub(stat.blocks_free() as u64) * fragment_size,
+        available: stat.blocks_available() as u64 * fragment_size,
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct PartitionUsage {
+    pub name: String,
+    pub usage: DiskUsage,
+    pub low_on_space: bool,
+}
+
+impl PartitionUsage {
+    pub fn low_space_warning(&self) -> Option<String> {
+        self.low_on_space
+            .then(|| format!("{} is low on space: {}", &self.name, &self.usage.summary()))
+    }
+}
+
+// Records usage of the boot and main partitions, flagging those with less free space than the configured threshold
+pub fn check_disk_space(boot_config: &BootConfig) -> Vec<PartitionUsage> {
+    let threshold_bytes = boot_config
+        .system
+        .low_space_threshold_mb
+        .unwrap_or(DEFAULT_LOW_SPACE_THRESHOLD_MB)
+        * 1_000_000;
+    let mut partition_usages = Vec::new();
+    for (name, mountpoint) in [
+        ("Boot partition", &crate::BOOT_PART_MOUNTPOINT),
+        ("Main partition", &crate::MAIN_PART_MOUNTPOINT),
+    ] {
+        let usage = match disk_usage(&mountpoint) {
+            Ok(usage) => usage,
+            Err(e) => {
+                warn!("{}", &e);
+                continue;
+            }
+        };
+        info!("{} usage: {:?}", &name, &usage);
+        let partition_usage = PartitionUsage {
+            name: name.to_string(),
+            usage,
+            low_on_space: usage.available < threshold_bytes,
+        };
+        if let Some(warning) = partition_usage.low_space_warning() {
+            warn!("{}", &warning);
+        }
+        partition_usages.push(partition_usage);
+    }
+    *DISK_USAGE.lock().unwrap() = partition_usages.clone();
+
+    partition_usages
+}
+
+// Result of the last check_disk_space() call
+pub fn last_disk_usage() -> Vec<PartitionUsage> {
+    DISK_USAGE.lock().unwrap().clone()
+}
+
+// Like `du -s`: allocated blocks rather than apparent sizes, hard links counted once and symbolic links not followed
+// Entries that disappear or cannot be read while walking are skipped
+pub fn dir_size(path: &str) -> Result<u64> {
+    let metadata =
+        fs::symlink_metadata(&path).with_context(|| format!("Failed to read '{}'", &path))?;
+    if !metadata.is_dir() {
+        return Ok(metadata.blocks() * 512);
+    }
+
+    let mut seen_inodes = HashSet::new();
+    let mut size = 0;
+    for entry in WalkDir::new(&path).follow_links(false) {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if metadata.nlink() > 1
+            && !metadata.is_dir()
+            && !seen_inodes.insert((metadata.dev(), metadata.ino()))
+        {
+            continue;
+        }
+        size += metadata.blocks() * 512;
+    }
+
+    Ok(size)
+}
+
+// e.g. "MemAvailable:    1776404 kB"
+fn parse_kb_field(content: &str, field: &str) -> Option<u64> {
+    content
+        .lines()
+        .find_map(|line| line.strip_prefix(&field))
+        .and_then(|value| {
+            value
+                .trim()
+                .trim_end_matches("kB")
+                .trim()
+                .parse::<u64>()
+                .ok()
+        })
+}
+
+pub fn parse_meminfo_available_kb(meminfo: &str) -> Option<u64> {
+    parse_kb_field(&meminfo, "MemAvailable:")
+}
+
+// In bytes, from the kernel's estimate of memory available without swapping
+pub fn available_memory() -> Result<u64> {
+    let meminfo = fs::read_to_string(&MEMINFO_PATH)
+        .with_context(|| format!("Failed to read '{}'", &MEMINFO_PATH))?;
+    let available_kb = parse_meminfo_available_kb(&meminfo)
+        .ok_or_else(|| anyhow::anyhow!("Failed to find available memory in '{}'", &MEMINFO_PATH))?;
+
+    Ok(available_kb * 1024)
+}
+
+pub fn mount_base_partitions(netboot_status: NetBootStatus) -> Result<()> {
+    info!("Mounting boot partition");
+    fs::create_dir_all(&crate::BOOT_PART_MOUNTPOINT)
+        .with_context(|| "Failed to create boot partition mountpoint's directory")?;
+    let boot_part_path =
+        partitions::wait_for_partition(&Partition::Boot, device_wait_timeout(None))?;
+
+    // The boot partition is already mounted once NetBoot becomes available
+    let boot_config = if netboot_status == NetBootStatus::Available {
+        BootConfig::peek()
+    } else {
+        peek_boot_config(&boot_part_path)
+    };
+    let device_wait_timeout = device_wait_timeout(boot_config.as_ref());
+
+    if netboot_status != NetBootStatus::Available
+        && !filesystem_check_skipped(boot_config.as_ref())?
+    {
+        let mut devices = vec![boot_part_path.clone()];
+        if netboot_status == NetBootStatus::None {
+            devices.push(partitions::wait_for_partition(
+                &Partition::Main,
+                device_wait_timeout,
+            )?);
+        }
+        let corruption =
+            check_filesystems(&devices.iter().map(|d| d.as_str()).collect::<Vec<&str>>())?;
+        *FILESYSTEM_CORRUPTION.lock().unwrap() = corruption;
+    } else {
+        info!("Skipping filesystem checks");
+    }
+
+    mount_base_partition(&boot_part_path, &crate::BOOT_PART_MOUNTPOINT)
+        .with_context(|| "Failed to mount boot partition")?;
+
+    if netboot_status == NetBootStatus::None || netboot_status == NetBootStatus::Available {
+        info!("Mounting main partition");
+        fs::create_dir_all(&crate::MAIN_PART_MOUNTPOINT)
+            .with_context(|| "Failed to create boot partition mountpoint's directory")?;
+
+        let main_part_path = match netboot_status {
+            NetBootStatus::Available => {
+                wait_for_path(
+                    &NETBOOT_DEVICE_NODE,
+                    device_wait_timeout,
+                    "the network block device",
+                )?;
+                NETBOOT_DEVICE_NODE.to_string()
+            }
+            _ => partitions::wait_for_partition(&Partition::Main, device_wait_timeout)?,
+        };
+
+        mount_base_partition(&main_part_path, &crate::MAIN_PART_MOUNTPOINT)
+            .with_context(|| "Failed to mount main partition")?;
+
+        fs::create_dir_all(&format!(
+            "{}/{}",
+            &crate::MAIN_PART_MOUNTPOINT,
+            &crate::SYSTEM_DIR
+        ))?;
+        fs::create_dir_all(&format!(
+            "{}/{}",
+            &crate::MAIN_PART_MOUNTPOINT,
+            &crate::SYSTEM_HOME_DIR
+        ))?;
+    }
+
+    Ok(())
+}
+
+// From linux/loop.h
+const LOOP_CONTROL_PATH: &str = "/dev/loop-control";
+const LO_FLAGS_READ_ONLY: u32 = 1;
+const LO_FLAGS_AUTOCLEAR: u32 = 4;
+const LO_NAME_SIZE: usize = 64;
+const LO_KEY_SIZE: usize = 32;
+const LOOP_ATTACH_ATTEMPTS: u32 = 3;
+const LOOP_MAJOR: u64 = 7;
+const SYS_BLOCK_DIR_PATH: &str = "/sys/block";
+// From linux/squashfs_fs.h: the superblock's bytes_used field is the archive's size as written by mksquashfs
+const SQUASHFS_MAGIC: &[u8; 4] = b"hsqs";
+const SQUASHFS_BYTES_USED_OFFSET: usize = 40;
+const SQUASHFS_SUPERBLOCK_SIZE: usize = 96;
+
+#[repr(C)]
+struct LoopInfo64 {
+    lo_device: u64,
+    lo_inode: u64,
+    lo_rdevice: u64,
+    lo_offset: u64,
+    lo_sizelimit: u64,
+    lo_number: u32,
+    lo_encrypt_type: u32,
+    lo_encrypt_key_size: u32,
+    lo_flags: u32,
+    lo_file_name: [u8; LO_NAME_SIZE],
+    lo_crypt_name: [u8; LO_NAME_SIZE],
+    lo_encrypt_key: [u8; LO_KEY_SIZE],
+    lo_init: [u64; 2],
+}
+
+#[repr(C)]
+struct LoopConfig {
+    fd: u32,
+    block_size: u32,
+    info: LoopInfo64,
+    reserved: [u64; 8],
+}
+
+nix::ioctl_none_bad!(loop_ctl_get_free, 0x4C82);
+nix::ioctl_write_int_bad!(loop_set_fd, 0x4C00);
+nix::ioctl_none_bad!(loop_clr_fd, 0x4C01);
+nix::ioctl_write_ptr_bad!(loop_set_status64, 0x4C04, LoopInfo64);
+nix::ioctl_write_ptr_bad!(loop_configure, 0x4C0A, LoopConfig);
+
+fn loop_info(backing_file_path: &str) -> LoopInfo64 {
+    // Only used for display purposes by losetup: a truncated name is fine
+    let mut lo_file_name = [0u8; LO_NAME_SIZE];
+    let name_len = backing_file_path.len().min(LO_NAME_SIZE - 1);
+    lo_file_name[..name_len].copy_from_slice(&backing_file_path.as_bytes()[..name_len]);
+
+    LoopInfo64 {
+        lo_device: 0,
+        lo_inode: 0,
+        lo_rdevice: 0,
+        lo_offset: 0,
+        lo_sizelimit: 0,
+        lo_number: 0,
+        lo_encrypt_type: 0,
+        lo_encrypt_key_size: 0,
+        // Auto-clear detaches the loop device as soon as its filesystem is unmounted
+        lo_flags: LO_FLAGS_READ_ONLY | LO_FLAGS_AUTOCLEAR,
+        lo_file_name,
+        lo_crypt_name: [0u8; LO_NAME_SIZE],
+        lo_encrypt_key: [0u8; LO_KEY_SIZE],
+        lo_init: [0u64; 2],
+    }
+}
+
+// The kernel adds a loop device when all existing ones are busy, but nothing creates its node without devtmpfs or udev keeping up
+fn get_free_loop_device() -> Result<String> {
+    let loop_control = fs::File::open(&LOOP_CONTROL_PATH)
+        .with_context(|| format!("Failed to open '{}'", &LOOP_CONTROL_PATH))?;
+    let loop_number = unsafe { loop_ctl_get_free(loop_control.as_raw_fd()) }
+        .with_context(|| "Failed to find a free loop device")?;
+    let loop_device_path = format!("/dev/loop{}", &loop_number);
+    if !fs::exists(&loop_device_path)? {
+        info!("Creating loop device node '{}'", &loop_device_path);
+        mknod(
+            loop_device_path.as_str(),
+            SFlag::S_IFBLK,
+            Mode::from_bits_truncate(0o660),
+            makedev(LOOP_MAJOR, loop_number as u64),
+        )
+        .with_context(|| format!("Failed to create loop device node '{}'", &loop_device_path))?;
+    }
+
+    Ok(loop_device_path)
+}
+
+fn try_attach_loop_device(backing_file: &fs::File, backing_file_path: &str) -> Result<String> {
+    let loop_device_path = get_free_loop_device()?;
+    let loop_device = fs::File::open(&loop_device_path)
+        .with_context(|| format!("Failed to open loop device '{}'", &loop_device_path))?;
+
+    let config = LoopConfig {
+        fd: backing_file.as_raw_fd() as u32,
+        block_size: 0,
+        info: loop_info(&backing_file_path),
+        reserved: [0u64; 8],
+    };
+    match unsafe { loop_configure(loop_device.as_raw_fd(), &config) } {
+        Ok(_) => {}
+        // LOOP_CONFIGURE only exists since Linux 5.8
+        Err(nix::errno::Errno::EINVAL) | Err(nix::errno::Errno::ENOTTY) => {
+            unsafe { loop_set_fd(loop_device.as_raw_fd(), backing_file.as_raw_fd()) }
+                .with_context(|| format!("Failed to attach '{}'", &loop_device_path))?;
+            if let Err(e) = unsafe { loop_set_status64(loop_device.as_raw_fd(), &config.info) } {
+                let _ = unsafe { loop_clr_fd(loop_device.as_raw_fd()) };
+                return Err(e)
+                    .with_context(|| format!("Failed to configure '{}'", &loop_device_path));
+            }
+        }
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to attach '{}'", &loop_device_path));
+        }
+    }
+
+    Ok(loop_device_path)
+}
+
+// Returns the path of the read-only loop device backed by the given file
+pub fn attach_loop_device(backing_file_path: &str) -> Result<String> {
+    let backing_file = fs::File::open(&backing_file_path)
+        .with_context(|| format!("Failed to open '{}'", &backing_file_path))?;
+
+    // Another process may grab the free loop device between our query and our attach attempt
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        match try_attach_loop_device(&backing_file, &backing_file_path) {
+            Ok(loop_device_path) => {
+                debug!(
+                    "Attached '{}' to '{}'",
+                    &backing_file_path, &loop_device_path
+                );
+                return Ok(loop_device_path);
             }
-        } else {
-            info!("Error getting capture group: returning false");
-            return Ok(false);
+            Err(e) if attempt < LOOP_ATTACH_ATTEMPTS => {
+                debug!("Failed to attach loop device, retrying: {:?}", &e);
+            }
+            Err(e) => return Err(e),
         }
-    } else {
-        info!("Could not find property: returning false");
-        return Ok(false);
     }
 }
 
-pub fn set_workdir(path: &str) -> Result<()> {
-    let root = Path::new(path);
-    env::set_current_dir(&root)?;
+pub fn detach_loop_device(loop_device_path: &str) -> Result<()> {
+    let loop_device = fs::File::open(&loop_device_path)
+        .with_context(|| format!("Failed to open loop device '{}'", &loop_device_path))?;
+    match unsafe { loop_clr_fd(loop_device.as_raw_fd()) } {
+        // Already detached (e.g. by auto-clear)
+        Ok(_) | Err(nix::errno::Errno::ENXIO) => Ok(()),
+        Err(e) => Err(e).with_context(|| format!("Failed to detach '{}'", &loop_device_path)),
+    }
+}
+
+// musl introduces compile-time issues with the 'loop' feature of the 'sys_mount' crate: loop devices are attached by hand instead
+fn mount_squashfs_natively(archive_path: &str, mountpoint: &str) -> Result<()> {
+    let loop_device_path = attach_loop_device(&archive_path)?;
+    if let Err(e) = Mount::builder()
+        .fstype("squashfs")
+        .flags(MountFlags::RDONLY)
+        .mount(&loop_device_path, &mountpoint)
+    {
+        let _ = detach_loop_device(&loop_device_path);
+        return Err(e).with_context(|| {
+            format!(
+                "Failed to mount '{}' at '{}'",
+                &loop_device_path, &mountpoint
+            )
+        });
+    }
 
     Ok(())
 }
 
-pub fn wait_for_path(path: &str) -> Result<()> {
-    while !fs::exists(&path)? {
-        thread::sleep(Duration::from_millis(100));
+// Busy loop devices are the ones with a backing file
+fn count_loop_devices(sys_block_dir: &str) -> Result<(usize, usize)> {
+    let mut total = 0;
+    let mut busy = 0;
+    for entry in fs::read_dir(&sys_block_dir)
+        .with_context(|| format!("Failed to list '{}'", &sys_block_dir))?
+    {
+        let entry = entry?;
+        if !entry.file_name().to_string_lossy().starts_with("loop") {
+            continue;
+        }
+        total += 1;
+        if fs::exists(entry.path().join("loop/backing_file"))? {
+            busy += 1;
+        }
     }
 
-    Ok(())
+    Ok((total - busy, total))
 }
 
-pub fn run_command(command: &str, args: &[&str]) -> Result<()> {
-    debug!(
-        "Running command '{}' with arguments '{}'",
-        &command,
-        &args.join(" ")
+// Checks the magic bytes, and the size declared by the superblock against the actual file's size
+pub fn check_squashfs_archive(archive_path: &str) -> Result<()> {
+    let mut archive = fs::File::open(&archive_path)
+        .with_context(|| format!("Failed to open '{}'", &archive_path))?;
+    let size = archive.metadata()?.len();
+    let mut superblock = [0u8; SQUASHFS_SUPERBLOCK_SIZE];
+    if archive.read_exact(&mut superblock).is_err() {
+        return Err(anyhow::anyhow!(
+            "Archive is only {} bytes long, too short for a SquashFS superblock",
+            &size
+        ));
+    }
+    if &superblock[..SQUASHFS_MAGIC.len()] != SQUASHFS_MAGIC {
+        return Err(anyhow::anyhow!(
+            "Archive does not start with SquashFS magic bytes (found {})",
+            to_hex(&superblock[..SQUASHFS_MAGIC.len()])
+        ));
+    }
+    let declared_size = u64::from_le_bytes(
+        superblock[SQUASHFS_BYTES_USED_OFFSET..SQUASHFS_BYTES_USED_OFFSET + 8].try_into()?,
     );
-    let status = Command::new(&command)
-        .args(args)
-        .status()
-        .with_context(|| format!("Failed to execute command: {}", &command))?;
-
-    debug!("Exit status is {}", &status);
-    if status.success() {
-        Ok(())
-    } else {
+    if size < declared_size {
         return Err(anyhow::anyhow!(
-            "Command `{}` exited with status: {}",
-            &command,
-            &status
+            "Archive is truncated: {} bytes long, while its superblock declares {} bytes",
+            &size,
+            &declared_size
         ));
     }
-}
-
-pub fn modprobe(args: &[&str]) -> Result<()> {
-    run_command("/sbin/modprobe", &args)
-        .with_context(|| format!("Failed to load module; modprobe arguments: {:?}\n", &args))?;
 
     Ok(())
 }
 
-pub fn mount_base_partitions(netboot_status: NetBootStatus) -> Result<()> {
-    info!("Mounting boot partition");
-    fs::create_dir_all(&crate::BOOT_PART_MOUNTPOINT)
-        .with_context(|| "Failed to create boot partition mountpoint's directory")?;
-    wait_for_path(&crate::BOOT_PART)?;
-    Mount::builder()
-        .fstype("ext4")
-        .data("rw")
-        .mount(&crate::BOOT_PART, &crate::BOOT_PART_MOUNTPOINT)
-        .with_context(|| "Failed to mount boot partition")?;
+// The mount error alone rarely tells whether loop devices ran out or the archive itself is damaged
+fn squashfs_mount_diagnostics(archive_path: &str, since_ms: u64) -> String {
+    let mut diagnostics = Vec::new();
+    match count_loop_devices(&SYS_BLOCK_DIR_PATH) {
+        Ok((free, total)) => {
+            diagnostics.push(format!("Loop devices: {} of {} free", &free, &total))
+        }
+        Err(e) => diagnostics.push(format!("Loop devices: unknown ({})", &e)),
+    }
+    match check_squashfs_archive(&archive_path) {
+        Ok(()) => diagnostics.push("Archive: superblock looks intact".to_string()),
+        Err(e) => diagnostics.push(format!("Archive: {}", &e)),
+    }
+    match rmesg::log_entries(rmesg::Backend::Default, false) {
+        Ok(entries) => {
+            let since = Duration::from_millis(since_ms);
+            let kernel_messages: Vec<String> = entries
+                .iter()
+                .filter(|entry| {
+                    entry
+                        .timestamp_from_system_start
+                        .is_none_or(|timestamp| timestamp >= since)
+                })
+                .filter(|entry| {
+                    let message = entry.message.to_lowercase();
+                    message.contains("squashfs") || message.contains("loop")
+                })
+                .map(|entry| entry.to_string())
+                .collect();
+            if kernel_messages.is_empty() {
+                diagnostics.push("Kernel messages: none related to this mount".to_string());
+            } else {
+                diagnostics.push(format!("Kernel messages:\n{}", kernel_messages.join("\n")));
+            }
+        }
+        Err(e) => diagnostics.push(format!("Kernel messages: not available ({})", &e)),
+    }
 
-    if netboot_status == NetBootStatus::None || netboot_status == NetBootStatus::Available {
-        info!("Mounting main partition");
-        fs::create_dir_all(&crate::MAIN_PART_MOUNTPOINT)
-            .with_context(|| "Failed to create boot partition mountpoint's directory")?;
+    diagnostics.join("\n")
+}
 
-        let main_part_path = match netboot_status {
-            NetBootStatus::Available => NETBOOT_DEVICE_NODE,
-            _ => &crate::MAIN_PART,
-        };
+pub fn mount_squashfs(archive_path: &str, mountpoint: &str) -> Result<()> {
+    let since_ms = monotonic_ms();
+    if let Err(e) = mount_squashfs_natively(&archive_path, &mountpoint) {
+        warn!(
+            "Failed to mount SquashFS archive '{}' natively, falling back to external mount binary: {:?}",
+            &archive_path, &e
+        );
+        // The external binary needs a free loop device just as much
+        if let Err(e) = get_free_loop_device() {
+            warn!("{:?}", &e);
+        }
+        run_command_with_timeout("/bin/mount", &[&archive_path, &mountpoint], MOUNT_TIMEOUT)
+            .with_context(|| squashfs_mount_diagnostics(&archive_path, since_ms))
+            .with_context(|| {
+                format!(
+                    "Failed to mount SquashFS archive '{}' at '{}'",
+                    &archive_path, &mountpoint
+                )
+            })?;
+    }
 
-        wait_for_path(&main_part_path)?;
-        Mount::builder()
-            .fstype("ext4")
-            .data("rw")
-            .mount(&main_part_path, &crate::MAIN_PART_MOUNTPOINT)
-            .with_context(|| "Failed to mount main partition")?;
+    Ok(())
+}
 
-        fs::create_dir_all(&format!(
-            "{}/{}",
-            &crate::MAIN_PART_MOUNTPOINT,
-            &crate::SYSTEM_DIR
-        ))?;
-        fs::create_dir_all(&format!(
-            "{}/{}",
-            &crate::MAIN_PART_MOUNTPOINT,
-            &crate::SYSTEM_HOME_DIR
-        ))?;
+// Detaching the loop device is normally handled by auto-clear: this also covers archives attached by an external mount binary
+pub fn unmount_squashfs(mountpoint: &str) -> Result<()> {
+    let source = list_mounts()?
+        .into_iter()
+        .rev()
+        .find(|(_, target, _, _)| target == mountpoint.trim_end_matches('/'))
+        .map(|(source, _, _, _)| source);
+    bulletproof_unmount(&mountpoint)?;
+    if let Some(source) = source
+        && source.starts_with("/dev/loop")
+    {
+        detach_loop_device(&source)?;
     }
 
     Ok(())
@@ -217,7 +1668,7 @@ pub fn mount_modules() -> Result<()> {
     fs::create_dir_all(&MODULES_DIR_PATH)?;
     let modules_archive_path = format!("/lib/{}", &MODULES_ARCHIVE);
 
-    run_command("/bin/mount", &[&modules_archive_path, &MODULES_DIR_PATH])
+    mount_squashfs(&modules_archive_path, &MODULES_DIR_PATH)
         .with_context(|| "Failed to mount kernel modules archive")?;
 
     Ok(())
@@ -225,10 +1676,9 @@ pub fn mount_modules() -> Result<()> {
 
 pub fn mount_firmware(pubkey: &PKey<Public>) -> Result<()> {
     info!("Mounting system firmware SquashFS archive");
-    let firmware_archive_path = format!("{}/{}", &crate::BOOT_PART_MOUNTPOINT, &FIRMWARE_ARCHIVE);
+    let firmware_archive_path = partitions::boot_file_path(&FIRMWARE_ARCHIVE);
     if fs::exists(&firmware_archive_path)? && check_signature(&pubkey, &firmware_archive_path)? {
-        // musl introduces compile-time issues with the 'loop' feature of the 'sys_mount' crate: I have disabled it. Thus, here we need to use an external binary to mount SquashFS files.
-        run_command("/bin/mount", &[&firmware_archive_path, &FIRMWARE_DIR_PATH])
+        mount_squashfs(&firmware_archive_path, &FIRMWARE_DIR_PATH)
             .with_context(|| "Failed to mount device's firmware")?;
         Mount::builder()
             .fstype("tmpfs")
@@ -244,6 +1694,91 @@ pub fn mount_firmware(pubkey: &PKey<Public>) -> Result<()> {
     Ok(())
 }
 
+// The currently selected algorithm is shown between brackets, e.g. "lzo lzo-rle [lz4] zstd"
+fn parse_zram_algorithms(comp_algorithm: &str) -> (Vec<String>, Option<String>) {
+    let mut algorithms = Vec::new();
+    let mut current = None;
+    for algorithm in comp_algorithm.split_whitespace() {
+        let name = algorithm.trim_start_matches('[').trim_end_matches(']');
+        if algorithm.starts_with('[') {
+            current = Some(name.to_string());
+        }
+        algorithms.push(name.to_string());
+    }
+
+    (algorithms, current)
+}
+
+// Returns false if the kernel has no zram support, in which case boot simply continues without swap
+pub fn setup_zram(size_mb: u64, algorithm: &str) -> Result<bool> {
+    info!("Setting up {} MiB zram swap", &size_mb);
+    if let Err(e) = modprobe(&["zram"]) {
+        // zram may also be built into the kernel
+        debug!("{:?}", &e);
+    }
+    if wait_for_path(&ZRAM_SYSFS_PATH, Duration::from_secs(1), "zram").is_err() {
+        warn!("This kernel does not seem to support zram: continuing without swap");
+        return Ok(false);
+    }
+
+    let comp_algorithm_path = format!("{}/comp_algorithm", &ZRAM_SYSFS_PATH);
+    let (algorithms, _) = parse_zram_algorithms(
+        &fs::read_to_string(&comp_algorithm_path)
+            .with_context(|| "Failed to read available zram compression algorithms")?,
+    );
+    // The algorithm can only be changed before the disk size is set
+    if algorithms.iter().any(|available| available == algorithm) {
+        fs::write(&comp_algorithm_path, &algorithm).with_context(|| {
+            format!(
+                "Failed to set zram compression algorithm to '{}'",
+                &algorithm
+            )
+        })?;
+    } else {
+        warn!(
+            "zram compression algorithm '{}' is not available (available: {}): keeping the kernel's default",
+            &algorithm,
+            algorithms.join(", ")
+        );
+    }
+    fs::write(
+        format!("{}/disksize", &ZRAM_SYSFS_PATH),
+        format!("{}M", &size_mb),
+    )
+    .with_context(|| "Failed to set zram disk size")?;
+
+    run_command("/sbin/mkswap", &[&ZRAM_DEVICE_PATH])
+        .with_context(|| "Failed to format zram device as swap")?;
+    run_command(
+        "/sbin/swapon",
+        &["-p", &ZRAM_SWAP_PRIORITY, &ZRAM_DEVICE_PATH],
+    )
+    .with_context(|| "Failed to enable zram swap")?;
+    ZRAM_SWAP_ACTIVE.store(true, Ordering::SeqCst);
+
+    let (_, effective_algorithm) =
+        parse_zram_algorithms(&fs::read_to_string(&comp_algorithm_path).unwrap_or_default());
+    info!(
+        "Enabled {} MiB zram swap using '{}' compression",
+        &size_mb,
+        effective_algorithm.as_deref().unwrap_or("unknown")
+    );
+
+    Ok(true)
+}
+
+pub fn tear_down_zram() -> Result<()> {
+    if !ZRAM_SWAP_ACTIVE.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+    info!("Disabling zram swap");
+    run_command("/sbin/swapoff", &[&ZRAM_DEVICE_PATH])
+        .with_context(|| "Failed to disable zram swap")?;
+    ZRAM_SWAP_ACTIVE.store(false, Ordering::SeqCst);
+
+    Ok(())
+}
+
 pub fn unmount_base_partitions() -> Result<()> {
     sync_disks()?;
     info!("Unmounting main partition");
@@ -262,26 +1797,112 @@ pub fn sync_disks() -> Result<()> {
 }
 
 pub fn start_service(service: &str) -> Result<()> {
-    run_command("/sbin/rc-service", &[&service, "start"])
-        .with_context(|| format!("Failed to start '{}' service", &service))?;
-
-    Ok(())
+    services::get().start(&service)
 }
 
 pub fn stop_service(service: &str) -> Result<()> {
-    run_command("/sbin/rc-service", &[&service, "stop"])
-        .with_context(|| format!("Failed to stop '{}' service", &service))?;
+    services::get().stop(&service)
+}
+
+pub fn restart_service(service: &str) -> Result<()> {
+    services::get().restart(&service)
+}
+
+// Meant to be asked for by the root filesystem, which cannot do so until libquillcom's CommandToQinit has a SchedulePowerOnAlarm variant: this tree cannot add it, so nothing schedules alarms yet
+pub fn schedule_power_on_alarm(epoch_seconds: i64) -> Result<()> {
+    let now = chrono::Utc::now().timestamp();
+    if epoch_seconds <= now {
+        return Err(anyhow::anyhow!(
+            "Power-on alarm {} is in the past",
+            epoch_seconds
+        ));
+    }
+    if epoch_seconds - now > POWER_ON_ALARM_MAX_DELAY_SECS {
+        return Err(anyhow::anyhow!(
+            "Power-on alarm {} is more than 30 days away",
+            epoch_seconds
+        ));
+    }
+    info!("Scheduling power-on alarm at {}", epoch_seconds);
+    *POWER_ON_ALARM.lock().unwrap() = Some(epoch_seconds);
 
     Ok(())
 }
 
-pub fn restart_service(service: &str) -> Result<()> {
-    run_command("/sbin/rc-service", &[&service, "restart"])
-        .with_context(|| format!("Failed to restart '{}' service", &service))?;
+pub fn cancel_power_on_alarm() {
+    if POWER_ON_ALARM.lock().unwrap().take().is_some() {
+        info!("Cancelled power-on alarm");
+    }
+}
+
+pub fn power_on_alarm() -> Option<i64> {
+    *POWER_ON_ALARM.lock().unwrap()
+}
+
+// The RTC refuses a new alarm while a previous one is still pending, hence the reset first
+#[cfg(not(feature = "gui_only"))]
+fn program_power_on_alarm() -> Result<()> {
+    let Some(epoch_seconds) = power_on_alarm() else {
+        return Ok(());
+    };
+    // Validated when it was scheduled, but it may have expired while the device stayed up
+    if epoch_seconds <= chrono::Utc::now().timestamp() {
+        return Err(anyhow::anyhow!(
+            "Power-on alarm {} expired before powering off",
+            epoch_seconds
+        ));
+    }
+    info!("Programming power-on alarm at {}", epoch_seconds);
+    fs::write(&RTC_WAKEALARM_PATH, "0")
+        .with_context(|| format!("Failed to clear '{}'", &RTC_WAKEALARM_PATH))?;
+    fs::write(&RTC_WAKEALARM_PATH, epoch_seconds.to_string())
+        .with_context(|| format!("Failed to write to '{}'", &RTC_WAKEALARM_PATH))?;
+
+    let marker_path = format!("{}/{}", &crate::BOOT_PART_MOUNTPOINT, &POWER_ON_ALARM_FILE);
+    fs::write(&marker_path, epoch_seconds.to_string())
+        .with_context(|| format!("Failed to write '{}'", &marker_path))?;
 
     Ok(())
 }
 
+// Consumes the marker left by the last power off either way: a boot that happens too long after the alarm time was not caused by it
+pub fn record_wake_reason() -> WakeReason {
+    let marker_path = format!("{}/{}", &crate::BOOT_PART_MOUNTPOINT, &POWER_ON_ALARM_FILE);
+    let wake_reason = match fs::read_to_string(&marker_path) {
+        Ok(marker) => {
+            if let Err(e) = fs::remove_file(&marker_path) {
+                warn!("Failed to remove '{}': {}", &marker_path, &e);
+            }
+            match marker.trim().parse::<i64>() {
+                Ok(epoch_seconds) => {
+                    let late_secs = chrono::Utc::now().timestamp() - epoch_seconds;
+                    if (0..=POWER_ON_ALARM_WAKE_SLACK_SECS).contains(&late_secs) {
+                        WakeReason::PowerOnAlarm
+                    } else {
+                        WakeReason::Other
+                    }
+                }
+                Err(_) => {
+                    warn!(
+                        "Ignoring malformed power-on alarm marker: '{}'",
+                        marker.trim()
+                    );
+                    WakeReason::Other
+                }
+            }
+        }
+        Err(_) => WakeReason::Other,
+    };
+    info!("Wake reason: {:?}", &wake_reason);
+    *WAKE_REASON.lock().unwrap() = Some(wake_reason);
+
+    wake_reason
+}
+
+pub fn wake_reason() -> Option<WakeReason> {
+    *WAKE_REASON.lock().unwrap()
+}
+
 pub fn real_shut_down(shut_down_type: PrimitiveShutDownType, mode: PowerDownMode) -> Result<()> {
     match shut_down_type {
         PrimitiveShutDownType::PowerOff => warn!("Powering off"),
@@ -289,10 +1910,22 @@ pub fn real_shut_down(shut_down_type: PrimitiveShutDownType, mode: PowerDownMode
         _ => {}
     };
 
+    // Has to happen while the boot partition is still mounted
+    #[cfg(not(feature = "gui_only"))]
+    if shut_down_type == PrimitiveShutDownType::PowerOff
+        && let Err(e) = program_power_on_alarm()
+    {
+        warn!("{:?}", &e);
+    }
+
     cfg_if::cfg_if! {
         if #[cfg(not(feature = "gui_only"))] {
             match mode {
                 PowerDownMode::Normal => {
+                    if let Err(e) = tear_down_zram() {
+                        warn!("{:?}", &e);
+                    }
+                    crate::storage_encryption::unmount_all();
                     unmount_base_partitions()?;
                     match shut_down_type {
                         PrimitiveShutDownType::PowerOff => run_command(&POWER_OFF_BINARY_PATH, &["-f"])?,
@@ -302,8 +1935,16 @@ pub fn real_shut_down(shut_down_type: PrimitiveShutDownType, mode: PowerDownMode
                 },
                 PowerDownMode::RootFS => {
                     match shut_down_type {
-                        PrimitiveShutDownType::PowerOff => run_chroot_command(&[&POWER_OFF_BINARY_PATH])?,
-                        PrimitiveShutDownType::Reboot => run_chroot_command(&[&REBOOT_BINARY_PATH])?,
+                        PrimitiveShutDownType::PowerOff => {
+                            run_chroot_command(&[&POWER_OFF_BINARY_PATH])?
+                                .check()
+                                .with_context(|| "Failed to power off from root filesystem")?;
+                        }
+                        PrimitiveShutDownType::Reboot => {
+                            run_chroot_command(&[&REBOOT_BINARY_PATH])?
+                                .check()
+                                .with_context(|| "Failed to reboot from root filesystem")?;
+                        }
                         _ => {},
                     }
                 }
@@ -314,11 +1955,8 @@ pub fn real_shut_down(shut_down_type: PrimitiveShutDownType, mode: PowerDownMode
     Ok(())
 }
 
-pub fn shut_down(
-    shut_down_type: PrimitiveShutDownType,
-    mode: PowerDownMode,
-    can_shut_down: Arc<AtomicBool>,
-) -> Result<()> {
+// Set by the GUI once the splash is on the panel and the frontlight is off
+fn wait_for_splash(can_shut_down: &AtomicBool) {
     loop {
         if can_shut_down.load(Ordering::SeqCst) {
             can_shut_down.store(false, Ordering::SeqCst);
@@ -326,12 +1964,83 @@ pub fn shut_down(
         }
         thread::sleep(std::time::Duration::from_millis(100));
     }
+}
 
+pub fn shut_down(
+    shut_down_type: PrimitiveShutDownType,
+    mode: PowerDownMode,
+    can_shut_down: Arc<AtomicBool>,
+) -> Result<()> {
+    wait_for_splash(&can_shut_down);
     thread::spawn(move || real_shut_down(shut_down_type, mode));
 
     Ok(())
 }
 
+// Blocks until the device wakes up again: whatever has to be restored afterwards is up to the caller
+pub fn suspend(can_shut_down: Arc<AtomicBool>) -> Result<()> {
+    wait_for_splash(&can_shut_down);
+    warn!("Suspending to memory");
+    sync_disks()?;
+    cfg_if::cfg_if! {
+        if #[cfg(not(feature = "gui_only"))] {
+            fs::write(&POWER_STATE_PATH, "mem")
+                .with_context(|| format!("Failed to write to '{}'", &POWER_STATE_PATH))?;
+        } else {
+            info!("Not suspending in GUI-only mode: '{}' left alone", &POWER_STATE_PATH);
+        }
+    }
+    info!("Resumed from suspend");
+
+    Ok(())
+}
+
+// RFC 1123: dot-separated labels of letters, digits and hyphens, none starting or ending with a hyphen
+pub fn validate_hostname(hostname: &str) -> Result<()> {
+    if hostname.is_empty() || hostname.len() > HOSTNAME_MAX_LENGTH {
+        return Err(anyhow::anyhow!(
+            "Hostname must be between 1 and {} characters long",
+            &HOSTNAME_MAX_LENGTH
+        ));
+    }
+    for label in hostname.split('.') {
+        if label.is_empty() || label.len() > HOSTNAME_LABEL_MAX_LENGTH {
+            return Err(anyhow::anyhow!(
+                "Each part of the hostname must be between 1 and {} characters long",
+                &HOSTNAME_LABEL_MAX_LENGTH
+            ));
+        }
+        if !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(anyhow::anyhow!(
+                "Hostname may only contain letters, digits, hyphens and dots"
+            ));
+        }
+        if label.starts_with('-') || label.ends_with('-') {
+            return Err(anyhow::anyhow!(
+                "Hostname parts may not start or end with a hyphen"
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+pub fn hostname(boot_config: &BootConfig) -> String {
+    match &boot_config.system.hostname {
+        Some(hostname) => match validate_hostname(&hostname) {
+            Ok(()) => hostname.to_string(),
+            Err(e) => {
+                warn!(
+                    "Ignoring invalid hostname '{}' from boot configuration: {}",
+                    &hostname, &e
+                );
+                DEFAULT_HOSTNAME.to_string()
+            }
+        },
+        None => DEFAULT_HOSTNAME.to_string(),
+    }
+}
+
 pub fn generate_version_string(
     boot_config: &mut BootConfig,
     qinit_commit: &str,
@@ -359,46 +2068,290 @@ pub fn generate_version_string(
         recovery_features_state = "Recovery features: disabled";
     }
 
+    let device_id = boot_config.system.device_id.as_deref().unwrap_or("Unknown");
+    let hostname = hostname(&boot_config);
+
     let version_string = format!(
-        "Kernel commit: {}\nGUI commit: {}\n{}\n{}\n{}",
-        &kernel_commit, &qinit_commit, &recovery_features_state, &signing_state, &debug_state
+        "Kernel commit: {}\nGUI commit: {}\nDevice ID: {}\nHostname: {}\n{}\n{}\n{}",
+        &kernel_commit,
+        &qinit_commit,
+        &device_id,
+        &hostname,
+        &recovery_features_state,
+        &signing_state,
+        &debug_state
     );
 
     return version_string;
 }
 
-pub fn generate_short_version_string(kernel_commit: &str, kernel_version: &str) -> String {
+pub fn generate_short_version_string(
+    kernel_commit: &str,
+    kernel_version: &str,
+    device_id: Option<&str>,
+) -> String {
     format!(
-        "Quill OS, kernel commit {}\n{}",
-        &kernel_commit, &kernel_version
+        "Quill OS, kernel commit {}, device ID {}\n{}",
+        &kernel_commit,
+        &device_id.unwrap_or("Unknown"),
+        &kernel_version
+    )
+}
+
+// Burnt into the SoC: unlike the device ID, it is not stored on any partition
+pub fn read_soc_serial_number() -> Option<String> {
+    let serial = fs::read_to_string(&SOC_SERIAL_NUMBER_PATH)
+        .ok()?
+        .trim_matches(|c: char| c == '\0' || c.is_whitespace())
+        .to_string();
+
+    (!serial.is_empty()).then_some(serial)
+}
+
+// Derived from the SoC serial number when the device tree exposes it, random UUIDv4 otherwise
+pub fn generate_device_id() -> Result<String> {
+    let mut bytes = [0u8; 16];
+    if let Some(serial) = read_soc_serial_number() {
+        info!("Deriving device ID from SoC serial number");
+        let digest = sha256::digest(serial.as_str());
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&digest[i * 2..i * 2 + 2], 16)?;
+        }
+    } else {
+        info!("Generating random device ID");
+        rand::rng().fill(&mut bytes);
+    }
+    // Version 4, RFC 4122 variant
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    Ok(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    ))
+}
+
+pub fn bind_mount(source: &str, mountpoint: &str, recursive: bool) -> Result<()> {
+    // Please figure out why Mount::builder() does not work for this kind of mount
+    let flag = if recursive { "--rbind" } else { "--bind" };
+    run_command("mount", &[&flag, &source, &mountpoint])?;
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CopyMode {
+    // Remove the target first: it ends up as an exact copy of the source
+    Clean,
+    // Copy over an existing target, replacing conflicting entries but keeping the others
+    Merge,
+}
+
+#[derive(Debug, Clone)]
+pub struct CopyOptions {
+    pub mode: CopyMode,
+    pub preserve_xattrs: bool,
+}
+
+fn copy_xattrs(source: &Path, target: &Path) -> Result<()> {
+    for name in xattr::list(&source)? {
+        if let Some(value) = xattr::get(&source, &name)? {
+            xattr::set(&target, &name, &value)
+                .with_context(|| format!("Failed to set extended attribute {:?}", &name))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn copy_metadata(
+    source: &Path,
+    target: &Path,
+    metadata: &fs::Metadata,
+    preserve_xattrs: bool,
+) -> Result<()> {
+    if metadata.file_type().is_symlink() {
+        lchown(&target, Some(metadata.uid()), Some(metadata.gid()))?;
+        return Ok(());
+    }
+
+    chown(&target, Some(metadata.uid()), Some(metadata.gid()))?;
+    // After chown(), which clears setuid and setgid bits
+    fs::set_permissions(
+        &target,
+        fs::Permissions::from_mode(metadata.mode() & 0o7777),
+    )?;
+    if preserve_xattrs {
+        copy_xattrs(&source, &target)?;
+    }
+    // Opening read-only is enough to set times, and also works with directories
+    fs::File::open(&target)?.set_times(
+        fs::FileTimes::new()
+            .set_accessed(metadata.accessed()?)
+            .set_modified(metadata.modified()?),
+    )?;
+
+    Ok(())
+}
+
+fn remove_conflicting_entry(target: &Path, source_is_dir: bool) -> Result<()> {
+    let Ok(target_metadata) = fs::symlink_metadata(&target) else {
+        return Ok(());
+    };
+    if target_metadata.is_dir() && !target_metadata.file_type().is_symlink() {
+        if !source_is_dir {
+            fs::remove_dir_all(&target)?;
+        }
+    } else if source_is_dir || target_metadata.file_type().is_symlink() {
+        fs::remove_file(&target)?;
+    }
+
+    Ok(())
+}
+
+// Preserves mode, ownership, timestamps, symlinks and optionally extended attributes
+pub fn copy_dir_recursively(source: &str, target: &str, options: &CopyOptions) -> Result<()> {
+    info!(
+        "Recursively copying directory '{}' to '{}' ({:?})",
+        &source, &target, &options.mode
+    );
+    if options.mode == CopyMode::Clean {
+        rm_dir_all(&target)?;
+    }
+
+    let mut directories = Vec::new();
+    for entry in WalkDir::new(&source).follow_links(false) {
+        let entry = entry.with_context(|| format!("Failed to walk '{}'", &source))?;
+        let source_path = entry.path();
+        let target_path = Path::new(&target).join(source_path.strip_prefix(&source)?);
+        let metadata = entry
+            .metadata()
+            .with_context(|| format!("Failed to read metadata of '{}'", &source_path.display()))?;
+        let file_type = metadata.file_type();
+
+        (|| -> Result<()> {
+            remove_conflicting_entry(&target_path, file_type.is_dir())?;
+            if file_type.is_dir() {
+                if !target_path.is_dir() {
+                    fs::create_dir(&target_path)?;
+                }
+                // Applied once everything inside has been copied: a read-only mode or new contents would get in the way
+                directories.push((source_path.to_path_buf(), target_path.clone(), metadata));
+                return Ok(());
+            } else if file_type.is_symlink() {
+                symlink(fs::read_link(&source_path)?, &target_path)?;
+            } else if file_type.is_file() {
+                fs::copy(&source_path, &target_path)?;
+            } else {
+                warn!("Skipping special file '{}'", &source_path.display());
+                return Ok(());
+            }
+            copy_metadata(
+                &source_path,
+                &target_path,
+                &metadata,
+                options.preserve_xattrs,
+            )
+        })()
+        .with_context(|| {
+            format!(
+                "Failed to copy '{}' to '{}'",
+                &source_path.display(),
+                &target_path.display()
+            )
+        })?;
+    }
+
+    for (source_path, target_path, metadata) in directories.iter().rev() {
+        copy_metadata(
+            &source_path,
+            &target_path,
+            &metadata,
+            options.preserve_xattrs,
+        )
+        .with_context(|| {
+            format!(
+                "Failed to copy metadata of '{}' to '{}'",
+                &source_path.display(),
+                &target_path.display()
+            )
+        })?;
+    }
+
+    Ok(())
+}
+
+pub fn clean_copy_dir_recursively(source: &str, target: &str) -> Result<()> {
+    copy_dir_recursively(
+        &source,
+        &target,
+        &CopyOptions {
+            mode: CopyMode::Clean,
+            preserve_xattrs: true,
+        },
     )
 }
 
-pub fn bind_mount(source: &str, mountpoint: &str) -> Result<()> {
-    // Please figure out why Mount::builder() does not work for this kind of mount
-    run_command("mount", &["--rbind", &source, &mountpoint])?;
+// Receives the number of bytes processed so far and the total
+pub type ProgressCallback<'a> = &'a mut dyn FnMut(u64, u64);
+
+// Reads the file in fixed-size chunks so that large archives are never held in memory as a whole
+pub fn for_each_file_chunk(
+    path: &str,
+    mut progress: Option<ProgressCallback>,
+    mut handle_chunk: impl FnMut(&[u8]) -> Result<()>,
+) -> Result<()> {
+    let mut file =
+        fs::File::open(&path).with_context(|| format!("Failed to open file '{}'", &path))?;
+    let total = file.metadata()?.len();
+    let mut buffer = vec![0u8; FILE_CHUNK_SIZE];
+    let mut done: u64 = 0;
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .with_context(|| format!("Failed to read file '{}'", &path))?;
+        if read == 0 {
+            break;
+        }
+        handle_chunk(&buffer[..read])?;
+        done += read as u64;
+        if let Some(progress) = progress.as_mut() {
+            progress(done, total);
+        }
+    }
 
     Ok(())
 }
 
-pub fn clean_copy_dir_recursively(source: &str, target: &str) -> Result<()> {
-    info!(
-        "Recursively copying directory '{}' to '{}'",
-        &source, &target
-    );
-    rm_dir_all(&target)?;
-    run_command("/bin/cp", &["-r", &source, &target])?;
-    // This does not seem to work with /overlay/etc/ssh directory (permission issues?)
-    /* fs::create_dir_all(&target)?;
-    let mut path = Vec::new();
-    path.push(&source);
-    copy_items(&path, &target, &dir::CopyOptions::new())?; */
+pub fn sha256_digest(path: &str, progress: Option<ProgressCallback>) -> Result<String> {
+    let mut hasher = openssl::sha::Sha256::new();
+    for_each_file_chunk(&path, progress, |chunk| {
+        hasher.update(&chunk);
+        Ok(())
+    })?;
 
-    Ok(())
+    Ok(to_hex(&hasher.finish()))
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
 }
 
 pub fn sha256_match(path: &str, write_new_checksum: bool) -> Result<bool> {
-    let checksum = sha256::try_digest(Path::new(&path))?;
+    sha256_match_with_progress(&path, write_new_checksum, None)
+}
+
+pub fn sha256_match_with_progress(
+    path: &str,
+    write_new_checksum: bool,
+    progress: Option<ProgressCallback>,
+) -> Result<bool> {
+    let checksum = sha256_digest(&path, progress)?;
     let checksum_file_path = format!("{}.sha256", &path);
     info!(
         "Checking for sha256sum match for file '{}' at path '{}'",
@@ -434,6 +2387,227 @@ pub fn read_kernel_buffer_singleshot() -> Result<String> {
     Ok(kernel_buffer)
 }
 
+fn monotonic_ms() -> u64 {
+    match clock_gettime(ClockId::CLOCK_MONOTONIC) {
+        Ok(time) => time.tv_sec() as u64 * 1000 + time.tv_nsec() as u64 / 1_000_000,
+        Err(_) => 0,
+    }
+}
+
+pub fn begin_phase(name: &str) {
+    let start_ms = monotonic_ms();
+    debug!("Boot phase '{}' started at {} ms", &name, &start_ms);
+    BOOT_PHASES.lock().unwrap().push(BootPhase {
+        name: name.to_string(),
+        start_ms,
+        end_ms: None,
+        start_sample: ResourceSample::take(),
+        resource_delta: None,
+    });
+}
+
+pub fn end_phase(name: &str) {
+    let end_ms = monotonic_ms();
+    let end_sample = ResourceSample::take();
+    let mut boot_phases = BOOT_PHASES.lock().unwrap();
+    if let Some(phase) = boot_phases
+        .iter_mut()
+        .rev()
+        .find(|phase| phase.name == name && phase.end_ms.is_none())
+    {
+        phase.end_ms = Some(end_ms);
+        phase.resource_delta = phase
+            .start_sample
+            .zip(end_sample)
+            .map(|(start_sample, end_sample)| start_sample.delta(&end_sample));
+        debug!(
+            "Boot phase '{}' took {} ms",
+            &name,
+            end_ms.saturating_sub(phase.start_ms)
+        );
+    } else {
+        warn!("Boot phase '{}' ended without having been started", &name);
+    }
+}
+
+pub fn phase_duration_ms(name: &str) -> Option<u64> {
+    BOOT_PHASES
+        .lock()
+        .unwrap()
+        .iter()
+        .rev()
+        .find(|phase| phase.name == name)
+        .and_then(|phase| {
+            phase
+                .end_ms
+                .map(|end_ms| end_ms.saturating_sub(phase.start_ms))
+        })
+}
+
+pub fn boot_timing_report() -> BootTimingReport {
+    BootTimingReport {
+        phases: BOOT_PHASES.lock().unwrap().clone(),
+        total_ms: monotonic_ms(),
+        overlay_backend: crate::rootfs::overlay_backend(),
+        wake_reason: wake_reason(),
+    }
+}
+
+// Only phases that used up a lot of memory are worth pointing out: memory pressure slows down a boot as much as time spent
+fn phase_memory_flag(phase: &BootPhase) -> String {
+    match phase
+        .resource_delta
+        .and_then(|resource_delta| resource_delta.memory_used_kb)
+    {
+        Some(memory_used_kb) if memory_used_kb > PHASE_MEMORY_DELTA_WARNING_KB => {
+            format!(" [!] +{} MB", memory_used_kb / 1024)
+        }
+        _ => String::new(),
+    }
+}
+
+// Unfinished phases are reported as such: this is what makes a hang attributable
+pub fn boot_timing_summary(report: &BootTimingReport) -> String {
+    let phases: Vec<String> = report
+        .phases
+        .iter()
+        .map(|phase| match phase.end_ms {
+            Some(end_ms) => format!(
+                "{} {} ms{}",
+                &phase.name,
+                end_ms.saturating_sub(phase.start_ms),
+                phase_memory_flag(&phase)
+            ),
+            None => format!(
+                "{} unfinished after {} ms",
+                &phase.name,
+                report.total_ms.saturating_sub(phase.start_ms)
+            ),
+        })
+        .collect();
+
+    let overlay_backend = report
+        .overlay_backend
+        .map(|backend| format!(", {:?} overlayfs", &backend))
+        .unwrap_or_default();
+    let wake_reason = if report.wake_reason == Some(WakeReason::PowerOnAlarm) {
+        ", woken up by power-on alarm"
+    } else {
+        ""
+    };
+
+    format!(
+        "Boot timing: {} (total {} ms{}{})",
+        phases.join(", "),
+        &report.total_ms,
+        &overlay_backend,
+        &wake_reason
+    )
+}
+
+pub fn save_first_stage_boot_phases() -> Result<()> {
+    fs::write(
+        &FIRST_STAGE_BOOT_PHASES_PATH,
+        ron::to_string(&*BOOT_PHASES.lock().unwrap())?,
+    )
+    .with_context(|| "Failed to save first stage boot phases")?;
+
+    Ok(())
+}
+
+pub fn load_first_stage_boot_phases() -> Result<()> {
+    if !fs::exists(&FIRST_STAGE_BOOT_PHASES_PATH)? {
+        return Ok(());
+    }
+    let first_stage_phases = ron::from_str::<Vec<BootPhase>>(
+        &fs::read_to_string(&FIRST_STAGE_BOOT_PHASES_PATH)
+            .with_context(|| "Failed to read first stage boot phases")?,
+    )
+    .with_context(|| "Failed to parse first stage boot phases")?;
+    BOOT_PHASES.lock().unwrap().splice(0..0, first_stage_phases);
+
+    Ok(())
+}
+
+pub fn write_boot_timing_report() -> Result<()> {
+    let report = boot_timing_report();
+    fs::write(
+        &BOOT_TIMING_REPORT_PATH,
+        ron::ser::to_string_pretty(&report, ron::ser::PrettyConfig::default())?,
+    )
+    .with_context(|| "Failed to write boot timing report")?;
+    info!("{}", boot_timing_summary(&report));
+
+    Ok(())
+}
+
+fn get_boot_history_path() -> String {
+    format!(
+        "{}/{}/{}",
+        &crate::MAIN_PART_MOUNTPOINT,
+        &crate::SYSTEM_DIR,
+        &BOOT_HISTORY_FILE
+    )
+}
+
+// Derived from the root filesystem's detached signature: cheap to read, and different for every image
+pub fn rootfs_identity() -> Result<String> {
+    let digest_file_path = format!(
+        "{}{}",
+        &crate::rootfs::active_rootfs_file_path(),
+        &crate::GENERIC_DIGEST_EXT
+    );
+    let signature = fs::read(&digest_file_path)
+        .with_context(|| format!("Failed to read '{}'", &digest_file_path))?;
+
+    Ok(to_hex(&openssl::sha::sha256(&signature))[0..16].to_string())
+}
+
+fn read_boot_history() -> Vec<BootRecord> {
+    let Ok(boot_history_str) = fs::read_to_string(&get_boot_history_path()) else {
+        return Vec::new();
+    };
+    ron::from_str::<Vec<BootRecord>>(&boot_history_str).unwrap_or_else(|e| {
+        warn!("Ignoring invalid boot history: {}", &e);
+        Vec::new()
+    })
+}
+
+// To be called once the boot has completed
+pub fn record_boot_duration() -> Result<()> {
+    let mut boot_history = read_boot_history();
+    boot_history.push(BootRecord {
+        rootfs_identity: rootfs_identity()?,
+        total_ms: monotonic_ms(),
+    });
+    let excess_records = boot_history.len().saturating_sub(BOOT_HISTORY_MAX_RECORDS);
+    boot_history.drain(0..excess_records);
+    fs::write(&get_boot_history_path(), ron::to_string(&boot_history)?)
+        .with_context(|| "Failed to write boot history")?;
+
+    Ok(())
+}
+
+// Averages the last few boots of the current root filesystem: returns the estimate along with how many boots it is based on
+pub fn estimate_boot_duration() -> Option<(Duration, usize)> {
+    let rootfs_identity = rootfs_identity().ok()?;
+    let durations: Vec<u64> = read_boot_history()
+        .into_iter()
+        .rev()
+        .filter(|record| record.rootfs_identity == rootfs_identity)
+        .take(BOOT_ESTIMATE_RECORDS)
+        .map(|record| record.total_ms)
+        .collect();
+    if durations.is_empty() {
+        return None;
+    }
+
+    Some((
+        Duration::from_millis(durations.iter().sum::<u64>() / durations.len() as u64),
+        durations.len(),
+    ))
+}
+
 pub fn keep_last_lines(string: &str, lines_to_keep: usize) -> String {
     let lines: Vec<&str> = string.lines().collect();
     let len = lines.len();
@@ -444,54 +2618,246 @@ pub fn keep_last_lines(string: &str, lines_to_keep: usize) -> String {
         .join("\n");
 }
 
+// Pages are counted from the end, so that the first one is what keep_last_lines() would keep: out of range pages show the oldest lines
+pub fn paginate(string: &str, lines_per_page: usize, page: usize) -> (String, usize) {
+    let lines_per_page = lines_per_page.max(1);
+    let pages_count = string.lines().count().div_ceil(lines_per_page).max(1);
+    let page = page.min(pages_count - 1);
+    let kept = keep_last_lines(&string, lines_per_page * (page + 1));
+    let lines_count = kept.lines().count();
+    let page_string = kept
+        .lines()
+        .take(lines_count.saturating_sub(lines_per_page * page))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    (page_string, pages_count)
+}
+
+// Every log ends up as its own file inside a new timestamped directory, e.g. 'qinit-logs-20250101-120000'
+pub fn save_logs(destination_dir: &Path, logs: &[(&str, &str)]) -> Result<PathBuf> {
+    let logs_dir_path = destination_dir.join(format!(
+        "qinit-logs-{}",
+        chrono::Local::now().format("%Y%m%d-%H%M%S")
+    ));
+    fs::create_dir_all(&logs_dir_path)
+        .with_context(|| format!("Failed to create '{}'", &logs_dir_path.display()))?;
+    for (file_name, contents) in logs {
+        let log_path = logs_dir_path.join(&file_name);
+        fs::write(&log_path, &contents)
+            .with_context(|| format!("Failed to write '{}'", &log_path.display()))?;
+    }
+    sync_disks()?;
+    info!("Saved logs to '{}'", &logs_dir_path.display());
+
+    Ok(logs_dir_path)
+}
+
 pub fn compress_string_to_xz(string: &str) -> Result<Vec<u8>> {
     debug!("Compressing string to xz");
-    let base64_string = BASE64_STANDARD.encode(string);
-    let data = Command::new("/bin/sh")
-        .args(&[
-            "-c",
-            &format!("printf '{}' | base64 -d | xz -9 -e", &base64_string),
-        ])
-        .output()?
-        .stdout;
-    debug!("Compressed string size: {} bytes", data.iter().count());
+    compress_bytes_to_xz(string.as_bytes())
+}
+
+pub fn compress_bytes_to_xz(bytes: &[u8]) -> Result<Vec<u8>> {
+    // Equivalent to 'xz -9 -e'
+    let stream = Stream::new_easy_encoder(9 | XZ_PRESET_EXTREME, Check::Crc64)
+        .with_context(|| "Failed to initialize xz encoder")?;
+    let mut encoder = XzEncoder::new_stream(Vec::new(), stream);
+    encoder
+        .write_all(&bytes)
+        .with_context(|| "Failed to compress data to xz")?;
+    let data = encoder
+        .finish()
+        .with_context(|| "Failed to finish xz stream")?;
+    debug!("Compressed data size: {} bytes", data.len());
 
     Ok(data)
 }
 
-pub fn sync_time() -> Result<()> {
+const NTP_PORT: u16 = 123;
+const NTP_PACKET_SIZE: usize = 48;
+// Short enough that a captive portal blocking NTP does not stall the Wi-Fi connection path
+const NTP_SERVER_TIMEOUT: Duration = Duration::from_millis(1500);
+// Seconds between the NTP epoch (1900) and the UNIX epoch (1970)
+const NTP_UNIX_EPOCH_OFFSET: u64 = 2_208_988_800;
+const RTC_DEVICE_PATH: &str = "/dev/rtc0";
+const ADJTIME_PATH: &str = "/etc/adjtime";
+
+#[repr(C)]
+struct RtcTime {
+    tm_sec: i32,
+    tm_min: i32,
+    tm_hour: i32,
+    tm_mday: i32,
+    tm_mon: i32,
+    tm_year: i32,
+    tm_wday: i32,
+    tm_yday: i32,
+    tm_isdst: i32,
+}
+
+nix::ioctl_write_ptr!(rtc_set_time, b'p', 0x0a, RtcTime);
+
+// Returns the server's transmit timestamp as a duration since the UNIX epoch
+fn query_sntp_server(server: &str) -> Result<Duration> {
+    let socket = std::net::UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_read_timeout(Some(NTP_SERVER_TIMEOUT))?;
+    socket.set_write_timeout(Some(NTP_SERVER_TIMEOUT))?;
+    socket
+        .connect((server, NTP_PORT))
+        .with_context(|| format!("Failed to resolve NTP server '{}'", &server))?;
+
+    let mut packet = [0u8; NTP_PACKET_SIZE];
+    // Leap indicator 0, version 4, client mode
+    packet[0] = 0b00_100_011;
+    socket.send(&packet)?;
+    let received = socket
+        .recv(&mut packet)
+        .with_context(|| format!("No answer from NTP server '{}'", &server))?;
+    if received < NTP_PACKET_SIZE || packet[0] & 0b111 != 4 || packet[1] == 0 {
+        return Err(anyhow::anyhow!(
+            "Invalid answer from NTP server '{}'",
+            &server
+        ));
+    }
+
+    let seconds = u32::from_be_bytes([packet[40], packet[41], packet[42], packet[43]]) as u64;
+    let fraction = u32::from_be_bytes([packet[44], packet[45], packet[46], packet[47]]) as u64;
+    let unix_seconds = seconds
+        .checked_sub(NTP_UNIX_EPOCH_OFFSET)
+        .ok_or_else(|| anyhow::anyhow!("NTP server '{}' sent an invalid timestamp", &server))?;
+
+    Ok(Duration::new(
+        unix_seconds,
+        ((fraction * 1_000_000_000) >> 32) as u32,
+    ))
+}
+
+// Follows hwclock's convention: the RTC keeps UTC unless /etc/adjtime says otherwise
+fn rtc_keeps_local_time() -> bool {
+    fs::read_to_string(&ADJTIME_PATH)
+        .map(|adjtime| adjtime.lines().nth(2).map(|line| line.trim()) == Some("LOCAL"))
+        .unwrap_or(false)
+}
+
+fn set_rtc(time: Duration) -> Result<()> {
+    use chrono::{Datelike, Timelike};
+
+    let utc = chrono::DateTime::from_timestamp(time.as_secs() as i64, 0)
+        .ok_or_else(|| anyhow::anyhow!("Time is out of range for the RTC"))?;
+    let time = if rtc_keeps_local_time() {
+        utc.with_timezone(&chrono::Local).naive_local()
+    } else {
+        utc.naive_utc()
+    };
+    let rtc_time = RtcTime {
+        tm_sec: time.second() as i32,
+        tm_min: time.minute() as i32,
+        tm_hour: time.hour() as i32,
+        tm_mday: time.day() as i32,
+        tm_mon: time.month0() as i32,
+        tm_year: time.year() - 1900,
+        tm_wday: time.weekday().num_days_from_sunday() as i32,
+        tm_yday: time.ordinal0() as i32,
+        tm_isdst: 0,
+    };
+
+    let rtc = fs::OpenOptions::new()
+        .write(true)
+        .open(&RTC_DEVICE_PATH)
+        .with_context(|| format!("Failed to open '{}'", &RTC_DEVICE_PATH))?;
+    unsafe { rtc_set_time(rtc.as_raw_fd(), &rtc_time) }
+        .with_context(|| "Failed to set hardware clock")?;
+
+    Ok(())
+}
+
+// Returns the server the time was obtained from
+pub fn sync_time(servers: &[String]) -> Result<String> {
     // This function assumes a working Internet connection
     info!("Syncing time");
-    run_command("/bin/busybox", &["ntpd", "-q", "-n", "-p", "pool.ntp.org"])?;
-    run_command("/sbin/hwclock", &["--systohc"])?;
+    for server in servers {
+        let time = match query_sntp_server(&server) {
+            Ok(time) => time,
+            Err(e) => {
+                warn!("{:?}", &e);
+                continue;
+            }
+        };
+        nix::time::clock_settime(
+            ClockId::CLOCK_REALTIME,
+            nix::sys::time::TimeSpec::from_duration(time),
+        )
+        .with_context(|| "Failed to set system clock")?;
+        info!("Time synchronized with NTP server '{}'", &server);
+        if let Err(e) = set_rtc(time) {
+            warn!("{:?}", &e);
+        }
 
-    Ok(())
+        return Ok(server.to_string());
+    }
+
+    Err(anyhow::anyhow!("No NTP server could be reached"))
 }
 
+fn is_timezone_file(path: &Path) -> bool {
+    let mut magic = [0u8; 4];
+    fs::File::open(&path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok()
+        && &magic == TIMEZONE_FILE_MAGIC
+}
+
+// Sorted, with anything that cannot be used as /etc/localtime left out (e.g. broken links, truncated files, tables)
 pub fn get_timezones_list() -> Result<Vec<String>> {
     let mut list: Vec<String> = Vec::new();
 
-    for entry in WalkDir::new(&TIMEZONE_FILES_DIR_PATH) {
-        let path = entry?
+    for entry in WalkDir::new(&TIMEZONE_FILES_DIR_PATH).follow_links(true) {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let path = entry
             .path()
             .strip_prefix(&TIMEZONE_FILES_DIR_PATH)?
             .display()
             .to_string();
 
-        if EXCLUDED_TIMEZONE_FILES.contains(&path.as_str()) {
+        if EXCLUDED_TIMEZONE_FILES.contains(&path.as_str())
+            || EXCLUDED_TIMEZONE_DIRS
+                .iter()
+                .any(|dir| path.starts_with(dir))
+            || !is_timezone_file(entry.path())
+        {
             continue;
         }
 
         list.push(path);
     }
+    list.sort();
 
     Ok(list)
 }
 
+// e.g. "Europe" for "Europe/Paris": zones without a region (e.g. "UTC") are grouped together
+pub fn timezone_region(timezone: &str) -> &str {
+    timezone
+        .split_once('/')
+        .map(|(region, _)| region)
+        .unwrap_or("Other")
+}
+
+// May be called again at any time: the clock follows right away
 pub fn set_timezone(timezone: &str) -> Result<()> {
     let timezone_data = format!("{}{}", &TIMEZONE_FILES_DIR_PATH, &timezone);
-    if fs::exists(&&timezone_data)? {
-        symlink(&timezone_data, "/etc/localtime")
+    if fs::symlink_metadata(&LOCALTIME_PATH).is_ok() {
+        fs::remove_file(&LOCALTIME_PATH)
+            .with_context(|| format!("Failed to remove '{}'", &LOCALTIME_PATH))?;
+    }
+    if !timezone.contains("..") && is_timezone_file(Path::new(&timezone_data)) {
+        symlink(&timezone_data, &LOCALTIME_PATH)
             .with_context(|| "Failed to symlink timezone data to /etc/localtime")?;
         info!("Setting timezone to '{}'", &timezone);
     } else {
@@ -527,14 +2893,77 @@ pub fn bulletproof_unmount(path: &str) -> Result<()> {
     Ok(())
 }
 
+// Mountinfo escapes spaces, tabs, newlines and backslashes in paths as octal sequences
+fn unescape_mountinfo_field(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\'
+            && i + 3 < bytes.len()
+            && bytes[i + 1..i + 4]
+                .iter()
+                .all(|byte| (b'0'..=b'7').contains(byte))
+        {
+            let value = bytes[i + 1..i + 4]
+                .iter()
+                .fold(0u32, |value, byte| value * 8 + (byte - b'0') as u32);
+            unescaped.push(value as u8);
+            i += 4;
+        } else {
+            unescaped.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8_lossy(&unescaped).to_string()
+}
+
+// See proc_pid_mountinfo(5)
+fn parse_mountinfo(mountinfo: &str) -> Vec<(String, String, String, String)> {
+    let mut mounts = Vec::new();
+    for line in mountinfo.lines() {
+        let fields: Vec<&str> = line.split(' ').collect();
+        let Some(separator_index) = fields.iter().position(|field| *field == "-") else {
+            continue;
+        };
+        if separator_index < 6 || fields.len() < separator_index + 3 {
+            continue;
+        }
+        mounts.push((
+            unescape_mountinfo_field(&fields[separator_index + 2]),
+            unescape_mountinfo_field(&fields[4]),
+            fields[separator_index + 1].to_string(),
+            fields[5].to_string(),
+        ));
+    }
+
+    mounts
+}
+
+// Returns (source, target, filesystem type, mount options) tuples
+pub fn list_mounts() -> Result<Vec<(String, String, String, String)>> {
+    let mountinfo = fs::read_to_string(&MOUNTINFO_PATH)
+        .with_context(|| format!("Failed to read '{}'", &MOUNTINFO_PATH))?;
+
+    Ok(parse_mountinfo(&mountinfo))
+}
+
 pub fn is_mountpoint(path: &str) -> Result<bool> {
-    // Could be replaced by proper Rust logic further on
-    if let Err(_e) = run_command("/bin/mountpoint", &[&path]) {
+    let Ok(canonical_path) = fs::canonicalize(&path) else {
         debug!("Path '{}' is not a mountpoint", &path);
         return Ok(false);
-    } else {
+    };
+    let canonical_path = canonical_path.to_string_lossy().to_string();
+    if list_mounts()?
+        .iter()
+        .any(|(_, target, _, _)| *target == canonical_path)
+    {
         debug!("Path '{}' is a mountpoint", &path);
         return Ok(true);
+    } else {
+        debug!("Path '{}' is not a mountpoint", &path);
+        return Ok(false);
     }
 }
 
@@ -552,11 +2981,8 @@ pub fn mount_qinit_binaries() -> Result<()> {
                 &QINIT_BINARIES_DIR_PATH
             )
         })?;
-        run_command(
-            "/bin/mount",
-            &[&qinit_binaries_archive_path, &QINIT_BINARIES_DIR_PATH],
-        )
-        .with_context(|| "Failed to mount qinit binaries")?;
+        mount_squashfs(&qinit_binaries_archive_path, &QINIT_BINARIES_DIR_PATH)
+            .with_context(|| "Failed to mount qinit binaries")?;
     }
 
     Ok(())
@@ -568,3 +2994,640 @@ pub fn run_core_settings() -> Result<()> {
 
     Ok(())
 }
+
+struct TemporaryMount {
+    mountpoint: String,
+    mounted: bool,
+}
+
+impl Drop for TemporaryMount {
+    fn drop(&mut self) {
+        if self.mounted {
+            if let Err(e) = bulletproof_unmount(&self.mountpoint) {
+                warn!(
+                    "Failed to unmount temporary mountpoint '{}': {}",
+                    &self.mountpoint, &e
+                );
+            }
+        }
+        if let Err(e) = fs::remove_dir(&self.mountpoint) {
+            warn!(
+                "Failed to remove temporary mountpoint '{}': {}",
+                &self.mountpoint, &e
+            );
+        }
+    }
+}
+
+// Mounts source on a unique temporary mountpoint for the duration of the closure: unmounting and cleanup are guaranteed even if the closure fails
+// Panics only unwind through the cleanup in debug and test builds: release builds abort on panic, leaving the mount behind
+pub fn with_mount<T, F>(
+    source: &str,
+    fstype: &str,
+    flags: MountFlags,
+    data: Option<&str>,
+    f: F,
+) -> Result<T>
+where
+    F: FnOnce(&str) -> Result<T>,
+{
+    with_mount_in(&crate::DEFAULT_MOUNTPOINT, &source, &fstype, flags, data, f)
+}
+
+fn with_mount_in<T, F>(
+    parent_dir: &str,
+    source: &str,
+    fstype: &str,
+    flags: MountFlags,
+    data: Option<&str>,
+    f: F,
+) -> Result<T>
+where
+    F: FnOnce(&str) -> Result<T>,
+{
+    let mountpoint = format!("{}/tmp-{}", &parent_dir, generate_random_string(12)?);
+    fs::create_dir_all(&mountpoint)
+        .with_context(|| format!("Failed to create temporary mountpoint '{}'", &mountpoint))?;
+    let mut guard = TemporaryMount {
+        mountpoint: mountpoint.clone(),
+        mounted: false,
+    };
+
+    debug!("Temporarily mounting '{}' at '{}'", &source, &mountpoint);
+    let mut builder = Mount::builder().fstype(fstype).flags(flags);
+    if let Some(data) = data {
+        builder = builder.data(data);
+    }
+    builder
+        .mount(&source, &mountpoint)
+        .with_context(|| format!("Failed to mount '{}' at '{}'", &source, &mountpoint))?;
+    guard.mounted = true;
+
+    f(&guard.mountpoint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    #[test]
+    fn cmdline_parameters_and_flags_are_told_apart() {
+        let cmdline = Cmdline::parse(
+            "console=ttyS2,1500000 quill_recovery=1 quiet quill_debug=false root=PARTLABEL=os1\n",
+        );
+        assert_eq!(cmdline.get_str("console"), Some("ttyS2,1500000"));
+        assert_eq!(cmdline.get_str("root"), Some("PARTLABEL=os1"));
+        assert!(cmdline.get_bool("quill_recovery"));
+        assert!(!cmdline.get_bool("quill_debug"));
+        assert!(cmdline.get_bool("quiet"));
+        assert!(cmdline.has_flag("quiet"));
+        assert!(!cmdline.has_flag("console"));
+        assert!(!cmdline.get_bool("missing"));
+        assert_eq!(cmdline.get_str("missing"), None);
+    }
+
+    #[test]
+    fn cmdline_quoted_values_keep_their_spaces() {
+        let cmdline = Cmdline::parse("splash_text=\"Hello there\" quiet");
+        assert_eq!(cmdline.get_str("splash_text"), Some("Hello there"));
+        assert!(cmdline.has_flag("quiet"));
+    }
+
+    #[test]
+    fn cmdline_integers_are_checked() {
+        let cmdline = Cmdline::parse("quill_timeout=30 quill_bad=thirty");
+        assert_eq!(cmdline.get_u32("quill_timeout").unwrap(), Some(30));
+        assert_eq!(cmdline.get_u32("missing").unwrap(), None);
+        assert!(cmdline.get_u32("quill_bad").is_err());
+    }
+
+    #[test]
+    fn cmdline_pubkey_is_decoded() {
+        let cmdline = Cmdline::parse(&format!("quiet {}=aGVsbG8=", &CMDLINE_PUBKEY_PROPERTY));
+        assert_eq!(cmdline.pubkey().unwrap(), Some(b"hello".to_vec()));
+        assert!(
+            Cmdline::parse(&format!("{}=!!!", &CMDLINE_PUBKEY_PROPERTY))
+                .pubkey()
+                .is_err()
+        );
+        assert_eq!(Cmdline::parse("quiet").pubkey().unwrap(), None);
+    }
+
+    #[test]
+    fn command_exceeding_its_timeout_is_killed() {
+        let start = std::time::Instant::now();
+        let error = run_command_with_timeout("/bin/sleep", &["10"], Duration::from_millis(200))
+            .unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        let timed_out = error.downcast_ref::<TimedOutError>().unwrap();
+        assert_eq!(timed_out.command, "/bin/sleep");
+    }
+
+    #[test]
+    fn command_within_its_timeout_succeeds() {
+        run_command_with_timeout("/bin/true", &[], Duration::from_secs(10)).unwrap();
+        let error = run_command_with_timeout(
+            "/bin/sh",
+            &["-c", "echo 'something broke' >&2; exit 3"],
+            Duration::from_secs(10),
+        )
+        .unwrap_err();
+        let command_failed = error.downcast_ref::<CommandFailed>().unwrap();
+        assert_eq!(command_failed.status.code(), Some(3));
+        assert_eq!(command_failed.stderr_tail, "something broke");
+    }
+
+    #[test]
+    fn captured_output_is_returned_whatever_the_status() {
+        let output =
+            run_command_capture("/bin/sh", &["-c", "echo out; echo err >&2; exit 2"]).unwrap();
+        assert_eq!(output.status.code(), Some(2));
+        assert_eq!(output.stdout, "out\n");
+        assert_eq!(output.stderr, "err\n");
+    }
+
+    #[test]
+    fn xz_compression_round_trips() {
+        // Single quotes and binary data used to break the shell pipeline
+        let text = "Error: 'rootfs' failed\n\u{0}\u{7f} café".repeat(100);
+        let compressed = compress_string_to_xz(&text).unwrap();
+        assert!(compressed.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]));
+        assert!(compressed.len() < text.len());
+        let mut decompressed = String::new();
+        xz2::read::XzDecoder::new(compressed.as_slice())
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, text);
+    }
+
+    #[test]
+    fn mountinfo_lines_are_parsed() {
+        let mountinfo = "22 1 179:9 / /main rw,relatime - ext4 /dev/mmcblk0p9 rw\n\
+                         36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 shared:2 - ext3 /dev/root rw,errors=continue\n\
+                         40 22 0:35 / /mnt/my\\040disk ro - vfat /dev/sd\\134a1 ro\n\
+                         garbage\n";
+        assert_eq!(
+            parse_mountinfo(&mountinfo),
+            vec![
+                (
+                    "/dev/mmcblk0p9".to_string(),
+                    "/main".to_string(),
+                    "ext4".to_string(),
+                    "rw,relatime".to_string()
+                ),
+                (
+                    "/dev/root".to_string(),
+                    "/mnt2".to_string(),
+                    "ext3".to_string(),
+                    "rw,noatime".to_string()
+                ),
+                (
+                    "/dev/sd\\a1".to_string(),
+                    "/mnt/my disk".to_string(),
+                    "vfat".to_string(),
+                    "ro".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn incomplete_escapes_are_kept() {
+        assert_eq!(unescape_mountinfo_field("a\\04"), "a\\04");
+        assert_eq!(unescape_mountinfo_field("a\\011b"), "a\tb");
+    }
+
+    #[test]
+    fn plain_directory_is_not_a_mountpoint() {
+        let dir = TempDir::new();
+        assert!(!is_mountpoint(&dir.path()).unwrap());
+        assert!(!is_mountpoint(&dir.join("missing")).unwrap());
+    }
+
+    fn boot_timing_report_sample() -> BootTimingReport {
+        BootTimingReport {
+            phases: vec![
+                BootPhase {
+                    name: "waveform load".to_string(),
+                    start_ms: 1200,
+                    end_ms: Some(1450),
+                    start_sample: Some(ResourceSample {
+                        available_memory_kb: Some(3_000_000),
+                        load_average: Some(0.5),
+                        rss_kb: Some(4096),
+                    }),
+                    resource_delta: Some(ResourceDelta {
+                        memory_used_kb: Some(80 * 1024),
+                        rss_kb: Some(128),
+                        load_average: Some(0.1),
+                    }),
+                },
+                BootPhase {
+                    name: "systemd wait".to_string(),
+                    start_ms: 5000,
+                    end_ms: None,
+                    start_sample: None,
+                    resource_delta: None,
+                },
+            ],
+            total_ms: 9000,
+            overlay_backend: Some(crate::rootfs::OverlayBackend::Kernel),
+            wake_reason: Some(WakeReason::PowerOnAlarm),
+        }
+    }
+
+    #[test]
+    fn boot_timing_report_round_trips() {
+        let report = boot_timing_report_sample();
+        let serialized =
+            ron::ser::to_string_pretty(&report, ron::ser::PrettyConfig::default()).unwrap();
+        assert_eq!(
+            ron::from_str::<BootTimingReport>(&serialized).unwrap(),
+            report
+        );
+    }
+
+    #[test]
+    fn reports_without_later_fields_still_parse() {
+        let report = ron::from_str::<BootTimingReport>(
+            "(phases: [(name: \"GUI start\", start_ms: 10, end_ms: Some(20))], total_ms: 30)",
+        )
+        .unwrap();
+        assert_eq!(report.phases[0].start_sample, None);
+        assert_eq!(report.overlay_backend, None);
+        assert_eq!(report.wake_reason, None);
+    }
+
+    #[test]
+    fn boot_timing_summary_points_at_unfinished_phases() {
+        let summary = boot_timing_summary(&boot_timing_report_sample());
+        assert!(summary.contains("waveform load 250 ms [!] +80 MB"));
+        assert!(summary.contains("systemd wait unfinished after 4000 ms"));
+        assert!(summary.contains("total 9000 ms, Kernel overlayfs, woken up by power-on alarm"));
+    }
+
+    fn copy_options(mode: CopyMode) -> CopyOptions {
+        CopyOptions {
+            mode,
+            preserve_xattrs: false,
+        }
+    }
+
+    #[test]
+    fn recursive_copy_preserves_modes_times_and_symlinks() {
+        let dir = TempDir::new();
+        let script = dir.write("source/bin/script", b"#!/bin/sh\n");
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o750)).unwrap();
+        let modified = std::time::SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        fs::File::open(&script)
+            .unwrap()
+            .set_times(fs::FileTimes::new().set_modified(modified))
+            .unwrap();
+        symlink("bin/script", dir.join("source/link")).unwrap();
+        fs::create_dir(dir.join("source/locked")).unwrap();
+        dir.write("source/locked/file", b"inside");
+        fs::set_permissions(dir.join("source/locked"), fs::Permissions::from_mode(0o500)).unwrap();
+
+        copy_dir_recursively(
+            &dir.join("source"),
+            &dir.join("target"),
+            &copy_options(CopyMode::Clean),
+        )
+        .unwrap();
+
+        let copied_script = fs::metadata(dir.join("target/bin/script")).unwrap();
+        assert_eq!(copied_script.mode() & 0o7777, 0o750);
+        assert_eq!(copied_script.modified().unwrap(), modified);
+        assert_eq!(
+            fs::read_link(dir.join("target/link")).unwrap(),
+            Path::new("bin/script")
+        );
+        // The read-only mode is applied only once the directory contents are in place
+        assert_eq!(
+            fs::read_to_string(dir.join("target/locked/file")).unwrap(),
+            "inside"
+        );
+        assert_eq!(
+            fs::metadata(dir.join("target/locked")).unwrap().mode() & 0o7777,
+            0o500
+        );
+
+        for locked in ["source/locked", "target/locked"] {
+            fs::set_permissions(dir.join(locked), fs::Permissions::from_mode(0o700)).unwrap();
+        }
+    }
+
+    #[test]
+    fn clean_copy_removes_stale_entries_and_merge_keeps_them() {
+        let dir = TempDir::new();
+        dir.write("source/config", b"new");
+        dir.write("source/shared/file", b"new");
+        dir.write("target/stale", b"old");
+        dir.write("target/config", b"old");
+        // Conflicting entry types are replaced
+        dir.write("target/shared", b"old");
+
+        copy_dir_recursively(
+            &dir.join("source"),
+            &dir.join("target"),
+            &copy_options(CopyMode::Merge),
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("target/config")).unwrap(),
+            "new"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("target/shared/file")).unwrap(),
+            "new"
+        );
+        assert!(fs::exists(dir.join("target/stale")).unwrap());
+
+        copy_dir_recursively(
+            &dir.join("source"),
+            &dir.join("target"),
+            &copy_options(CopyMode::Clean),
+        )
+        .unwrap();
+        assert!(!fs::exists(dir.join("target/stale")).unwrap());
+        assert_eq!(
+            fs::read_to_string(dir.join("target/config")).unwrap(),
+            "new"
+        );
+    }
+
+    #[test]
+    fn sha256_digest_streams_across_chunks() {
+        let dir = TempDir::new();
+        let empty = dir.write("empty", b"");
+        assert_eq!(
+            sha256_digest(&empty, None).unwrap(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+
+        let contents: Vec<u8> = (0..FILE_CHUNK_SIZE * 2 + 10)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let large = dir.write("large", &contents);
+        let mut progress_reports = Vec::new();
+        let digest = sha256_digest(
+            &large,
+            Some(&mut |done, total| progress_reports.push((done, total))),
+        )
+        .unwrap();
+        let expected: String = openssl::sha::sha256(&contents)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        assert_eq!(digest, expected);
+
+        let total = contents.len() as u64;
+        assert_eq!(
+            progress_reports,
+            vec![
+                (FILE_CHUNK_SIZE as u64, total),
+                (FILE_CHUNK_SIZE as u64 * 2, total),
+                (total, total)
+            ]
+        );
+    }
+
+    #[test]
+    fn sha256_match_writes_the_checksum_when_asked() {
+        let dir = TempDir::new();
+        let file = dir.write("rootfs.squashfs", b"archive");
+        assert!(!sha256_match(&file, false).unwrap());
+        assert!(!fs::exists(format!("{}.sha256", &file)).unwrap());
+        assert!(!sha256_match(&file, true).unwrap());
+        assert!(sha256_match(&file, false).unwrap());
+
+        fs::write(&file, b"tampered").unwrap();
+        assert!(!sha256_match(&file, false).unwrap());
+    }
+
+    // Just the superblock fields that check_squashfs_archive() looks at
+    fn squashfs_archive(declared_size: u64, actual_size: usize) -> Vec<u8> {
+        let mut archive = vec![0u8; actual_size];
+        archive[..SQUASHFS_MAGIC.len()].copy_from_slice(SQUASHFS_MAGIC);
+        archive[SQUASHFS_BYTES_USED_OFFSET..SQUASHFS_BYTES_USED_OFFSET + 8]
+            .copy_from_slice(&declared_size.to_le_bytes());
+        archive
+    }
+
+    #[test]
+    fn intact_squashfs_archive_passes() {
+        let dir = TempDir::new();
+        let archive = dir.write("rootfs.squashfs", &squashfs_archive(4096, 4096));
+        check_squashfs_archive(&archive).unwrap();
+        // mksquashfs pads archives
+        let padded = dir.write("padded.squashfs", &squashfs_archive(4000, 4096));
+        check_squashfs_archive(&padded).unwrap();
+    }
+
+    #[test]
+    fn damaged_squashfs_archives_are_explained() {
+        let dir = TempDir::new();
+        let truncated = dir.write("truncated.squashfs", &squashfs_archive(8192, 4096));
+        assert!(
+            check_squashfs_archive(&truncated)
+                .unwrap_err()
+                .to_string()
+                .contains("truncated: 4096 bytes long, while its superblock declares 8192 bytes")
+        );
+
+        let mut not_squashfs = squashfs_archive(4096, 4096);
+        not_squashfs[..4].copy_from_slice(b"\x7fELF");
+        let not_squashfs = dir.write("not.squashfs", &not_squashfs);
+        assert!(
+            check_squashfs_archive(&not_squashfs)
+                .unwrap_err()
+                .to_string()
+                .contains("magic bytes")
+        );
+
+        let short = dir.write("short.squashfs", b"hsqs");
+        assert!(
+            check_squashfs_archive(&short)
+                .unwrap_err()
+                .to_string()
+                .contains("only 4 bytes long")
+        );
+        assert!(check_squashfs_archive(&dir.join("missing.squashfs")).is_err());
+    }
+
+    #[test]
+    fn loop_devices_with_a_backing_file_are_busy() {
+        let dir = TempDir::new();
+        dir.write("loop0/loop/backing_file", b"/boot/rootfs.squashfs\n");
+        fs::create_dir_all(dir.join("loop1/loop")).unwrap();
+        fs::create_dir_all(dir.join("loop2")).unwrap();
+        fs::create_dir_all(dir.join("mmcblk0")).unwrap();
+        assert_eq!(count_loop_devices(&dir.path()).unwrap(), (2, 3));
+        assert!(count_loop_devices(&dir.join("missing")).is_err());
+    }
+
+    #[test]
+    fn wait_for_polls_until_the_condition_yields() {
+        let mut polls = 0;
+        let value = wait_for(
+            || {
+                polls += 1;
+                Ok((polls == 3).then_some("ready"))
+            },
+            Duration::from_secs(10),
+            "the test device",
+        )
+        .unwrap();
+        assert_eq!(value, "ready");
+        assert_eq!(polls, 3);
+    }
+
+    #[test]
+    fn wait_for_stops_on_errors_and_timeouts() {
+        let mut polls = 0;
+        let error = wait_for(
+            || -> Result<Option<()>> {
+                polls += 1;
+                Err(anyhow::anyhow!("Device is broken"))
+            },
+            Duration::from_secs(10),
+            "the test device",
+        )
+        .unwrap_err();
+        assert_eq!(polls, 1);
+        assert_eq!(error.to_string(), "Device is broken");
+
+        let start = std::time::Instant::now();
+        let error = wait_for(
+            || -> Result<Option<()>> { Ok(None) },
+            Duration::from_millis(300),
+            "the test device",
+        )
+        .unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            error.downcast_ref::<WaitTimedOut>().unwrap().description,
+            "the test device"
+        );
+    }
+
+    #[test]
+    fn standard_input_data_stays_out_of_the_arguments() {
+        let dir = TempDir::new();
+        let script = format!(
+            "cat > '{}'; echo \"$0 $*\" > '{}'",
+            &dir.join("stdin"),
+            &dir.join("args")
+        );
+        run_command_with_stdin("/bin/sh", &["-c", &script, "gocryptfs", "-q"], b"hunter2\n")
+            .unwrap();
+        assert_eq!(fs::read_to_string(dir.join("stdin")).unwrap(), "hunter2\n");
+        let args = fs::read_to_string(dir.join("args")).unwrap();
+        assert_eq!(args, "gocryptfs -q\n");
+        assert!(!args.contains("hunter2"));
+    }
+
+    #[test]
+    fn command_ignoring_its_standard_input_is_reaped() {
+        let start = std::time::Instant::now();
+        // Much larger than a pipe buffer: the write fails once the child is gone
+        let error =
+            run_command_with_stdin("/bin/true", &[], &vec![0u8; 16 * 1024 * 1024]).unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(
+            error
+                .to_string()
+                .contains("Failed to write to standard input")
+        );
+
+        let error =
+            run_command_with_stdin("/bin/sh", &["-c", "cat > /dev/null; exit 1"], b"").unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<CommandFailed>().unwrap().status.code(),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn failed_temporary_mount_is_cleaned_up() {
+        let dir = TempDir::new();
+        let result = with_mount_in(
+            &dir.path(),
+            &dir.join("missing.img"),
+            "ext4",
+            MountFlags::RDONLY,
+            None,
+            |_mountpoint| -> Result<()> { panic!("The closure must not run without a mount") },
+        );
+        assert!(result.is_err());
+        assert_eq!(fs::read_dir(&dir.path()).unwrap().count(), 0);
+    }
+
+    // Set in the copy of the test binary which runs inside its own user and mount namespaces
+    const MOUNT_NAMESPACE_VAR: &str = "QINIT_TEST_MOUNT_NAMESPACE";
+
+    #[test]
+    fn temporary_mount_is_released_when_the_closure_panics() {
+        if env::var_os(&MOUNT_NAMESPACE_VAR).is_none() {
+            let unshare = ["--user", "--map-root-user", "--mount"];
+            let namespaces_available = Command::new("unshare")
+                .args(&unshare)
+                .arg("true")
+                .stderr(Stdio::null())
+                .status()
+                .is_ok_and(|status| status.success());
+            if !namespaces_available {
+                eprintln!("Skipping: user namespaces are not available");
+                return;
+            }
+            let status = Command::new("unshare")
+                .args(&unshare)
+                .arg(env::current_exe().unwrap())
+                .args([
+                    "--exact",
+                    "system::tests::temporary_mount_is_released_when_the_closure_panics",
+                    "--test-threads=1",
+                ])
+                .env(&MOUNT_NAMESPACE_VAR, "1")
+                .status()
+                .unwrap();
+            assert!(status.success());
+            return;
+        }
+
+        let dir = TempDir::new();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            with_mount_in(
+                &dir.path(),
+                "tmpfs",
+                "tmpfs",
+                MountFlags::empty(),
+                None,
+                |mountpoint| -> Result<()> {
+                    fs::write(format!("{}/file", &mountpoint), b"")?;
+                    panic!("The closure panics while the mount is held")
+                },
+            )
+        }));
+        assert!(result.is_err());
+        assert_eq!(fs::read_dir(&dir.path()).unwrap().count(), 0);
+        assert!(
+            !fs::read_to_string("/proc/self/mountinfo")
+                .unwrap()
+                .contains(&dir.path())
+        );
+    }
+
+    #[test]
+    fn unmounted_temporary_mountpoint_is_removed_on_drop() {
+        let dir = TempDir::new();
+        let mountpoint = dir.join("tmp-test");
+        fs::create_dir(&mountpoint).unwrap();
+        drop(TemporaryMount {
+            mountpoint: mountpoint.clone(),
+            mounted: false,
+        });
+        assert!(!fs::exists(&mountpoint).unwrap());
+    }
+}
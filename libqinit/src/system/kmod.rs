@@ -0,0 +1,345 @@
+use anyhow::{Context, Result};
+use log::{debug, info, warn};
+use nix::errno::Errno;
+use nix::kmod::{DeleteModuleFlags, ModuleInitFlags, delete_module, finit_module, init_module};
+use std::collections::{HashMap, HashSet};
+use std::ffi::CString;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Mutex;
+use xz2::read::XzDecoder;
+
+use super::{MODULES_DIR_PATH, modprobe};
+
+const KERNEL_RELEASE_PATH: &str = "/proc/sys/kernel/osrelease";
+const MODULES_DEP_FILE: &str = "modules.dep";
+const SYS_MODULE_DIR_PATH: &str = "/sys/module";
+
+// Only successful parses are kept: modules.dep may be read before the modules archive is mounted
+static MODULES_DEP: Mutex<Option<ModulesDep>> = Mutex::new(None);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModuleEntry {
+    pub name: String,
+    pub path: String,
+    pub dependencies: Vec<String>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ModulesDep {
+    modules: HashMap<String, ModuleEntry>,
+}
+
+// The kernel treats dashes and underscores in module names as equivalent
+pub fn normalize_name(name: &str) -> String {
+    name.replace('-', "_")
+}
+
+// e.g. "kernel/drivers/net/wireless/brcmfmac-wcc.ko.xz" -> "brcmfmac_wcc"
+pub fn module_name(path: &str) -> String {
+    let file_name = path.rsplit('/').next().unwrap_or(&path);
+    let stem = file_name
+        .split_once(".ko")
+        .map(|(stem, _)| stem)
+        .unwrap_or(&file_name);
+
+    normalize_name(&stem)
+}
+
+impl ModulesDep {
+    // Each line reads "path/to/module.ko: path/to/dependency.ko path/to/other.ko", with paths relative to the modules directory
+    pub fn parse(modules_dep: &str) -> Result<ModulesDep> {
+        let mut modules = HashMap::new();
+        for (line_number, line) in modules_dep.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (path, dependencies) = line.split_once(':').ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Malformed line {} in modules.dep: '{}'",
+                    line_number + 1,
+                    &line
+                )
+            })?;
+            let path = path.trim();
+            if path.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "Missing module path on line {} in modules.dep",
+                    line_number + 1
+                ));
+            }
+            let entry = ModuleEntry {
+                name: module_name(&path),
+                path: path.to_string(),
+                dependencies: dependencies
+                    .split_whitespace()
+                    .map(|dependency| module_name(&dependency))
+                    .collect(),
+            };
+            modules.insert(entry.name.clone(), entry);
+        }
+
+        Ok(ModulesDep { modules })
+    }
+
+    pub fn read(modules_dir: &str) -> Result<ModulesDep> {
+        let modules_dep_path = format!("{}/{}", &modules_dir, &MODULES_DEP_FILE);
+        let modules_dep = fs::read_to_string(&modules_dep_path)
+            .with_context(|| format!("Failed to read '{}'", &modules_dep_path))?;
+
+        ModulesDep::parse(&modules_dep)
+            .with_context(|| format!("Failed to parse '{}'", &modules_dep_path))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ModuleEntry> {
+        self.modules.get(&normalize_name(&name))
+    }
+
+    // Dependencies come before the modules that need them, and the requested module comes last
+    pub fn load_order(&self, name: &str) -> Result<Vec<&ModuleEntry>> {
+        let mut order = Vec::new();
+        let mut visited = HashSet::new();
+        let mut visiting = HashSet::new();
+        self.visit(
+            &normalize_name(&name),
+            &mut order,
+            &mut visited,
+            &mut visiting,
+        )?;
+
+        Ok(order)
+    }
+
+    fn visit<'a>(
+        &'a self,
+        name: &str,
+        order: &mut Vec<&'a ModuleEntry>,
+        visited: &mut HashSet<String>,
+        visiting: &mut HashSet<String>,
+    ) -> Result<()> {
+        if visited.contains(name) {
+            return Ok(());
+        }
+        if !visiting.insert(name.to_string()) {
+            return Err(anyhow::anyhow!(
+                "Circular dependency involving module '{}'",
+                &name
+            ));
+        }
+        let entry = self
+            .modules
+            .get(name)
+            .ok_or_else(|| anyhow::anyhow!("Module '{}' not found in modules.dep", &name))?;
+        for dependency in &entry.dependencies {
+            self.visit(&dependency, order, visited, visiting)?;
+        }
+        visiting.remove(name);
+        visited.insert(name.to_string());
+        order.push(entry);
+
+        Ok(())
+    }
+}
+
+fn get_modules_dir() -> Result<String> {
+    let kernel_release = fs::read_to_string(&KERNEL_RELEASE_PATH)
+        .with_context(|| format!("Failed to read '{}'", &KERNEL_RELEASE_PATH))?;
+
+    Ok(format!("{}/{}", &MODULES_DIR_PATH, kernel_release.trim()))
+}
+
+pub fn is_loaded(name: &str) -> bool {
+    Path::new(&format!(
+        "{}/{}",
+        &SYS_MODULE_DIR_PATH,
+        &normalize_name(&name)
+    ))
+    .exists()
+}
+
+// finit_module() reads the file in the kernel; compressed modules have to be decompressed here for init_module() instead
+fn insert(modules_dir: &str, entry: &ModuleEntry, params: &str) -> Result<()> {
+    let module_path = if entry.path.starts_with('/') {
+        entry.path.clone()
+    } else {
+        format!("{}/{}", &modules_dir, &entry.path)
+    };
+    let params = CString::new(params)
+        .with_context(|| format!("Invalid parameters for module '{}'", &entry.name))?;
+    let file = fs::File::open(&module_path)
+        .with_context(|| format!("Failed to open module '{}'", &module_path))?;
+    debug!("Inserting module '{}' from '{}'", &entry.name, &module_path);
+
+    let result = if module_path.ends_with(".ko") {
+        finit_module(&file, &params, ModuleInitFlags::empty())
+    } else if module_path.ends_with(".ko.xz") {
+        let mut image = Vec::new();
+        XzDecoder::new(file)
+            .read_to_end(&mut image)
+            .with_context(|| format!("Failed to decompress module '{}'", &module_path))?;
+        init_module(&image, &params)
+    } else {
+        return Err(anyhow::anyhow!(
+            "Unsupported module compression: '{}'",
+            &module_path
+        ));
+    };
+
+    match result {
+        // Loaded concurrently, e.g. by udev
+        Ok(()) | Err(Errno::EEXIST) => Ok(()),
+        Err(e) => Err(e).with_context(|| format!("Failed to insert module '{}'", &entry.name)),
+    }
+}
+
+// Equivalent to modprobe, without spawning a process for every module
+// Parameters (e.g. "host_addr=...") only apply to the requested module, not to its dependencies
+pub fn load_module(name: &str, params: &[&str]) -> Result<()> {
+    let name = normalize_name(&name);
+    if is_loaded(&name) {
+        debug!("Module '{}' is already loaded", &name);
+        return Ok(());
+    }
+    info!("Loading module '{}'", &name);
+
+    let modprobe_args = [&[name.as_str()][..], params].concat();
+    let modules_dir = get_modules_dir()?;
+    let mut modules_dep = MODULES_DEP.lock().unwrap();
+    if modules_dep.is_none() {
+        match ModulesDep::read(&modules_dir) {
+            Ok(parsed) => *modules_dep = Some(parsed),
+            Err(e) => warn!("{:?}: falling back to modprobe", &e),
+        }
+    }
+    let load_order = match modules_dep
+        .as_ref()
+        .map(|modules_dep| modules_dep.load_order(&name))
+    {
+        Some(Ok(load_order)) => load_order,
+        Some(Err(e)) => {
+            warn!("{}: falling back to modprobe", &e);
+            return modprobe(&modprobe_args);
+        }
+        None => return modprobe(&modprobe_args),
+    };
+
+    for entry in &load_order {
+        if entry.name == name {
+            insert(&modules_dir, &entry, &params.join(" "))?;
+        } else if !is_loaded(&entry.name) {
+            insert(&modules_dir, &entry, "")?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn load_modules(names: &[&str]) -> Result<()> {
+    for name in names {
+        load_module(&name, &[])?;
+    }
+
+    Ok(())
+}
+
+// Fails if the module is in use: callers are expected to release its devices first
+pub fn unload_module(name: &str) -> Result<()> {
+    let name = normalize_name(&name);
+    if !is_loaded(&name) {
+        debug!("Module '{}' is not loaded", &name);
+        return Ok(());
+    }
+    info!("Unloading module '{}'", &name);
+    let c_name =
+        CString::new(name.as_str()).with_context(|| format!("Invalid module name '{}'", &name))?;
+
+    delete_module(&c_name, DeleteModuleFlags::O_NONBLOCK)
+        .with_context(|| format!("Failed to unload module '{}'", &name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    const MODULES_DEP_SAMPLE: &str = "\
+kernel/drivers/net/wireless/brcmfmac.ko.xz: kernel/net/wireless/cfg80211.ko.xz kernel/drivers/net/wireless/brcmutil.ko.xz
+kernel/drivers/net/wireless/brcmutil.ko.xz:
+kernel/net/wireless/cfg80211.ko.xz: kernel/net/rfkill/rfkill.ko.xz
+kernel/drivers/net/wireless/brcmfmac-wcc.ko.xz: kernel/drivers/net/wireless/brcmfmac.ko.xz kernel/net/wireless/cfg80211.ko.xz
+kernel/net/rfkill/rfkill.ko.xz:
+
+# Comments and blank lines are skipped
+/lib/modules/extra/rockchip_ebc.ko:
+";
+
+    #[test]
+    fn module_names_are_normalized() {
+        assert_eq!(
+            module_name("kernel/drivers/net/wireless/brcmfmac-wcc.ko.xz"),
+            "brcmfmac_wcc"
+        );
+        assert_eq!(module_name("rockchip_ebc.ko"), "rockchip_ebc");
+        assert_eq!(module_name("g_ether"), "g_ether");
+    }
+
+    #[test]
+    fn modules_dep_lines_are_parsed() {
+        let modules_dep = ModulesDep::parse(&MODULES_DEP_SAMPLE).unwrap();
+        assert_eq!(
+            modules_dep.get("brcmfmac-wcc"),
+            Some(&ModuleEntry {
+                name: "brcmfmac_wcc".to_string(),
+                path: "kernel/drivers/net/wireless/brcmfmac-wcc.ko.xz".to_string(),
+                dependencies: vec!["brcmfmac".to_string(), "cfg80211".to_string()],
+            })
+        );
+        assert!(modules_dep.get("rfkill").unwrap().dependencies.is_empty());
+        assert_eq!(
+            modules_dep.get("rockchip_ebc").unwrap().path,
+            "/lib/modules/extra/rockchip_ebc.ko"
+        );
+        assert_eq!(modules_dep.get("missing"), None);
+    }
+
+    #[test]
+    fn malformed_modules_dep_is_rejected() {
+        assert!(ModulesDep::parse("kernel/foo.ko kernel/bar.ko").is_err());
+        assert!(ModulesDep::parse(": kernel/bar.ko").is_err());
+    }
+
+    #[test]
+    fn dependencies_are_loaded_first_and_once() {
+        let modules_dep = ModulesDep::parse(&MODULES_DEP_SAMPLE).unwrap();
+        let load_order: Vec<&str> = modules_dep
+            .load_order("brcmfmac_wcc")
+            .unwrap()
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect();
+        assert_eq!(
+            load_order,
+            vec!["rfkill", "cfg80211", "brcmutil", "brcmfmac", "brcmfmac_wcc"]
+        );
+    }
+
+    #[test]
+    fn unknown_and_circular_dependencies_are_errors() {
+        let modules_dep = ModulesDep::parse(&MODULES_DEP_SAMPLE).unwrap();
+        assert!(modules_dep.load_order("missing").is_err());
+        let modules_dep = ModulesDep::parse("a.ko: b.ko\nb.ko: c.ko\nc.ko: a.ko\n").unwrap();
+        let error = modules_dep.load_order("a").unwrap_err();
+        assert!(error.to_string().contains("Circular dependency"));
+    }
+
+    #[test]
+    fn modules_dep_is_read_from_the_modules_directory() {
+        let dir = TempDir::new();
+        dir.write(&MODULES_DEP_FILE, MODULES_DEP_SAMPLE.as_bytes());
+        let modules_dep = ModulesDep::read(&dir.path()).unwrap();
+        assert!(modules_dep.get("cfg80211").is_some());
+        assert!(ModulesDep::read(&dir.join("missing")).is_err());
+    }
+}
@@ -1,21 +1,97 @@
 use crate::boot_config::BootConfig;
 use crate::rootfs;
+use crate::system::{keep_last_lines, read_kernel_buffer_singleshot};
 use anyhow::{Context, Result};
-use log::{info, warn};
+use log::{error, info, warn};
+use nix::fcntl::OFlag;
 use rmesg;
 use std::{
-    fs,
-    os::unix::fs::MetadataExt,
+    fs::{self, File},
+    io::{ErrorKind, Read, Seek, SeekFrom},
+    os::unix::fs::{MetadataExt, OpenOptionsExt},
+    path::PathBuf,
     sync::{
         Arc,
         atomic::{AtomicBool, Ordering},
         mpsc::Sender,
     },
     thread,
+    time::{Duration, Instant},
 };
 
 const REACHED_TARGET_MAGIC: &str = "systemd[1]: Reached target";
 const STARTUP_COMPLETE_MAGIC: &str = "systemd[1]: Startup finished in";
+const SYSTEMD_MAGIC: &str = "systemd[1]:";
+const INIT_PROCESS_EXE_PATH: &str = "/proc/1/exe";
+const KMSG_PATH: &str = "/dev/kmsg";
+// The kernel never hands out longer records
+const KMSG_RECORD_MAX_SIZE: usize = 8192;
+const PROC_DIR_PATH: &str = "/proc";
+pub const DEFAULT_HANDOFF_TIMEOUT_SECS: u64 = 90;
+// Slow first boots (e.g. package installs in the chroot) must never trigger the watchdog: be generous with the historical startup duration
+const HANDOFF_HISTORY_MULTIPLIER: u64 = 3;
+const HANDOFF_POLL_INTERVAL: Duration = Duration::from_millis(500);
+const HANDOFF_DMESG_TAIL_LINES: usize = 10;
+// Share of the targets past which the boot splash only says that startup is nearly done
+const ALMOST_READY_TARGETS_SHARE: f32 = 0.8;
+// Bounds on how much slower or faster than the last boot this one is assumed to keep going
+const MIN_PACE: f64 = 0.5;
+const MAX_PACE: f64 = 2.0;
+
+// Coarse steps of the boot, shown on the boot splash
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BootPhase {
+    MountingSystem,
+    StartingServices,
+    AlmostReady,
+}
+
+impl BootPhase {
+    pub fn label(&self) -> &'static str {
+        match self {
+            BootPhase::MountingSystem => "Mounting system",
+            BootPhase::StartingServices => "Starting services",
+            BootPhase::AlmostReady => "Almost ready",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BootStatus {
+    Phase(BootPhase),
+    // None once there is nothing left to base an estimate on
+    TimeLeft(Option<Duration>),
+}
+
+// The rest of the last boot from the same target on, scaled by how far behind or ahead of it this one runs
+pub fn estimate_time_left(
+    target_times_ms: &[u64],
+    targets_count: usize,
+    elapsed: Duration,
+) -> Option<Duration> {
+    let total_ms = *target_times_ms.last()?;
+    if targets_count > target_times_ms.len() {
+        return None;
+    }
+    let elapsed_ms = elapsed.as_millis() as f64;
+    let (reached_then_ms, pace) = match targets_count.checked_sub(1) {
+        Some(index) if target_times_ms[index] > 0 => (
+            target_times_ms[index],
+            (elapsed_ms / target_times_ms[index] as f64).clamp(MIN_PACE, MAX_PACE),
+        ),
+        Some(index) => (target_times_ms[index], 1.0),
+        // Nothing reached yet: whatever time passed already counts
+        None => {
+            return Some(Duration::from_millis(
+                total_ms.saturating_sub(elapsed_ms as u64),
+            ));
+        }
+    };
+
+    Some(Duration::from_millis(
+        ((total_ms - reached_then_ms) as f64 * pace) as u64,
+    ))
+}
 
 pub fn wait_and_count_targets(
     boot_config: Option<&mut BootConfig>,
@@ -23,11 +99,14 @@ pub fn wait_and_count_targets(
     boot_finished: Option<Arc<AtomicBool>>,
 ) -> Result<i32> {
     info!("Waiting for systemd 'Reached target' messages");
+    let start = Instant::now();
     let mut targets_count = 0;
+    let mut target_times_ms = Vec::new();
     for maybe_entry in rmesg::logs_iter(rmesg::Backend::Default, false, false)? {
         let entry = maybe_entry?.to_string();
         if entry.contains(&REACHED_TARGET_MAGIC) {
             targets_count += 1;
+            target_times_ms.push(start.elapsed().as_millis() as u64);
         } else if entry.contains(&STARTUP_COMPLETE_MAGIC) {
             break;
         }
@@ -37,6 +116,8 @@ pub fn wait_and_count_targets(
         && let Some(progress_sender) = progress_sender
     {
         boot_config.rootfs.systemd_targets_total = Some(targets_count);
+        boot_config.rootfs.systemd_target_times_ms = target_times_ms;
+        boot_config.rootfs.startup_duration_secs = Some(start.elapsed().as_secs());
         progress_sender.send(crate::READY_PROGRESS_VALUE)?;
     }
 
@@ -51,6 +132,7 @@ pub fn wait_for_targets(
     boot_config: &mut BootConfig,
     targets_total: i32,
     progress_sender: Sender<f32>,
+    status_sender: Sender<BootStatus>,
 ) -> Result<()> {
     info!("Waiting for systemd 'Reached target' messages to update boot progress bar");
     info!(
@@ -58,19 +140,46 @@ pub fn wait_for_targets(
         &targets_total
     );
 
+    let start = Instant::now();
     let boot_finished = Arc::new(AtomicBool::new(false));
     let boot_finished_clone = boot_finished.clone();
     let counting_thread =
         thread::spawn(move || wait_and_count_targets(None, None, Some(boot_finished_clone)));
 
+    // Timings recorded for a different number of targets belong to another root filesystem
+    let previous_target_times_ms =
+        if boot_config.rootfs.systemd_target_times_ms.len() == targets_total as usize {
+            boot_config.rootfs.systemd_target_times_ms.clone()
+        } else {
+            Vec::new()
+        };
+    let _ = status_sender.send(BootStatus::TimeLeft(estimate_time_left(
+        &previous_target_times_ms,
+        0,
+        start.elapsed(),
+    )));
+
     let mut targets_count = 0;
+    let mut target_times_ms = Vec::new();
+    let mut almost_ready = false;
     for maybe_entry in rmesg::logs_iter(rmesg::Backend::Default, false, false)? {
         if maybe_entry?.to_string().contains(&REACHED_TARGET_MAGIC) {
             targets_count += 1;
+            target_times_ms.push(start.elapsed().as_millis() as u64);
+            let targets_share = targets_count as f32 / targets_total as f32;
             let progress_value = &rootfs::ROOTFS_MOUNTED_PROGRESS_VALUE
-                + (targets_count as f32 / targets_total as f32
-                    * (1.0 - &rootfs::ROOTFS_MOUNTED_PROGRESS_VALUE));
+                + (targets_share * (1.0 - &rootfs::ROOTFS_MOUNTED_PROGRESS_VALUE));
             progress_sender.send(progress_value)?;
+            // The GUI going away must not hold the boot up
+            let _ = status_sender.send(BootStatus::TimeLeft(estimate_time_left(
+                &previous_target_times_ms,
+                targets_count as usize,
+                start.elapsed(),
+            )));
+            if !almost_ready && targets_share >= ALMOST_READY_TARGETS_SHARE {
+                almost_ready = true;
+                let _ = status_sender.send(BootStatus::Phase(BootPhase::AlmostReady));
+            }
         }
 
         if boot_finished.load(Ordering::SeqCst) {
@@ -91,6 +200,8 @@ pub fn wait_for_targets(
             boot_config.rootfs.systemd_targets_total = Some(fresh_targets_count);
         }
     }
+    boot_config.rootfs.systemd_target_times_ms = target_times_ms;
+    boot_config.rootfs.startup_duration_secs = Some(start.elapsed().as_secs());
 
     progress_sender.send(crate::READY_PROGRESS_VALUE)?;
 
@@ -120,6 +231,7 @@ pub fn get_targets_total(boot_config: &mut BootConfig) -> Result<Option<i32>> {
             }
         } else {
             boot_config.rootfs.timestamp = current_rootfs_timestamp;
+            boot_config.rootfs.systemd_target_times_ms.clear();
             info!("Not displaying boot progress bar: number of systemd targets is not yet known");
             return Ok(None);
         }
@@ -128,3 +240,168 @@ pub fn get_targets_total(boot_config: &mut BootConfig) -> Result<Option<i32>> {
         return Ok(None);
     }
 }
+
+pub fn get_handoff_timeout(boot_config: &BootConfig) -> Duration {
+    let mut timeout_secs = boot_config
+        .rootfs
+        .handoff_timeout_secs
+        .unwrap_or(DEFAULT_HANDOFF_TIMEOUT_SECS);
+    if let Some(startup_duration_secs) = boot_config.rootfs.startup_duration_secs {
+        timeout_secs = timeout_secs.max(startup_duration_secs * HANDOFF_HISTORY_MULTIPLIER);
+    }
+    info!(
+        "Chroot handoff watchdog timeout is {} seconds",
+        &timeout_secs
+    );
+
+    Duration::from_secs(timeout_secs)
+}
+
+// Reads only the records logged since it was opened, whichever process they come from
+struct KernelLogCursor {
+    kmsg: File,
+}
+
+impl KernelLogCursor {
+    fn open() -> Result<KernelLogCursor> {
+        let mut kmsg = fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(OFlag::O_NONBLOCK.bits())
+            .open(&KMSG_PATH)
+            .with_context(|| format!("Failed to open '{}'", &KMSG_PATH))?;
+        kmsg.seek(SeekFrom::End(0))
+            .with_context(|| "Failed to skip existing kernel buffer records")?;
+
+        Ok(KernelLogCursor { kmsg })
+    }
+
+    // How many records were logged since the last call, and whether systemd logged any of them
+    fn poll(&mut self) -> Result<(usize, bool)> {
+        let mut buffer = vec![0u8; KMSG_RECORD_MAX_SIZE];
+        let mut records_count = 0;
+        let mut from_systemd = false;
+        loop {
+            match self.kmsg.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => {
+                    records_count += 1;
+                    from_systemd |=
+                        String::from_utf8_lossy(&buffer[..read]).contains(&SYSTEMD_MAGIC);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                // The ring buffer wrapped past unread records: the reader moves on to the oldest one left, and that much logging is activity too
+                Err(e) if e.raw_os_error() == Some(nix::libc::EPIPE) => records_count += 1,
+                Err(e) => return Err(e).with_context(|| "Failed to read kernel buffer"),
+            }
+        }
+
+        Ok((records_count, from_systemd))
+    }
+}
+
+// Grows whenever a process is spawned: systemd keeps doing so while starting units, even once it only logs to the journal
+fn highest_pid() -> Result<u32> {
+    Ok(fs::read_dir(&PROC_DIR_PATH)
+        .with_context(|| format!("Failed to list '{}'", &PROC_DIR_PATH))?
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<u32>().ok())
+        .max()
+        .unwrap_or(0))
+}
+
+// Taken before the overlay is declared ready: the first stage may exec the root filesystem's init right after
+pub struct HandoffBaseline {
+    init_exe: Option<PathBuf>,
+    kernel_log: KernelLogCursor,
+    highest_pid: u32,
+}
+
+pub fn handoff_baseline() -> Result<HandoffBaseline> {
+    Ok(HandoffBaseline {
+        init_exe: fs::read_link(&INIT_PROCESS_EXE_PATH).ok(),
+        kernel_log: KernelLogCursor::open()?,
+        highest_pid: highest_pid()?,
+    })
+}
+
+// Watches for indirect signs of a failed handoff to the root filesystem's init between the moment the overlay is declared ready and systemd's startup completion
+// Any new kernel buffer record or process counts as activity
+pub fn monitor_handoff(
+    baseline: HandoffBaseline,
+    timeout: Duration,
+    boot_finished: Arc<AtomicBool>,
+    interrupt_sender: Sender<String>,
+    toast_sender: Sender<String>,
+) -> Result<()> {
+    info!("Monitoring chroot handoff health");
+    let HandoffBaseline {
+        init_exe: initial_init_exe,
+        mut kernel_log,
+        highest_pid: mut last_highest_pid,
+    } = baseline;
+    let mut took_over = false;
+    let mut systemd_logged = false;
+    let mut last_activity = Instant::now();
+    let mut warned = false;
+
+    while !boot_finished.load(Ordering::SeqCst) {
+        thread::sleep(HANDOFF_POLL_INTERVAL);
+
+        if !took_over && fs::read_link(&INIT_PROCESS_EXE_PATH).ok() != initial_init_exe {
+            info!("Root filesystem init took over PID 1");
+            took_over = true;
+            last_activity = Instant::now();
+        }
+
+        let (records_count, from_systemd) = kernel_log.poll()?;
+        systemd_logged |= from_systemd;
+        // Exits and PID wrap-around change it as well, which are just as much activity
+        let fresh_highest_pid = highest_pid()?;
+        if records_count > 0 || fresh_highest_pid != last_highest_pid {
+            last_highest_pid = fresh_highest_pid;
+            last_activity = Instant::now();
+            warned = false;
+            continue;
+        }
+
+        let idle = last_activity.elapsed();
+        if !warned && idle >= timeout / 2 {
+            warn!(
+                "No systemd activity for {} seconds during chroot handoff",
+                idle.as_secs()
+            );
+            let _ = toast_sender.send("Boot is taking longer than usual".to_string());
+            warned = true;
+        }
+
+        if idle >= timeout {
+            let likely_cause = if !took_over && !systemd_logged {
+                "/sbin/init inside the root filesystem failed to start (wrong interpreter or missing library?)"
+            } else if !took_over {
+                "the first stage did not hand PID 1 over to the root filesystem's init"
+            } else {
+                "systemd stopped making progress"
+            };
+            error!(
+                "Chroot handoff failed: no systemd activity for {} seconds",
+                idle.as_secs()
+            );
+
+            let mut error_reason = format!(
+                "Boot handoff to the root filesystem failed: {} (no systemd activity for {} seconds)",
+                &likely_cause,
+                idle.as_secs()
+            );
+            if let Ok(kernel_buffer) = read_kernel_buffer_singleshot() {
+                error_reason.push_str("\nLast kernel messages:\n");
+                error_reason.push_str(&keep_last_lines(&kernel_buffer, HANDOFF_DMESG_TAIL_LINES));
+            }
+            interrupt_sender
+                .send(error_reason)
+                .with_context(|| "Failed to send chroot handoff failure to GUI")?;
+            break;
+        }
+    }
+
+    Ok(())
+}
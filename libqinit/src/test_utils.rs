@@ -0,0 +1,93 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::notifier::{PinRequest, UiNotifier};
+use crate::secret::Secret;
+
+static NEXT_TEMP_DIR_ID: AtomicUsize = AtomicUsize::new(0);
+
+// Removed along with its contents once dropped: tests run in parallel, so every one of them gets its own
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> TempDir {
+        let path = std::env::temp_dir().join(format!(
+            "libqinit-test-{}-{}",
+            std::process::id(),
+            NEXT_TEMP_DIR_ID.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> String {
+        self.0.display().to_string()
+    }
+
+    pub fn join(&self, name: &str) -> String {
+        self.0.join(&name).display().to_string()
+    }
+
+    pub fn write(&self, name: &str, contents: &[u8]) -> String {
+        let path = self.0.join(&name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(&parent).unwrap();
+        }
+        fs::write(&path, &contents).unwrap();
+        path.display().to_string()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+// Keeps everything it was asked to show, in order, and gives every confirmation request the same answer
+pub struct RecordingNotifier {
+    events: Mutex<Vec<String>>,
+    confirm: bool,
+}
+
+impl RecordingNotifier {
+    pub fn new(confirm: bool) -> RecordingNotifier {
+        RecordingNotifier {
+            events: Mutex::new(Vec::new()),
+            confirm,
+        }
+    }
+
+    pub fn events(&self) -> Vec<String> {
+        self.events.lock().unwrap().clone()
+    }
+
+    fn record(&self, kind: &str, message: &str) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("{}: {}", &kind, &message));
+    }
+}
+
+impl UiNotifier for RecordingNotifier {
+    fn toast(&self, message: &str) {
+        self.record("toast", &message);
+    }
+
+    fn sticky_toast(&self, message: &str) {
+        self.record("sticky toast", &message);
+    }
+
+    fn ask_confirmation(&self, message: &str) -> bool {
+        self.record("confirmation", &message);
+        self.confirm
+    }
+
+    fn ask_pin(&self, request: &PinRequest) -> Option<Secret> {
+        self.record("PIN", &request.message);
+        None
+    }
+}
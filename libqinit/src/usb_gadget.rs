@@ -0,0 +1,476 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use sys_mount::{Mount, MountFlags, UnmountFlags, unmount};
+
+use crate::partitions::{self, Partition};
+use crate::system::{is_mountpoint, kmod, list_mounts, sync_disks};
+
+const CONFIGFS_MOUNTPOINT: &str = "/sys/kernel/config";
+const UDC_CLASS_PATH: &str = "/sys/class/udc";
+const GADGET_NAME: &str = "qinit";
+// Linux Foundation's multifunction composite gadget
+const VENDOR_ID: &str = "0x1d6b";
+const PRODUCT_ID: &str = "0x0104";
+const USB_VERSION: &str = "0x0200";
+// US English
+const STRINGS_LANGUAGE: &str = "0x409";
+const CONFIGURATION: &str = "c.1";
+const MAX_POWER_MA: &str = "250";
+const MANUFACTURER: &str = "Pine64";
+const PRODUCT: &str = "PineNote";
+const USB_PHY_MODULE: &str = "phy-rockchip-inno-usb2";
+const LIBCOMPOSITE_MODULE: &str = "libcomposite";
+
+// A gadget only ever exposes one of these at a time for now
+pub enum Function {
+    MassStorage { file: String, read_only: bool },
+    Ecm { host_addr: String, dev_addr: String },
+}
+
+impl Function {
+    fn instance(&self) -> &'static str {
+        match self {
+            Function::MassStorage { .. } => "mass_storage.0",
+            Function::Ecm { .. } => "ecm.0",
+        }
+    }
+
+    fn module(&self) -> &'static str {
+        match self {
+            Function::MassStorage { .. } => "usb_f_mass_storage",
+            Function::Ecm { .. } => "usb_f_ecm",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            Function::MassStorage { .. } => "Mass storage",
+            Function::Ecm { .. } => "Ethernet",
+        }
+    }
+
+    // Relative to the function's directory, written in order: the kernel refuses to change 'ro' once 'file' is set
+    fn attributes(&self) -> Vec<(&'static str, String)> {
+        match self {
+            Function::MassStorage { file, read_only } => vec![
+                ("lun.0/removable", "1".to_string()),
+                ("lun.0/ro", if *read_only { "1" } else { "0" }.to_string()),
+                ("lun.0/file", file.to_string()),
+            ],
+            Function::Ecm {
+                host_addr,
+                dev_addr,
+            } => vec![
+                ("host_addr", host_addr.to_string()),
+                ("dev_addr", dev_addr.to_string()),
+            ],
+        }
+    }
+}
+
+// Set up through configfs: both paths can point to a fake tree instead of the real configfs and sysfs
+pub struct Gadget {
+    configfs_root: PathBuf,
+    udc_class_path: PathBuf,
+}
+
+impl Gadget {
+    pub fn system() -> Gadget {
+        Gadget::at(Path::new(&CONFIGFS_MOUNTPOINT), Path::new(&UDC_CLASS_PATH))
+    }
+
+    pub fn at(configfs_root: &Path, udc_class_path: &Path) -> Gadget {
+        Gadget {
+            configfs_root: configfs_root.to_path_buf(),
+            udc_class_path: udc_class_path.to_path_buf(),
+        }
+    }
+
+    fn path(&self) -> PathBuf {
+        self.configfs_root.join("usb_gadget").join(&GADGET_NAME)
+    }
+
+    pub fn is_set_up(&self) -> bool {
+        self.path().exists()
+    }
+
+    fn first_udc(&self) -> Result<String> {
+        let mut udcs: Vec<String> = fs::read_dir(&self.udc_class_path)
+            .with_context(|| format!("Failed to list '{}'", &self.udc_class_path.display()))?
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .collect();
+        udcs.sort();
+
+        udcs.into_iter()
+            .next()
+            .with_context(|| "No USB device controller found")
+    }
+
+    // Anything left half set up is torn down again on failure
+    pub fn start(&self, function: &Function) -> Result<()> {
+        if self.is_set_up() {
+            return Err(anyhow::anyhow!("A USB gadget is already set up"));
+        }
+        info!("Setting up USB gadget: {}", function.description());
+        let gadget_path = self.path();
+        let result = (|| -> Result<()> {
+            fs::create_dir_all(&gadget_path)?;
+            write_attribute(&gadget_path.join("idVendor"), &VENDOR_ID)?;
+            write_attribute(&gadget_path.join("idProduct"), &PRODUCT_ID)?;
+            write_attribute(&gadget_path.join("bcdUSB"), &USB_VERSION)?;
+
+            let strings_path = gadget_path.join("strings").join(&STRINGS_LANGUAGE);
+            fs::create_dir_all(&strings_path)?;
+            write_attribute(&strings_path.join("manufacturer"), &MANUFACTURER)?;
+            write_attribute(&strings_path.join("product"), &PRODUCT)?;
+
+            let config_path = gadget_path.join("configs").join(&CONFIGURATION);
+            let config_strings_path = config_path.join("strings").join(&STRINGS_LANGUAGE);
+            fs::create_dir_all(&config_strings_path)?;
+            write_attribute(
+                &config_strings_path.join("configuration"),
+                function.description(),
+            )?;
+            write_attribute(&config_path.join("MaxPower"), &MAX_POWER_MA)?;
+
+            let function_path = gadget_path.join("functions").join(function.instance());
+            fs::create_dir_all(&function_path)?;
+            for (attribute, value) in function.attributes() {
+                write_attribute(&function_path.join(&attribute), &value)?;
+            }
+            symlink(&function_path, config_path.join(function.instance()))
+                .with_context(|| "Failed to add function to USB gadget configuration")?;
+
+            write_attribute(&gadget_path.join("UDC"), &self.first_udc()?)
+        })();
+
+        if let Err(e) = result {
+            if let Err(cleanup_error) = self.stop() {
+                warn!(
+                    "Failed to tear half set up USB gadget down: {:?}",
+                    &cleanup_error
+                );
+            }
+            return Err(e).with_context(|| "Failed to set up USB gadget");
+        }
+
+        Ok(())
+    }
+
+    // configfs only lets directories go in reverse order of creation, once the gadget is unbound
+    pub fn stop(&self) -> Result<()> {
+        let gadget_path = self.path();
+        if !gadget_path.exists() {
+            return Ok(());
+        }
+        info!("Tearing USB gadget down");
+        let udc_path = gadget_path.join("UDC");
+        if fs::read_to_string(&udc_path).is_ok_and(|udc| !udc.trim().is_empty()) {
+            write_attribute(&udc_path, "\n")?;
+        }
+
+        for config_path in list_dir(&gadget_path.join("configs"))? {
+            for entry_path in list_dir(&config_path)? {
+                if entry_path.is_symlink() {
+                    remove_file(&entry_path)?;
+                }
+            }
+            remove_dir_if_exists(&config_path.join("strings").join(&STRINGS_LANGUAGE))?;
+            remove_dir_if_exists(&config_path)?;
+        }
+        for function_path in list_dir(&gadget_path.join("functions"))? {
+            remove_dir_if_exists(&function_path)?;
+        }
+        remove_dir_if_exists(&gadget_path.join("strings").join(&STRINGS_LANGUAGE))?;
+        remove_dir_if_exists(&gadget_path)
+    }
+}
+
+fn write_attribute(path: &Path, value: &str) -> Result<()> {
+    fs::write(&path, &value).with_context(|| format!("Failed to write '{}'", &path.display()))
+}
+
+fn list_dir(path: &Path) -> Result<Vec<PathBuf>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    Ok(fs::read_dir(&path)
+        .with_context(|| format!("Failed to list '{}'", &path.display()))?
+        .flatten()
+        .map(|entry| entry.path())
+        .collect())
+}
+
+fn remove_file(path: &Path) -> Result<()> {
+    fs::remove_file(&path).with_context(|| format!("Failed to remove '{}'", &path.display()))
+}
+
+// Attributes do not need to go first: configfs removes them along with their directory
+fn remove_dir_if_exists(path: &Path) -> Result<()> {
+    if path.exists() {
+        fs::remove_dir(&path).with_context(|| format!("Failed to remove '{}'", &path.display()))?;
+    }
+
+    Ok(())
+}
+
+// Loads what configfs gadgets need, and mounts configfs unless something already did
+pub fn prepare(function: &Function) -> Result<()> {
+    kmod::load_modules(&[&USB_PHY_MODULE, &LIBCOMPOSITE_MODULE, function.module()])?;
+    if !is_mountpoint(&CONFIGFS_MOUNTPOINT)? {
+        Mount::builder()
+            .fstype("configfs")
+            .mount("configfs", &CONFIGFS_MOUNTPOINT)
+            .with_context(|| "Failed to mount configfs")?;
+    }
+
+    Ok(())
+}
+
+struct Remount {
+    mountpoint: String,
+    fstype: String,
+    // As listed in mountinfo, e.g. "rw,nosuid,relatime"
+    options: String,
+}
+
+// A partition exported over USB, and where it was unmounted from
+pub struct SharedStorage {
+    pub device: String,
+    pub read_only: bool,
+    unmounted: Vec<Remount>,
+}
+
+impl SharedStorage {
+    // The host may have changed anything: the gadget has to let go of the partition before it is mounted again
+    pub fn stop(self) -> Result<()> {
+        info!("Stopping sharing '{}' over USB", &self.device);
+        Gadget::system().stop()?;
+        self.remount()
+    }
+
+    fn remount(&self) -> Result<()> {
+        for remount in self.unmounted.iter().rev() {
+            let (flags, data) = split_mount_options(&remount.options);
+            let mut builder = Mount::builder()
+                .fstype(remount.fstype.as_str())
+                .flags(flags);
+            if !data.is_empty() {
+                builder = builder.data(&data);
+            }
+            builder
+                .mount(&self.device, &remount.mountpoint)
+                .with_context(|| {
+                    format!(
+                        "Failed to mount '{}' back at '{}'",
+                        &self.device, &remount.mountpoint
+                    )
+                })?;
+        }
+
+        Ok(())
+    }
+}
+
+fn is_read_only(mount_options: &str) -> bool {
+    mount_options.split(',').any(|option| option == "ro")
+}
+
+// Mountinfo names per-mount options which mount(2) only takes as flags: whatever else is left goes as data
+fn split_mount_options(mount_options: &str) -> (MountFlags, String) {
+    let mut flags = MountFlags::empty();
+    let mut data = Vec::new();
+    for option in mount_options.split(',') {
+        match option {
+            "ro" => flags |= MountFlags::RDONLY,
+            "nosuid" => flags |= MountFlags::NOSUID,
+            "nodev" => flags |= MountFlags::NODEV,
+            "noexec" => flags |= MountFlags::NOEXEC,
+            "noatime" => flags |= MountFlags::NOATIME,
+            "nodiratime" => flags |= MountFlags::NODIRATIME,
+            "relatime" => flags |= MountFlags::RELATIME,
+            "strictatime" => flags |= MountFlags::STRICTATIME,
+            "" => {}
+            _ => data.push(option),
+        }
+    }
+
+    (flags, data.join(","))
+}
+
+// Whatever cannot be unmounted must at least be read-only: the host and this side writing to the same filesystem would corrupt it
+pub fn share_partition(partition: &Partition) -> Result<SharedStorage> {
+    if Gadget::system().is_set_up() {
+        return Err(anyhow::anyhow!("Storage is already shared over USB"));
+    }
+    let device = partitions::get_path(&partition)?;
+    info!("Sharing '{}' over USB", &device);
+    let mounts_of = |device: &str| -> Result<Vec<(String, String, String, String)>> {
+        Ok(list_mounts()?
+            .into_iter()
+            .filter(|(source, _, _, _)| source == device)
+            .collect())
+    };
+
+    sync_disks()?;
+    let mut shared_storage = SharedStorage {
+        device: device.clone(),
+        read_only: false,
+        unmounted: Vec::new(),
+    };
+    // Most recent mounts first, in case some are stacked
+    for (_, mountpoint, fstype, options) in mounts_of(&device)?.into_iter().rev() {
+        match unmount(&mountpoint, UnmountFlags::empty()) {
+            Ok(()) => shared_storage.unmounted.push(Remount {
+                mountpoint,
+                fstype,
+                options,
+            }),
+            Err(e) => warn!("Failed to unmount '{}': {}", &mountpoint, &e),
+        }
+    }
+
+    let still_mounted = mounts_of(&device)?;
+    if let Some((_, mountpoint, _, _)) = still_mounted
+        .iter()
+        .find(|(_, _, _, options)| !is_read_only(&options))
+    {
+        let error = anyhow::anyhow!(
+            "'{}' is still mounted read-write at '{}' (in use?): not sharing it",
+            &device,
+            &mountpoint
+        );
+        if let Err(e) = shared_storage.remount() {
+            warn!("{:?}", &e);
+        }
+        return Err(error);
+    }
+    shared_storage.read_only = !still_mounted.is_empty();
+
+    let function = Function::MassStorage {
+        file: device.clone(),
+        read_only: shared_storage.read_only,
+    };
+    if let Err(e) = prepare(&function).and_then(|_| Gadget::system().start(&function)) {
+        if let Err(remount_error) = shared_storage.remount() {
+            warn!("{:?}", &remount_error);
+        }
+        return Err(e);
+    }
+
+    Ok(shared_storage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+
+    fn read(path: &Path, name: &str) -> String {
+        fs::read_to_string(path.join(&name)).unwrap()
+    }
+
+    fn ecm() -> Function {
+        Function::Ecm {
+            host_addr: "02:00:00:00:00:01".to_string(),
+            dev_addr: "02:00:00:00:00:02".to_string(),
+        }
+    }
+
+    // Unlike configfs, a plain directory does not create 'lun.0' by itself: ECM only has top-level attributes
+    #[test]
+    fn gadget_is_bound_to_first_controller() {
+        let root = TempDir::new();
+        let configfs_root = PathBuf::from(root.join("config"));
+        let udc_class_path = PathBuf::from(root.join("udc"));
+        root.write("udc/fe800000.usb", b"");
+        root.write("udc/fcc00000.usb", b"");
+        let gadget = Gadget::at(&configfs_root, &udc_class_path);
+        assert!(!gadget.is_set_up());
+
+        gadget.start(&ecm()).unwrap();
+        let gadget_path = configfs_root.join("usb_gadget").join("qinit");
+        assert!(gadget.is_set_up());
+        assert_eq!(read(&gadget_path, "idVendor"), "0x1d6b");
+        assert_eq!(read(&gadget_path, "idProduct"), "0x0104");
+        assert_eq!(read(&gadget_path, "bcdUSB"), "0x0200");
+        assert_eq!(read(&gadget_path, "strings/0x409/manufacturer"), "Pine64");
+        assert_eq!(read(&gadget_path, "strings/0x409/product"), "PineNote");
+        assert_eq!(
+            read(&gadget_path, "configs/c.1/strings/0x409/configuration"),
+            "Ethernet"
+        );
+        assert_eq!(read(&gadget_path, "configs/c.1/MaxPower"), "250");
+        assert_eq!(
+            read(&gadget_path, "functions/ecm.0/host_addr"),
+            "02:00:00:00:00:01"
+        );
+        assert_eq!(
+            read(&gadget_path, "functions/ecm.0/dev_addr"),
+            "02:00:00:00:00:02"
+        );
+        assert_eq!(
+            fs::read_link(gadget_path.join("configs/c.1/ecm.0")).unwrap(),
+            gadget_path.join("functions/ecm.0")
+        );
+        assert_eq!(read(&gadget_path, "UDC"), "fcc00000.usb");
+
+        assert!(gadget.start(&ecm()).is_err());
+    }
+
+    #[test]
+    fn start_fails_without_controller() {
+        let root = TempDir::new();
+        let udc_class_path = PathBuf::from(root.join("udc"));
+        fs::create_dir_all(&udc_class_path).unwrap();
+        let gadget = Gadget::at(Path::new(&root.join("config")), &udc_class_path);
+
+        let error = gadget.start(&ecm()).unwrap_err();
+        assert!(format!("{:?}", &error).contains("No USB device controller found"));
+    }
+
+    #[test]
+    fn stopping_without_gadget_does_nothing() {
+        let root = TempDir::new();
+        let gadget = Gadget::at(Path::new(&root.path()), Path::new(&root.join("udc")));
+        gadget.stop().unwrap();
+        assert!(!gadget.is_set_up());
+    }
+
+    #[test]
+    fn mass_storage_read_only_is_set_before_file() {
+        let function = Function::MassStorage {
+            file: "/dev/mmcblk0p7".to_string(),
+            read_only: true,
+        };
+        assert_eq!(function.instance(), "mass_storage.0");
+        assert_eq!(
+            function.attributes(),
+            vec![
+                ("lun.0/removable", "1".to_string()),
+                ("lun.0/ro", "1".to_string()),
+                ("lun.0/file", "/dev/mmcblk0p7".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn mount_options_are_kept_for_remounting() {
+        assert_eq!(
+            split_mount_options("ro,nosuid,nodev,noatime"),
+            (
+                MountFlags::RDONLY | MountFlags::NOSUID | MountFlags::NODEV | MountFlags::NOATIME,
+                String::new()
+            )
+        );
+        assert_eq!(
+            split_mount_options("rw,relatime,errors=remount-ro"),
+            (MountFlags::RELATIME, "rw,errors=remount-ro".to_string())
+        );
+    }
+}
@@ -0,0 +1,151 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::thread;
+
+use crate::brightness;
+use crate::eink::{self, ScreenRotation};
+
+// Relative to the user's (decrypted) home directory
+const USER_PREFERENCES_FILE: &str = ".config/quill/preferences.ron";
+
+// Applied for the rest of the session after login: global settings in BootConfig are left untouched
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Clone)]
+pub struct UserPreferences {
+    #[serde(default)]
+    pub screen_rotation: Option<ScreenRotation>,
+    // Code of the language, as in Language::code()
+    #[serde(default)]
+    pub language: Option<String>,
+    #[serde(default)]
+    pub brightness: Option<BrightnessPreset>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq, Clone)]
+pub struct BrightnessPreset {
+    pub cool: i32,
+    pub warm: i32,
+}
+
+fn get_path(user: &str) -> String {
+    format!(
+        "{}/{}/{}/{}",
+        &crate::OVERLAY_MOUNTPOINT,
+        &crate::SYSTEM_HOME_DIR,
+        &user,
+        &USER_PREFERENCES_FILE
+    )
+}
+
+// Missing or corrupt preference files silently fall back to global settings
+pub fn read(user: &str) -> UserPreferences {
+    let path = get_path(&user);
+    let Ok(preferences_str) = fs::read_to_string(&path) else {
+        info!("No preferences found for user '{}'", &user);
+        return UserPreferences::default();
+    };
+
+    parse(&user, &preferences_str)
+}
+
+fn parse(user: &str, preferences_str: &str) -> UserPreferences {
+    match ron::from_str::<UserPreferences>(&preferences_str) {
+        Ok(preferences) => preferences,
+        Err(e) => {
+            warn!("Ignoring invalid preferences for user '{}': {}", &user, &e);
+            UserPreferences::default()
+        }
+    }
+}
+
+pub fn write(user: &str, preferences: &UserPreferences) -> Result<()> {
+    let path = get_path(&user);
+    if let Some(parent) = std::path::Path::new(&path).parent() {
+        fs::create_dir_all(&parent)
+            .with_context(|| format!("Failed to create directory '{}'", &parent.display()))?;
+    }
+    let preferences_str =
+        ron::ser::to_string_pretty(&preferences, ron::ser::PrettyConfig::default())
+            .with_context(|| "Failed to serialize user preferences")?;
+    fs::write(&path, &preferences_str)
+        .with_context(|| format!("Failed to write user preferences to '{}'", &path))?;
+
+    Ok(())
+}
+
+// The language is left to the caller: switching it is up to the GUI
+pub fn apply(user: &str, preferences: &UserPreferences) {
+    info!(
+        "Applying preferences of user '{}': {:?}",
+        &user, &preferences
+    );
+    if let Some(preset) = &preferences.brightness {
+        let (cool, warm) = (
+            preset.cool.clamp(0, brightness::MAX_BRIGHTNESS),
+            preset.warm.clamp(0, brightness::MAX_BRIGHTNESS),
+        );
+        // Brightness ramps gradually: do not block the caller (usually the GUI thread)
+        thread::spawn(move || {
+            if let Err(e) = brightness::set_brightness_unified(cool, warm) {
+                warn!("Failed to apply brightness preset: {}", &e);
+            }
+        });
+    }
+    // Same reason: re-triggering the touchscreen waits for udev
+    if let Some(rotation) = preferences.screen_rotation.clone() {
+        thread::spawn(move || {
+            if let Err(e) = eink::apply_touchscreen_rotation(&rotation) {
+                warn!("Failed to apply screen rotation preference: {:?}", &e);
+            }
+        });
+    }
+}
+
+pub fn read_and_apply(user: &str) -> UserPreferences {
+    let preferences = read(&user);
+    apply(&user, &preferences);
+
+    preferences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_fields_fall_back_to_globals() {
+        assert_eq!(
+            parse("alice", "(language: Some(\"fr\"))"),
+            UserPreferences {
+                language: Some("fr".to_string()),
+                ..Default::default()
+            }
+        );
+        assert_eq!(parse("alice", "()"), UserPreferences::default());
+    }
+
+    #[test]
+    fn corrupt_preferences_fall_back_to_globals() {
+        assert_eq!(
+            parse("alice", "(screen_rotation: Some(Sideways"),
+            UserPreferences::default()
+        );
+        assert_eq!(
+            parse("alice", "(brightness: Some((cool: \"high\", warm: 0)))"),
+            UserPreferences::default()
+        );
+    }
+
+    #[test]
+    fn preferences_round_trip() {
+        let preferences = UserPreferences {
+            screen_rotation: Some(ScreenRotation::Cw90),
+            language: Some("fr".to_string()),
+            brightness: Some(BrightnessPreset { cool: 40, warm: 10 }),
+        };
+        let preferences_str =
+            ron::ser::to_string_pretty(&preferences, ron::ser::PrettyConfig::default()).unwrap();
+        assert_eq!(parse("alice", &preferences_str), preferences);
+    }
+}
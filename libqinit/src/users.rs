@@ -0,0 +1,247 @@
+use anyhow::{Context, Result};
+use log::{info, warn};
+use std::fs;
+
+use crate::boot_config::BootConfig;
+use crate::rootfs::{self, run_chroot_command};
+use crate::secret::Secret;
+use crate::storage_encryption::{self, Credential};
+use crate::system::is_mountpoint;
+
+// Inside the root filesystem
+const USERADD_BINARY_PATH: &str = "/usr/sbin/useradd";
+const USERDEL_BINARY_PATH: &str = "/usr/sbin/userdel";
+const USERMOD_BINARY_PATH: &str = "/usr/sbin/usermod";
+const GROUPMOD_BINARY_PATH: &str = "/usr/sbin/groupmod";
+const CP_BINARY_PATH: &str = "/bin/cp";
+const CHOWN_BINARY_PATH: &str = "/bin/chown";
+const SKELETON_DIR_PATH: &str = "/etc/skel";
+const PASSWD_FILE_PATH: &str = "etc/passwd";
+// Same limit as useradd's default
+const USER_NAME_MAX_LENGTH: usize = 32;
+
+#[derive(Debug, Clone)]
+pub struct User {
+    pub name: String,
+    pub encrypted: bool,
+}
+
+pub fn list() -> Result<Vec<User>> {
+    storage_encryption::get_users()?
+        .into_iter()
+        .map(|name| {
+            Ok(User {
+                encrypted: storage_encryption::get_user_storage_encryption_status(&name)?,
+                name,
+            })
+        })
+        .collect()
+}
+
+// Accounts live in the root filesystem: they can only be changed once it is set up and the home directories are mounted in it
+pub fn can_manage() -> bool {
+    let passwd_path = format!("{}/{}", &crate::OVERLAY_MOUNTPOINT, &PASSWD_FILE_PATH);
+    let home_path = format!("{}/{}", &crate::OVERLAY_MOUNTPOINT, &crate::SYSTEM_HOME_DIR);
+
+    fs::exists(&passwd_path).unwrap_or(false) && is_mountpoint(&home_path).unwrap_or(false)
+}
+
+fn system_users() -> Result<Vec<String>> {
+    let passwd_path = format!("{}/{}", &crate::OVERLAY_MOUNTPOINT, &PASSWD_FILE_PATH);
+    let passwd = fs::read_to_string(&passwd_path)
+        .with_context(|| format!("Failed to read '{}'", &passwd_path))?;
+
+    Ok(passwd
+        .lines()
+        .filter_map(|line| line.split(':').next())
+        .map(str::to_string)
+        .collect())
+}
+
+// Lowercase letters, digits, '_' and '-', not starting with a digit or '-': what every tool in the root filesystem agrees on
+pub fn validate_new_user_name(user: &str) -> Result<()> {
+    if user.is_empty() || user.chars().count() > USER_NAME_MAX_LENGTH {
+        return Err(anyhow::anyhow!(
+            "User name must be between 1 and {} characters long",
+            &USER_NAME_MAX_LENGTH
+        ));
+    }
+    let mut characters = user.chars();
+    let first_character_valid = characters
+        .next()
+        .is_some_and(|character| character.is_ascii_lowercase() || character == '_');
+    if !first_character_valid
+        || !characters.all(|character| {
+            character.is_ascii_lowercase()
+                || character.is_ascii_digit()
+                || character == '_'
+                || character == '-'
+        })
+    {
+        return Err(anyhow::anyhow!(
+            "User name may only contain lowercase letters, digits, '_' and '-', and must start with a letter"
+        ));
+    }
+    // System users (root, daemons...) included
+    if system_users()?
+        .iter()
+        .any(|system_user| system_user == user)
+        || storage_encryption::get_users()?
+            .iter()
+            .any(|existing_user| existing_user == user)
+    {
+        return Err(anyhow::anyhow!("User '{}' already exists", &user));
+    }
+
+    Ok(())
+}
+
+// With a password, the home ends up in encrypted storage that is initialized right away: the skeleton is copied into it once mounted
+pub fn create(user: &str, password: Option<&Secret>) -> Result<()> {
+    validate_new_user_name(&user)?;
+    info!("Creating user '{}'", &user);
+    let home_dir_path = format!(
+        "/{}/{}",
+        &crate::SYSTEM_HOME_DIR.trim_end_matches('/'),
+        &user
+    );
+    let Some(password) = password else {
+        run_chroot_command(&[
+            &USERADD_BINARY_PATH,
+            "--create-home",
+            "--skel",
+            &SKELETON_DIR_PATH,
+            "--user-group",
+            &user,
+        ])?
+        .check()
+        .with_context(|| format!("Failed to create user '{}'", &user))?;
+        return storage_encryption::init_disabled_mode(&user);
+    };
+
+    run_chroot_command(&[
+        &USERADD_BINARY_PATH,
+        "--no-create-home",
+        "--user-group",
+        &user,
+    ])?
+    .check()
+    .with_context(|| format!("Failed to create user '{}'", &user))?;
+    let result = (|| -> Result<()> {
+        rootfs::change_user_password(&user, &password)?;
+        storage_encryption::init_vault(&user, &password)?;
+        storage_encryption::mount_storage(&user, &Credential::Password(password.clone()))?;
+        let populated = run_chroot_command(&[
+            &CP_BINARY_PATH,
+            "-a",
+            &format!("{}/.", &SKELETON_DIR_PATH),
+            &home_dir_path,
+        ])
+        .and_then(|result| result.check())
+        .and_then(|_| {
+            run_chroot_command(&[
+                &CHOWN_BINARY_PATH,
+                "-R",
+                &format!("{}:{}", &user, &user),
+                &home_dir_path,
+            ])?
+            .check()
+        });
+        storage_encryption::unmount_storage(&user)?;
+        populated.with_context(|| format!("Failed to populate home of user '{}'", &user))?;
+
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        // The encrypted storage is left alone: init_vault() already cleaned up after itself, and anything past that holds the skeleton at most
+        if let Err(cleanup_error) =
+            run_chroot_command(&[&USERDEL_BINARY_PATH, &user]).and_then(|result| result.check())
+        {
+            warn!(
+                "Failed to remove user '{}' after failed creation: {:?}",
+                &user, &cleanup_error
+            );
+        }
+        return Err(e);
+    }
+    info!("Created user '{}' with encrypted storage", &user);
+
+    Ok(())
+}
+
+pub fn rename(boot_config: &mut BootConfig, user: &str, new_user: &str) -> Result<()> {
+    validate_new_user_name(&new_user)?;
+    if !system_users()?
+        .iter()
+        .any(|system_user| system_user == user)
+    {
+        return Err(anyhow::anyhow!("User '{}' does not exist", &user));
+    }
+    info!("Renaming user '{}' to '{}'", &user, &new_user);
+    // Checked first: nothing would be renamed at all if the home is in use
+    storage_encryption::rename_storage(&user, &new_user)?;
+    let new_home_dir_path = format!(
+        "/{}/{}",
+        &crate::SYSTEM_HOME_DIR.trim_end_matches('/'),
+        &new_user
+    );
+    let renamed = run_chroot_command(&[
+        &USERMOD_BINARY_PATH,
+        "--login",
+        &new_user,
+        "--home",
+        &new_home_dir_path,
+        "--move-home",
+        &user,
+    ])
+    .and_then(|result| result.check());
+    if let Err(e) = renamed {
+        if let Err(rollback_error) = storage_encryption::rename_storage(&new_user, &user) {
+            warn!("{:?}", &rollback_error);
+        }
+        return Err(e).with_context(|| format!("Failed to rename user '{}'", &user));
+    }
+    // User groups are a convenience: a stale name does not lock anyone out
+    if let Err(e) = run_chroot_command(&[&GROUPMOD_BINARY_PATH, "--new-name", &new_user, &user])
+        .and_then(|result| result.check())
+    {
+        warn!("Failed to rename group of user '{}': {:?}", &user, &e);
+    }
+    // The full password still works under the new name
+    if let Err(e) = crate::pin::rename_quick_unlock(&user, &new_user) {
+        warn!("Failed to keep quick unlock of user '{}': {:?}", &user, &e);
+    }
+
+    let rename_in = |boot_config: &mut BootConfig| {
+        if boot_config.system.default_user.as_deref() == Some(user) {
+            boot_config.system.default_user = Some(new_user.to_string());
+        }
+        if let Some(digest) = boot_config.system.recovery_keys.remove(user) {
+            boot_config
+                .system
+                .recovery_keys
+                .insert(new_user.to_string(), digest);
+        }
+    };
+    rename_in(boot_config);
+    BootConfig::update_stored(rename_in)?;
+
+    Ok(())
+}
+
+pub fn set_default(boot_config: &mut BootConfig, user: &str) -> Result<()> {
+    if !storage_encryption::get_users()?
+        .iter()
+        .any(|existing_user| existing_user == user)
+    {
+        return Err(anyhow::anyhow!("User '{}' does not exist", &user));
+    }
+    info!("Setting default user to '{}'", &user);
+    boot_config.system.default_user = Some(user.to_string());
+    BootConfig::update_stored(|stored_boot_config| {
+        stored_boot_config.system.default_user = Some(user.to_string());
+    })?;
+
+    Ok(())
+}
@@ -1,35 +1,78 @@
-use crate::system::{modprobe, restart_service, run_command, stop_service, sync_time};
-use anyhow::Result;
-use log::{error, info};
+use crate::boot_config::BootConfig;
+use crate::notifier;
+use crate::secret::Secret;
+use crate::system::{
+    RetryPolicy, kmod, modprobe, restart_service, retry, run_command, run_command_capture,
+    run_command_with_timeout, stop_service, sync_time, to_hex, wait_for_path,
+};
+use anyhow::{Context, Result};
+use log::{error, info, warn};
 use regex::Regex;
+use std::collections::VecDeque;
 use std::fs;
-use std::process::Command;
-use std::sync::mpsc::{Receiver, Sender};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
 
 pub const WIFI_IF: &str = "wlan0";
 
 const WIFI_MODULE: &str = "brcmfmac_wcc";
-const IWCTL_PATH: &str = "/usr/bin/iwctl";
+pub const IWCTL_PATH: &str = "/usr/bin/iwctl";
 const IWD_SERVICE: &str = "iwd";
-const MAX_SCAN_RETRIES: i32 = 30;
+const IWD_STORAGE_DIR: &str = "/var/lib/iwd";
+const MAX_SCAN_RETRIES: u32 = 30;
 const MAX_PING_RETRIES: i32 = 5;
 const PING_TIMEOUT_SECS: i32 = 5;
+const IWCTL_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_IDLE_SUSPEND_MINS: u64 = 5;
+const INTERFACE_WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+// Signal strength thresholds, in dBm
+const SIGNAL_HIGH_DBM: i32 = -60;
+const SIGNAL_MEDIUM_DBM: i32 = -70;
+static NEXT_COMMAND_SEQUENCE: AtomicU64 = AtomicU64::new(1);
+
+const DEFAULT_NTP_SERVERS: [&str; 2] = ["pool.ntp.org", "time.google.com"];
 
 #[derive(Debug, PartialEq)]
 pub struct Network {
     pub name: String,
     pub open: bool,
     pub currently_connected: bool,
-    // Maybe something to implement in the future?
-    // strength: i32,
+    // In dBm, as reported by the last scan
+    pub strength: Option<i32>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SignalLevel {
+    Low,
+    Medium,
+    High,
+}
+
+impl Network {
+    pub fn signal_level(&self) -> Option<SignalLevel> {
+        self.strength.map(|strength| {
+            if strength >= SIGNAL_HIGH_DBM {
+                SignalLevel::High
+            } else if strength >= SIGNAL_MEDIUM_DBM {
+                SignalLevel::Medium
+            } else {
+                SignalLevel::Low
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum StatusType {
     Disabled,
     NotConnected,
     Connected,
     Error,
+    // Enabled, but powered down after a while without activity: any command wakes it up
+    Suspended,
 }
 
 #[derive(Debug, PartialEq)]
@@ -37,6 +80,22 @@ pub struct Status {
     pub status_type: StatusType,
     pub list: Option<Vec<Network>>,
     pub error: Option<String>,
+    // Sequence number of the command this status answers
+    pub sequence: u64,
+}
+
+impl Status {
+    // Unknown unless connected, and the status carries a networks list with a reported strength
+    pub fn signal_level(&self) -> Option<SignalLevel> {
+        if self.status_type != StatusType::Connected {
+            return None;
+        }
+        self.list
+            .as_ref()?
+            .iter()
+            .find(|network| network.currently_connected)?
+            .signal_level()
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -47,30 +106,153 @@ pub enum CommandType {
     Disconnect,
     GetStatus,
     GetNetworks,
+    // Keeps the interface awake (e.g. while the Wi-Fi panel is open), waking it up first if needed
+    Wake,
+    Release,
 }
 
 #[derive(Debug, PartialEq)]
 pub struct NetworkForm {
     pub name: String,
-    pub passphrase: Option<String>,
+    pub passphrase: Option<Secret>,
 }
 
 #[derive(Debug, PartialEq)]
 pub struct CommandForm {
     pub command_type: CommandType,
     pub arguments: Option<NetworkForm>,
+    pub sequence: u64,
+}
+
+impl CommandForm {
+    // Sequence numbers increase monotonically, in the order commands are created
+    pub fn new(command_type: CommandType, arguments: Option<NetworkForm>) -> CommandForm {
+        CommandForm {
+            command_type,
+            arguments,
+            sequence: NEXT_COMMAND_SEQUENCE.fetch_add(1, Ordering::SeqCst),
+        }
+    }
+
+    // Commands without side effects or arguments: answering the latest one answers them all
+    fn coalescible(&self) -> bool {
+        self.command_type == CommandType::GetStatus || self.command_type == CommandType::GetNetworks
+    }
+}
+
+// Lets the receiving side discard statuses that arrive after a newer one
+#[derive(Debug, Default)]
+pub struct StatusSequencer {
+    latest_sequence: u64,
+}
+
+impl StatusSequencer {
+    pub fn accept(&mut self, status: &Status) -> bool {
+        if status.sequence < self.latest_sequence {
+            return false;
+        }
+        self.latest_sequence = status.sequence;
+
+        true
+    }
+}
+
+// Pops the next command to run, merging a run of identical coalescible commands at the front of the queue into the latest one
+fn next_command(pending_commands: &mut VecDeque<CommandForm>) -> Option<CommandForm> {
+    let mut command_form = pending_commands.pop_front()?;
+    while command_form.coalescible()
+        && pending_commands
+            .front()
+            .is_some_and(|next| next.command_type == command_form.command_type)
+    {
+        let next = pending_commands.pop_front()?;
+        info!(
+            "Wi-Fi daemon: coalescing command {} into {}",
+            &command_form.sequence, &next.sequence
+        );
+        command_form = next;
+    }
+
+    Some(command_form)
 }
 
 pub fn daemon(
     wifi_status_sender: Sender<Status>,
     wifi_command_receiver: Receiver<CommandForm>,
 ) -> Result<()> {
+    let mut pending_commands = VecDeque::new();
+    let idle_suspend_timeout = get_idle_suspend_timeout();
+    let mut powered_down = false;
+    let mut held_awake = false;
+    let mut last_activity = Instant::now();
+    let mut last_status_type = StatusType::Disabled;
+    let mut last_sequence = 0;
     loop {
-        if let Ok(command_form) = wifi_command_receiver.recv() {
+        if pending_commands.is_empty() {
+            // Only an idle, enabled and unconnected interface is worth powering down
+            let idle_timeout = idle_suspend_timeout.filter(|_| {
+                !powered_down && !held_awake && last_status_type == StatusType::NotConnected
+            });
+            let received = match idle_timeout {
+                Some(idle_timeout) => wifi_command_receiver
+                    .recv_timeout(idle_timeout.saturating_sub(last_activity.elapsed())),
+                None => wifi_command_receiver
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            match received {
+                Ok(command_form) => pending_commands.push_back(command_form),
+                Err(RecvTimeoutError::Timeout) => {
+                    // A command sent while the timeout expired (e.g. the Wi-Fi panel being opened) wins over powering down
+                    pending_commands.extend(wifi_command_receiver.try_iter());
+                    if pending_commands.is_empty() {
+                        match power_down() {
+                            Ok(()) => {
+                                powered_down = true;
+                                last_status_type = StatusType::Suspended;
+                                // Answers the last command instead of a new one, so that the answer to any command sent since supersedes it
+                                wifi_status_sender.send(Status {
+                                    status_type: StatusType::Suspended,
+                                    list: None,
+                                    error: None,
+                                    sequence: last_sequence,
+                                })?;
+                            }
+                            Err(e) => {
+                                error!("Failed to power Wi-Fi down: {}", &e);
+                                last_activity = Instant::now();
+                            }
+                        }
+                        continue;
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+        pending_commands.extend(wifi_command_receiver.try_iter());
+
+        if let Some(command_form) = next_command(&mut pending_commands) {
             info!(
-                "Wi-Fi daemon: received new command: {:?}",
-                &command_form.command_type
+                "Wi-Fi daemon: received new command {}: {:?}",
+                &command_form.sequence, &command_form.command_type
             );
+            last_activity = Instant::now();
+            last_sequence = command_form.sequence;
+
+            if command_form.command_type == CommandType::Release {
+                held_awake = false;
+                continue;
+            } else if command_form.command_type == CommandType::Wake {
+                held_awake = true;
+            }
+            if powered_down {
+                powered_down = false;
+                if let Err(e) = power_up() {
+                    error!("Failed to wake Wi-Fi up: {}", &e);
+                }
+            } else if command_form.command_type == CommandType::Wake {
+                continue;
+            }
 
             let mut wifi_status: Status;
 
@@ -91,6 +273,7 @@ pub fn daemon(
                     status_type: StatusType::Error,
                     list: None,
                     error: Some("Failed to get Wi-Fi status".to_string()),
+                    sequence: 0,
                 }
             }
 
@@ -121,6 +304,7 @@ pub fn daemon(
                                 status_type: StatusType::Error,
                                 list: None,
                                 error: Some("Failed to get Wi-Fi status".to_string()),
+                                sequence: 0,
                             }
                         }
                     }
@@ -131,7 +315,11 @@ pub fn daemon(
                 }
             }
 
+            wifi_status.sequence = command_form.sequence;
+            last_status_type = wifi_status.status_type;
             wifi_status_sender.send(wifi_status)?;
+            // Scans and connections take a while: idle time starts counting once they are done
+            last_activity = Instant::now();
         }
     }
 }
@@ -141,23 +329,18 @@ fn get_networks() -> Result<Vec<Network>> {
 
     let mut networks_list = Vec::new();
 
-    let mut scan_retries = 0;
-    loop {
-        if scan_retries < MAX_SCAN_RETRIES {
-            if let Ok(()) = run_command(&IWCTL_PATH, &["station", &WIFI_IF, "scan"]) {
-                break;
-            }
-        } else {
-            return Err(anyhow::anyhow!("Failed to scan for networks"));
-        }
-        std::thread::sleep(std::time::Duration::from_millis(100));
-        scan_retries += 1;
-    }
+    retry(
+        &RetryPolicy::fixed(Duration::from_millis(100), MAX_SCAN_RETRIES),
+        || run_command_with_timeout(&IWCTL_PATH, &["station", &WIFI_IF, "scan"], IWCTL_TIMEOUT),
+    )
+    .with_context(|| "Failed to scan for networks")?;
 
-    let raw_iwd_output = Command::new(&IWCTL_PATH)
-        .args(&["station", &WIFI_IF, "get-networks"])
-        .output()?;
-    let raw_networks_list = String::from_utf8_lossy(&raw_iwd_output.stdout);
+    // Signal strength in dBm rather than as stars, which only differ by their color
+    let raw_networks_list = run_command_capture(
+        &IWCTL_PATH,
+        &["station", &WIFI_IF, "get-networks", "rssi-dbms"],
+    )?
+    .stdout;
 
     let ansi_escape = Regex::new(r"\x1b\[[0-9;]*m")?;
 
@@ -170,6 +353,9 @@ fn get_networks() -> Result<Vec<Network>> {
         // Maximum SSID length for a Wi-Fi network is 32 characters, so we should be safe here
         let network_name_str = &clean_line[..32].trim();
         let security_str = &clean_line[34..54].trim();
+        let strength = clean_line
+            .get(54..)
+            .and_then(|signal_str| signal_str.trim().parse::<i32>().ok());
 
         let mut open = false;
         if security_str.contains("open") {
@@ -187,6 +373,7 @@ fn get_networks() -> Result<Vec<Network>> {
             name: final_network_name,
             open: open,
             currently_connected: currently_connected,
+            strength: strength,
         };
         networks_list.push(network);
     }
@@ -204,47 +391,113 @@ fn disable() -> Result<()> {
 
 fn enable() -> Result<()> {
     info!("Enabling Wi-Fi");
-    modprobe(&[&WIFI_MODULE])?;
+    kmod::load_module(&WIFI_MODULE, &[])?;
     // Wait for Wi-Fi interface to appear before trying to enable it
-    loop {
-        if fs::exists(&format!("/sys/class/net/{}", &WIFI_IF))? {
-            run_command("/sbin/ifconfig", &[WIFI_IF, "up"])?;
-            break;
-        } else {
-            std::thread::sleep(std::time::Duration::from_millis(100));
-        }
-    }
+    wait_for_path(
+        &format!("/sys/class/net/{}", &WIFI_IF),
+        INTERFACE_WAIT_TIMEOUT,
+        "the Wi-Fi interface",
+    )?;
+    run_command("/sbin/ifconfig", &[WIFI_IF, "up"])?;
 
     Ok(())
 }
 
+// Zero disables powering down entirely
+fn get_idle_suspend_timeout() -> Option<Duration> {
+    let idle_suspend_mins = BootConfig::peek()
+        .and_then(|boot_config| boot_config.system.wifi_idle_suspend_mins)
+        .unwrap_or(DEFAULT_IDLE_SUSPEND_MINS);
+
+    (idle_suspend_mins > 0).then(|| Duration::from_secs(idle_suspend_mins * 60))
+}
+
+fn get_power_control_path() -> String {
+    format!("/sys/class/net/{}/device/power/control", &WIFI_IF)
+}
+
+// The module stays loaded: bringing the interface back up is much faster than loading it again
+fn power_down() -> Result<()> {
+    info!("Wi-Fi is idle: powering it down");
+    run_command("/sbin/ifconfig", &[WIFI_IF, "down"])?;
+    // Lets the device runtime-suspend now that the interface is down
+    fs::write(&get_power_control_path(), "auto")
+        .with_context(|| "Failed to enable Wi-Fi device's runtime power management")?;
+
+    Ok(())
+}
+
+fn power_up() -> Result<()> {
+    info!("Waking Wi-Fi up");
+    fs::write(&get_power_control_path(), "on").with_context(|| "Failed to resume Wi-Fi device")?;
+    run_command("/sbin/ifconfig", &[WIFI_IF, "up"])?;
+
+    Ok(())
+}
+
+// iwd stores SSIDs made of anything but alphanumerics, spaces, underscores and dashes hex-encoded, after an equals sign
+fn iwd_network_file_name(name: &str) -> String {
+    if name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, ' ' | '_' | '-'))
+    {
+        format!("{}.psk", &name)
+    } else {
+        format!("={}.psk", to_hex(name.as_bytes()))
+    }
+}
+
+// Unlike "iwctl --passphrase", which shows up in /proc/<pid>/cmdline, iwd picks network files up on its own
+fn write_iwd_network_file(storage_dir: &str, name: &str, passphrase: &Secret) -> Result<String> {
+    if passphrase.expose().contains(['\n', '\r']) {
+        return Err(anyhow::anyhow!("Passphrase must not contain line breaks"));
+    }
+    fs::create_dir_all(&storage_dir)
+        .with_context(|| format!("Failed to create '{}'", &storage_dir))?;
+    let network_file_path = format!("{}/{}", &storage_dir, &iwd_network_file_name(&name));
+    let contents = Secret::new(format!("[Security]\nPassphrase={}\n", passphrase.expose()));
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&network_file_path)
+        .and_then(|mut file| file.write_all(contents.expose().as_bytes()))
+        .with_context(|| format!("Failed to write network file '{}'", &network_file_path))?;
+
+    Ok(network_file_path)
+}
+
 fn connect(network: &NetworkForm) -> Result<()> {
     info!(
         "Attempting to connect to network with the following credentials: {:?}",
         &network
     );
-    if network.passphrase.is_none() {
-        run_command(
-            &IWCTL_PATH,
-            &["station", &WIFI_IF, "connect", &network.name],
-        )?;
-    } else {
-        if let Some(passphrase) = &network.passphrase {
-            run_command(
-                &IWCTL_PATH,
-                &[
-                    "--passphrase",
-                    &passphrase,
-                    "station",
-                    &WIFI_IF,
-                    "connect",
-                    &network.name,
-                ],
-            )?;
-        }
+    if let Some(passphrase) = &network.passphrase {
+        write_iwd_network_file(&IWD_STORAGE_DIR, &network.name, &passphrase)?;
     }
-
-    let _ = sync_time();
+    run_command_with_timeout(
+        &IWCTL_PATH,
+        &["station", &WIFI_IF, "connect", &network.name],
+        IWCTL_TIMEOUT,
+    )?;
+
+    // The clock shown in the status bar is often wrong after a while without network access
+    let ntp_servers = BootConfig::peek()
+        .and_then(|boot_config| boot_config.system.ntp_servers)
+        .unwrap_or(
+            DEFAULT_NTP_SERVERS
+                .iter()
+                .map(|server| server.to_string())
+                .collect(),
+        );
+    std::thread::spawn(move || match sync_time(&ntp_servers) {
+        Ok(_) => notifier::get().toast("Time synchronized"),
+        Err(e) => {
+            warn!("Failed to synchronize time: {:?}", &e);
+            notifier::get().toast("Failed to synchronize time");
+        }
+    });
 
     Ok(())
 }
@@ -261,12 +514,14 @@ fn get_status(do_ping: bool) -> Result<Status> {
                     status_type: StatusType::Connected,
                     list: None,
                     error: None,
+                    sequence: 0,
                 };
             } else {
                 status = Status {
                     status_type: StatusType::NotConnected,
                     list: None,
                     error: None,
+                    sequence: 0,
                 };
             }
         } else {
@@ -274,6 +529,7 @@ fn get_status(do_ping: bool) -> Result<Status> {
                 status_type: StatusType::NotConnected,
                 list: None,
                 error: None,
+                sequence: 0,
             };
         }
     } else {
@@ -281,6 +537,7 @@ fn get_status(do_ping: bool) -> Result<Status> {
             status_type: StatusType::Disabled,
             list: None,
             error: None,
+            sequence: 0,
         };
     }
 
@@ -310,3 +567,48 @@ fn is_connected_to_internet() -> Result<bool> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TempDir;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn network_file_names_follow_iwd_conventions() {
+        assert_eq!(iwd_network_file_name("Home Wi-Fi_2"), "Home Wi-Fi_2.psk");
+        assert_eq!(iwd_network_file_name("café"), "=636166c3a9.psk");
+        assert_eq!(iwd_network_file_name("../etc"), "=2e2e2f657463.psk");
+    }
+
+    #[test]
+    fn passphrase_goes_to_a_private_network_file() {
+        let dir = TempDir::new();
+        let storage_dir = dir.join("iwd");
+        let network_file_path =
+            write_iwd_network_file(&storage_dir, "Home", &Secret::new("hunter2 ok".to_string()))
+                .unwrap();
+        assert_eq!(network_file_path, format!("{}/Home.psk", &storage_dir));
+        assert_eq!(
+            fs::read_to_string(&network_file_path).unwrap(),
+            "[Security]\nPassphrase=hunter2 ok\n"
+        );
+        assert_eq!(
+            fs::metadata(&network_file_path)
+                .unwrap()
+                .permissions()
+                .mode()
+                & 0o777,
+            0o600
+        );
+
+        assert!(
+            write_iwd_network_file(
+                &storage_dir,
+                "Home",
+                &Secret::new("hunter2\n[Settings]".to_string())
+            )
+            .is_err()
+        );
+    }
+}
@@ -4,5 +4,7 @@ fn main() {
     let year = Utc::now().year();
     println!("cargo:rustc-env=BUILD_YEAR={}", year);
 
-    slint_build::compile("ui/app-window.slint").expect("Slint build failed");
+    // Catalogs are compiled in: nothing is read from the root filesystem at runtime
+    let config = slint_build::CompilerConfiguration::new().with_bundled_translations("lang");
+    slint_build::compile_with_config("ui/app-window.slint", config).expect("Slint build failed");
 }
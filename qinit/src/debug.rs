@@ -1,7 +1,7 @@
 use anyhow::{Context, Result};
 use libqinit::boot_config::BootConfig;
 use libqinit::signing::check_signature;
-use libqinit::system::{modprobe, run_command, start_service};
+use libqinit::system::{kmod, run_command, start_service};
 use log::warn;
 use network_interface::NetworkInterface;
 use network_interface::NetworkInterfaceConfig;
@@ -9,7 +9,6 @@ use openssl::pkey::PKey;
 use openssl::pkey::Public;
 use regex::Regex;
 use std::fs;
-use std::os::unix::fs::symlink;
 use std::process::Command;
 
 const IP_ADDR: &str = "192.168.3.2";
@@ -17,13 +16,14 @@ const IP_POOL_END: &str = "192.168.3.254";
 const REDSOCKS_PORT: u16 = 12345;
 const UDHCPD_CONF_PATH: &str = "/etc/udhcpd.conf";
 const DROPBEAR_RSA_KEY_FILE: &str = "rsa_hkey";
+const DROPBEAR_BANNER_PATH: &str = "/etc/dropbear_banner";
 const DEBUG_SETUP_SCRIPT: &str = "debug-setup.sh";
 const COPIED_DEBUG_SCRIPT: &str = ".profile";
 const USER_UDHCPD_CONF_FILE: &str = "udhcpd.conf";
 
 pub fn start_debug_framework(pubkey: &PKey<Public>, boot_config: &mut BootConfig) -> Result<()> {
     start_usbnet(&pubkey, boot_config)?;
-    start_sshd()?;
+    start_sshd(&libqinit::system::hostname(&boot_config))?;
     prepare_script_login(&pubkey)?;
 
     Ok(())
@@ -68,13 +68,14 @@ pub fn start_usbnet(pubkey: &PKey<Public>, boot_config: &mut BootConfig) -> Resu
         &usbnet_host_mac_address, &usbnet_dev_mac_address
     );
 
-    // liblmod is not able to load g_ether properly, it seems
-    modprobe(&["phy-rockchip-inno-usb2"])?;
-    modprobe(&[
+    kmod::load_module("phy-rockchip-inno-usb2", &[])?;
+    kmod::load_module(
         "g_ether",
-        &format!("host_addr={}", &usbnet_host_mac_address),
-        &format!("dev_addr={}", &usbnet_dev_mac_address),
-    ])?;
+        &[
+            &format!("host_addr={}", &usbnet_host_mac_address),
+            &format!("dev_addr={}", &usbnet_dev_mac_address),
+        ],
+    )?;
 
     let network_interfaces =
         NetworkInterface::show().with_context(|| "Failed to retrieve network interfaces")?;
@@ -153,8 +154,13 @@ pub fn start_usbnet(pubkey: &PKey<Public>, boot_config: &mut BootConfig) -> Resu
     Ok(())
 }
 
-pub fn start_sshd() -> Result<()> {
+pub fn start_sshd(hostname: &str) -> Result<()> {
     warn!("Starting SSH server");
+    fs::write(
+        &DROPBEAR_BANNER_PATH,
+        format!("QuillBoot debug shell on '{}'\n", &hostname),
+    )
+    .with_context(|| "Failed to write SSH banner")?;
     let dropbear_rsa_key_path = format!(
         "{}/{}",
         &libqinit::BOOT_PART_MOUNTPOINT,
@@ -169,7 +175,15 @@ pub fn start_sshd() -> Result<()> {
     }
     run_command(
         "/usr/sbin/dropbear",
-        &["-p", "2222", "-r", &dropbear_rsa_key_path, "-B"],
+        &[
+            "-p",
+            "2222",
+            "-r",
+            &dropbear_rsa_key_path,
+            "-b",
+            &DROPBEAR_BANNER_PATH,
+            "-B",
+        ],
     )
     .with_context(|| "Failed to start Dropbear SSH server")?;
 
@@ -227,8 +241,7 @@ pub fn setup_ssh_tunneling(subnet: &str, iface_name: &str) -> Result<()> {
     run_command("/sbin/ip", &["route", "replace", "default", "dev", &iface_name, "via", "192.168.3.1"])
         .with_context(|| format!("Failed to route default traffic through {} interface", &iface_name))?;
 
-    symlink("/etc/init.d/networking", "/run/openrc/started/networking")
-        .with_context(|| "Failed to create OpenRC networking symlink in /run")?;
+    libqinit::openrc::get().mark_started("networking")?;
 
     start_service("redsocks")
         .with_context(|| "Failed to start redsocks daemon")?;
@@ -0,0 +1,104 @@
+use libqinit::signing;
+use libqinit::system::{FilesystemCorruptedError, WaitTimedOut};
+
+// Exit codes of the qinit binaries: the first stage supervises the second stage based on them, and scripts may rely on them too
+// Usage: qinit --exit-codes
+pub const EXIT_CODES_ARG: &str = "--exit-codes";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExitCode {
+    HandedOff = 0,
+    Failure = 1,
+    ConfigError = 10,
+    StorageFailure = 11,
+    DisplayFailure = 12,
+    SignatureFailure = 13,
+    PowerOffHandled = 20,
+}
+
+// What the first stage does when the second stage exits with a given code
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Recovery {
+    Nothing,
+    Retry,
+    PowerOff,
+}
+
+const EXIT_CODES: &[ExitCode] = &[
+    ExitCode::HandedOff,
+    ExitCode::Failure,
+    ExitCode::ConfigError,
+    ExitCode::StorageFailure,
+    ExitCode::DisplayFailure,
+    ExitCode::SignatureFailure,
+    ExitCode::PowerOffHandled,
+];
+
+impl ExitCode {
+    pub fn from_code(code: i32) -> ExitCode {
+        EXIT_CODES
+            .iter()
+            .copied()
+            .find(|exit_code| *exit_code as i32 == code)
+            .unwrap_or(ExitCode::Failure)
+    }
+
+    // The outermost recognized cause wins
+    pub fn classify(e: &anyhow::Error) -> ExitCode {
+        for cause in e.chain() {
+            if cause.is::<FilesystemCorruptedError>() || cause.is::<WaitTimedOut>() {
+                return ExitCode::StorageFailure;
+            }
+            if cause.is::<slint::PlatformError>() {
+                return ExitCode::DisplayFailure;
+            }
+            if cause.is::<ron::error::SpannedError>() || cause.is::<ron::Error>() {
+                return ExitCode::ConfigError;
+            }
+        }
+        // Recorded whenever an archive fails verification: the fatal error itself only carries a message
+        if !signing::signature_failures().is_empty() {
+            return ExitCode::SignatureFailure;
+        }
+
+        ExitCode::Failure
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            ExitCode::HandedOff => "Handed off to the root filesystem",
+            ExitCode::Failure => "Unclassified failure",
+            ExitCode::ConfigError => "Fatal boot configuration error",
+            ExitCode::StorageFailure => "Storage failure (missing or corrupted partition)",
+            ExitCode::DisplayFailure => "Display failure (the GUI could not start)",
+            ExitCode::SignatureFailure => "Signature verification failure",
+            ExitCode::PowerOffHandled => "User-requested power off, handled internally",
+        }
+    }
+
+    // Storage may show up late (e.g. slow SD cards) and unclassified failures may be transient: anything else would fail the same way again
+    pub fn recovery(&self) -> Recovery {
+        match self {
+            ExitCode::HandedOff | ExitCode::PowerOffHandled => Recovery::Nothing,
+            ExitCode::Failure | ExitCode::StorageFailure => Recovery::Retry,
+            ExitCode::ConfigError | ExitCode::DisplayFailure | ExitCode::SignatureFailure => {
+                Recovery::PowerOff
+            }
+        }
+    }
+}
+
+pub fn table() -> String {
+    EXIT_CODES
+        .iter()
+        .map(|exit_code| {
+            format!(
+                "{}\t{}\t(recovery: {:?})",
+                *exit_code as i32,
+                exit_code.description(),
+                exit_code.recovery()
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
@@ -7,37 +7,116 @@ use std::time::Duration;
 
 use anyhow::Result;
 use chrono::prelude::*;
-use libqinit::boot_config::BootConfig;
+use libqinit::boot_config::{
+    self, BootConfig, ConfigSetting, KeyboardLayout, Language, Theme, UiScale,
+};
 use libqinit::brightness;
+use libqinit::diagnostics;
 use libqinit::eink::{self, ScreenRotation};
+use libqinit::file_browser::{self, FileBrowser, FilePurpose};
+use libqinit::input;
 use libqinit::networking;
-use libqinit::recovery::soft_reset;
+use libqinit::notifier::{self, ChannelNotifier, EarlyEvent, Notification, PinRequest};
+use libqinit::partitions::Partition;
+use libqinit::pin::{self, PinCheck, PinPromptOutcome};
+use libqinit::qr;
+use libqinit::recovery::{self, soft_reset};
+use libqinit::rootfs;
+use libqinit::screenshot;
+use libqinit::secret::{self, Secret};
+use libqinit::services;
+use libqinit::signing;
 use libqinit::splash;
-use libqinit::storage_encryption;
+use libqinit::storage_encryption::{self, Credential, VaultStatus};
 use libqinit::system::{
-    BootCommand, BootCommandForm, PowerDownMode, compress_string_to_xz, keep_last_lines,
-    read_kernel_buffer_singleshot, shut_down,
+    BootCommand, BootCommandForm, PowerDownMode, ShutdownScheduler, compress_string_to_xz,
+    keep_last_lines, read_kernel_buffer_singleshot, shut_down,
 };
+use libqinit::systemd::BootStatus;
+use libqinit::usb_gadget::{self, SharedStorage};
+use libqinit::user_preferences;
+use libqinit::users;
 use libqinit::wifi;
 use libqinit::{battery, system};
 use libquillcom::socket::{LoginForm, PrimitiveShutDownType};
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use qrcode_generator::QrCodeEcc;
-use slint::{Color, Image, SharedString, Timer, TimerMode};
-use std::{fs, path::Path, thread};
+use slint::platform::{Key, WindowEvent};
+use slint::{Color, Image, Model, RenderingState, SharedString, Timer, TimerMode, Weak};
+use std::panic::{self, AssertUnwindSafe};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, HashMap, VecDeque},
+    fs,
+    path::Path,
+    rc::Rc,
+    thread,
+    time::Instant,
+};
 
 use crate::BootSelection;
+use crate::translations::{self, tr, tr_format};
 slint::include_modules!();
 
 pub const TOAST_DURATION_MILLIS: i32 = 5000;
+// Oldest pending toasts are dropped past this
+const TOAST_QUEUE_CAPACITY: usize = 10;
+const BOOT_TIME_LEFT_STEP_SECS: u64 = 5;
+const RESUME_REFRESH_DELAY: Duration = Duration::from_millis(500);
+// Same as the targets of the touchscreen calibration page, in the order they are shown, as fractions of the window size
+const TOUCH_CALIBRATION_TARGETS: [(f32, f32); 4] = [(0.1, 0.1), (0.9, 0.1), (0.9, 0.9), (0.1, 0.9)];
+// In case the previewed rotation leaves nothing within reach
+const TOUCH_ROTATION_REVERT_DELAY: Duration = Duration::from_secs(20);
 const NOT_AVAILABLE: &str = "(Not currently available)";
+const COLLECTING_DETAILS: &str = "(Collecting details…)";
+const KERNEL_VERSION_PATH: &str = "/proc/version";
 const HELP_URI: &str =
     "https://github.com/PorQ-Pine/docs/blob/main/troubleshooting/fatal-errors.md";
 const QR_CODE_TAB_INDEX: i32 = 0;
 const QR_CODE_NOT_AVAILABLE_TAB_INDEX: i32 = 1;
+const QR_MAX_LOG_LINES: usize = 100;
+const QR_LOG_LINES_STEP: usize = 5;
+const SERVICE_RESTART_COOLDOWN: Duration = Duration::from_secs(10);
+const IDLE_LOCK_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+// Shown before an idle power off, any touch cancels it
+const IDLE_POWER_OFF_COUNTDOWN: Duration = Duration::from_secs(30);
+// After which a pending login is acknowledged, rather than left looking dead
+const SLOW_LOGIN_DELAY: Duration = Duration::from_secs(5);
+// Where boot configuration changes come from, as shown in the pending changes drawer
+const BOOT_CONFIGURATION_PAGE: &str = "Boot configuration";
+const RECOVERY_OPTIONS_PAGE: &str = "Recovery options";
+const QUILLBOOT_PAGE: &str = "QuillBoot";
+const USER_LOGIN_PAGE: &str = "User login";
+// Below this level, an update may only be installed with the charger plugged in
+const MIN_UPDATE_BATTERY_LEVEL: i32 = 20;
+// Approximate on-screen size of the debug QR code and smallest module a phone camera reliably resolves on the e-ink panel
+const QR_DISPLAY_SIZE_PX: u32 = 600;
+const QR_MIN_MODULE_MICROMETERS: u32 = 400;
+// Same as the fatal error page shows: older lines are on the following pages
+const LOG_VIEWER_PAGE_LINES: usize = 150;
+const KERNEL_BUFFER_TAB_INDEX: i32 = 1;
+
+thread_local! {
+    // Name of the GUI callback running on this thread, if any
+    static CURRENT_CALLBACK: Cell<Option<&'static str>> = const { Cell::new(None) };
+    // Toasts waiting for the one shown to go away: translated messages, with how many times each came in a row
+    static TOAST_QUEUE: RefCell<VecDeque<(String, usize)>> = const { RefCell::new(VecDeque::new()) };
+    // Toast shown by show_toast(), for repeats to be counted on it
+    static SHOWN_TOAST: RefCell<Option<(String, usize)>> = const { RefCell::new(None) };
+    // Debug QR codes of the fatal error page: images can only live on the GUI thread
+    static DEBUG_QR_CODES: RefCell<Vec<Image>> = const { RefCell::new(Vec::new()) };
+}
+
+// Wraps a callback registered on the GUI: see run_guarded()
+macro_rules! guarded {
+    ($name:literal, move |$($arg:pat_param),*| $body:block) => {
+        move |$($arg),*| run_guarded($name, || $body)
+    };
+}
 
 pub fn setup_gui(
     progress_receiver: Receiver<f32>,
+    boot_status_receiver: Receiver<BootStatus>,
     boot_sender: Sender<BootCommandForm>,
     login_credentials_sender: Sender<LoginForm>,
     splash_receiver: Receiver<PrimitiveShutDownType>,
@@ -54,12 +133,23 @@ pub fn setup_gui(
     boot_selection: BootSelection,
     netboot_ready_receiver: Receiver<()>,
 ) -> Result<()> {
+    report_callback_panics();
     let gui = AppWindow::new()?;
     let gui_weak = gui.as_weak();
     let first_boot_done;
     let can_shut_down = Arc::new(AtomicBool::new(false));
     let core_settings_finished_running = Arc::new(AtomicBool::new(false));
     let (core_settings_sender, core_settings_receiver): (Sender<()>, Receiver<()>) = channel();
+    // Owned here rather than by a page so that a scheduled power off survives navigation
+    let shutdown_scheduler = ShutdownScheduler::new();
+    // Lets the refresh watchdog tell a wedged display driver from a GUI that has nothing new to show
+    if let Err(e) = gui.window().set_rendering_notifier(|state, _| {
+        if let RenderingState::AfterRendering = state {
+            eink::note_frame_rendered();
+        }
+    }) {
+        warn!("Failed to watch GUI rendering: {}", &e);
+    }
 
     // Copyright year
     gui.set_max_copyright_year(SharedString::from(format!(
@@ -69,6 +159,7 @@ pub fn setup_gui(
 
     // Boot configuration
     set_default_user_from_boot_config(&gui, boot_config_mutex.clone());
+    set_vault_users(&gui);
     {
         let boot_config_mutex = boot_config_mutex.clone();
         let boot_config_guard = boot_config_mutex.lock().unwrap();
@@ -78,13 +169,36 @@ pub fn setup_gui(
         // Activate switches if needed
         gui.set_persistent_rootfs(boot_config_guard.rootfs.persistent_storage);
         gui.set_recovery_features(boot_config_guard.system.recovery_features);
-        match boot_config_guard.system.initial_screen_rotation {
-            ScreenRotation::Cw0 => gui.set_orientations_list_index(0),
-            ScreenRotation::Cw90 => gui.set_orientations_list_index(1),
-            ScreenRotation::Cw180 => gui.set_orientations_list_index(2),
-            ScreenRotation::Cw270 => gui.set_orientations_list_index(3),
+
+        // Language
+        let languages: Vec<SharedString> = Language::ALL
+            .iter()
+            .map(|language| SharedString::from(language.name()))
+            .collect();
+        gui.set_languages_list(slint::ModelRc::new(slint::VecModel::from(languages)));
+        show_language(&gui, boot_config_guard.system.language);
+        show_ui_scale(&gui, boot_config_guard.system.ui_scale);
+        show_theme(&gui, boot_config_guard.system.theme);
+        let keyboard_layouts: Vec<SharedString> = KeyboardLayout::ALL
+            .iter()
+            .map(|keyboard_layout| SharedString::from(keyboard_layout.name()))
+            .collect();
+        gui.set_keyboard_layouts_list(slint::ModelRc::new(slint::VecModel::from(keyboard_layouts)));
+        show_keyboard_layout(&gui, boot_config_guard.system.keyboard_layout);
+        gui.set_hostname(SharedString::from(system::hostname(&boot_config_guard)));
+        if let Some(active_slot) = boot_config_guard.rootfs.active_slot {
+            gui.set_rootfs_slot(SharedString::from(format!("{:?}", &active_slot)));
+            gui.set_rootfs_other_slot_available(
+                rootfs::available_slots().contains(&active_slot.other()),
+            );
         }
+        // Service controls are only meant for development builds
+        gui.set_developer_mode(cfg!(feature = "debug"));
+        gui.set_orientations_list_index(rotation_list_index(
+            &boot_config_guard.system.initial_screen_rotation,
+        ));
         gui.set_original_orientations_list_index(gui.get_orientations_list_index());
+        gui.set_recovery_passcode_set(boot_config_guard.system.recovery_passcode_hash.is_some());
 
         // Splash wallpaper settings
         {
@@ -113,27 +227,120 @@ pub fn setup_gui(
         }
 
         // Timezones
-        {
-            let timezones_vec = system::get_timezones_list()?;
-            let timezones_slint_vec: Vec<SharedString> = timezones_vec
-                .iter()
-                .map(|entry| SharedString::from(entry))
-                .collect();
-            gui.set_timezones_list(slint::ModelRc::new(slint::VecModel::from(
-                timezones_slint_vec,
-            )));
-            let index = timezones_vec
-                .iter()
-                .position(|name| name == &boot_config_guard.system.timezone)
-                .map(|i| i as i32);
-            if let Some(i) = index {
-                gui.set_timezones_list_index(i);
+        // A broken time zone database only leaves the list empty
+        match system::get_timezones_list() {
+            Ok(timezones_vec) => {
+                let timezones_slint_vec: Vec<SharedString> = timezones_vec
+                    .iter()
+                    .map(|entry| SharedString::from(entry))
+                    .collect();
+                gui.set_timezones_list(slint::ModelRc::new(slint::VecModel::from(
+                    timezones_slint_vec,
+                )));
             }
+            Err(e) => warn!("Failed to list timezones: {:?}", &e),
         }
+        gui.set_current_timezone(SharedString::from(&boot_config_guard.system.timezone));
     }
 
     // Channels
     let (set_page_sender, set_page_receiver): (Sender<Page>, Receiver<Page>) = channel();
+    let (notification_sender, notification_receiver): (
+        Sender<Notification>,
+        Receiver<Notification>,
+    ) = channel();
+    notifier::register(Box::new(ChannelNotifier::new(notification_sender.clone())));
+
+    // Raised before the GUI existed: only the first fatal error matters since it blocks the UI until the next boot
+    let mut early_fatal_error = None;
+    for early_event in notifier::EARLY_EVENTS.drain() {
+        match early_event {
+            EarlyEvent::Toast(message) => {
+                let _ = notification_sender.send(Notification::Toast(message));
+            }
+            EarlyEvent::StickyToast(message) => {
+                let _ = notification_sender.send(Notification::StickyToast(message));
+            }
+            EarlyEvent::FatalError(error_reason) => {
+                early_fatal_error.get_or_insert(error_reason);
+            }
+        }
+    }
+
+    if let Some(issue) = system::boot_partition_write_issue() {
+        notifier::get().sticky_toast(&issue.notice());
+    }
+    if let Some(notice) = rootfs::slot_fallback_notice() {
+        notifier::get().sticky_toast(&notice);
+    }
+    let filesystem_corruption = system::filesystem_corruption();
+    for corrupted in &filesystem_corruption {
+        notifier::get().sticky_toast(&corrupted.to_string());
+    }
+    let partition_usages = system::last_disk_usage();
+    let low_space_warnings: Vec<String> = partition_usages
+        .iter()
+        .filter_map(|partition_usage| partition_usage.low_space_warning())
+        .collect();
+    if !low_space_warnings.is_empty() {
+        notifier::get().toast(&low_space_warnings.join("\n"));
+    }
+    gui.set_storage_summary(SharedString::from(
+        partition_usages
+            .iter()
+            .map(|partition_usage| {
+                format!(
+                    "{}: {}",
+                    &partition_usage.name,
+                    &partition_usage.usage.summary()
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n"),
+    ));
+
+    // Touch input
+    let (navigation_sender, navigation_receiver): (
+        Sender<input::NavigationAction>,
+        Receiver<input::NavigationAction>,
+    ) = channel();
+    if let Some(input_probe) = input::last_probe() {
+        input::spawn_activity_monitor(&input_probe);
+        // The probe already warned about missing devices
+        if input_probe.touchscreen.is_none() {
+            if let Some(power_button) = &input_probe.power_button {
+                if let Err(e) =
+                    input::spawn_power_button_navigation(&power_button, navigation_sender)
+                {
+                    error!("Failed to enable power button navigation: {}", &e);
+                }
+            } else {
+                error!("No power button found: navigation fallback is unavailable");
+            }
+        }
+    }
+    let navigation_timer = Timer::default();
+    navigation_timer.start(
+        TimerMode::Repeated,
+        std::time::Duration::from_millis(100),
+        {
+            let gui_weak = gui_weak.clone();
+            move || {
+                if let Some(gui) = gui_weak.upgrade() {
+                    while let Ok(action) = navigation_receiver.try_recv() {
+                        let key: SharedString = match action {
+                            input::NavigationAction::FocusNext => Key::Tab.into(),
+                            input::NavigationAction::Activate => Key::Return.into(),
+                        };
+                        gui.window()
+                            .dispatch_event(WindowEvent::KeyPressed { text: key.clone() });
+                        gui.window()
+                            .dispatch_event(WindowEvent::KeyReleased { text: key });
+                    }
+                }
+            }
+        },
+    );
     let (wifi_status_sender, wifi_status_receiver): (Sender<wifi::Status>, Receiver<wifi::Status>) =
         channel();
     let (wifi_command_sender, wifi_command_receiver): (
@@ -141,10 +348,24 @@ pub fn setup_gui(
         Receiver<wifi::CommandForm>,
     ) = channel();
 
-    // Guard that ensures that no one can set a page if the current one is Page::Error
+    // Locked from the start in recovery mode if there is a passcode
+    let recovery_gate = Arc::new(Mutex::new(RecoveryGate {
+        unlocked: !(boot_selection == BootSelection::Recovery
+            && boot_config_mutex
+                .lock()
+                .unwrap()
+                .system
+                .recovery_passcode_hash
+                .is_some()),
+        failed_attempts: 0,
+        prompting: false,
+    }));
+
+    // Guard that ensures that no one can set a page if the current one is Page::Error, or get past the recovery passcode prompt
     let page_timer = Timer::default();
     page_timer.start(TimerMode::Repeated, std::time::Duration::from_millis(20), {
         let gui_weak = gui_weak.clone();
+        let recovery_gate = recovery_gate.clone();
         move || {
             if let Some(gui) = gui_weak.upgrade() {
                 if let Ok(page) = set_page_receiver.try_recv() {
@@ -154,6 +375,8 @@ pub fn setup_gui(
                     );
                     if gui.get_page() == Page::Error {
                         error!("Denying request: current page is '{:?}'", Page::Error);
+                    } else if !recovery_gate.lock().unwrap().allows(page) {
+                        error!("Denying request: recovery menu is locked");
                     } else {
                         gui.set_page(page);
                     }
@@ -173,7 +396,8 @@ pub fn setup_gui(
     gui.set_quill_recovery(boot_selection == BootSelection::Recovery);
 
     if boot_config_valid {
-        if boot_selection == BootSelection::Recovery {
+        // Booting normally from a partition that e2fsck could not fix would only make it worse
+        if boot_selection == BootSelection::Recovery || !filesystem_corruption.is_empty() {
             info!("Showing QuillBoot menu");
             thread::spawn(|| {
                 brightness::set_brightness_unified(
@@ -181,7 +405,11 @@ pub fn setup_gui(
                     &libqinit::brightness::MAX_BRIGHTNESS / 2 as i32,
                 )
             });
-            set_page_sender.send(Page::QuillBoot)?;
+            if recovery_gate.lock().unwrap().unlocked {
+                set_page_sender.send(Page::QuillBoot)?;
+            } else {
+                set_page_sender.send(Page::RecoveryPasscode)?;
+            }
         } else if boot_selection == BootSelection::NetBoot {
             info!("Showing NetBoot GUI");
             set_page_sender.send(Page::NetBoot)?;
@@ -193,6 +421,7 @@ pub fn setup_gui(
                 &gui,
                 &boot_sender,
                 &set_page_sender,
+                &shutdown_scheduler,
                 &gui.get_default_user().to_string(),
                 first_boot_done,
                 login_credentials_sender,
@@ -249,6 +478,27 @@ pub fn setup_gui(
         },
     );
 
+    // Boot phase and time left, shown on the boot splash
+    let boot_status_timer = Timer::default();
+    boot_status_timer.start(TimerMode::Repeated, Duration::from_millis(100), {
+        let gui_weak = gui_weak.clone();
+        move || {
+            while let Ok(boot_status) = boot_status_receiver.try_recv() {
+                let Some(gui) = gui_weak.upgrade() else {
+                    return;
+                };
+                match boot_status {
+                    BootStatus::Phase(phase) => {
+                        gui.set_boot_phase(SharedString::from(tr(phase.label())))
+                    }
+                    BootStatus::TimeLeft(time_left) => {
+                        gui.set_boot_time_left(SharedString::from(time_left_text(time_left)))
+                    }
+                }
+            }
+        }
+    });
+
     // Timer to show toasts from external threads/classes
     let toast_timer = Timer::default();
     toast_timer.start(
@@ -266,6 +516,196 @@ pub fn setup_gui(
         },
     );
 
+    // Notifications from library code
+    let pending_confirmation: Rc<RefCell<Option<Sender<bool>>>> = Rc::new(RefCell::new(None));
+    let pending_pin_prompt: Rc<RefCell<Option<PinPrompt>>> = Rc::new(RefCell::new(None));
+    let notification_timer = Timer::default();
+    notification_timer.start(TimerMode::Repeated, Duration::from_millis(100), {
+        let gui_weak = gui_weak.clone();
+        let pending_confirmation = pending_confirmation.clone();
+        let pending_pin_prompt = pending_pin_prompt.clone();
+        move || {
+            // Keep confirmations and PIN prompts queued until the current one is answered
+            if pending_confirmation.borrow().is_some() || pending_pin_prompt.borrow().is_some() {
+                return;
+            }
+            // An open action confirmation dialog is answered first
+            if gui_weak
+                .upgrade()
+                .is_some_and(|gui| gui.get_dialog() == DialogType::ActionConfirmation)
+            {
+                return;
+            }
+            if let Ok(notification) = notification_receiver.try_recv() {
+                if let Some(gui) = gui_weak.upgrade() {
+                    match notification {
+                        Notification::Toast(message) => toast(&gui, &message),
+                        Notification::StickyToast(message) => sticky_toast(&gui, &message),
+                        Notification::Confirmation(message, answer_sender) => {
+                            *pending_confirmation.borrow_mut() = Some(answer_sender);
+                            gui.set_dialog_message(SharedString::from(message));
+                            gui.set_dialog(DialogType::Confirmation);
+                        }
+                        Notification::PinEntry(request, answer_sender) => {
+                            let pin_prompt = PinPrompt::new(request.max_length, answer_sender);
+                            pin_prompt.show(&gui, &request);
+                            *pending_pin_prompt.borrow_mut() = Some(pin_prompt);
+                        }
+                    }
+                }
+            }
+        }
+    });
+
+    gui.on_answer_confirmation({
+        let pending_confirmation = pending_confirmation.clone();
+        guarded!("answer_confirmation", move |answer| {
+            if let Some(answer_sender) = pending_confirmation.borrow_mut().take() {
+                info!("Confirmation answered: {}", &answer);
+                let _ = answer_sender.send(answer);
+            }
+        })
+    });
+
+    // Destructive actions
+    gui.on_request_confirmation({
+        let gui_weak = gui_weak.clone();
+        guarded!("request_confirmation", move |action| {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            let (title, message, confirm_text, busy_message) = match action {
+                ConfirmableAction::SoftReset => (
+                    tr("Soft-reset this device"),
+                    tr(
+                        "This will erase all of the user data on this device and reset settings to default, without reinstalling the firmware. Are you sure you want to continue?",
+                    ),
+                    tr("Soft reset"),
+                    tr("Soft reset in progress"),
+                ),
+                ConfirmableAction::ResetWriteLayer => (
+                    tr("Discard root filesystem changes"),
+                    tr(
+                        "This will discard every change made to the root filesystem (installed packages, system configuration and logs). As this goes through a soft reset, user data will be erased and settings will be reset to default too. Are you sure you want to continue?",
+                    ),
+                    tr("Reset write layer"),
+                    tr("Soft reset in progress"),
+                ),
+                ConfirmableAction::TogglePersistentRootfs => {
+                    if gui.get_persistent_rootfs() {
+                        (
+                            tr("Disable persistent root filesystem"),
+                            tr(
+                                "From the next boot, root filesystem changes will only be kept in memory and dropped on every reboot. Are you sure you want to continue?",
+                            ),
+                            tr("Disable"),
+                            String::new(),
+                        )
+                    } else {
+                        (
+                            tr("Enable persistent root filesystem"),
+                            tr(
+                                "From the next boot, root filesystem changes will be kept on persistent storage. Are you sure you want to continue?",
+                            ),
+                            tr("Enable"),
+                            String::new(),
+                        )
+                    }
+                }
+                ConfirmableAction::EraseUserData => (
+                    tr_format("Erase data of '{}'", &[gui.get_encryption_user().as_str()]),
+                    tr(
+                        "Every file of this user will be destroyed for good, without any way to get it back. Are you sure you want to continue?",
+                    ),
+                    tr("Erase data"),
+                    tr("Erasing user data"),
+                ),
+                ConfirmableAction::None => return,
+            };
+            show_action_confirmation(
+                &gui,
+                action,
+                &title,
+                &message,
+                &confirm_text,
+                &busy_message,
+            );
+        })
+    });
+
+    gui.on_confirm_action({
+        let gui_weak = gui_weak.clone();
+        let boot_config_mutex = boot_config_mutex.clone();
+        guarded!("confirm_action", move |action| {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            info!("Action confirmed: {:?}", &action);
+            match action {
+                // The dialog stays up, busy, until the device reboots
+                ConfirmableAction::SoftReset | ConfirmableAction::ResetWriteLayer => {
+                    gui.invoke_soft_reset()
+                }
+                ConfirmableAction::TogglePersistentRootfs => {
+                    gui.invoke_toggle_persistent_rootfs();
+                    close_action_confirmation(&gui);
+                }
+                ConfirmableAction::EraseUserData => erase_user_data(
+                    &gui,
+                    &boot_config_mutex,
+                    gui.get_encryption_user().to_string(),
+                ),
+                ConfirmableAction::None => close_action_confirmation(&gui),
+            }
+        })
+    });
+
+    gui.on_pin_digit({
+        let gui_weak = gui_weak.clone();
+        let pending_pin_prompt = pending_pin_prompt.clone();
+        guarded!("pin_digit", move |digit| {
+            if let Some(gui) = gui_weak.upgrade()
+                && let Some(pin_prompt) = pending_pin_prompt.borrow_mut().as_mut()
+            {
+                pin_prompt.push_digit(&gui, digit);
+            }
+        })
+    });
+
+    gui.on_pin_backspace({
+        let gui_weak = gui_weak.clone();
+        let pending_pin_prompt = pending_pin_prompt.clone();
+        guarded!("pin_backspace", move || {
+            if let Some(gui) = gui_weak.upgrade()
+                && let Some(pin_prompt) = pending_pin_prompt.borrow_mut().as_mut()
+            {
+                pin_prompt.pop_digit(&gui);
+            }
+        })
+    });
+
+    gui.on_pin_submit({
+        let gui_weak = gui_weak.clone();
+        let pending_pin_prompt = pending_pin_prompt.clone();
+        guarded!("pin_submit", move || {
+            if let Some(gui) = gui_weak.upgrade()
+                && let Some(pin_prompt) = pending_pin_prompt.borrow_mut().take()
+            {
+                gui.set_dialog(DialogType::None);
+                pin_prompt.submit();
+            }
+        })
+    });
+
+    gui.on_pin_cancel({
+        let pending_pin_prompt = pending_pin_prompt.clone();
+        guarded!("pin_cancel", move || {
+            if let Some(pin_prompt) = pending_pin_prompt.borrow_mut().take() {
+                pin_prompt.cancel();
+            }
+        })
+    });
+
     let login_page_trigger_timer = Timer::default();
     login_page_trigger_timer.start(TimerMode::Repeated, Duration::from_millis(100), {
         let set_page_sender = set_page_sender.clone();
@@ -296,11 +736,14 @@ pub fn setup_gui(
                             let future_count = current_count + toast_gc_delay;
                             if future_count > TOAST_DURATION_MILLIS {
                                 gui.set_dialog_millis_count(0);
-                                gui.set_dialog(DialogType::None);
+                                show_next_toast(&gui);
                             } else {
                                 gui.set_dialog_millis_count(future_count);
                             }
                         }
+                    } else if gui.get_dialog() == DialogType::None {
+                        // Tapping a toast away moves on to the next one
+                        show_next_toast(&gui);
                     }
                 }
             }
@@ -350,6 +793,21 @@ pub fn setup_gui(
     );
 
     // Fatal errors
+    gui.on_show_debug_qr_code({
+        let gui_weak = gui_weak.clone();
+        guarded!("show_debug_qr_code", move |index| {
+            if let Some(gui) = gui_weak.upgrade() {
+                if let Some(image) =
+                    DEBUG_QR_CODES.with_borrow(|images| images.get(index as usize).cloned())
+                {
+                    gui.set_debug_qr_code(image);
+                    gui.set_debug_qr_code_index(index);
+                }
+            }
+        })
+    });
+
+    let qr_max_modules = get_qr_max_modules(&boot_config_mutex);
     let interrupt_timer = Timer::default();
     let interrupt_timer_delay = 100;
     interrupt_timer.start(
@@ -359,30 +817,23 @@ pub fn setup_gui(
             let gui_weak = gui_weak.clone();
             let set_page_sender = set_page_sender.clone();
             move || {
-                if let Ok(error_reason) = interrupt_receiver.try_recv() {
+                if let Some(error_reason) = early_fatal_error
+                    .take()
+                    .or_else(|| interrupt_receiver.try_recv().ok())
+                {
                     if let Some(gui) = gui_weak.upgrade() {
-                        let mut program_output = String::new();
-                        let mut kernel_buffer = String::new();
-                        let qinit_log_file_path =
-                            format!("{}/{}", &crate::QINIT_LOG_DIR, &crate::QINIT_LOG_FILE);
-                        let lines_to_keep_ui = 150;
-
-                        if let Ok(contents) = fs::read_to_string(&qinit_log_file_path) {
-                            program_output = contents.clone();
-                            let stripped_program_output =
-                                keep_last_lines(&contents, lines_to_keep_ui);
-                            gui.set_program_output(SharedString::from(&stripped_program_output));
-                        } else {
-                            gui.set_program_output(SharedString::from(NOT_AVAILABLE));
+                        let mut error_reason = secret::scrub(&error_reason);
+                        // Likely related to the error, if not its cause
+                        if let Some(issue) = system::boot_partition_write_issue() {
+                            error_reason.push_str(&format!("\nNote: {}", &issue.notice()));
                         }
-
-                        if let Ok(contents) = read_kernel_buffer_singleshot() {
-                            kernel_buffer = contents.clone();
-                            let stripped_kernel_buffer =
-                                keep_last_lines(&contents, lines_to_keep_ui);
-                            gui.set_kernel_buffer(SharedString::from(&stripped_kernel_buffer));
-                        } else {
-                            gui.set_kernel_buffer(SharedString::from(NOT_AVAILABLE));
+                        // Phases still running at this point are the likely culprits
+                        let boot_timing =
+                            system::boot_timing_summary(&system::boot_timing_report());
+                        info!("{}", &boot_timing);
+                        gui.set_boot_timing(SharedString::from(&boot_timing));
+                        if let Some(failure) = signing::last_signature_failure() {
+                            gui.set_signature_failure_path(SharedString::from(&failure.path));
                         }
 
                         if let Ok(qr_code_svg) = qrcode_generator::to_svg_to_string(
@@ -398,68 +849,40 @@ pub fn setup_gui(
                             }
                         }
 
-                        // Algorithm to find what number of lines to keep to fit the QR code
-                        let mut lines_to_keep_qr = 100;
-                        let mut compressed_size = 0;
-                        let mut compressed_data = vec![];
-                        // Yes, it is very specific: one more byte, and the QR code seems to shrink
-                        let ideal_size = 2563;
-                        info!("Attempting to optimize QR code data");
-                        loop {
-                            if compressed_size == 0 || compressed_size >= ideal_size {
-                                let mut qr_code_string = String::new();
-                                qr_code_string.push_str(&error_reason);
-                                qr_code_string.push_str("\n\n");
-                                qr_code_string
-                                    .push_str(&keep_last_lines(&program_output, lines_to_keep_qr));
-                                qr_code_string.push_str("\n\n");
-                                qr_code_string
-                                    .push_str(&keep_last_lines(&kernel_buffer, lines_to_keep_qr));
-                                if let Ok(data) = compress_string_to_xz(&qr_code_string) {
-                                    compressed_size = data.len();
-                                    if compressed_size <= ideal_size {
-                                        info!("Keeping {} lines from each logging source for a total of {} compressed bytes", &lines_to_keep_qr, &compressed_size);
-                                        compressed_data = data;
-                                        break;
-                                    } else {
-                                        lines_to_keep_qr -= 1;
-                                    }
-                                } else {
-                                    break;
-                                }
-                            }
-                        }
-
-                        let mut set_not_available = false;
-                        if !compressed_data.is_empty() {
-                            if let Ok(qr_code_svg) = qrcode_generator::to_svg_to_string(
-                                &compressed_data,
-                                QrCodeEcc::Low,
-                                1024,
-                                None::<&str>,
-                            ) {
-                                if let Ok(debug_qr_code) =
-                                    Image::load_from_svg_data(&qr_code_svg.as_bytes())
-                                {
-                                    gui.set_debug_tab_index(QR_CODE_TAB_INDEX);
-                                    gui.set_qr_code_page(QrCodePage::QrCode);
-                                    gui.set_debug_qr_code(debug_qr_code);
-                                }
-                            } else {
-                                set_not_available = true;
-                            }
-                        } else {
-                            set_not_available = true;
-                        }
-
-                        if set_not_available {
-                            gui.set_debug_tab_index(QR_CODE_NOT_AVAILABLE_TAB_INDEX);
-                            gui.set_qr_code_page(QrCodePage::NotAvailable);
-                        }
-
+                        // Shown right away: logs and QR codes fill in as the worker thread gets them ready
+                        gui.set_program_output(SharedString::from(COLLECTING_DETAILS));
+                        gui.set_kernel_buffer(SharedString::from(COLLECTING_DETAILS));
+                        gui.set_debug_tab_index(QR_CODE_TAB_INDEX);
+                        gui.set_qr_code_page(QrCodePage::Collecting);
+                        DEBUG_QR_CODES.set(Vec::new());
                         gui.set_short_version_string(SharedString::from(&short_version_string));
                         gui.set_error_reason(SharedString::from(&format!("{}", &error_reason)));
                         let _ = set_page_sender.send(Page::Error);
+
+                        let gui_weak = gui_weak.clone();
+                        let summary = [short_version_string.clone(), error_reason, boot_timing];
+                        thread::spawn(move || {
+                            if panic::catch_unwind(AssertUnwindSafe(|| {
+                                collect_error_details(&gui_weak, &summary, qr_max_modules)
+                            }))
+                            .is_err()
+                            {
+                                error!("Failed to collect fatal error details");
+                            }
+                            // Whatever the worker did not get to is not available, rather than collecting forever
+                            let _ = gui_weak.upgrade_in_event_loop(|gui| {
+                                if gui.get_program_output().as_str() == COLLECTING_DETAILS {
+                                    gui.set_program_output(SharedString::from(NOT_AVAILABLE));
+                                }
+                                if gui.get_kernel_buffer().as_str() == COLLECTING_DETAILS {
+                                    gui.set_kernel_buffer(SharedString::from(NOT_AVAILABLE));
+                                }
+                                if gui.get_qr_code_page() == QrCodePage::Collecting {
+                                    gui.set_debug_tab_index(QR_CODE_NOT_AVAILABLE_TAB_INDEX);
+                                    gui.set_qr_code_page(QrCodePage::NotAvailable);
+                                }
+                            });
+                        });
                     }
                 }
             }
@@ -482,10 +905,24 @@ pub fn setup_gui(
                 Image::load_from_svg_data(include_bytes!("../../icons/wifi-connected.svg"))?;
             let wifi_error_icon =
                 Image::load_from_svg_data(include_bytes!("../../icons/wifi-error.svg"))?;
+            let wifi_connected_low_icon =
+                Image::load_from_svg_data(include_bytes!("../../icons/wifi-connected-low.svg"))?;
+            let wifi_connected_medium_icon =
+                Image::load_from_svg_data(include_bytes!("../../icons/wifi-connected-medium.svg"))?;
+            let wifi_connected_high_icon =
+                Image::load_from_svg_data(include_bytes!("../../icons/wifi-connected-high.svg"))?;
             let mut hold_wifi_locks = false;
+            let mut status_sequencer = wifi::StatusSequencer::default();
             move || {
                 if let Ok(wifi_status) = wifi_status_receiver.try_recv() {
                     info!("Received new Wi-Fi status: {:?}", &wifi_status);
+                    if !status_sequencer.accept(&wifi_status) {
+                        info!(
+                            "Dropping stale Wi-Fi status for command {}",
+                            &wifi_status.sequence
+                        );
+                        return;
+                    }
                     if let Some(gui) = gui_weak.upgrade() {
                         match wifi_status.status_type {
                             wifi::StatusType::Disabled => {
@@ -501,12 +938,14 @@ pub fn setup_gui(
                             wifi::StatusType::Connected => {
                                 gui.set_wifi_enabled(true);
                                 gui.set_wifi_connected(true);
-                                if let Ok(ip_address) =
-                                    networking::get_if_ip_address(&wifi::WIFI_IF)
-                                {
-                                    gui.set_wifi_ip_address(SharedString::from(&ip_address));
-                                }
-                                gui.set_wifi_icon(wifi_connected_icon.to_owned());
+                                // Strength is unknown when the driver does not report it
+                                let wifi_icon = match wifi_status.signal_level() {
+                                    Some(wifi::SignalLevel::Low) => &wifi_connected_low_icon,
+                                    Some(wifi::SignalLevel::Medium) => &wifi_connected_medium_icon,
+                                    Some(wifi::SignalLevel::High) => &wifi_connected_high_icon,
+                                    None => &wifi_connected_icon,
+                                };
+                                gui.set_wifi_icon(wifi_icon.to_owned());
                             }
                             wifi::StatusType::Error => {
                                 gui.set_wifi_connected(false);
@@ -516,16 +955,25 @@ pub fn setup_gui(
                                     toast(&gui, &error);
                                 }
                             }
+                            wifi::StatusType::Suspended => {
+                                gui.set_wifi_connected(false);
+                                gui.set_wifi_enabled(true);
+                                gui.set_wifi_icon(wifi_not_connected_icon.to_owned());
+                            }
                         }
+                        let suspended = wifi_status.status_type == wifi::StatusType::Suspended;
+                        gui.set_wifi_suspended(suspended);
+                        // The panel may have been opened while the daemon was powering down: its wake-up command is queued right behind
+                        gui.set_wifi_waking_lock(suspended && gui.get_wifi_panel_open());
 
                         if wifi_status.list.is_none()
                             && wifi_status.status_type != wifi::StatusType::Disabled
+                            && !suspended
                         {
                             // Trigger networks scan
-                            if let Err(e) = wifi_command_sender.send(wifi::CommandForm {
-                                command_type: wifi::CommandType::GetNetworks,
-                                arguments: None,
-                            }) {
+                            if let Err(e) = wifi_command_sender
+                                .send(wifi::CommandForm::new(wifi::CommandType::GetNetworks, None))
+                            {
                                 error_toast(&gui, "Failed to get networks list", e.into());
                             }
                             gui.set_wifi_scanning_lock(true);
@@ -583,17 +1031,14 @@ pub fn setup_gui(
 
     thread::spawn(|| wifi::daemon(wifi_status_sender, wifi_command_receiver));
     // Set initial Wi-Fi icon
-    wifi_command_sender.send(wifi::CommandForm {
-        command_type: wifi::CommandType::GetStatus,
-        arguments: None,
-    })?;
+    wifi_command_sender.send(wifi::CommandForm::new(wifi::CommandType::GetStatus, None))?;
 
     // System
     gui.on_power_off({
         let boot_sender = boot_sender.clone();
         let gui_weak = gui_weak.clone();
         let can_shut_down = can_shut_down.clone();
-        move || {
+        guarded!("power_off", move || {
             let shut_down_type = PrimitiveShutDownType::PowerOff;
             if let Some(gui) = gui_weak.upgrade() {
                 set_wallpaper_splash_text(&gui, &shut_down_type);
@@ -618,124 +1063,777 @@ pub fn setup_gui(
                     }
                 }
             }
-        }
+        })
     });
 
-    gui.on_direct_power_off({
+    // Lets the user fix an untrusted artifact (e.g. by copying a new one over) without a full reboot cycle first
+    gui.on_reverify_signature({
         let gui_weak = gui_weak.clone();
-        let can_shut_down = can_shut_down.clone();
-        move || {
-            if let Some(gui) = gui_weak.upgrade() {
-                let power_down_mode = determine_power_down_mode(&gui);
-                if let Err(e) = gui_shut_down(
-                    &gui,
-                    PrimitiveShutDownType::PowerOff,
-                    power_down_mode,
-                    can_shut_down.clone(),
-                ) {
-                    error_toast(&gui, "Failed to power off", e.into());
-                }
+        guarded!("reverify_signature", move || {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            let path = gui.get_signature_failure_path().to_string();
+            toast(&gui, "Verifying signature again");
+            let gui_weak = gui_weak.clone();
+            thread::spawn(move || {
+                signing::invalidate_cached_verdicts(&path);
+                let result = signing::read_public_key()
+                    .and_then(|pubkey| signing::check_signature(&pubkey, &path));
+                let _ = gui_weak.upgrade_in_event_loop(move |gui| match result {
+                    Ok(true) => {
+                        toast(&gui, "Signature is now valid: rebooting");
+                        gui.invoke_standard_reboot();
+                    }
+                    Ok(false) => toast(&gui, "Signature is still invalid"),
+                    Err(e) => error_toast(&gui, "Failed to verify signature", e),
+                });
+            });
+        })
+    });
+
+    // Everything the QR codes had to leave out
+    gui.on_save_full_report({
+        let gui_weak = gui_weak.clone();
+        guarded!("save_full_report", move || {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            let error_reason = gui.get_error_reason().to_string();
+            let version_string = gui.get_version_string().to_string();
+            toast(&gui, "Saving full report");
+            gui.set_enable_ui(false);
+            let gui_weak = gui_weak.clone();
+            thread::spawn(move || {
+                let result = diagnostics::save_report(&diagnostics::Report {
+                    error_reason: Some(&error_reason),
+                    version_string: &version_string,
+                    qinit_log_path: &format!(
+                        "{}/{}",
+                        &crate::QINIT_LOG_DIR,
+                        &crate::QINIT_LOG_FILE
+                    ),
+                });
+                let _ = gui_weak.upgrade_in_event_loop(move |gui| {
+                    gui.set_enable_ui(true);
+                    match result {
+                        Ok(location) => {
+                            toast(&gui, &tr_format("Full report saved to {}", &[&location]))
+                        }
+                        Err(e) => error_toast(&gui, "Failed to save full report", e),
+                    }
+                });
+            });
+        })
+    });
+
+    #[cfg(feature = "debug")]
+    gui.on_dump_public_key({
+        let gui_weak = gui_weak.clone();
+        guarded!("dump_public_key", move || {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            if !gui.get_developer_mode() {
+                return;
             }
-        }
+            let path = format!("{}/public.pem", &libqinit::BOOT_PART_MOUNTPOINT);
+            match signing::dump_public_key(&path) {
+                Ok(()) => toast(&gui, "Public key was exported to the boot partition"),
+                Err(e) => error_toast(&gui, "Failed to export public key", e),
+            }
+        })
     });
 
-    gui.on_reboot({
-        let boot_sender = boot_sender.clone();
-        let can_shut_down = can_shut_down.clone();
+    // Refreshed on demand only: the developer panel is rarely open
+    gui.on_refresh_services({
         let gui_weak = gui_weak.clone();
-        move || {
-            let shut_down_type = PrimitiveShutDownType::Reboot;
+        guarded!("refresh_services", move || {
             if let Some(gui) = gui_weak.upgrade() {
-                set_wallpaper_splash_text(&gui, &shut_down_type);
-                if let Err(e) = boot_sender.send(BootCommandForm {
-                    command: BootCommand::Reboot,
-                    can_shut_down: Some(can_shut_down.clone()),
-                }) {
-                    let display_error;
-                    if let Err(_e) = gui_shut_down(
-                        &gui,
-                        shut_down_type,
-                        PowerDownMode::Normal,
-                        can_shut_down.clone(),
-                    ) {
-                        display_error = true;
-                    } else {
-                        display_error = false;
+                gui.set_power_on_alarm(power_on_alarm_label());
+            }
+            let gui_weak = gui_weak.clone();
+            thread::spawn(move || {
+                let result = services::get().status();
+                let _ = gui_weak.upgrade_in_event_loop(move |gui| match result {
+                    Ok(statuses) => {
+                        let names: Vec<SharedString> = statuses
+                            .iter()
+                            .map(|status| SharedString::from(&status.name))
+                            .collect();
+                        let states: Vec<SharedString> = statuses
+                            .iter()
+                            .map(|status| {
+                                SharedString::from(format!("{:?}", &status.state).to_lowercase())
+                            })
+                            .collect();
+                        let log_tails: Vec<SharedString> = statuses
+                            .iter()
+                            .map(|status| {
+                                SharedString::from(status.log_tail.as_deref().unwrap_or(""))
+                            })
+                            .collect();
+                        gui.set_service_names(slint::ModelRc::new(slint::VecModel::from(names)));
+                        gui.set_service_states(slint::ModelRc::new(slint::VecModel::from(states)));
+                        gui.set_service_log_tails(slint::ModelRc::new(slint::VecModel::from(
+                            log_tails,
+                        )));
                     }
+                    Err(e) => error_toast(&gui, "Failed to query services", e),
+                });
+            });
+        })
+    });
 
-                    if display_error {
-                        error_toast(&gui, "Failed to reboot", e.into());
-                    }
-                }
+    gui.on_cancel_power_on_alarm({
+        let gui_weak = gui_weak.clone();
+        guarded!("cancel_power_on_alarm", move || {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            if !gui.get_developer_mode() {
+                return;
             }
-        }
+            libqinit::system::cancel_power_on_alarm();
+            gui.set_power_on_alarm(power_on_alarm_label());
+            toast(&gui, "Power-on alarm cancelled");
+        })
     });
 
-    gui.on_direct_reboot({
-        let can_shut_down = can_shut_down.clone();
+    gui.on_restart_service({
         let gui_weak = gui_weak.clone();
-        move || {
-            if let Some(gui) = gui_weak.upgrade() {
-                let power_down_mode = determine_power_down_mode(&gui);
-                if let Err(e) = gui_shut_down(
-                    &gui,
-                    PrimitiveShutDownType::Reboot,
-                    power_down_mode,
-                    can_shut_down.clone(),
-                ) {
-                    error_toast(&gui, "Failed to reboot", e.into());
-                }
+        let last_restarts: Rc<RefCell<HashMap<String, Instant>>> =
+            Rc::new(RefCell::new(HashMap::new()));
+        guarded!("restart_service", move |service| {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            if !gui.get_developer_mode() {
+                return;
             }
-        }
+            let service = service.to_string();
+            if let Some(last_restart) = last_restarts.borrow().get(&service)
+                && last_restart.elapsed() < SERVICE_RESTART_COOLDOWN
+            {
+                toast(&gui, "Please wait before restarting this service again");
+                return;
+            }
+            last_restarts
+                .borrow_mut()
+                .insert(service.clone(), Instant::now());
+
+            toast(&gui, &tr_format("Restarting service '{}'", &[&service]));
+            let gui_weak = gui_weak.clone();
+            thread::spawn(move || {
+                let result = services::get().restart(&service);
+                let _ = gui_weak.upgrade_in_event_loop(move |gui| {
+                    if let Err(e) = result {
+                        error_toast(&gui, "Failed to restart service", e);
+                    }
+                    gui.invoke_refresh_services();
+                });
+            });
+        })
     });
 
-    // Scaling
-    gui.on_toggle_ui_scale({
+    let current_file_browser: Rc<RefCell<Option<(FileBrowser, file_browser::Listing)>>> =
+        Rc::new(RefCell::new(None));
+    gui.on_open_file_browser({
         let gui_weak = gui_weak.clone();
-        move || {
+        let current_file_browser = current_file_browser.clone();
+        guarded!("open_file_browser", move || {
             if let Some(gui) = gui_weak.upgrade() {
-                if gui.get_scaling_factor() == 1.0 {
-                    gui.set_button_scaling_multiplier(0.6);
-                    gui.set_scaling_factor(1.25);
-                } else {
-                    gui.set_button_scaling_multiplier(1.0);
-                    gui.set_scaling_factor(1.0);
+                let file_browser = FileBrowser::new(FilePurpose::Any);
+                refresh_file_browser(&gui, &current_file_browser, file_browser);
+            }
+        })
+    });
+
+    gui.on_file_browser_open({
+        let gui_weak = gui_weak.clone();
+        let current_file_browser = current_file_browser.clone();
+        guarded!("file_browser_open", move |index| {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            let Some((mut file_browser, listing)) = current_file_browser.borrow_mut().take() else {
+                return;
+            };
+            let Some(entry) = listing.entries.get(index as usize).cloned() else {
+                *current_file_browser.borrow_mut() = Some((file_browser, listing));
+                return;
+            };
+            if entry.is_dir {
+                if let Err(e) = file_browser.enter(&entry.path) {
+                    error_toast(&gui, "Cannot open this folder", e);
+                }
+            } else {
+                match file_browser.pick(&entry.path) {
+                    Ok(path) => toast(
+                        &gui,
+                        &tr_format(
+                            "{}\n{} · {}",
+                            &[
+                                &path.display().to_string(),
+                                &entry.size_label(),
+                                &entry.date_label(),
+                            ],
+                        ),
+                    ),
+                    Err(e) => error_toast(&gui, "Cannot open this file", e),
                 }
             }
-        }
+            refresh_file_browser(&gui, &current_file_browser, file_browser);
+        })
     });
 
-    // Boot configuration
-    gui.on_toggle_persistent_rootfs({
-        let boot_config_mutex = boot_config_mutex.clone();
-        move || {
-            let mut locked_boot_config = boot_config_mutex.lock().unwrap();
-            locked_boot_config.rootfs.persistent_storage =
-                !locked_boot_config.rootfs.persistent_storage;
-        }
+    gui.on_file_browser_up({
+        let gui_weak = gui_weak.clone();
+        let current_file_browser = current_file_browser.clone();
+        guarded!("file_browser_up", move || {
+            let Some(gui) = gui_weak.upgrade() else {
+                return false;
+            };
+            let Some((mut file_browser, _)) = current_file_browser.borrow_mut().take() else {
+                return false;
+            };
+            if !file_browser.back() {
+                return false;
+            }
+            refresh_file_browser(&gui, &current_file_browser, file_browser);
+
+            true
+        })
+    });
+
+    gui.on_file_browser_change_page({
+        let gui_weak = gui_weak.clone();
+        let current_file_browser = current_file_browser.clone();
+        guarded!("file_browser_change_page", move |delta| {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            let Some((mut file_browser, _)) = current_file_browser.borrow_mut().take() else {
+                return;
+            };
+            if delta > 0 {
+                file_browser.next_page();
+            } else {
+                file_browser.previous_page();
+            }
+            refresh_file_browser(&gui, &current_file_browser, file_browser);
+        })
+    });
+
+    // Program output and kernel buffer, as last read
+    let logs: Rc<RefCell<(String, String)>> = Rc::new(RefCell::new((String::new(), String::new())));
+    gui.on_refresh_logs({
+        let gui_weak = gui_weak.clone();
+        let logs = logs.clone();
+        guarded!("refresh_logs", move || {
+            if let Some(gui) = gui_weak.upgrade() {
+                *logs.borrow_mut() = read_logs();
+                gui.set_logs_page(0);
+                show_logs(&gui, &logs.borrow());
+            }
+        })
+    });
+
+    gui.on_logs_change_page({
+        let gui_weak = gui_weak.clone();
+        let logs = logs.clone();
+        guarded!("logs_change_page", move |delta| {
+            if let Some(gui) = gui_weak.upgrade() {
+                let page = (gui.get_logs_page() + delta).clamp(0, gui.get_logs_pages_count() - 1);
+                gui.set_logs_page(page);
+                show_logs(&gui, &logs.borrow());
+            }
+        })
+    });
+
+    gui.on_change_logs_tab({
+        let gui_weak = gui_weak.clone();
+        let logs = logs.clone();
+        guarded!("change_logs_tab", move |index| {
+            if let Some(gui) = gui_weak.upgrade() {
+                gui.set_logs_tab_index(index);
+                gui.set_logs_page(0);
+                show_logs(&gui, &logs.borrow());
+            }
+        })
+    });
+
+    gui.on_save_logs({
+        let gui_weak = gui_weak.clone();
+        let logs = logs.clone();
+        guarded!("save_logs", move || {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            // USB drives first: the data partition is only reachable again once Quill OS has booted
+            let destination_dir = file_browser::usb_drives()
+                .into_iter()
+                .next()
+                .map(|root| root.path)
+                .unwrap_or_else(|| Path::new(&libqinit::MAIN_PART_MOUNTPOINT).to_path_buf());
+            let (program_output, kernel_buffer) = &*logs.borrow();
+            match system::save_logs(
+                &destination_dir,
+                &[
+                    (crate::QINIT_LOG_FILE, program_output.as_str()),
+                    ("kernel.log", kernel_buffer.as_str()),
+                ],
+            ) {
+                Ok(logs_dir_path) => toast(
+                    &gui,
+                    &tr_format("Logs saved to {}", &[&logs_dir_path.display().to_string()]),
+                ),
+                Err(e) => error_toast(&gui, "Failed to save logs", e),
+            }
+        })
+    });
+
+    gui.on_take_screenshot({
+        let gui_weak = gui_weak.clone();
+        guarded!("take_screenshot", move || {
+            if let Some(gui) = gui_weak.upgrade() {
+                match screenshot::take() {
+                    Ok(screenshot_path) => toast(
+                        &gui,
+                        &tr_format(
+                            "Screenshot saved to {}",
+                            &[&screenshot_path.display().to_string()],
+                        ),
+                    ),
+                    Err(e) => error_toast(&gui, "Failed to take screenshot", e),
+                }
+            }
+        })
+    });
+
+    gui.on_schedule_power_off({
+        let gui_weak = gui_weak.clone();
+        let shutdown_scheduler = shutdown_scheduler.clone();
+        guarded!("schedule_power_off", move |minutes| {
+            if let Some(gui) = gui_weak.upgrade() {
+                gui.set_scheduled_power_off_minutes(minutes);
+                toast(
+                    &gui,
+                    &tr_format(
+                        "Device will power off in {} minutes",
+                        &[&minutes.to_string()],
+                    ),
+                );
+            }
+            let gui_weak = gui_weak.clone();
+            shutdown_scheduler.schedule(Duration::from_secs(minutes as u64 * 60), move || {
+                let _ = gui_weak.upgrade_in_event_loop(|gui| {
+                    gui.set_scheduled_power_off_minutes(0);
+                    gui.invoke_standard_power_off();
+                });
+            });
+        })
+    });
+
+    gui.on_cancel_scheduled_power_off({
+        let gui_weak = gui_weak.clone();
+        let shutdown_scheduler = shutdown_scheduler.clone();
+        guarded!("cancel_scheduled_power_off", move || {
+            shutdown_scheduler.cancel();
+            if let Some(gui) = gui_weak.upgrade() {
+                gui.set_scheduled_power_off_minutes(0);
+                toast(&gui, "Scheduled power off cancelled");
+            }
+        })
+    });
+
+    // Countdown shown in the status bar
+    let shutdown_countdown_timer = Timer::default();
+    shutdown_countdown_timer.start(TimerMode::Repeated, std::time::Duration::from_secs(1), {
+        let gui_weak = gui_weak.clone();
+        let shutdown_scheduler = shutdown_scheduler.clone();
+        move || {
+            if let Some(gui) = gui_weak.upgrade() {
+                let minutes = shutdown_scheduler
+                    .remaining()
+                    .map(|remaining| remaining.as_secs().div_ceil(60) as i32)
+                    .unwrap_or(0);
+                if gui.get_scheduled_power_off_minutes() != minutes {
+                    gui.set_scheduled_power_off_minutes(minutes);
+                }
+            }
+        }
+    });
+
+    gui.on_direct_power_off({
+        let gui_weak = gui_weak.clone();
+        let can_shut_down = can_shut_down.clone();
+        guarded!("direct_power_off", move || {
+            if let Some(gui) = gui_weak.upgrade() {
+                let power_down_mode = determine_power_down_mode(&gui);
+                if let Err(e) = gui_shut_down(
+                    &gui,
+                    PrimitiveShutDownType::PowerOff,
+                    power_down_mode,
+                    can_shut_down.clone(),
+                ) {
+                    error_toast(&gui, "Failed to power off", e.into());
+                }
+            }
+        })
+    });
+
+    gui.on_reboot({
+        let boot_sender = boot_sender.clone();
+        let can_shut_down = can_shut_down.clone();
+        let gui_weak = gui_weak.clone();
+        guarded!("reboot", move || {
+            let shut_down_type = PrimitiveShutDownType::Reboot;
+            if let Some(gui) = gui_weak.upgrade() {
+                set_wallpaper_splash_text(&gui, &shut_down_type);
+                if let Err(e) = boot_sender.send(BootCommandForm {
+                    command: BootCommand::Reboot,
+                    can_shut_down: Some(can_shut_down.clone()),
+                }) {
+                    let display_error;
+                    if let Err(_e) = gui_shut_down(
+                        &gui,
+                        shut_down_type,
+                        PowerDownMode::Normal,
+                        can_shut_down.clone(),
+                    ) {
+                        display_error = true;
+                    } else {
+                        display_error = false;
+                    }
+
+                    if display_error {
+                        error_toast(&gui, "Failed to reboot", e.into());
+                    }
+                }
+            }
+        })
+    });
+
+    gui.on_direct_reboot({
+        let can_shut_down = can_shut_down.clone();
+        let gui_weak = gui_weak.clone();
+        guarded!("direct_reboot", move || {
+            if let Some(gui) = gui_weak.upgrade() {
+                let power_down_mode = determine_power_down_mode(&gui);
+                if let Err(e) = gui_shut_down(
+                    &gui,
+                    PrimitiveShutDownType::Reboot,
+                    power_down_mode,
+                    can_shut_down.clone(),
+                ) {
+                    error_toast(&gui, "Failed to reboot", e.into());
+                }
+            }
+        })
+    });
+
+    // Wi-Fi goes down for the night and comes back on resume, as does the frontlight
+    gui.on_sleep({
+        let can_shut_down = can_shut_down.clone();
+        let wifi_command_sender = wifi_command_sender.clone();
+        let gui_weak = gui_weak.clone();
+        guarded!("sleep", move || {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            set_wallpaper_splash_text(&gui, &PrimitiveShutDownType::Sleep);
+            let brightness_levels = (
+                brightness::get_brightness(&brightness::Mode::Cool).unwrap_or(0),
+                brightness::get_brightness(&brightness::Mode::Warm).unwrap_or(0),
+            );
+            let wifi_was_enabled = gui.get_wifi_enabled();
+            if wifi_was_enabled {
+                gui.set_wifi_disabling_lock(true);
+                if let Err(e) = wifi_command_sender
+                    .send(wifi::CommandForm::new(wifi::CommandType::Disable, None))
+                {
+                    error!("Failed to disable Wi-Fi before suspend: {}", &e);
+                }
+            }
+
+            let can_shut_down = can_shut_down.clone();
+            let wifi_command_sender = wifi_command_sender.clone();
+            let gui_weak = gui_weak.clone();
+            thread::spawn(move || {
+                let suspended = system::suspend(can_shut_down);
+                if let Err(e) =
+                    brightness::set_brightness_unified(brightness_levels.0, brightness_levels.1)
+                {
+                    error!("Failed to restore brightness after resume: {}", &e);
+                }
+                let _ = gui_weak.upgrade_in_event_loop(move |gui| {
+                    gui.invoke_resume_from_sleep();
+                    if let Err(e) = suspended {
+                        error_toast(&gui, "Failed to sleep", e);
+                    }
+                    if wifi_was_enabled {
+                        gui.set_wifi_enabling_lock(true);
+                        if let Err(e) = wifi_command_sender
+                            .send(wifi::CommandForm::new(wifi::CommandType::Enable, None))
+                        {
+                            error_toast(&gui, "Failed to enable Wi-Fi", e.into());
+                        }
+                    }
+                });
+                // Ghosting from the splash is cleared once the previous page is back
+                thread::sleep(RESUME_REFRESH_DELAY);
+                eink::full_refresh();
+            });
+        })
+    });
+
+    // Scaling
+    gui.on_change_ui_scale({
+        let gui_weak = gui_weak.clone();
+        let boot_config_mutex = boot_config_mutex.clone();
+        guarded!("change_ui_scale", move |index| {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            let Some(ui_scale) = UiScale::ALL.get(index as usize).copied() else {
+                return;
+            };
+            let source_page = if gui.get_page() == Page::UserLogin {
+                &USER_LOGIN_PAGE
+            } else {
+                &QUILLBOOT_PAGE
+            };
+            change_setting(
+                &gui,
+                &mut boot_config_mutex.lock().unwrap(),
+                ConfigSetting::UiScale(ui_scale),
+                &source_page,
+            );
+            show_ui_scale(&gui, ui_scale);
+        })
+    });
+
+    // Boot configuration
+    gui.on_toggle_persistent_rootfs({
+        let gui_weak = gui_weak.clone();
+        let boot_config_mutex = boot_config_mutex.clone();
+        guarded!("toggle_persistent_rootfs", move || {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            let mut locked_boot_config = boot_config_mutex.lock().unwrap();
+            let persistent_storage = !locked_boot_config.rootfs.persistent_storage;
+            change_setting(
+                &gui,
+                &mut locked_boot_config,
+                ConfigSetting::PersistentStorage(persistent_storage),
+                &BOOT_CONFIGURATION_PAGE,
+            );
+        })
+    });
+
+    gui.on_undo_pending_change({
+        let gui_weak = gui_weak.clone();
+        let boot_config_mutex = boot_config_mutex.clone();
+        guarded!("undo_pending_change", move |index| {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            let mut locked_boot_config = boot_config_mutex.lock().unwrap();
+            match boot_config::undo_change(&mut locked_boot_config, index as usize) {
+                Ok(setting) => show_setting(&gui, &setting, &locked_boot_config),
+                Err(e) => error_toast(&gui, "Failed to undo change", e),
+            }
+            show_pending_changes(&gui);
+        })
+    });
+
+    gui.on_switch_rootfs_slot({
+        let gui_weak = gui_weak.clone();
+        let boot_config_mutex = boot_config_mutex.clone();
+        guarded!("switch_rootfs_slot", move || {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            let mut locked_boot_config = boot_config_mutex.lock().unwrap();
+            let Some(active_slot) = locked_boot_config.rootfs.active_slot else {
+                return;
+            };
+            let new_slot = active_slot.other();
+            change_setting(
+                &gui,
+                &mut locked_boot_config,
+                ConfigSetting::switch_slot(new_slot),
+                &RECOVERY_OPTIONS_PAGE,
+            );
+            gui.set_rootfs_slot(SharedString::from(format!("{:?}", &new_slot)));
+            toast(
+                &gui,
+                &tr_format(
+                    "Root filesystem slot {} will be used",
+                    &[&format!("{:?}", &new_slot)],
+                ),
+            );
+        })
+    });
+
+    let system_update_files: Rc<RefCell<Vec<file_browser::Entry>>> =
+        Rc::new(RefCell::new(Vec::new()));
+    gui.on_open_system_update({
+        let gui_weak = gui_weak.clone();
+        let system_update_files = system_update_files.clone();
+        guarded!("open_system_update", move || {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            let files = file_browser::find_files(FilePurpose::UpdateArchive);
+            let names: Vec<SharedString> = files
+                .iter()
+                .map(|file| SharedString::from(&file.name))
+                .collect();
+            let details: Vec<SharedString> = files
+                .iter()
+                .map(|file| {
+                    SharedString::from(format!(
+                        "{} · {} · {}",
+                        file.path
+                            .parent()
+                            .map(|parent| parent.display().to_string())
+                            .unwrap_or_default(),
+                        &file.size_label(),
+                        &file.date_label()
+                    ))
+                })
+                .collect();
+            gui.set_system_update_names(slint::ModelRc::new(slint::VecModel::from(names)));
+            gui.set_system_update_details(slint::ModelRc::new(slint::VecModel::from(details)));
+            *system_update_files.borrow_mut() = files;
+        })
+    });
+
+    gui.on_install_system_update({
+        let gui_weak = gui_weak.clone();
+        let boot_config_mutex = boot_config_mutex.clone();
+        let system_update_files = system_update_files.clone();
+        guarded!("install_system_update", move |index| {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            if gui.get_system_update_in_progress() {
+                return;
+            }
+            let Some(file) = system_update_files.borrow().get(index as usize).cloned() else {
+                return;
+            };
+            if !gui.get_charger_plugged_in() && gui.get_battery_level() < MIN_UPDATE_BATTERY_LEVEL {
+                toast(
+                    &gui,
+                    &tr_format(
+                        "Battery level too low: plug in a charger or charge this device above {} %",
+                        &[&MIN_UPDATE_BATTERY_LEVEL.to_string()],
+                    ),
+                );
+                return;
+            }
+            let brownout_policy = battery::BrownoutPolicy::get();
+            if let Err(e) = brownout_policy.check_before_heavy_operation() {
+                toast(&gui, &e.to_string());
+                return;
+            }
+            let pubkey = match signing::read_public_key() {
+                Ok(pubkey) => pubkey,
+                Err(e) => {
+                    error_toast(&gui, "Failed to read public key", e);
+                    return;
+                }
+            };
+
+            let mut rootfs_config = boot_config_mutex.lock().unwrap().rootfs.clone();
+            gui.set_boot_progress(0.0);
+            gui.set_system_update_in_progress(true);
+            let gui_weak = gui_weak.clone();
+            let boot_config_mutex = boot_config_mutex.clone();
+            thread::spawn(move || {
+                let path = file.path.display().to_string();
+                // Only whole percents are forwarded: the event loop does not need to hear about every chunk
+                let mut reported_percent = 0;
+                let mut brownout_guard = battery::BrownoutGuard::new(brownout_policy);
+                let mut report_progress = |done: u64, total: u64| {
+                    brownout_guard.pause_while_sagging();
+                    let percent = done * 100 / total.max(1);
+                    if percent != reported_percent {
+                        reported_percent = percent;
+                        let _ = gui_weak.upgrade_in_event_loop(move |gui| {
+                            gui.set_boot_progress(percent as f32 / 100.0)
+                        });
+                    }
+                };
+                let result = rootfs::install_update(
+                    &path,
+                    &pubkey,
+                    &mut rootfs_config,
+                    Some(&mut report_progress),
+                );
+                let _ = gui_weak.upgrade_in_event_loop(move |gui| {
+                    gui.set_system_update_in_progress(false);
+                    match result {
+                        Ok(()) => {
+                            // Only what the install touched: other settings may have changed in the meantime
+                            let apply_install = |boot_config: &mut BootConfig| {
+                                boot_config.rootfs.timestamp = rootfs_config.timestamp;
+                                boot_config.rootfs.systemd_targets_total =
+                                    rootfs_config.systemd_targets_total;
+                                boot_config.rootfs.active_slot = rootfs_config.active_slot;
+                                boot_config.rootfs.boot_attempts = rootfs_config.boot_attempts;
+                            };
+                            apply_install(&mut boot_config_mutex.lock().unwrap());
+                            // Stored right away as well: the new slot must be booted even if this session does not end cleanly
+                            if let Err(e) = BootConfig::update_stored(apply_install) {
+                                error_toast(&gui, "Failed to store boot configuration", e);
+                                return;
+                            }
+                            if let Some(active_slot) = rootfs_config.active_slot {
+                                gui.set_rootfs_slot(SharedString::from(format!(
+                                    "{:?}",
+                                    &active_slot
+                                )));
+                                gui.set_rootfs_other_slot_available(true);
+                            }
+                            toast(
+                                &gui,
+                                "System update was installed: it will be used on next boot",
+                            );
+                        }
+                        Err(e) => error_toast(&gui, "Failed to install system update", e),
+                    }
+                });
+            });
+        })
     });
 
     // System commands
     gui.on_boot_default({
         let boot_sender = boot_sender.clone();
+        let shutdown_scheduler = shutdown_scheduler.clone();
         let set_page_sender = set_page_sender.clone();
         let wifi_command_sender = wifi_command_sender.clone();
         let login_credentials_sender = login_credentials_sender.clone();
         let core_settings_sender = core_settings_sender.clone();
         let gui_weak = gui_weak.clone();
-        move || {
+        guarded!("boot_default", move || {
             if let Some(gui) = gui_weak.upgrade() {
                 // Turn off Wi-Fi
-                if let Err(e) = wifi_command_sender.send(wifi::CommandForm {
-                    command_type: wifi::CommandType::Disable,
-                    arguments: None,
-                }) {
+                if let Err(e) = wifi_command_sender
+                    .send(wifi::CommandForm::new(wifi::CommandType::Disable, None))
+                {
                     error_toast(&gui, "Failed to disable Wi-Fi", e.into());
                 }
                 if let Err(e) = boot_normal(
                     &gui,
                     &boot_sender,
                     &set_page_sender,
+                    &shutdown_scheduler,
                     &gui.get_default_user().to_string(),
                     first_boot_done,
                     login_credentials_sender.clone(),
@@ -744,7 +1842,7 @@ pub fn setup_gui(
                     error_toast(&gui, "Failed to send boot command", e.into())
                 }
             }
-        }
+        })
     });
 
     // Soft reset
@@ -752,7 +1850,7 @@ pub fn setup_gui(
     gui.on_soft_reset({
         let boot_config_mutex = boot_config_mutex.clone();
         let gui_weak = gui_weak.clone();
-        move || {
+        guarded!("soft_reset", move || {
             let boot_config_mutex = boot_config_mutex.clone();
             let gui_weak = gui_weak.clone();
             soft_reset_timer.start(
@@ -762,6 +1860,7 @@ pub fn setup_gui(
                     if let Some(gui) = gui_weak.upgrade() {
                         gui.set_enable_ui(false);
                         if let Err(e) = soft_reset(boot_config_mutex.clone()) {
+                            close_action_confirmation(&gui);
                             error_toast(&gui, "Failed to soft-reset", e.into());
                             gui.set_enable_ui(true);
                         } else {
@@ -770,222 +1869,1478 @@ pub fn setup_gui(
                     }
                 },
             )
-        }
+        })
+    });
+
+    // Factory reset
+    gui.on_start_factory_reset({
+        let gui_weak = gui_weak.clone();
+        let boot_config_mutex = boot_config_mutex.clone();
+        guarded!(
+            "start_factory_reset",
+            move |system_changes, boot_configuration, user_data| {
+                let Some(gui) = gui_weak.upgrade() else {
+                    return;
+                };
+                let steps: Vec<FactoryResetStep> = [
+                    (system_changes, FactoryResetStep::SystemChanges),
+                    (boot_configuration, FactoryResetStep::BootConfiguration),
+                    (user_data, FactoryResetStep::UserData),
+                ]
+                .into_iter()
+                .filter_map(|(selected, step)| selected.then_some(step))
+                .collect();
+                if steps.is_empty() {
+                    toast(&gui, "Nothing to reset: select at least one step");
+                    return;
+                }
+                let gui_weak = gui_weak.clone();
+                let boot_config_mutex = boot_config_mutex.clone();
+                thread::spawn(move || {
+                    let notifier = notifier::get();
+                    if !notifier.ask_confirmation(&tr(
+                        "The selected steps cannot be undone, and the device reboots once they are done. Are you sure you want to continue?",
+                    )) {
+                        return;
+                    }
+                    let _ = gui_weak.upgrade_in_event_loop(|gui| {
+                        gui.set_enable_ui(false);
+                        gui.set_factory_reset_running(true);
+                    });
+
+                    for (index, step) in steps.iter().enumerate() {
+                        notifier.toast(&tr_format(
+                            "Step {} of {}: {}",
+                            &[
+                                &(index + 1).to_string(),
+                                &steps.len().to_string(),
+                                &tr(step.label()),
+                            ],
+                        ));
+                        let result = match step {
+                            FactoryResetStep::SystemChanges => recovery::reset_system_changes(),
+                            FactoryResetStep::BootConfiguration => {
+                                recovery::reset_boot_config(&mut boot_config_mutex.lock().unwrap())
+                            }
+                            FactoryResetStep::UserData => {
+                                recovery::erase_user_homes(&mut boot_config_mutex.lock().unwrap())
+                            }
+                        };
+                        if let Err(e) = result {
+                            error!("{:?}", &e);
+                            let list = |steps: &[FactoryResetStep]| {
+                                if steps.is_empty() {
+                                    return tr("nothing");
+                                }
+                                steps
+                                    .iter()
+                                    .map(|step| tr(step.label()))
+                                    .collect::<Vec<String>>()
+                                    .join(", ")
+                            };
+                            let message = tr_format(
+                                "Factory reset stopped: '{}' failed ({}).\nDone: {}.\nNot done: {}.",
+                                &[
+                                    &tr(step.label()),
+                                    &e.to_string(),
+                                    &list(&steps[..index]),
+                                    &list(&steps[index..]),
+                                ],
+                            );
+                            let _ = gui_weak.upgrade_in_event_loop(move |gui| {
+                                gui.set_enable_ui(true);
+                                gui.set_factory_reset_running(false);
+                                set_vault_users(&gui);
+                                sticky_toast(&gui, &message);
+                            });
+                            return;
+                        }
+                    }
+
+                    info!("Factory reset done: rebooting");
+                    let _ = gui_weak.upgrade_in_event_loop(|gui| {
+                        gui.invoke_standard_reboot();
+                    });
+                });
+            }
+        )
+    });
+
+    // USB storage
+    let shared_storage: Arc<Mutex<Option<SharedStorage>>> = Arc::new(Mutex::new(None));
+    gui.on_share_storage({
+        let gui_weak = gui_weak.clone();
+        let shared_storage = shared_storage.clone();
+        guarded!("share_storage", move |partition| {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            let partition = match partition {
+                0 => Partition::Boot,
+                _ => Partition::Main,
+            };
+            gui.set_enable_ui(false);
+            let gui_weak = gui_weak.clone();
+            let shared_storage = shared_storage.clone();
+            thread::spawn(move || {
+                let result = usb_gadget::share_partition(&partition);
+                let _ = gui_weak.upgrade_in_event_loop(move |gui| {
+                    gui.set_enable_ui(true);
+                    match result {
+                        Ok(storage) => {
+                            gui.set_shared_storage_device(SharedString::from(&storage.device));
+                            gui.set_shared_storage_read_only(storage.read_only);
+                            *shared_storage.lock().unwrap() = Some(storage);
+                            gui.set_section_header_title(SharedString::from(tr("USB storage")));
+                            gui.set_page(Page::UsbStorage);
+                            toast(&gui, "Sharing storage over USB");
+                        }
+                        Err(e) => error_toast(&gui, "Failed to share storage over USB", e),
+                    }
+                });
+            });
+        })
+    });
+
+    gui.on_stop_sharing_storage({
+        let gui_weak = gui_weak.clone();
+        let shared_storage = shared_storage.clone();
+        guarded!("stop_sharing_storage", move || {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            let Some(storage) = shared_storage.lock().unwrap().take() else {
+                return;
+            };
+            gui.set_enable_ui(false);
+            let gui_weak = gui_weak.clone();
+            thread::spawn(move || {
+                let result = storage.stop();
+                let _ = gui_weak.upgrade_in_event_loop(move |gui| {
+                    gui.set_enable_ui(true);
+                    gui.set_shared_storage_device(SharedString::new());
+                    gui.set_section_header_title(SharedString::from(tr("Recovery options")));
+                    gui.set_page(Page::RecoveryOptions);
+                    match result {
+                        Ok(()) => toast(&gui, "Stopped sharing storage over USB"),
+                        // Whatever went wrong is worth a reboot before anything touches the partition again
+                        Err(e) => error_toast(&gui, "Failed to stop sharing storage over USB", e),
+                    }
+                });
+            });
+        })
+    });
+
+    // Wi-Fi (toggle)
+    gui.on_toggle_wifi({
+        let wifi_command_sender = wifi_command_sender.clone();
+        let gui_weak = gui_weak.clone();
+        guarded!("toggle_wifi", move || {
+            if let Some(gui) = gui_weak.upgrade() {
+                if gui.get_wifi_enabled() {
+                    gui.set_wifi_disabling_lock(true);
+                    if let Err(e) = wifi_command_sender
+                        .send(wifi::CommandForm::new(wifi::CommandType::Disable, None))
+                    {
+                        error_toast(&gui, "Failed to enable Wi-Fi", e.into());
+                    }
+                } else {
+                    gui.set_wifi_enabling_lock(true);
+                    if let Err(e) = wifi_command_sender
+                        .send(wifi::CommandForm::new(wifi::CommandType::Enable, None))
+                    {
+                        error_toast(&gui, "Failed to disable Wi-Fi", e.into());
+                    }
+                }
+            }
+        })
+    });
+
+    // Wi-Fi (connect)
+    gui.on_connect_to_wifi_network({
+        let wifi_command_sender = wifi_command_sender.clone();
+        let gui_weak = gui_weak.clone();
+        guarded!(
+            "connect_to_wifi_network",
+            move |network_name, passphrase| {
+                if let Some(gui) = gui_weak.upgrade() {
+                    let err_msg = "Failed to connect to network";
+                    gui.set_wifi_connecting_lock(true);
+                    if passphrase.is_empty() {
+                        if let Err(e) = wifi_command_sender.send(wifi::CommandForm::new(
+                            wifi::CommandType::Connect,
+                            Some(wifi::NetworkForm {
+                                name: network_name.to_string(),
+                                passphrase: None,
+                            }),
+                        )) {
+                            error_toast(&gui, &err_msg, e.into());
+                        }
+                    } else {
+                        if let Err(e) = wifi_command_sender.send(wifi::CommandForm::new(
+                            wifi::CommandType::Connect,
+                            Some(wifi::NetworkForm {
+                                name: network_name.to_string(),
+                                passphrase: Some(Secret::new(passphrase.to_string())),
+                            }),
+                        )) {
+                            error_toast(&gui, "Failed to connect to network", e.into());
+                        }
+                    }
+                }
+            }
+        )
+    });
+
+    // Wi-Fi (idle power down)
+    gui.on_wifi_panel_toggled({
+        let wifi_command_sender = wifi_command_sender.clone();
+        let gui_weak = gui_weak.clone();
+        guarded!("wifi_panel_toggled", move |open| {
+            if let Some(gui) = gui_weak.upgrade() {
+                let command_type = if open {
+                    if gui.get_wifi_suspended() {
+                        gui.set_wifi_waking_lock(true);
+                    }
+                    wifi::CommandType::Wake
+                } else {
+                    wifi::CommandType::Release
+                };
+                if let Err(e) = wifi_command_sender.send(wifi::CommandForm::new(command_type, None))
+                {
+                    error_toast(&gui, "Failed to wake Wi-Fi up", e.into());
+                }
+            }
+        })
+    });
+
+    // Wi-Fi (get networks)
+    gui.on_get_networks({
+        let wifi_command_sender = wifi_command_sender.clone();
+        let gui_weak = gui_weak.clone();
+        guarded!("get_networks", move || {
+            if let Some(gui) = gui_weak.upgrade() {
+                gui.set_wifi_scanning_lock(true);
+                if let Err(e) = wifi_command_sender
+                    .send(wifi::CommandForm::new(wifi::CommandType::GetNetworks, None))
+                {
+                    error_toast(&gui, "Failed to scan networks", e.into());
+                }
+            }
+        })
+    });
+
+    // Asking iwd for the link details may take a moment: the page shows up right away and is filled in afterwards
+    gui.on_show_wifi_details({
+        let gui_weak = gui_weak.clone();
+        guarded!("show_wifi_details", move || {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            gui.set_wifi_details_labels(slint::ModelRc::default());
+            gui.set_wifi_details_values(slint::ModelRc::default());
+            let gui_weak = gui_weak.clone();
+            thread::spawn(move || {
+                let details = networking::get_if_details(&wifi::WIFI_IF);
+                let _ = gui_weak.upgrade_in_event_loop(move |gui| {
+                    let (labels, values): (Vec<SharedString>, Vec<SharedString>) = details
+                        .rows()
+                        .into_iter()
+                        .map(|(label, value)| {
+                            (
+                                SharedString::from(tr(label)),
+                                SharedString::from(tr(&value)),
+                            )
+                        })
+                        .unzip();
+                    gui.set_wifi_details_labels(slint::ModelRc::new(slint::VecModel::from(labels)));
+                    gui.set_wifi_details_values(slint::ModelRc::new(slint::VecModel::from(values)));
+                });
+            });
+        })
+    });
+
+    // About page: read on every visit, as most of it changes while the menu is up
+    let about_report = Arc::new(Mutex::new(String::new()));
+    gui.on_load_about_info({
+        let gui_weak = gui_weak.clone();
+        let boot_config_mutex = boot_config_mutex.clone();
+        let about_report = about_report.clone();
+        guarded!("load_about_info", move || {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            gui.set_about_labels(slint::ModelRc::default());
+            gui.set_about_values(slint::ModelRc::default());
+            let version_string = gui.get_version_string().to_string();
+            let gui_weak = gui_weak.clone();
+            let boot_config_mutex = boot_config_mutex.clone();
+            let about_report = about_report.clone();
+            thread::spawn(move || {
+                let boot_config = boot_config_mutex.lock().unwrap().clone();
+                let rows = about_rows(&boot_config);
+                // Untranslated, for bug reports
+                *about_report.lock().unwrap() = format!(
+                    "{}\n\n{}",
+                    &version_string,
+                    rows.iter()
+                        .map(|(label, value)| format!("{}: {}", &label, &value))
+                        .collect::<Vec<String>>()
+                        .join("\n")
+                );
+                let _ = gui_weak.upgrade_in_event_loop(move |gui| {
+                    let (labels, values): (Vec<SharedString>, Vec<SharedString>) = rows
+                        .into_iter()
+                        .map(|(label, value)| {
+                            (
+                                SharedString::from(tr(label)),
+                                SharedString::from(tr(&value)),
+                            )
+                        })
+                        .unzip();
+                    gui.set_about_labels(slint::ModelRc::new(slint::VecModel::from(labels)));
+                    gui.set_about_values(slint::ModelRc::new(slint::VecModel::from(values)));
+                });
+            });
+        })
+    });
+
+    // Same pipeline as the QR codes of the fatal error page
+    let about_qr_codes: Rc<RefCell<Vec<Image>>> = Rc::new(RefCell::new(Vec::new()));
+    gui.on_open_about_qr_codes({
+        let gui_weak = gui_weak.clone();
+        let about_report = about_report.clone();
+        let about_qr_codes = about_qr_codes.clone();
+        let qr_max_modules = get_qr_max_modules(&boot_config_mutex);
+        guarded!("open_about_qr_codes", move || {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            let report = about_report.lock().unwrap().clone();
+            if report.is_empty() {
+                toast(&gui, "System information is still being read");
+                return;
+            }
+            let images = match compress_string_to_xz(&report)
+                .ok()
+                .and_then(|data| plan_qr_codes(&data, qr_max_modules))
+            {
+                Some((parts, ecc)) => parts
+                    .iter()
+                    .map(|part| {
+                        let qr_code_svg =
+                            qrcode_generator::to_svg_to_string(&part, ecc, 1024, None::<&str>)
+                                .ok()?;
+                        Image::load_from_svg_data(&qr_code_svg.as_bytes()).ok()
+                    })
+                    .collect::<Option<Vec<Image>>>(),
+                None => None,
+            };
+            let Some(images) = images.filter(|images| !images.is_empty()) else {
+                toast(&gui, "Failed to generate QR code");
+                return;
+            };
+            gui.set_about_qr_code(images[0].clone());
+            gui.set_about_qr_code_index(0);
+            gui.set_about_qr_code_count(images.len() as i32);
+            *about_qr_codes.borrow_mut() = images;
+            gui.set_dialog(DialogType::AboutQrCode);
+        })
+    });
+
+    gui.on_show_about_qr_code({
+        let gui_weak = gui_weak.clone();
+        let about_qr_codes = about_qr_codes.clone();
+        guarded!("show_about_qr_code", move |index| {
+            if let Some(gui) = gui_weak.upgrade() {
+                if let Some(image) = about_qr_codes.borrow().get(index as usize) {
+                    gui.set_about_qr_code(image.clone());
+                    gui.set_about_qr_code_index(index);
+                }
+            }
+        })
+    });
+
+    // Virtual keyboard
+    gui.global::<VirtualKeyboardHandler>().on_key_pressed({
+        let gui_weak = gui_weak.clone();
+        guarded!("key_pressed", move |key| {
+            if let Some(gui) = gui_weak.upgrade() {
+                // Keys come in as shown, shifted or not: shift only has to be released afterwards
+                let printable = is_printable_key(&key);
+                gui.window()
+                    .dispatch_event(slint::platform::WindowEvent::KeyPressed { text: key.clone() });
+                gui.window()
+                    .dispatch_event(slint::platform::WindowEvent::KeyReleased { text: key });
+                let keyboard = gui.global::<VirtualKeyboardHandler>();
+                if printable && keyboard.get_shift() && !keyboard.get_caps_lock() {
+                    keyboard.set_shift(false);
+                }
+            }
+        })
+    });
+
+    // Brightness
+    gui.on_set_brightness_sliders_levels({
+        let gui_weak = gui_weak.clone();
+        guarded!("set_brightness_sliders_levels", move || {
+            if let Some(gui) = gui_weak.upgrade() {
+                // Backlight nodes are missing on some hardware (e.g. when running elsewhere than on a PineNote)
+                match brightness::get_brightness(&brightness::Mode::Cool) {
+                    Ok(level) => gui.set_cool_brightness(level * 100 / brightness::MAX_BRIGHTNESS),
+                    Err(e) => error_toast(&gui, "Failed to read cool brightness", e),
+                }
+                match brightness::get_brightness(&brightness::Mode::Warm) {
+                    Ok(level) => gui.set_warm_brightness(level * 100 / brightness::MAX_BRIGHTNESS),
+                    Err(e) => error_toast(&gui, "Failed to read warm brightness", e),
+                }
+            }
+        })
+    });
+
+    gui.on_change_cool_brightness({
+        guarded!("change_cool_brightness", move |value| {
+            let _ = brightness::set_brightness_(
+                value * brightness::MAX_BRIGHTNESS / 100,
+                &brightness::Mode::Cool,
+            );
+        })
+    });
+
+    gui.on_change_warm_brightness({
+        guarded!("change_warm_brightness", move |value| {
+            let _ = brightness::set_brightness_(
+                value * brightness::MAX_BRIGHTNESS / 100,
+                &brightness::Mode::Warm,
+            );
+        })
+    });
+
+    // Battery status timer
+    let battery_status_timer = Timer::default();
+    battery_status_timer.start(
+        TimerMode::Repeated,
+        std::time::Duration::from_millis(100),
+        {
+            let gui_weak = gui_weak.clone();
+            let mut current_level: i32 = -1;
+            let mut current_plug_status = false;
+            let mut level_smoother =
+                battery::LevelSmoother::new(battery::DEFAULT_SMOOTHING_SAMPLES);
+            move || {
+                if let Ok(raw_level) = battery::get_level() {
+                    if let Some(gui) = gui_weak.upgrade() {
+                        if let Ok(charger_plugged_in) = battery::charger_plugged_in() {
+                            let new_level = level_smoother.update(raw_level, charger_plugged_in);
+                            if new_level != current_level {
+                                debug!(
+                                    "Battery level: raw {} %, smoothed {} %",
+                                    &raw_level, &new_level
+                                );
+                            }
+                            gui.set_battery_level(new_level);
+                            let new_plug_status = charger_plugged_in;
+                            if charger_plugged_in {
+                                gui.set_charger_plugged_in(new_plug_status);
+                                if new_plug_status != current_plug_status {
+                                    if let Ok(icon) = Image::load_from_svg_data(include_bytes!(
+                                        "../../icons/battery-charging.svg"
+                                    )) {
+                                        info!("Setting 'Charging' battery icon");
+                                        gui.set_battery_icon(icon);
+                                    }
+                                }
+                            } else {
+                                gui.set_charger_plugged_in(new_plug_status);
+                                if current_level != new_level
+                                    || new_plug_status != current_plug_status
+                                {
+                                    if let Ok(icon) = Image::load_from_svg_data(
+                                        battery::generate_svg_from_level(new_level).as_bytes(),
+                                    ) {
+                                        info!(
+                                            "Changing battery icon for charge level {}",
+                                            new_level
+                                        );
+                                        gui.set_battery_icon(icon);
+                                    }
+                                }
+                            }
+                            current_level = new_level;
+                            current_plug_status = new_plug_status;
+                        } else {
+                            error!("Could not get battery status");
+                        }
+                    }
+                } else {
+                    error!("Could not get battery level");
+                }
+            }
+        },
+    );
+
+    // Homes unlocked on the login page while the boot has not finished yet
+    let unlocked_users: Rc<RefCell<Vec<String>>> = Rc::new(RefCell::new(Vec::new()));
+    let idle_lock_timeout = boot_config_mutex
+        .lock()
+        .unwrap()
+        .system
+        .login_idle_lock_mins
+        .filter(|mins| *mins > 0)
+        .map(|mins| Duration::from_secs(mins * 60));
+    let idle_lock_timer = Timer::default();
+    if let Some(idle_lock_timeout) = idle_lock_timeout {
+        idle_lock_timer.start(TimerMode::Repeated, IDLE_LOCK_CHECK_INTERVAL, {
+            let gui_weak = gui_weak.clone();
+            let unlocked_users = unlocked_users.clone();
+            let set_page_sender = set_page_sender.clone();
+            let login_credentials_sender = login_credentials_sender.clone();
+            move || {
+                let Some(gui) = gui_weak.upgrade() else {
+                    return;
+                };
+                // Disarmed for good once systemd started: the session is the root filesystem's business from then on
+                if gui.get_startup_finished() {
+                    unlocked_users.borrow_mut().clear();
+                    return;
+                }
+                if input::idle_duration() < idle_lock_timeout {
+                    return;
+                }
+                let usernames: Vec<String> = unlocked_users.borrow_mut().drain(..).collect();
+                if usernames.is_empty() {
+                    return;
+                }
+                for username in &usernames {
+                    info!("Locking home of user '{}' after inactivity", &username);
+                    if let Err(e) = storage_encryption::unmount_storage(&username) {
+                        error!("{:?}", &e);
+                    }
+                }
+                // An empty form clears whatever credentials the root filesystem did not pick up yet
+                let _ = login_credentials_sender.send(LoginForm {
+                    username: String::new(),
+                    password: String::new(),
+                });
+                switch_to_login_page(&gui, &set_page_sender);
+                toast(&gui, "Homes locked after inactivity: please log in again");
+            }
+        });
+    }
+
+    // Devices left at the boot menu or login page would drain overnight
+    let idle_power_off_timeout = boot_config_mutex
+        .lock()
+        .unwrap()
+        .system
+        .idle_power_off_mins
+        .filter(|mins| *mins > 0)
+        .map(|mins| Duration::from_secs(mins * 60));
+    let idle_power_off_timer = Timer::default();
+    if let Some(idle_power_off_timeout) = idle_power_off_timeout {
+        let countdown_deadline: Cell<Option<Instant>> = Cell::new(None);
+        idle_power_off_timer.start(TimerMode::Repeated, Duration::from_secs(1), {
+            let gui_weak = gui_weak.clone();
+            move || {
+                let Some(gui) = gui_weak.upgrade() else {
+                    return;
+                };
+                let cancel_countdown = || {
+                    if countdown_deadline.take().is_some()
+                        && gui.get_dialog() == DialogType::IdlePowerOff
+                    {
+                        gui.set_dialog(DialogType::None);
+                    }
+                };
+                // Disarmed for good once a boot started, and kept out of the way of anything that must not be cut short
+                if gui.get_boot_started()
+                    || gui.get_page() == Page::Error
+                    || gui.get_page() == Page::ShutDownSplash
+                    || !gui.get_enable_ui()
+                    || gui.get_system_update_in_progress()
+                    || gui.get_factory_reset_running()
+                    || !gui.get_shared_storage_device().is_empty()
+                {
+                    cancel_countdown();
+                    return;
+                }
+                if input::idle_duration() < idle_power_off_timeout {
+                    cancel_countdown();
+                    return;
+                }
+                let Some(deadline) = countdown_deadline.get() else {
+                    info!("No activity at the boot menu for a while: counting down to power off");
+                    countdown_deadline.set(Some(Instant::now() + IDLE_POWER_OFF_COUNTDOWN));
+                    gui.set_idle_power_off_seconds(IDLE_POWER_OFF_COUNTDOWN.as_secs() as i32);
+                    gui.set_dialog(DialogType::IdlePowerOff);
+                    return;
+                };
+                // Dismissed by a touch the activity monitor did not see
+                if gui.get_dialog() != DialogType::IdlePowerOff {
+                    countdown_deadline.set(None);
+                    input::note_activity();
+                    return;
+                }
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if !remaining.is_zero() {
+                    gui.set_idle_power_off_seconds(remaining.as_secs().max(1) as i32);
+                    return;
+                }
+                info!("Powering off after inactivity");
+                countdown_deadline.set(None);
+                gui.set_dialog(DialogType::None);
+                gui.invoke_standard_power_off();
+            }
+        });
+    }
+
+    // Mounting a big home takes seconds: done on a worker thread, with the outcome picked up here
+    let (login_attempt_sender, login_attempt_receiver): (
+        Sender<LoginAttempt>,
+        Receiver<LoginAttempt>,
+    ) = channel();
+    // Cleared once the slow login toast was shown, or the attempt is over
+    let login_started: Rc<RefCell<Option<Instant>>> = Rc::new(RefCell::new(None));
+    let login_attempt_timer = Timer::default();
+    login_attempt_timer.start(TimerMode::Repeated, Duration::from_millis(100), {
+        let gui_weak = gui_weak.clone();
+        let set_page_sender = set_page_sender.clone();
+        let login_credentials_sender = login_credentials_sender.clone();
+        let boot_config_mutex = boot_config_mutex.clone();
+        let unlocked_users = unlocked_users.clone();
+        let login_started = login_started.clone();
+        move || {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            let Ok(LoginAttempt {
+                username,
+                password,
+                result,
+            }) = login_attempt_receiver.try_recv()
+            else {
+                let mut login_started = login_started.borrow_mut();
+                if login_started.is_some_and(|started| started.elapsed() >= SLOW_LOGIN_DELAY) {
+                    *login_started = None;
+                    toast(&gui, "Still working…");
+                }
+                return;
+            };
+            login_started.borrow_mut().take();
+            gui.set_login_pending(false);
+
+            if let Err((e, vault_status)) = result {
+                match vault_status {
+                    VaultStatus::WrongPassword => {
+                        error_toast(&gui, "Login failed: please try again", e)
+                    }
+                    VaultStatus::MissingVault => error_toast(
+                        &gui,
+                        "Encrypted storage for this user could not be found",
+                        e,
+                    ),
+                    VaultStatus::CorruptConfig
+                        if storage_encryption::has_config_backup(&username) =>
+                    {
+                        error!("{:?}", &e);
+                        offer_vault_config_restore(username);
+                    }
+                    VaultStatus::CorruptConfig => error_toast(
+                        &gui,
+                        "Encrypted storage configuration is corrupted and has no backup",
+                        e,
+                    ),
+                }
+                return;
+            }
+
+            input::note_activity();
+            if !gui.get_startup_finished() && !unlocked_users.borrow().contains(&username) {
+                unlocked_users.borrow_mut().push(username.clone());
+            }
+            apply_session_language(&gui, &user_preferences::read_and_apply(&username));
+            // LoginForm lives in libquillcom and carries a plain String: never log it
+            if let Err(e) = login_credentials_sender.send(LoginForm {
+                username: username.clone(),
+                password: password.expose().to_string(),
+            }) {
+                error_toast(&gui, "Failed to send login credentials", e.into());
+            } else {
+                let _ = set_page_sender.send(Page::BootSplash);
+            }
+            // Offered once per user, whether it is accepted or not
+            if !storage_encryption::has_recovery_key(&username)
+                && !boot_config_mutex
+                    .lock()
+                    .unwrap()
+                    .system
+                    .recovery_keys
+                    .contains_key(username.as_str())
+            {
+                offer_recovery_key(
+                    gui_weak.clone(),
+                    boot_config_mutex.clone(),
+                    username,
+                    password,
+                );
+            }
+        }
+    });
+
+    gui.on_login({
+        let gui_weak = gui_weak.clone();
+        let login_started = login_started.clone();
+        guarded!("login", move |username, password| {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            // Repeated taps would otherwise start overlapping gocryptfs processes
+            if gui.get_login_pending() {
+                return;
+            }
+            gui.set_login_pending(true);
+            *login_started.borrow_mut() = Some(Instant::now());
+            let username = username.to_string();
+            let password = Secret::new(password.to_string());
+            let login_attempt_sender = login_attempt_sender.clone();
+            thread::spawn(move || {
+                // Already unlocked earlier in this boot session: the password still has to be right
+                let result = if storage_encryption::is_mounted(&username) {
+                    storage_encryption::check_password(&username, &password)
+                } else {
+                    storage_encryption::mount_storage(
+                        &username,
+                        &Credential::Password(password.clone()),
+                    )
+                };
+                let result = result.map_err(|e| (e, storage_encryption::check_vault(&username)));
+                let _ = login_attempt_sender.send(LoginAttempt {
+                    username,
+                    password,
+                    result,
+                });
+            });
+        })
+    });
+
+    gui.on_unlock_with_recovery_key({
+        let gui_weak = gui_weak.clone();
+        let unlocked_users = unlocked_users.clone();
+        let set_page_sender = set_page_sender.clone();
+        let login_credentials_sender = login_credentials_sender.clone();
+        guarded!("unlock_with_recovery_key", move |username, recovery_key| {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            if gui.get_login_pending() {
+                return;
+            }
+            if !storage_encryption::has_recovery_key(&username) {
+                toast(&gui, "This user has no recovery key");
+                return;
+            }
+            let recovery_key = Credential::RecoveryKey(Secret::new(recovery_key.to_string()));
+            if let Err(e) = storage_encryption::mount_storage(&username, &recovery_key) {
+                error_toast(&gui, "Unlocking failed: please check the recovery key", e);
+                return;
+            }
+            input::note_activity();
+            if !gui.get_startup_finished() {
+                unlocked_users.borrow_mut().push(username.to_string());
+            }
+            apply_session_language(&gui, &user_preferences::read_and_apply(&username));
+            // Without a password, the root filesystem asks for one itself
+            if let Err(e) = login_credentials_sender.send(LoginForm {
+                username: username.to_string(),
+                password: String::new(),
+            }) {
+                error_toast(&gui, "Failed to send login credentials", e.into());
+            } else {
+                let _ = set_page_sender.send(Page::BootSplash);
+            }
+        })
+    });
+
+    gui.on_unlock_with_pin({
+        let gui_weak = gui_weak.clone();
+        let boot_config_mutex = boot_config_mutex.clone();
+        guarded!("unlock_with_pin", move |username| {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            if gui.get_login_pending() {
+                return;
+            }
+            if !pin::has_quick_unlock(&username) {
+                toast(&gui, "No PIN was set up for this user");
+                return;
+            }
+            let (device_id, max_attempts) = {
+                let boot_config = boot_config_mutex.lock().unwrap();
+                (
+                    boot_config.system.device_id.clone(),
+                    boot_config
+                        .system
+                        .quick_unlock_max_attempts
+                        .unwrap_or(pin::DEFAULT_QUICK_UNLOCK_MAX_ATTEMPTS),
+                )
+            };
+            // Blobs are bound to it: without one, every PIN would count as a wrong one
+            let Some(device_id) = device_id else {
+                toast(
+                    &gui,
+                    "This device has no device ID: PIN unlock is unavailable",
+                );
+                return;
+            };
+            let gui_weak = gui_weak.clone();
+            let username = username.to_string();
+            thread::spawn(move || {
+                let notifier = notifier::get();
+                let outcome = pin::prompt_and_verify(
+                    &tr_format("Enter the PIN of '{}'", &[&username]),
+                    |entered_pin| {
+                        let outcome =
+                            pin::quick_unlock(&username, &entered_pin, &device_id, max_attempts)?;
+                        Ok(match outcome {
+                            pin::QuickUnlockOutcome::Unlocked(password) => {
+                                PinCheck::Accepted(password)
+                            }
+                            pin::QuickUnlockOutcome::WrongPin { remaining_attempts } => {
+                                PinCheck::Rejected(tr_format(
+                                    "Wrong PIN: {} attempt(s) left",
+                                    &[&remaining_attempts.to_string()],
+                                ))
+                            }
+                            pin::QuickUnlockOutcome::Disabled => PinCheck::Exhausted,
+                        })
+                    },
+                );
+                match outcome {
+                    // Same path as typing the password: the root filesystem gets it too
+                    Ok(PinPromptOutcome::Accepted(password)) => {
+                        let _ = gui_weak.upgrade_in_event_loop(move |gui| {
+                            gui.invoke_login(
+                                SharedString::from(&username),
+                                SharedString::from(password.expose()),
+                            );
+                        });
+                    }
+                    Ok(PinPromptOutcome::Exhausted) => {
+                        notifier.toast("Too many wrong PINs: please log in with the password");
+                    }
+                    Ok(PinPromptOutcome::Cancelled) => {}
+                    Err(e) => {
+                        error!("{:?}", &e);
+                        notifier.toast("Failed to unlock with PIN");
+                    }
+                }
+            });
+        })
+    });
+
+    gui.on_set_up_quick_unlock({
+        let gui_weak = gui_weak.clone();
+        let boot_config_mutex = boot_config_mutex.clone();
+        guarded!("set_up_quick_unlock", move |username, password| {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            let Some(device_id) = boot_config_mutex.lock().unwrap().system.device_id.clone() else {
+                toast(
+                    &gui,
+                    "This device has no device ID: PIN unlock is unavailable",
+                );
+                return;
+            };
+            let username = username.to_string();
+            let password = Secret::new(password.to_string());
+            thread::spawn(move || {
+                let notifier = notifier::get();
+                if let Err(e) = storage_encryption::check_password(&username, &password) {
+                    error!("{:?}", &e);
+                    notifier.toast("Wrong password: PIN was not set up");
+                    return;
+                }
+                let ask = |message: &str| {
+                    notifier.ask_pin(&PinRequest {
+                        message: tr(message),
+                        max_length: pin::DEFAULT_PIN_MAX_LENGTH,
+                        error_message: None,
+                    })
+                };
+                let Some(new_pin) = ask("Choose a PIN") else {
+                    return;
+                };
+                if let Err(e) = pin::validate_quick_unlock_pin(&new_pin) {
+                    notifier.toast(&e.to_string());
+                    return;
+                }
+                let Some(confirmation) = ask("Enter the same PIN again") else {
+                    return;
+                };
+                if new_pin != confirmation {
+                    notifier.toast("PINs do not match: PIN was not set up");
+                    return;
+                }
+                notifier.toast("Setting up PIN");
+                match pin::enable_quick_unlock(&username, &new_pin, &password, &device_id) {
+                    Ok(()) => notifier.toast("PIN set up"),
+                    Err(e) => {
+                        error!("{:?}", &e);
+                        notifier.toast("Failed to set up PIN");
+                    }
+                }
+            });
+        })
+    });
+
+    gui.on_remove_quick_unlock({
+        let gui_weak = gui_weak.clone();
+        guarded!("remove_quick_unlock", move |username| {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            match pin::disable_quick_unlock(&username) {
+                Ok(()) => toast(&gui, "PIN removed"),
+                Err(e) => error_toast(&gui, "Failed to remove PIN", e),
+            }
+        })
     });
 
-    // Wi-Fi (toggle)
-    gui.on_toggle_wifi({
-        let wifi_command_sender = wifi_command_sender.clone();
+    gui.on_erase_user_data({
         let gui_weak = gui_weak.clone();
-        move || {
+        guarded!("erase_user_data", move |username, confirmation| {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            if confirmation != username {
+                toast(&gui, "The user name does not match: nothing was erased");
+                return;
+            }
+            gui.invoke_request_confirmation(ConfirmableAction::EraseUserData);
+        })
+    });
+
+    gui.on_open_users({
+        let gui_weak = gui_weak.clone();
+        guarded!("open_users", move || {
             if let Some(gui) = gui_weak.upgrade() {
-                if gui.get_wifi_enabled() {
-                    gui.set_wifi_disabling_lock(true);
-                    if let Err(e) = wifi_command_sender.send(wifi::CommandForm {
-                        command_type: wifi::CommandType::Disable,
-                        arguments: None,
-                    }) {
-                        error_toast(&gui, "Failed to enable Wi-Fi", e.into());
-                    }
-                } else {
-                    gui.set_wifi_enabling_lock(true);
-                    if let Err(e) = wifi_command_sender.send(wifi::CommandForm {
-                        command_type: wifi::CommandType::Enable,
-                        arguments: None,
-                    }) {
-                        error_toast(&gui, "Failed to disable Wi-Fi", e.into());
-                    }
+                set_users(&gui);
+            }
+        })
+    });
+
+    gui.on_set_default_user({
+        let gui_weak = gui_weak.clone();
+        let boot_config_mutex = boot_config_mutex.clone();
+        guarded!("set_default_user", move |username| {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            match users::set_default(&mut boot_config_mutex.lock().unwrap(), &username) {
+                Ok(()) => {
+                    gui.set_default_user(username.clone());
+                    toast(&gui, &tr_format("Default user is now '{}'", &[&username]));
                 }
+                Err(e) => error_toast(&gui, "Failed to change default user", e),
             }
-        }
+        })
     });
 
-    // Wi-Fi (connect)
-    gui.on_connect_to_wifi_network({
-        let wifi_command_sender = wifi_command_sender.clone();
+    gui.on_create_user({
         let gui_weak = gui_weak.clone();
-        move |network_name, passphrase| {
-            if let Some(gui) = gui_weak.upgrade() {
-                let err_msg = "Failed to connect to network";
-                gui.set_wifi_connecting_lock(true);
-                if passphrase.is_empty() {
-                    if let Err(e) = wifi_command_sender.send(wifi::CommandForm {
-                        command_type: wifi::CommandType::Connect,
-                        arguments: Some(wifi::NetworkForm {
-                            name: network_name.to_string(),
-                            passphrase: None,
-                        }),
-                    }) {
-                        error_toast(&gui, &err_msg, e.into());
+        let boot_config_mutex = boot_config_mutex.clone();
+        guarded!(
+            "create_user",
+            move |username, encrypted, password, confirmation| {
+                let Some(gui) = gui_weak.upgrade() else {
+                    return;
+                };
+                let username = username.to_string();
+                if let Err(e) = users::validate_new_user_name(&username) {
+                    toast(&gui, &e.to_string());
+                    return;
+                }
+                let password = if encrypted {
+                    let password = Secret::new(password.to_string());
+                    let confirmation = Secret::new(confirmation.to_string());
+                    if let Err(e) =
+                        storage_encryption::validate_new_password(&password, &confirmation)
+                    {
+                        toast(&gui, &e.to_string());
+                        return;
                     }
+                    Some(password)
                 } else {
-                    if let Err(e) = wifi_command_sender.send(wifi::CommandForm {
-                        command_type: wifi::CommandType::Connect,
-                        arguments: Some(wifi::NetworkForm {
-                            name: network_name.to_string(),
-                            passphrase: Some(passphrase.to_string()),
-                        }),
-                    }) {
-                        error_toast(&gui, "Failed to connect to network", e.into());
+                    None
+                };
+                gui.set_dialog(DialogType::None);
+                gui.set_enable_ui(false);
+                toast(&gui, "Creating user");
+                let gui_weak = gui_weak.clone();
+                let boot_config_mutex = boot_config_mutex.clone();
+                thread::spawn(move || {
+                    let result = users::create(&username, password.as_ref());
+                    if result.is_ok()
+                        && let Some(password) = password
+                    {
+                        // Same as enabling encryption from the login page
+                        offer_recovery_key(
+                            gui_weak.clone(),
+                            boot_config_mutex,
+                            username.clone(),
+                            password,
+                        );
                     }
-                }
+                    let _ = gui_weak.upgrade_in_event_loop(move |gui| {
+                        gui.set_enable_ui(true);
+                        set_users(&gui);
+                        set_vault_users(&gui);
+                        match result {
+                            Ok(()) => toast(&gui, &tr_format("User '{}' created", &[&username])),
+                            Err(e) => error_toast(&gui, "Failed to create user", e),
+                        }
+                    });
+                });
             }
-        }
+        )
     });
 
-    // Wi-Fi (get networks)
-    gui.on_get_networks({
-        let wifi_command_sender = wifi_command_sender.clone();
+    gui.on_rename_user({
         let gui_weak = gui_weak.clone();
-        move || {
-            if let Some(gui) = gui_weak.upgrade() {
-                gui.set_wifi_scanning_lock(true);
-                if let Err(e) = wifi_command_sender.send(wifi::CommandForm {
-                    command_type: wifi::CommandType::GetNetworks,
-                    arguments: None,
-                }) {
-                    error_toast(&gui, "Failed to scan networks", e.into());
+        let boot_config_mutex = boot_config_mutex.clone();
+        guarded!("rename_user", move |username, new_username| {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            match users::rename(
+                &mut boot_config_mutex.lock().unwrap(),
+                &username,
+                &new_username,
+            ) {
+                Ok(()) => {
+                    if gui.get_default_user() == username {
+                        gui.set_default_user(new_username.clone());
+                    }
+                    toast(
+                        &gui,
+                        &tr_format("User '{}' renamed to '{}'", &[&username, &new_username]),
+                    );
                 }
+                Err(e) => error_toast(&gui, "Failed to rename user", e),
             }
-        }
+            set_users(&gui);
+            set_vault_users(&gui);
+        })
     });
 
-    // Virtual keyboard
-    gui.global::<VirtualKeyboardHandler>().on_key_pressed({
+    gui.on_recovery_key_dismissed({
         let gui_weak = gui_weak.clone();
-        move |key| {
-            if let Some(gui) = gui_weak.upgrade() {
-                gui.window()
-                    .dispatch_event(slint::platform::WindowEvent::KeyPressed { text: key.clone() });
-                gui.window()
-                    .dispatch_event(slint::platform::WindowEvent::KeyReleased { text: key });
+        guarded!("recovery_key_dismissed", move || {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            gui.set_recovery_key(SharedString::new());
+            gui.set_recovery_key_qr_code(Image::default());
+            gui.set_dialog(DialogType::None);
+        })
+    });
+
+    gui.on_enable_encryption({
+        let gui_weak = gui_weak.clone();
+        let boot_config_mutex = boot_config_mutex.clone();
+        guarded!(
+            "enable_encryption",
+            move |username, password, confirmation| {
+                let Some(gui) = gui_weak.upgrade() else {
+                    return;
+                };
+                let password = Secret::new(password.to_string());
+                let confirmation = Secret::new(confirmation.to_string());
+                if let Err(e) = storage_encryption::validate_new_password(&password, &confirmation)
+                {
+                    toast(&gui, &e.to_string());
+                    return;
+                }
+                match storage_encryption::init_vault(&username, &password) {
+                    Ok(()) => {
+                        toast(
+                            &gui,
+                            "Encryption enabled: please log in with the new password",
+                        );
+                        offer_recovery_key(
+                            gui_weak.clone(),
+                            boot_config_mutex.clone(),
+                            username.to_string(),
+                            password,
+                        );
+                    }
+                    Err(e) => error_toast(&gui, "Failed to enable encryption", e),
+                }
             }
-        }
+        )
     });
 
-    // Brightness
-    gui.on_set_brightness_sliders_levels({
+    gui.on_change_password({
         let gui_weak = gui_weak.clone();
-        move || {
-            if let Some(gui) = gui_weak.upgrade() {
-                // Assuming this will not fail. Otherwise, there would probably be something really wrong with the device...
-                gui.set_cool_brightness(
-                    brightness::get_brightness(&brightness::Mode::Cool).unwrap() * 100
-                        / brightness::MAX_BRIGHTNESS,
-                );
-                gui.set_warm_brightness(
-                    brightness::get_brightness(&brightness::Mode::Warm).unwrap() * 100
-                        / brightness::MAX_BRIGHTNESS,
-                );
+        guarded!(
+            "change_password",
+            move |username, old_password, new_password, confirmation| {
+                let Some(gui) = gui_weak.upgrade() else {
+                    return;
+                };
+                let old_password = Secret::new(old_password.to_string());
+                let new_password = Secret::new(new_password.to_string());
+                let confirmation = Secret::new(confirmation.to_string());
+                if let Err(e) =
+                    storage_encryption::validate_new_password(&new_password, &confirmation)
+                {
+                    toast(&gui, &e.to_string());
+                    return;
+                }
+                match change_password(&username, &old_password, &new_password) {
+                    Ok(()) => toast(&gui, "Password changed"),
+                    Err(e) => error_toast(&gui, "Failed to change password", e),
+                }
             }
-        }
+        )
     });
 
-    gui.on_change_cool_brightness({
-        move |value| {
-            let _ = brightness::set_brightness_(
-                value * brightness::MAX_BRIGHTNESS / 100,
-                &brightness::Mode::Cool,
+    gui.on_change_initial_screen_rotation({
+        let gui_weak = gui_weak.clone();
+        let boot_config_mutex = boot_config_mutex.clone();
+        guarded!("change_initial_screen_rotation", move |index| {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            let rotation = match index {
+                0 => ScreenRotation::Cw0,
+                1 => ScreenRotation::Cw90,
+                2 => ScreenRotation::Cw180,
+                3 | _ => ScreenRotation::Cw270,
+            };
+            let mut locked_boot_config = boot_config_mutex.lock().unwrap();
+            change_setting(
+                &gui,
+                &mut locked_boot_config,
+                ConfigSetting::InitialScreenRotation(rotation),
+                &BOOT_CONFIGURATION_PAGE,
             );
-        }
+        })
     });
 
-    gui.on_change_warm_brightness({
-        move |value| {
-            let _ = brightness::set_brightness_(
-                value * brightness::MAX_BRIGHTNESS / 100,
-                &brightness::Mode::Warm,
-            );
-        }
+    // Recovery passcode
+    gui.on_unlock_recovery({
+        let boot_config_mutex = boot_config_mutex.clone();
+        let recovery_gate = recovery_gate.clone();
+        let set_page_sender = set_page_sender.clone();
+        guarded!("unlock_recovery", move || {
+            let mut gate = recovery_gate.lock().unwrap();
+            if gate.unlocked
+                || gate.prompting
+                || gate.failed_attempts >= recovery::PASSCODE_MAX_ATTEMPTS
+            {
+                return;
+            }
+            let Some(passcode_hash) = boot_config_mutex
+                .lock()
+                .unwrap()
+                .system
+                .recovery_passcode_hash
+                .clone()
+            else {
+                return;
+            };
+            gate.prompting = true;
+            let mut failed_attempts = gate.failed_attempts;
+            drop(gate);
+
+            let recovery_gate = recovery_gate.clone();
+            let set_page_sender = set_page_sender.clone();
+            thread::spawn(move || {
+                let outcome = prompt_recovery_passcode(
+                    "Enter the recovery passcode",
+                    &passcode_hash,
+                    &mut failed_attempts,
+                );
+                let mut gate = recovery_gate.lock().unwrap();
+                gate.prompting = false;
+                gate.failed_attempts = failed_attempts;
+                match outcome {
+                    Ok(PinPromptOutcome::Accepted(())) => {
+                        info!("Recovery menu unlocked");
+                        gate.unlocked = true;
+                        drop(gate);
+                        let _ = set_page_sender.send(Page::QuillBoot);
+                    }
+                    Ok(PinPromptOutcome::Exhausted) => {
+                        warn!("Too many wrong recovery passcodes: locking recovery menu");
+                        drop(gate);
+                        let _ = set_page_sender.send(Page::RecoveryLocked);
+                    }
+                    Ok(PinPromptOutcome::Cancelled) => {}
+                    Err(e) => {
+                        error!("{:?}", &e);
+                        notifier::get().toast("Failed to check recovery passcode");
+                    }
+                }
+            });
+        })
     });
+    if !recovery_gate.lock().unwrap().unlocked {
+        gui.invoke_unlock_recovery();
+    }
 
-    // Battery status timer
-    let battery_status_timer = Timer::default();
-    battery_status_timer.start(
-        TimerMode::Repeated,
-        std::time::Duration::from_millis(100),
-        {
+    gui.on_change_recovery_passcode({
+        let gui_weak = gui_weak.clone();
+        let boot_config_mutex = boot_config_mutex.clone();
+        guarded!("change_recovery_passcode", move |remove| {
             let gui_weak = gui_weak.clone();
-            let mut current_level: i32 = -1;
-            let mut current_plug_status = false;
-            move || {
-                if let Ok(new_level) = battery::get_level() {
-                    if let Some(gui) = gui_weak.upgrade() {
-                        gui.set_battery_level(new_level);
-                        if let Ok(charger_plugged_in) = battery::charger_plugged_in() {
-                            let new_plug_status = charger_plugged_in;
-                            if charger_plugged_in {
-                                gui.set_charger_plugged_in(new_plug_status);
-                                if new_plug_status != current_plug_status {
-                                    if let Ok(icon) = Image::load_from_svg_data(include_bytes!(
-                                        "../../icons/battery-charging.svg"
-                                    )) {
-                                        info!("Setting 'Charging' battery icon");
-                                        gui.set_battery_icon(icon);
-                                    }
-                                }
-                            } else {
-                                gui.set_charger_plugged_in(new_plug_status);
-                                if current_level != new_level
-                                    || new_plug_status != current_plug_status
-                                {
-                                    if let Ok(icon) = Image::load_from_svg_data(
-                                        battery::generate_svg_from_level(new_level).as_bytes(),
-                                    ) {
-                                        info!(
-                                            "Changing battery icon for charge level {}",
-                                            new_level
-                                        );
-                                        gui.set_battery_icon(icon);
-                                    }
-                                }
-                            }
-                            current_level = new_level;
-                            current_plug_status = new_plug_status;
-                        } else {
-                            error!("Could not get battery status");
+            let boot_config_mutex = boot_config_mutex.clone();
+            thread::spawn(move || {
+                let notifier = notifier::get();
+                let current_hash = boot_config_mutex
+                    .lock()
+                    .unwrap()
+                    .system
+                    .recovery_passcode_hash
+                    .clone();
+                if let Some(current_hash) = current_hash {
+                    let mut failed_attempts = 0;
+                    match prompt_recovery_passcode(
+                        "Enter the current recovery passcode",
+                        &current_hash,
+                        &mut failed_attempts,
+                    ) {
+                        Ok(PinPromptOutcome::Accepted(())) => {}
+                        Ok(PinPromptOutcome::Exhausted) => {
+                            notifier.toast(
+                                "Too many wrong passcodes: recovery passcode was not changed",
+                            );
+                            return;
+                        }
+                        Ok(PinPromptOutcome::Cancelled) => return,
+                        Err(e) => {
+                            error!("{:?}", &e);
+                            notifier.toast("Failed to check recovery passcode");
+                            return;
                         }
                     }
-                } else {
-                    error!("Could not get battery level");
                 }
-            }
-        },
-    );
 
-    gui.on_login({
-        let gui_weak = gui_weak.clone();
-        let set_page_sender = set_page_sender.clone();
-        let login_credentials_sender = login_credentials_sender.clone();
-        move |username, password| {
-            if let Some(gui) = gui_weak.upgrade() {
-                if let Err(e) = storage_encryption::mount_storage(&username, &password) {
-                    error_toast(&gui, "Login failed: please try again", e.into());
+                let new_passcode = if remove {
+                    None
                 } else {
-                    if let Err(e) = login_credentials_sender.send(LoginForm {
-                        username: username.to_string(),
-                        password: password.to_string(),
-                    }) {
-                        error_toast(&gui, "Failed to send login credentials", e.into());
+                    let ask = |message: &str| {
+                        notifier.ask_pin(&PinRequest {
+                            message: tr(message),
+                            max_length: pin::DEFAULT_PIN_MAX_LENGTH,
+                            error_message: None,
+                        })
+                    };
+                    let Some(new_passcode) = ask("Choose a recovery passcode") else {
+                        return;
+                    };
+                    let Some(confirmation) = ask("Enter the same passcode again") else {
+                        return;
+                    };
+                    if new_passcode != confirmation {
+                        notifier.toast("Passcodes do not match: recovery passcode was not changed");
+                        return;
+                    }
+                    Some(new_passcode)
+                };
+                let mut boot_config = boot_config_mutex.lock().unwrap();
+                match recovery::set_passcode(&mut boot_config, new_passcode.as_ref()) {
+                    Ok(()) => notifier.toast(if remove {
+                        "Recovery passcode removed"
                     } else {
-                        let _ = set_page_sender.send(Page::BootSplash);
+                        "Recovery passcode set"
+                    }),
+                    Err(e) => {
+                        error!("{:?}", &e);
+                        notifier.toast(&e.to_string());
                     }
                 }
+                let passcode_set = boot_config.system.recovery_passcode_hash.is_some();
+                let _ = gui_weak.upgrade_in_event_loop(move |gui| {
+                    gui.set_recovery_passcode_set(passcode_set);
+                });
+            });
+        })
+    });
+
+    // Touchscreen calibration
+    let touch_calibration = Rc::new(RefCell::new(TouchCalibration::new()));
+    let touch_rotation_revert_timer = Rc::new(Timer::default());
+    gui.on_start_touch_calibration({
+        let gui_weak = gui_weak.clone();
+        let touch_calibration = touch_calibration.clone();
+        guarded!("start_touch_calibration", move || {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            let mut calibration = touch_calibration.borrow_mut();
+            calibration.taps.clear();
+            calibration.suggested_rotation = None;
+            gui.set_touch_calibration_step(0);
+            gui.set_touch_calibration_suggestion(false);
+            gui.set_touch_calibration_confirmed(false);
+            gui.set_touch_calibration_message(
+                tr("Tap the circle in each corner as it shows up").into(),
+            );
+        })
+    });
+
+    gui.on_touch_calibration_tap({
+        let gui_weak = gui_weak.clone();
+        let touch_calibration = touch_calibration.clone();
+        let touch_rotation_revert_timer = touch_rotation_revert_timer.clone();
+        guarded!("touch_calibration_tap", move |x, y| {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            let mut calibration = touch_calibration.borrow_mut();
+            calibration.taps.push((x, y));
+            gui.set_touch_calibration_step(calibration.taps.len() as i32);
+            if calibration.taps.len() < TOUCH_CALIBRATION_TARGETS.len() {
+                return;
+            }
+
+            let tested_rotation = calibration.tested_rotation();
+            let suggested_rotation = eink::suggest_touchscreen_rotation(
+                &eink::geometry().with_rotation(&tested_rotation),
+                &TOUCH_CALIBRATION_TARGETS,
+                &calibration.taps,
+            );
+            if calibration.previewing {
+                if suggested_rotation.as_ref() == Some(&tested_rotation) {
+                    gui.set_touch_calibration_confirmed(true);
+                    gui.set_touch_calibration_message(
+                        tr("Touches now land on the targets: keep this rotation?").into(),
+                    );
+                } else {
+                    touch_rotation_revert_timer.stop();
+                    revert_touch_rotation(&gui, &mut calibration);
+                    gui.set_touch_calibration_message(
+                        tr("Touches still miss the targets: rotation reverted").into(),
+                    );
+                }
+                return;
             }
-        }
+
+            let message = match &suggested_rotation {
+                None => tr("Taps do not match any rotation: please try again"),
+                Some(rotation) if rotation == &tested_rotation => tr("Touches land where expected"),
+                Some(rotation) => tr_format(
+                    "Touches match a rotation of {} degrees",
+                    &[&(rotation.quarter_turns() * 90).to_string()],
+                ),
+            };
+            gui.set_touch_calibration_suggestion(
+                suggested_rotation
+                    .as_ref()
+                    .is_some_and(|rotation| rotation != &tested_rotation),
+            );
+            gui.set_touch_calibration_message(message.into());
+            calibration.suggested_rotation = suggested_rotation;
+        })
     });
 
-    gui.on_change_initial_screen_rotation({
-        let boot_config_mutex = boot_config_mutex.clone();
-        move |index| {
-            let mut locked_boot_config = boot_config_mutex.lock().unwrap();
-            match index {
-                0 => locked_boot_config.system.initial_screen_rotation = ScreenRotation::Cw0,
-                1 => locked_boot_config.system.initial_screen_rotation = ScreenRotation::Cw90,
-                2 => locked_boot_config.system.initial_screen_rotation = ScreenRotation::Cw180,
-                3 | _ => locked_boot_config.system.initial_screen_rotation = ScreenRotation::Cw270,
+    gui.on_preview_touch_rotation({
+        let gui_weak = gui_weak.clone();
+        let touch_calibration = touch_calibration.clone();
+        let touch_rotation_revert_timer = touch_rotation_revert_timer.clone();
+        guarded!("preview_touch_rotation", move || {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            let mut calibration = touch_calibration.borrow_mut();
+            let Some(suggested_rotation) = calibration.suggested_rotation.clone() else {
+                return;
+            };
+            if let Err(e) = eink::apply_touchscreen_rotation(&suggested_rotation) {
+                error_toast(&gui, "Failed to apply touchscreen rotation", e);
+                revert_touch_rotation(&gui, &mut calibration);
+                return;
             }
-        }
+            calibration.previewing = true;
+            calibration.taps.clear();
+            gui.set_touch_calibration_previewing(true);
+            gui.set_touch_calibration_confirmed(false);
+            gui.set_touch_calibration_step(0);
+            gui.set_touch_calibration_message(
+                tr_format(
+                    "Tap the circles again to confirm: the rotation is reverted in {} s otherwise",
+                    &[&TOUCH_ROTATION_REVERT_DELAY.as_secs().to_string()],
+                )
+                .into(),
+            );
+
+            let gui_weak = gui_weak.clone();
+            let touch_calibration = touch_calibration.clone();
+            touch_rotation_revert_timer.start(
+                TimerMode::SingleShot,
+                TOUCH_ROTATION_REVERT_DELAY,
+                move || {
+                    let Some(gui) = gui_weak.upgrade() else {
+                        return;
+                    };
+                    let mut calibration = touch_calibration.borrow_mut();
+                    if calibration.previewing {
+                        revert_touch_rotation(&gui, &mut calibration);
+                        gui.set_touch_calibration_message(
+                            tr("Not confirmed in time: rotation reverted").into(),
+                        );
+                    }
+                },
+            );
+        })
+    });
+
+    gui.on_keep_touch_rotation({
+        let gui_weak = gui_weak.clone();
+        let boot_config_mutex = boot_config_mutex.clone();
+        let touch_calibration = touch_calibration.clone();
+        let touch_rotation_revert_timer = touch_rotation_revert_timer.clone();
+        guarded!("keep_touch_rotation", move || {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            touch_rotation_revert_timer.stop();
+            let mut calibration = touch_calibration.borrow_mut();
+            let Some(suggested_rotation) = calibration.suggested_rotation.take() else {
+                return;
+            };
+            calibration.previewing = false;
+            calibration.applied_rotation = suggested_rotation.clone();
+            gui.set_touch_calibration_previewing(false);
+            gui.set_touch_calibration_suggestion(false);
+            change_setting(
+                &gui,
+                &mut boot_config_mutex.lock().unwrap(),
+                ConfigSetting::InitialScreenRotation(suggested_rotation),
+                &BOOT_CONFIGURATION_PAGE,
+            );
+            gui.set_touch_calibration_message(
+                tr("Rotation kept: the display follows it after a reboot").into(),
+            );
+        })
+    });
+
+    gui.on_revert_touch_rotation({
+        let gui_weak = gui_weak.clone();
+        let touch_calibration = touch_calibration.clone();
+        let touch_rotation_revert_timer = touch_rotation_revert_timer.clone();
+        guarded!("revert_touch_rotation", move || {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            touch_rotation_revert_timer.stop();
+            revert_touch_rotation(&gui, &mut touch_calibration.borrow_mut());
+            gui.set_touch_calibration_message(tr("Rotation reverted").into());
+        })
     });
 
     gui.on_generate_splash_wallpaper({
@@ -993,7 +3348,7 @@ pub fn setup_gui(
         let splash_ready_sender = splash_ready_sender.clone();
         let boot_config_mutex = boot_config_mutex.clone();
         let can_shut_down = can_shut_down.clone();
-        move |from_socket| {
+        guarded!("generate_splash_wallpaper", move |from_socket| {
             if let Some(gui) = gui_weak.upgrade() {
                 let shut_down_command = gui.get_shutdown_command();
                 if shut_down_command != RootFsShutDownCommand::Reboot {
@@ -1034,34 +3389,175 @@ pub fn setup_gui(
                     let _ = splash_ready_sender.send(());
                 }
             }
-        }
+        })
     });
 
     gui.on_change_splash_wallpaper_model({
+        let gui_weak = gui_weak.clone();
         let boot_config_mutex = boot_config_mutex.clone();
-        move |wallpaper| {
+        guarded!("change_splash_wallpaper_model", move |wallpaper| {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
             info!("Changing splash wallpaper model to '{}'", &wallpaper);
             let mut locked_boot_config = boot_config_mutex.lock().unwrap();
-            locked_boot_config
-                .system
-                .splash_wallpaper_options
-                .splash_wallpaper = Some(wallpaper.to_string());
-        }
+            change_setting(
+                &gui,
+                &mut locked_boot_config,
+                ConfigSetting::SplashWallpaper(Some(wallpaper.to_string())),
+                &BOOT_CONFIGURATION_PAGE,
+            );
+        })
     });
 
     gui.on_change_timezone({
+        let gui_weak = gui_weak.clone();
+        let boot_config_mutex = boot_config_mutex.clone();
+        guarded!("change_timezone", move |timezone| {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            info!("Changing timezone to '{}'", &timezone);
+            let mut locked_boot_config = boot_config_mutex.lock().unwrap();
+            change_setting(
+                &gui,
+                &mut locked_boot_config,
+                ConfigSetting::Timezone(timezone.to_string()),
+                &BOOT_CONFIGURATION_PAGE,
+            );
+            show_timezone(&gui, &timezone);
+        })
+    });
+
+    gui.on_filter_timezones({
+        let gui_weak = gui_weak.clone();
+        guarded!("filter_timezones", move |search_text| {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            // "new york" finds "America/New_York"
+            let search_text = search_text.to_lowercase().replace(' ', "_");
+            let mut regions: BTreeMap<String, Vec<SharedString>> = BTreeMap::new();
+            for timezone in gui.get_timezones_list().iter() {
+                if timezone.to_lowercase().contains(&search_text) {
+                    regions
+                        .entry(system::timezone_region(&timezone).to_string())
+                        .or_default()
+                        .push(timezone);
+                }
+            }
+            let mut names: Vec<SharedString> = Vec::new();
+            let mut is_region: Vec<bool> = Vec::new();
+            for (region, timezones) in regions {
+                names.push(SharedString::from(region));
+                is_region.push(true);
+                is_region.extend(timezones.iter().map(|_| false));
+                names.extend(timezones);
+            }
+            gui.set_timezone_names(slint::ModelRc::new(slint::VecModel::from(names)));
+            gui.set_timezone_is_region(slint::ModelRc::new(slint::VecModel::from(is_region)));
+        })
+    });
+
+    gui.on_change_language({
+        let gui_weak = gui_weak.clone();
+        let boot_config_mutex = boot_config_mutex.clone();
+        guarded!("change_language", move |index| {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            let Some(language) = Language::ALL.get(index as usize).copied() else {
+                return;
+            };
+            change_setting(
+                &gui,
+                &mut boot_config_mutex.lock().unwrap(),
+                ConfigSetting::Language(language),
+                &BOOT_CONFIGURATION_PAGE,
+            );
+            show_language(&gui, language);
+        })
+    });
+
+    gui.on_change_theme({
+        let gui_weak = gui_weak.clone();
+        let boot_config_mutex = boot_config_mutex.clone();
+        guarded!("change_theme", move |index| {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            let Some(theme) = Theme::ALL.get(index as usize).copied() else {
+                return;
+            };
+            change_setting(
+                &gui,
+                &mut boot_config_mutex.lock().unwrap(),
+                ConfigSetting::Theme(theme),
+                &BOOT_CONFIGURATION_PAGE,
+            );
+            show_theme(&gui, theme);
+        })
+    });
+
+    gui.on_change_keyboard_layout({
+        let gui_weak = gui_weak.clone();
+        let boot_config_mutex = boot_config_mutex.clone();
+        guarded!("change_keyboard_layout", move |index| {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            let Some(keyboard_layout) = KeyboardLayout::ALL.get(index as usize).copied() else {
+                return;
+            };
+            change_setting(
+                &gui,
+                &mut boot_config_mutex.lock().unwrap(),
+                ConfigSetting::KeyboardLayout(keyboard_layout),
+                &BOOT_CONFIGURATION_PAGE,
+            );
+            show_keyboard_layout(&gui, keyboard_layout);
+        })
+    });
+
+    // From the keyboard's own layout key, wherever it is open
+    gui.global::<VirtualKeyboardHandler>().on_layout_changed({
+        let gui_weak = gui_weak.clone();
+        guarded!("layout_changed", move |index| {
+            if let Some(gui) = gui_weak.upgrade() {
+                gui.invoke_change_keyboard_layout(index);
+            }
+        })
+    });
+
+    gui.on_change_hostname({
+        let gui_weak = gui_weak.clone();
         let boot_config_mutex = boot_config_mutex.clone();
-        move |timezone| {
-            info!("Changing timezone to '{}'", &timezone);
-            boot_config_mutex.lock().unwrap().system.timezone = timezone.to_string();
-        }
+        guarded!("change_hostname", move |hostname| {
+            let Some(gui) = gui_weak.upgrade() else {
+                return;
+            };
+            // Rejected as typed rather than silently fixed up
+            if let Err(e) = system::validate_hostname(&hostname) {
+                toast(&gui, &tr_format("Invalid hostname: {}", &[&e.to_string()]));
+                return;
+            }
+            info!("Changing hostname to '{}'", &hostname);
+            change_setting(
+                &gui,
+                &mut boot_config_mutex.lock().unwrap(),
+                ConfigSetting::Hostname(Some(hostname.to_string())),
+                &BOOT_CONFIGURATION_PAGE,
+            );
+            gui.set_hostname(hostname);
+            toast(&gui, "Hostname changed");
+        })
     });
 
     gui.on_refresh_screen({
         let can_shut_down = can_shut_down.clone();
-        move |prepare_shut_down| {
+        guarded!("refresh_screen", move |prepare_shut_down| {
             handle_screen_refresh(prepare_shut_down, can_shut_down.clone());
-        }
+        })
     });
 
     let core_settings_receiver_timer = Timer::default();
@@ -1142,7 +3638,7 @@ pub fn setup_gui(
     gui.on_launch_core_settings({
         let gui_weak = gui_weak.clone();
         let core_settings_sender = core_settings_sender.clone();
-        move || {
+        guarded!("launch_core_settings", move || {
             if let Some(gui) = gui_weak.upgrade() {
                 gui.set_enable_ui(false);
                 if !gui.get_startup_finished() {
@@ -1154,7 +3650,7 @@ pub fn setup_gui(
                 }
                 let _ = core_settings_sender.send(());
             }
-        }
+        })
     });
 
     let netboot_ready_timer = Timer::default();
@@ -1173,29 +3669,920 @@ pub fn setup_gui(
         },
     );
 
+    system::end_phase("GUI start");
     gui.run()?;
 
     Ok(())
 }
 
-fn toast(gui: &AppWindow, message: &str) {
-    gui.set_sticky_toast(false);
-    gui.set_dialog_message(SharedString::from(message));
+fn get_qr_max_modules(boot_config_mutex: &Arc<Mutex<BootConfig>>) -> usize {
+    if let Some(qr_max_modules) = boot_config_mutex.lock().unwrap().system.qr_max_modules {
+        return qr_max_modules as usize;
+    }
+    let min_module_px = (eink::geometry().dpi * QR_MIN_MODULE_MICROMETERS).div_ceil(25400);
+
+    (QR_DISPLAY_SIZE_PX / min_module_px.max(1)) as usize
+}
+
+// Runs off the GUI thread: each batch of results is posted all at once, so that a failure never leaves one half shown
+fn collect_error_details(gui_weak: &Weak<AppWindow>, summary: &[String], qr_max_modules: usize) {
+    let services_summary = services::status_summary();
+    let display_recovery_summary = eink::display_recovery_summary();
+    let input_summary = match input::last_probe() {
+        Some(input_probe) => input_probe.summary(),
+        None => "Input devices: not probed".to_string(),
+    };
+    let qinit_log_file_path = format!("{}/{}", &crate::QINIT_LOG_DIR, &crate::QINIT_LOG_FILE);
+    let lines_to_keep_ui = 150;
+
+    let program_output = fs::read_to_string(&qinit_log_file_path)
+        .ok()
+        .map(|contents| secret::scrub(&contents));
+    let kernel_buffer = read_kernel_buffer_singleshot()
+        .ok()
+        .map(|contents| secret::scrub(&contents));
+    let program_output_ui = program_output
+        .as_ref()
+        .map_or(NOT_AVAILABLE.to_string(), |program_output| {
+            keep_last_lines(&program_output, lines_to_keep_ui)
+        });
+    let kernel_buffer_ui = kernel_buffer
+        .as_ref()
+        .map_or(NOT_AVAILABLE.to_string(), |kernel_buffer| {
+            keep_last_lines(&kernel_buffer, lines_to_keep_ui)
+        });
+    let _ = gui_weak.upgrade_in_event_loop(move |gui| {
+        gui.set_program_output(SharedString::from(&program_output_ui));
+        gui.set_kernel_buffer(SharedString::from(&kernel_buffer_ui));
+    });
+    let program_output = program_output.unwrap_or_default();
+    let kernel_buffer = kernel_buffer.unwrap_or_default();
+
+    // Algorithm to find what number of lines to keep to fit the QR code(s)
+    let mut lines_to_keep_qr = QR_MAX_LOG_LINES;
+    let mut qr_codes = None;
+    info!("Attempting to optimize QR code data");
+    while lines_to_keep_qr > 0 {
+        let mut qr_code_string = summary.join("\n\n");
+        for part in [
+            &input_summary,
+            &services_summary,
+            &display_recovery_summary,
+            &keep_last_lines(&program_output, lines_to_keep_qr),
+            &keep_last_lines(&kernel_buffer, lines_to_keep_qr),
+        ] {
+            qr_code_string.push_str("\n\n");
+            qr_code_string.push_str(&part);
+        }
+        let Ok(data) = compress_string_to_xz(&qr_code_string) else {
+            break;
+        };
+        if let Some((parts, ecc)) = plan_qr_codes(&data, qr_max_modules) {
+            info!(
+                "Keeping {} lines from each logging source for a total of {} compressed bytes split into {} QR code(s) with {:?} error correction",
+                &lines_to_keep_qr,
+                data.len(),
+                parts.len(),
+                &ecc
+            );
+            qr_codes = Some((parts, ecc));
+            break;
+        }
+        lines_to_keep_qr = lines_to_keep_qr.saturating_sub(QR_LOG_LINES_STEP);
+    }
+
+    // SVG data crosses over, as images cannot
+    let Some(qr_code_svgs) = qr_codes.and_then(|(parts, ecc)| {
+        parts
+            .iter()
+            .map(|part| qrcode_generator::to_svg_to_string(&part, ecc, 1024, None::<&str>).ok())
+            .collect::<Option<Vec<String>>>()
+    }) else {
+        return;
+    };
+    let _ = gui_weak.upgrade_in_event_loop(move |gui| {
+        let Some(images) = qr_code_svgs
+            .iter()
+            .map(|qr_code_svg| Image::load_from_svg_data(&qr_code_svg.as_bytes()).ok())
+            .collect::<Option<Vec<Image>>>()
+            .filter(|images| !images.is_empty())
+        else {
+            return;
+        };
+        gui.set_debug_tab_index(QR_CODE_TAB_INDEX);
+        gui.set_qr_code_page(QrCodePage::QrCode);
+        gui.set_debug_qr_code(images[0].clone());
+        gui.set_debug_qr_code_index(0);
+        gui.set_debug_qr_code_count(images.len() as i32);
+        DEBUG_QR_CODES.set(images);
+    });
+}
+
+fn qr_code_fits(data: &[u8], ecc: QrCodeEcc, max_modules: usize) -> bool {
+    match qrcode_generator::to_matrix(&data, ecc) {
+        Ok(matrix) => matrix.len() <= max_modules,
+        Err(_) => false,
+    }
+}
+
+// Uses as few QR codes as possible, preferring Medium error correction when it does not require more of them
+fn plan_qr_codes(data: &[u8], max_modules: usize) -> Option<(Vec<Vec<u8>>, QrCodeEcc)> {
+    let session_id = qr::new_session_id();
+    for parts_count in 1..=qr::MAX_PARTS {
+        let Ok(parts) = qr::split_payload(&data, session_id, parts_count) else {
+            break;
+        };
+        for ecc in [QrCodeEcc::Medium, QrCodeEcc::Low] {
+            if parts
+                .iter()
+                .all(|part| qr_code_fits(&part, ecc, max_modules))
+            {
+                return Some((parts, ecc));
+            }
+        }
+    }
+
+    None
+}
+
+// Label and value pairs for the About page, on top of the version string: anything that cannot be read is shown as unknown
+fn about_rows(boot_config: &BootConfig) -> Vec<(&'static str, String)> {
+    let or_unknown =
+        |value: Result<String>| value.unwrap_or_else(|_| networking::UNKNOWN.to_string());
+    let mut rows = vec![(
+        "Kernel version",
+        or_unknown(
+            fs::read_to_string(&KERNEL_VERSION_PATH)
+                .map(|kernel_version| kernel_version.trim().to_string())
+                .map_err(anyhow::Error::from),
+        ),
+    )];
+    rows.push((
+        "Device ID",
+        boot_config
+            .system
+            .device_id
+            .clone()
+            .unwrap_or(networking::UNKNOWN.to_string()),
+    ));
+    for (label, mountpoint) in [
+        ("Boot partition", &libqinit::BOOT_PART_MOUNTPOINT),
+        ("Main partition", &libqinit::MAIN_PART_MOUNTPOINT),
+    ] {
+        rows.push((
+            label,
+            or_unknown(system::disk_usage(&mountpoint).map(|usage| usage.summary())),
+        ));
+    }
+    rows.push((
+        "Battery level",
+        or_unknown(battery::get_level().map(|level| format!("{} %", level))),
+    ));
+    rows.push((
+        "Battery voltage",
+        or_unknown(battery::get_voltage_mv().map(|voltage| format!("{} mV", voltage))),
+    ));
+    rows.push((
+        "Charger",
+        or_unknown(battery::charger_plugged_in().map(|plugged_in| {
+            if plugged_in {
+                "Plugged in".to_string()
+            } else {
+                "Not plugged in".to_string()
+            }
+        })),
+    ));
+    // The rest of the interface details are in the Wi-Fi dialog
+    rows.extend(
+        networking::get_if_details(&wifi::WIFI_IF)
+            .rows()
+            .into_iter()
+            .take(2),
+    );
+    rows.push((
+        "Screen rotation",
+        format!(
+            "{}°",
+            boot_config.system.initial_screen_rotation.quarter_turns() * 90
+        ),
+    ));
+
+    rows
+}
+
+// Run in this order, whichever are selected
+#[derive(Debug, Clone, Copy)]
+enum FactoryResetStep {
+    SystemChanges,
+    BootConfiguration,
+    UserData,
+}
+
+impl FactoryResetStep {
+    fn label(&self) -> &'static str {
+        match self {
+            FactoryResetStep::SystemChanges => "Reset system changes",
+            FactoryResetStep::BootConfiguration => "Reset boot configuration",
+            FactoryResetStep::UserData => "Erase all user data",
+        }
+    }
+}
+
+// Until the recovery passcode is entered, only the prompt and the locked page may be shown
+struct RecoveryGate {
+    unlocked: bool,
+    // Across prompts: cancelling one does not give more attempts
+    failed_attempts: u32,
+    prompting: bool,
+}
+
+impl RecoveryGate {
+    // Fatal errors are shown regardless
+    fn allows(&self, page: Page) -> bool {
+        self.unlocked
+            || matches!(
+                page,
+                Page::RecoveryPasscode | Page::RecoveryLocked | Page::Error
+            )
+    }
+}
+
+// Blocks until answered: must never be called from the GUI thread
+fn prompt_recovery_passcode(
+    message: &str,
+    stored_hash: &str,
+    failed_attempts: &mut u32,
+) -> Result<PinPromptOutcome<()>> {
+    if *failed_attempts >= recovery::PASSCODE_MAX_ATTEMPTS {
+        return Ok(PinPromptOutcome::Exhausted);
+    }
+
+    pin::prompt_and_verify(&tr(message), |passcode| {
+        if recovery::verify_passcode(&passcode, &stored_hash)? {
+            *failed_attempts = 0;
+            return Ok(PinCheck::Accepted(()));
+        }
+        *failed_attempts += 1;
+        warn!(
+            "Wrong recovery passcode ({} of {} attempts)",
+            &failed_attempts,
+            &recovery::PASSCODE_MAX_ATTEMPTS
+        );
+        if *failed_attempts >= recovery::PASSCODE_MAX_ATTEMPTS {
+            return Ok(PinCheck::Exhausted);
+        }
+
+        Ok(PinCheck::Rejected(tr_format(
+            "Wrong passcode: {} attempt(s) left",
+            &[&(recovery::PASSCODE_MAX_ATTEMPTS - *failed_attempts).to_string()],
+        )))
+    })
+}
+
+// Taps on the touchscreen calibration page, and the rotation they call for
+struct TouchCalibration {
+    taps: Vec<(f32, f32)>,
+    suggested_rotation: Option<ScreenRotation>,
+    // What the touchscreen rules were last written with: the one from boot until a rotation is kept
+    applied_rotation: ScreenRotation,
+    // Set while the suggested rotation is applied, until it is kept or reverted
+    previewing: bool,
+}
+
+impl TouchCalibration {
+    fn new() -> TouchCalibration {
+        TouchCalibration {
+            taps: Vec::new(),
+            suggested_rotation: None,
+            applied_rotation: eink::geometry().rotation,
+            previewing: false,
+        }
+    }
+
+    // Taps land according to this one
+    fn tested_rotation(&self) -> ScreenRotation {
+        match &self.suggested_rotation {
+            Some(suggested_rotation) if self.previewing => suggested_rotation.clone(),
+            _ => self.applied_rotation.clone(),
+        }
+    }
+}
+
+// The suggestion is left as is, so that it can be tried again: targets stay hidden until the calibration starts over
+fn revert_touch_rotation(gui: &AppWindow, calibration: &mut TouchCalibration) {
+    calibration.previewing = false;
+    calibration.taps.clear();
+    gui.set_touch_calibration_previewing(false);
+    gui.set_touch_calibration_confirmed(false);
+    gui.set_touch_calibration_step(TOUCH_CALIBRATION_TARGETS.len() as i32);
+    if let Err(e) = eink::apply_touchscreen_rotation(&calibration.applied_rotation) {
+        error_toast(&gui, "Failed to revert touchscreen rotation", e);
+    }
+}
+
+// Digits never leave Rust: the keypad is only told how many of them were entered
+struct PinPrompt {
+    digits: String,
+    max_length: usize,
+    answer_sender: Sender<Option<Secret>>,
+}
+
+impl PinPrompt {
+    fn new(max_length: usize, answer_sender: Sender<Option<Secret>>) -> PinPrompt {
+        PinPrompt {
+            digits: String::new(),
+            max_length,
+            answer_sender,
+        }
+    }
+
+    fn show(&self, gui: &AppWindow, request: &PinRequest) {
+        gui.set_dialog_message(SharedString::from(&request.message));
+        gui.set_pin_max_length(self.max_length as i32);
+        gui.set_pin_digits_count(0);
+        gui.set_pin_error_message(SharedString::from(
+            request.error_message.as_deref().unwrap_or(""),
+        ));
+        gui.set_pin_error(request.error_message.is_some());
+        gui.set_dialog(DialogType::PinEntry);
+    }
+
+    fn push_digit(&mut self, gui: &AppWindow, digit: i32) {
+        if self.digits.len() < self.max_length
+            && let Some(digit) = char::from_digit(digit as u32, 10)
+        {
+            self.digits.push(digit);
+        }
+        gui.set_pin_error(false);
+        gui.set_pin_digits_count(self.digits.len() as i32);
+    }
+
+    fn pop_digit(&mut self, gui: &AppWindow) {
+        self.digits.pop();
+        gui.set_pin_error(false);
+        gui.set_pin_digits_count(self.digits.len() as i32);
+    }
+
+    fn submit(mut self) {
+        let pin = Secret::new(std::mem::take(&mut self.digits));
+        let _ = self.answer_sender.send(Some(pin));
+    }
+
+    fn cancel(self) {
+        let _ = self.answer_sender.send(None);
+    }
+}
+
+fn refresh_file_browser(
+    gui: &AppWindow,
+    current_file_browser: &Rc<RefCell<Option<(FileBrowser, file_browser::Listing)>>>,
+    mut file_browser: FileBrowser,
+) {
+    let listing = match file_browser.list() {
+        Ok(listing) => listing,
+        Err(e) => {
+            error_toast(&gui, "Failed to list files", e);
+            file_browser.back();
+            file_browser.list().unwrap_or(file_browser::Listing {
+                entries: Vec::new(),
+                page: 0,
+                pages_count: 1,
+            })
+        }
+    };
+    gui.set_file_browser_path(SharedString::from(
+        file_browser
+            .current_dir()
+            .map(|path| path.display().to_string())
+            .unwrap_or_default(),
+    ));
+    let names: Vec<SharedString> = listing
+        .entries
+        .iter()
+        .map(|entry| SharedString::from(&entry.name))
+        .collect();
+    let is_dir: Vec<bool> = listing.entries.iter().map(|entry| entry.is_dir).collect();
+    let sizes: Vec<SharedString> = listing
+        .entries
+        .iter()
+        .map(|entry| SharedString::from(entry.size_label()))
+        .collect();
+    let dates: Vec<SharedString> = listing
+        .entries
+        .iter()
+        .map(|entry| SharedString::from(entry.date_label()))
+        .collect();
+    gui.set_file_browser_names(slint::ModelRc::new(slint::VecModel::from(names)));
+    gui.set_file_browser_is_dir(slint::ModelRc::new(slint::VecModel::from(is_dir)));
+    gui.set_file_browser_sizes(slint::ModelRc::new(slint::VecModel::from(sizes)));
+    gui.set_file_browser_dates(slint::ModelRc::new(slint::VecModel::from(dates)));
+    gui.set_file_browser_page(listing.page as i32);
+    gui.set_file_browser_pages_count(listing.pages_count as i32);
+    *current_file_browser.borrow_mut() = Some((file_browser, listing));
+}
+
+// Scrubbed like the fatal error page does: these may end up on a USB drive
+fn read_logs() -> (String, String) {
+    let qinit_log_file_path = format!("{}/{}", &crate::QINIT_LOG_DIR, &crate::QINIT_LOG_FILE);
+    let program_output = match fs::read_to_string(&qinit_log_file_path) {
+        Ok(contents) => secret::scrub(&contents),
+        Err(e) => {
+            warn!("Failed to read '{}': {}", &qinit_log_file_path, &e);
+            String::new()
+        }
+    };
+    let kernel_buffer = match read_kernel_buffer_singleshot() {
+        Ok(contents) => secret::scrub(&contents),
+        Err(e) => {
+            warn!("Failed to read kernel buffer: {}", &e);
+            String::new()
+        }
+    };
+
+    (program_output, kernel_buffer)
+}
+
+fn show_logs(gui: &AppWindow, logs: &(String, String)) {
+    let log = match gui.get_logs_tab_index() {
+        KERNEL_BUFFER_TAB_INDEX => &logs.1,
+        _ => &logs.0,
+    };
+    if log.is_empty() {
+        gui.set_logs_text(SharedString::from(tr(NOT_AVAILABLE)));
+        gui.set_logs_pages_count(1);
+        return;
+    }
+    let (page_text, pages_count) = system::paginate(
+        &log,
+        LOG_VIEWER_PAGE_LINES,
+        gui.get_logs_page().max(0) as usize,
+    );
+    gui.set_logs_text(SharedString::from(&page_text));
+    gui.set_logs_pages_count(pages_count as i32);
+}
+
+// Local time, as the user would have asked for it
+fn power_on_alarm_label() -> SharedString {
+    libqinit::system::power_on_alarm()
+        .and_then(|epoch_seconds| DateTime::from_timestamp(epoch_seconds, 0))
+        .map(|time| {
+            SharedString::from(
+                time.with_timezone(&Local)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string(),
+            )
+        })
+        .unwrap_or_default()
+}
+
+// System password and encrypted storage password change together: a vault that still opens with the old password would lock the user out of its home
+fn change_password(username: &str, old_password: &Secret, new_password: &Secret) -> Result<()> {
+    // Nothing else would prove that whoever is changing the system password knows the old one
+    if !storage_encryption::get_users_using_storage_encryption()?
+        .iter()
+        .any(|user| user == username)
+    {
+        return Err(anyhow::anyhow!(
+            "User '{}' does not use storage encryption",
+            &username
+        ));
+    }
+    storage_encryption::check_password(&username, &old_password)?;
+
+    rootfs::change_user_password(&username, &new_password)?;
+    if let Err(e) = storage_encryption::change_password(&username, &old_password, &new_password) {
+        if let Err(rollback_error) = rootfs::change_user_password(&username, &old_password) {
+            error!(
+                "Failed to restore system password of user '{}': {:?}",
+                &username, &rollback_error
+            );
+        }
+        return Err(e);
+    }
+    // It only holds the old password now: PIN unlock would fail every time
+    if let Err(e) = pin::disable_quick_unlock(&username) {
+        error!("{:?}", &e);
+    }
+
+    Ok(())
+}
+
+// Also on a separate thread, for the confirmation dialog
+fn offer_recovery_key(
+    gui_weak: Weak<AppWindow>,
+    boot_config_mutex: Arc<Mutex<BootConfig>>,
+    username: String,
+    password: Secret,
+) {
+    thread::spawn(move || {
+        let notifier = notifier::get();
+        let recovery_key = if notifier.ask_confirmation(
+            "Create a recovery key? It unlocks the encrypted storage of this user should the password ever be forgotten.",
+        ) {
+            match storage_encryption::create_recovery_key(&username, &password) {
+                Ok(recovery_key) => Some(recovery_key),
+                Err(e) => {
+                    error!("{:?}", &e);
+                    notifier.toast("Failed to create recovery key");
+                    return;
+                }
+            }
+        } else {
+            None
+        };
+        if let Err(e) = storage_encryption::record_recovery_key(
+            &mut boot_config_mutex.lock().unwrap(),
+            &username,
+            recovery_key.as_ref(),
+        ) {
+            error!("{:?}", &e);
+        }
+        if let Some(recovery_key) = recovery_key {
+            let _ = gui_weak.upgrade_in_event_loop(move |gui| {
+                show_recovery_key(&gui, &username, &recovery_key)
+            });
+        }
+    });
+}
+
+// Shown only once: nothing able to unlock the storage is kept around
+fn show_recovery_key(gui: &AppWindow, username: &str, recovery_key: &Secret) {
+    gui.set_encryption_user(SharedString::from(username));
+    gui.set_recovery_key(SharedString::from(recovery_key.expose()));
+    if let Ok(qr_code_svg) = qrcode_generator::to_svg_to_string(
+        recovery_key.expose(),
+        QrCodeEcc::Medium,
+        1024,
+        None::<&str>,
+    ) && let Ok(qr_code) = Image::load_from_svg_data(&qr_code_svg.as_bytes())
+    {
+        gui.set_recovery_key_qr_code(qr_code);
+    } else {
+        error!("Failed to generate recovery key QR code");
+    }
+    gui.set_dialog(DialogType::RecoveryKey);
+}
+
+// Confirmation dialogs block until answered, which cannot happen on the GUI thread
+fn offer_vault_config_restore(username: String) {
+    thread::spawn(move || {
+        let notifier = notifier::get();
+        if !notifier.ask_confirmation(
+            "The encrypted storage configuration of this user is corrupted, so the password could not be checked. Restore it from its automatic backup?",
+        ) {
+            return;
+        }
+        match storage_encryption::restore_config_backup(&username) {
+            Ok(()) => notifier.toast("Configuration restored: please log in again"),
+            Err(e) => {
+                error!("{:?}", &e);
+                notifier.toast("Failed to restore encrypted storage configuration");
+            }
+        }
+    });
+}
+
+fn rotation_list_index(rotation: &ScreenRotation) -> i32 {
+    match rotation {
+        ScreenRotation::Cw0 => 0,
+        ScreenRotation::Cw90 => 1,
+        ScreenRotation::Cw180 => 2,
+        ScreenRotation::Cw270 => 3,
+    }
+}
+
+fn show_pending_changes(gui: &AppWindow) {
+    let labels: Vec<SharedString> = boot_config::session_changes()
+        .iter()
+        .map(|change| SharedString::from(change.label()))
+        .collect();
+    gui.set_pending_changes(slint::ModelRc::new(slint::VecModel::from(labels)));
+}
+
+// Goes through the same validation as undoing does, and keeps the pending changes drawer up to date
+fn change_setting(
+    gui: &AppWindow,
+    boot_config: &mut BootConfig,
+    setting: ConfigSetting,
+    source_page: &str,
+) {
+    if let Err(e) = boot_config::change_setting(boot_config, setting, &source_page) {
+        error_toast(&gui, "Failed to change setting", e);
+    }
+    show_pending_changes(&gui);
+}
+
+// Puts the control of an undone setting back in line with the boot configuration
+fn show_setting(gui: &AppWindow, setting: &ConfigSetting, boot_config: &BootConfig) {
+    match setting {
+        ConfigSetting::PersistentStorage(enabled) => gui.set_persistent_rootfs(*enabled),
+        ConfigSetting::RootFSSlot { slot, .. } => {
+            if let Some(slot) = slot {
+                gui.set_rootfs_slot(SharedString::from(format!("{:?}", &slot)));
+            }
+        }
+        ConfigSetting::InitialScreenRotation(rotation) => {
+            gui.set_orientations_list_index(rotation_list_index(&rotation))
+        }
+        ConfigSetting::SplashWallpaper(wallpaper) => {
+            if let Some(index) = wallpaper.as_ref().and_then(|wallpaper| {
+                splash::WALLPAPER_MODELS_LIST
+                    .iter()
+                    .position(|name| name == wallpaper)
+            }) {
+                gui.set_splash_wallpaper_models_list_index(index as i32);
+            }
+        }
+        ConfigSetting::Timezone(timezone) => show_timezone(&gui, &timezone),
+        ConfigSetting::Hostname(_) => {
+            gui.set_hostname(SharedString::from(system::hostname(&boot_config)))
+        }
+        ConfigSetting::Language(language) => show_language(&gui, *language),
+        ConfigSetting::UiScale(ui_scale) => show_ui_scale(&gui, *ui_scale),
+        ConfigSetting::Theme(theme) => show_theme(&gui, *theme),
+        ConfigSetting::KeyboardLayout(keyboard_layout) => {
+            show_keyboard_layout(&gui, *keyboard_layout)
+        }
+    }
+}
+
+// Applied right away, like the timezone
+fn show_language(gui: &AppWindow, language: Language) {
+    if let Some(index) = Language::ALL.iter().position(|other| *other == language) {
+        gui.set_languages_list_index(index as i32);
+    }
+    if let Err(e) = translations::set_language(language) {
+        error_toast(&gui, "Failed to switch language", e);
+    }
+    // Unlike the languages, the GUI scales are named in the language shown
+    let ui_scales: Vec<SharedString> = UiScale::ALL
+        .iter()
+        .map(|ui_scale| SharedString::from(tr(ui_scale.name())))
+        .collect();
+    gui.set_ui_scales_list(slint::ModelRc::new(slint::VecModel::from(ui_scales)));
+    let themes: Vec<SharedString> = Theme::ALL
+        .iter()
+        .map(|theme| SharedString::from(tr(theme.name())))
+        .collect();
+    gui.set_themes_list(slint::ModelRc::new(slint::VecModel::from(themes)));
+}
+
+// For the rest of the session: the language setting of the boot configuration is left untouched
+fn apply_session_language(gui: &AppWindow, preferences: &user_preferences::UserPreferences) {
+    let Some(code) = &preferences.language else {
+        return;
+    };
+    match Language::ALL
+        .into_iter()
+        .find(|language| language.code() == code)
+    {
+        Some(language) => show_language(&gui, language),
+        None => warn!("Ignoring unknown preferred language '{}'", &code),
+    }
+}
+
+// Scaling factors above 1 also switch layouts to their roomier variants: text alone grows past Large
+fn show_ui_scale(gui: &AppWindow, ui_scale: UiScale) {
+    if let Some(index) = UiScale::ALL.iter().position(|other| *other == ui_scale) {
+        gui.set_ui_scales_list_index(index as i32);
+    }
+    let (scaling_factor, button_scaling_multiplier, text_scale) = match ui_scale {
+        UiScale::Small => (1.0, 1.0, 0.85),
+        UiScale::Medium => (1.0, 1.0, 1.0),
+        UiScale::Large => (1.25, 0.8, 1.0),
+        UiScale::ExtraLarge => (1.25, 1.0, 1.2),
+    };
+    gui.set_scaling_factor(scaling_factor);
+    gui.set_button_scaling_multiplier(button_scaling_multiplier);
+    gui.set_text_scale(text_scale);
+}
+
+// The palette lives in the Slint code: icons follow it through colorize
+fn show_theme(gui: &AppWindow, theme: Theme) {
+    if let Some(index) = Theme::ALL.iter().position(|other| *other == theme) {
+        gui.set_themes_list_index(index as i32);
+    }
+    gui.set_dark_mode(theme == Theme::Dark);
+}
+
+// The list index is bound to the layout the keyboard shows
+fn show_keyboard_layout(gui: &AppWindow, keyboard_layout: KeyboardLayout) {
+    if let Some(index) = KeyboardLayout::ALL
+        .iter()
+        .position(|other| *other == keyboard_layout)
+    {
+        gui.set_keyboard_layouts_list_index(index as i32);
+    }
+}
+
+// Slint's special keys (arrows, backspace...) are either control characters or in the private use area
+fn is_printable_key(key: &str) -> bool {
+    !key.is_empty()
+        && key.chars().all(|character| {
+            !character.is_control() && !('\u{f700}'..='\u{f8ff}').contains(&character)
+        })
+}
+
+// Names the callback a panic happened in: with panic = "abort" (in release builds), this is the only trace left behind
+fn report_callback_panics() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        match CURRENT_CALLBACK.with(|current| current.get()) {
+            Some(name) => error!("Panic in GUI callback '{}': {}", &name, &info),
+            None => default_hook(info),
+        }
+    }));
+}
+
+// A panic is reported and the callback returns its default value, which keeps the event loop alive, provided that panics unwind
+fn run_guarded<R: Default>(name: &'static str, body: impl FnOnce() -> R) -> R {
+    // Callbacks may invoke each other
+    let previous = CURRENT_CALLBACK.with(|current| current.replace(Some(name)));
+    let result = panic::catch_unwind(AssertUnwindSafe(body));
+    CURRENT_CALLBACK.with(|current| current.set(previous));
+
+    result.unwrap_or_else(|_| {
+        notifier::get().toast(&format!("Internal error in '{}'", &name));
+        R::default()
+    })
+}
+
+// Rounded up to 5 seconds: under that, the 'Almost ready' phase says enough
+fn time_left_text(time_left: Option<Duration>) -> String {
+    match time_left.map(|time_left| time_left.as_secs_f32().ceil() as u64) {
+        Some(secs) if secs >= BOOT_TIME_LEFT_STEP_SECS => tr_format(
+            "about {} s left",
+            &[&secs.next_multiple_of(BOOT_TIME_LEFT_STEP_SECS).to_string()],
+        ),
+        _ => String::new(),
+    }
+}
+
+fn toast_text(message: &str, count: usize) -> String {
+    if count > 1 {
+        format!("{} (x{})", &message, count)
+    } else {
+        message.to_string()
+    }
+}
+
+fn show_toast(gui: &AppWindow, message: String, count: usize, sticky: bool) {
+    gui.set_sticky_toast(sticky);
+    gui.set_dialog_millis_count(0);
+    gui.set_dialog_message(SharedString::from(toast_text(&message, count)));
     gui.set_dialog(DialogType::Toast);
+    SHOWN_TOAST.set(Some((message, count)));
+}
+
+// Replaces whatever dialog is open, like a toast coming in always did
+fn show_next_toast(gui: &AppWindow) {
+    match TOAST_QUEUE.with_borrow_mut(|queue| queue.pop_front()) {
+        Some((message, count)) => show_toast(&gui, message, count, false),
+        None => {
+            SHOWN_TOAST.set(None);
+            if gui.get_dialog() == DialogType::Toast {
+                gui.set_dialog(DialogType::None);
+            }
+        }
+    }
+}
+
+// A sticky toast stays until the next toast comes in, which clears it: what was queued before shows first
+fn queue_toast(gui: &AppWindow, message: String) {
+    let toast_shown = gui.get_dialog() == DialogType::Toast;
+    let sticky_shown = toast_shown && gui.get_sticky_toast();
+    let repeats_shown = SHOWN_TOAST.with_borrow(|shown| {
+        shown.as_ref().is_some_and(|(shown_message, count)| {
+            shown_message == &message
+                && gui.get_dialog_message().as_str() == toast_text(&shown_message, *count)
+        })
+    });
+    if toast_shown
+        && !sticky_shown
+        && repeats_shown
+        && TOAST_QUEUE.with_borrow(|queue| queue.is_empty())
+    {
+        let count =
+            SHOWN_TOAST.with_borrow(|shown| shown.as_ref().map_or(1, |(_, count)| count + 1));
+        show_toast(&gui, message, count, false);
+        return;
+    }
+
+    TOAST_QUEUE.with_borrow_mut(|queue| {
+        if let Some((last_message, count)) = queue.back_mut()
+            && last_message == &message
+        {
+            *count += 1;
+            return;
+        }
+        if queue.len() >= TOAST_QUEUE_CAPACITY {
+            queue.pop_front();
+        }
+        queue.push_back((message, 1));
+    });
+    // Whatever comes in while an action is being confirmed waits for the dialog to close
+    if (!toast_shown || sticky_shown) && gui.get_dialog() != DialogType::ActionConfirmation {
+        show_next_toast(&gui);
+    }
+}
+
+// Messages are logged in English, whatever the language shown
+// A toast it replaces is shown again once the dialog is closed
+fn show_action_confirmation(
+    gui: &AppWindow,
+    action: ConfirmableAction,
+    title: &str,
+    message: &str,
+    confirm_text: &str,
+    busy_message: &str,
+) {
+    if gui.get_dialog() == DialogType::Toast
+        && let Some(shown) = SHOWN_TOAST.take()
+    {
+        TOAST_QUEUE.with_borrow_mut(|queue| queue.push_front(shown));
+    }
+    gui.set_confirmation_action(action);
+    gui.set_confirmation_title(SharedString::from(title));
+    gui.set_confirmation_message(SharedString::from(message));
+    gui.set_confirmation_confirm_text(SharedString::from(confirm_text));
+    gui.set_confirmation_busy_message(SharedString::from(busy_message));
+    gui.set_confirmation_running(false);
+    gui.set_dialog(DialogType::ActionConfirmation);
+}
+
+// Queued toasts show up again from there
+fn close_action_confirmation(gui: &AppWindow) {
+    gui.set_confirmation_running(false);
+    gui.set_confirmation_action(ConfirmableAction::None);
+    if gui.get_dialog() == DialogType::ActionConfirmation {
+        gui.set_dialog(DialogType::None);
+    }
+}
+
+fn toast(gui: &AppWindow, message: &str) {
+    queue_toast(&gui, tr(message));
     info!("{}", &message);
 }
 
 fn error_toast(gui: &AppWindow, message: &str, e: anyhow::Error) {
-    gui.set_sticky_toast(false);
-    gui.set_dialog_message(SharedString::from(message));
-    gui.set_dialog(DialogType::Toast);
+    queue_toast(&gui, tr(message));
     error!("{}: {}", &message, e);
 }
 
+// Jumps the queue, and holds it until cleared
+fn sticky_toast(gui: &AppWindow, message: &str) {
+    if gui.get_dialog() == DialogType::ActionConfirmation {
+        queue_toast(&gui, tr(message));
+        info!("{}", &message);
+        return;
+    }
+    // An interrupted toast gets its full time again afterwards
+    if gui.get_dialog() == DialogType::Toast
+        && !gui.get_sticky_toast()
+        && let Some(shown) = SHOWN_TOAST.take()
+    {
+        TOAST_QUEUE.with_borrow_mut(|queue| queue.push_front(shown));
+    }
+    show_toast(&gui, tr(message), 1, true);
+    info!("{}", &message);
+}
+
+// The confirmation dialog shows it is busy until the vault is gone
+fn erase_user_data(gui: &AppWindow, boot_config_mutex: &Arc<Mutex<BootConfig>>, username: String) {
+    gui.set_enable_ui(false);
+    let gui_weak = gui.as_weak();
+    let boot_config_mutex = boot_config_mutex.clone();
+    thread::spawn(move || {
+        let result =
+            storage_encryption::destroy_vault(&mut boot_config_mutex.lock().unwrap(), &username);
+        let default_user_cleared = boot_config_mutex
+            .lock()
+            .unwrap()
+            .system
+            .default_user
+            .is_none();
+        let _ = gui_weak.upgrade_in_event_loop(move |gui| {
+            close_action_confirmation(&gui);
+            gui.set_enable_ui(true);
+            set_vault_users(&gui);
+            match result {
+                Ok(()) => {
+                    if default_user_cleared {
+                        gui.set_default_user(SharedString::new());
+                    }
+                    toast(&gui, &tr_format("Data of user '{}' erased", &[&username]));
+                }
+                Err(e) => error_toast(&gui, "Failed to erase user data", e),
+            }
+        });
+    });
+}
+
 fn boot_normal(
     gui: &AppWindow,
     boot_sender: &Sender<BootCommandForm>,
     set_page_sender: &Sender<Page>,
+    shutdown_scheduler: &ShutdownScheduler,
     default_user: &str,
     first_boot_done: bool,
     login_credentials_sender: Sender<LoginForm>,
@@ -1203,6 +4590,9 @@ fn boot_normal(
 ) -> Result<()> {
     let mut wait_for_login = false;
     let default_user = default_user.to_string();
+    shutdown_scheduler.cancel();
+    // Every path below sends BootCommand::NormalBoot
+    gui.set_boot_started(true);
 
     if first_boot_done {
         let encryption_users_list = storage_encryption::get_users_using_storage_encryption()?;
@@ -1225,8 +4615,11 @@ fn boot_normal(
                 });
                 storage_encryption::mount_storage(
                     &default_user,
-                    &storage_encryption::DISABLED_MODE_PASSWORD,
+                    &Credential::Password(Secret::new(
+                        storage_encryption::DISABLED_MODE_PASSWORD.to_string(),
+                    )),
                 )?;
+                apply_session_language(&gui, &user_preferences::read_and_apply(&default_user));
                 if let Err(e) = login_credentials_sender.send(LoginForm {
                     username: default_user,
                     password: storage_encryption::DISABLED_MODE_PASSWORD.to_string(),
@@ -1286,17 +4679,17 @@ fn set_wallpaper_splash_text(gui: &AppWindow, shut_down_type: &PrimitiveShutDown
     match shut_down_type {
         PrimitiveShutDownType::PowerOff => {
             let current_time: DateTime<Local> = Local::now();
-            gui.set_splash_wallpaper_text(SharedString::from("Powered off"));
+            gui.set_splash_wallpaper_text(SharedString::from(tr("Powered off")));
             gui.set_splash_wallpaper_date_time_information(SharedString::from(
                 current_time.format("%d/%m").to_string(),
             ));
         }
         PrimitiveShutDownType::Reboot => {
-            gui.set_splash_wallpaper_text(SharedString::from("Rebooting"));
+            gui.set_splash_wallpaper_text(SharedString::from(tr("Rebooting")));
             gui.set_splash_wallpaper_date_time_information(gui.get_current_time());
         }
         PrimitiveShutDownType::Sleep => {
-            gui.set_splash_wallpaper_text(SharedString::from("Sleeping"));
+            gui.set_splash_wallpaper_text(SharedString::from(tr("Sleeping")));
             gui.set_splash_wallpaper_date_time_information(gui.get_current_time());
         }
     }
@@ -1345,6 +4738,51 @@ fn set_default_user_from_boot_config(gui: &AppWindow, boot_config: Arc<Mutex<Boo
     }
 }
 
+// Sent back by the login worker thread, along with why mounting failed if it did
+struct LoginAttempt {
+    username: String,
+    password: Secret,
+    result: Result<(), (anyhow::Error, VaultStatus)>,
+}
+
+// Applied right away, so that the clock in the status bar shows local time without waiting for the next boot
+fn show_timezone(gui: &AppWindow, timezone: &str) {
+    gui.set_current_timezone(SharedString::from(timezone));
+    // Simulator builds leave the host's clock alone
+    if cfg!(feature = "gui_only") {
+        return;
+    }
+    if let Err(e) = system::set_timezone(&timezone) {
+        error_toast(&gui, "Failed to set timezone", e);
+    }
+}
+
+fn set_vault_users(gui: &AppWindow) {
+    match storage_encryption::get_users_using_storage_encryption() {
+        Ok(users) => {
+            let users: Vec<SharedString> = users.iter().map(SharedString::from).collect();
+            gui.set_vault_users(slint::ModelRc::new(slint::VecModel::from(users)));
+        }
+        Err(e) => warn!("Failed to list users with a home: {:?}", &e),
+    }
+}
+
+fn set_users(gui: &AppWindow) {
+    gui.set_users_editable(users::can_manage());
+    match users::list() {
+        Ok(users) => {
+            let names: Vec<SharedString> = users
+                .iter()
+                .map(|user| SharedString::from(&user.name))
+                .collect();
+            let encrypted: Vec<bool> = users.iter().map(|user| user.encrypted).collect();
+            gui.set_users_names(slint::ModelRc::new(slint::VecModel::from(names)));
+            gui.set_users_encrypted(slint::ModelRc::new(slint::VecModel::from(encrypted)));
+        }
+        Err(e) => error_toast(&gui, "Failed to list users", e),
+    }
+}
+
 fn switch_to_login_page(gui: &AppWindow, set_page_sender: &Sender<Page>) {
     gui.set_login_captive_portal(true);
     let _ = set_page_sender.send(Page::UserLogin);
@@ -28,8 +28,19 @@ cfg_if::cfg_if! {
         use nix::unistd::Pid;
         use libqinit::eink::ScreenRotation;
         use libqinit::system::mount_base_filesystems;
+        use std::sync::OnceLock;
+        use std::sync::atomic::{AtomicI32, AtomicU32, Ordering};
+        use exit_codes::{ExitCode, Recovery};
 
         pub const QINIT_PATH: &str = "/etc/init.d/qinit";
+        // Restarts of the second stage after failures that may be transient
+        const MAX_SECOND_STAGE_RESTARTS: u32 = 2;
+        const POWER_OFF_BINARY_PATH: &str = "/sbin/poweroff";
+
+        // Kept around for restarts, along with the command's PID for the SIGCHLD handler to know which child it is
+        static SECOND_STAGE_COMMAND: OnceLock<String> = OnceLock::new();
+        static SECOND_STAGE_PID: AtomicI32 = AtomicI32::new(0);
+        static SECOND_STAGE_RESTARTS: AtomicU32 = AtomicU32::new(0);
     } else {
         cfg_if::cfg_if! {
             if #[cfg(not(feature = "gui_only"))] {
@@ -47,23 +58,31 @@ cfg_if::cfg_if! {
             }
         }
         mod gui;
+        #[cfg(feature = "snapshots")]
+        mod snapshots;
+        mod translations;
 
         use libqinit::signing::{read_public_key};
         use libqinit::system::{generate_version_string, generate_short_version_string, shut_down, BootCommand, BootCommandForm};
         use libqinit::rootfs_socket;
         use std::time::Duration;
         use std::thread;
-        use std::sync::{Arc, atomic::AtomicBool, Mutex};
+        use std::sync::{Arc, atomic::{AtomicBool, Ordering}, Mutex};
 
         const SYSTEMD_NO_TARGETS: i32 = -1;
         const QINIT_SOCKET: &str = "qinit.sock";
     }
 }
 
+mod exit_codes;
+
 use anyhow::{Context, Result};
 use libqinit::netboot::NetBootStatus;
 use libqinit::system::mount_base_partitions;
-use libqinit::{BootSelection, boot_config::BootConfig};
+use libqinit::{
+    BootSelection,
+    boot_config::{self, BootConfig, BootConfigTransaction},
+};
 use libquillcom::socket;
 use log::{error, info};
 use postcard::{from_bytes, to_allocvec};
@@ -74,6 +93,9 @@ pub const QINIT_LOG_DIR: &str = "/var/log";
 pub const QINIT_LOG_FILE: &str = "qinit.log";
 pub const MAX_COPYRIGHT_YEAR: &str = env!("BUILD_YEAR");
 const BOOT_SOCKET_PATH: &str = "/qinit.sock";
+// Frames starting with this byte carry a message for the first stage's console
+// Any other frame is an OverlayStatus, which is all that older second stage binaries ever send (a lone postcard bool cannot start with it)
+const BOOT_SOCKET_INFO_FRAME: u8 = 0xFF;
 
 #[derive(Serialize, Deserialize)]
 struct OverlayStatus {
@@ -82,6 +104,14 @@ struct OverlayStatus {
 
 fn main() {
     env_logger::init();
+    #[cfg(feature = "snapshots")]
+    if std::env::args().any(|arg| arg == snapshots::SNAPSHOTS_ARG) {
+        std::process::exit(snapshots::main());
+    }
+    if std::env::args().any(|arg| arg == exit_codes::EXIT_CODES_ARG) {
+        println!("{}", exit_codes::table());
+        return;
+    }
     let (interrupt_sender, interrupt_receiver): (Sender<String>, Receiver<String>) = channel();
     let interrupt_sender_clone = interrupt_sender.clone();
     if let Err(e) = init(interrupt_sender_clone, interrupt_receiver) {
@@ -94,8 +124,23 @@ fn main() {
             error_string.truncate(error_string_initial_length - 12);
         }
         error!("{}", &error_string.replace("\n", " | "));
+        let exit_code = exit_codes::ExitCode::classify(&e);
         // Send error reason to GUI (if ever it is alive)
-        let _ = interrupt_sender.send(error_string);
+        if interrupt_sender.send(error_string).is_err() {
+            // Without a GUI to show the error, the first stage supervisor is the only one left to act upon it
+            // The first stage itself is PID 1: it must never exit
+            #[cfg(not(feature = "init_wrapper"))]
+            {
+                error!(
+                    "No GUI to show this error: exiting with code {} ({})",
+                    exit_code as i32,
+                    exit_code.description()
+                );
+                std::process::exit(exit_code as i32);
+            }
+            #[cfg(feature = "init_wrapper")]
+            let _ = exit_code;
+        }
     }
 
     cfg_if::cfg_if! {
@@ -107,7 +152,11 @@ fn main() {
 
 fn init(interrupt_sender: Sender<String>, interrupt_receiver: Receiver<String>) -> Result<()> {
     #[cfg(feature = "init_wrapper")]
-    mount_base_filesystems()?;
+    {
+        libqinit::system::begin_phase("base filesystems");
+        mount_base_filesystems()?;
+        libqinit::system::end_phase("base filesystems");
+    }
 
     let boot_selection: BootSelection;
     if libqinit::system::get_cmdline_bool("quill_recovery")? {
@@ -132,11 +181,13 @@ fn init(interrupt_sender: Sender<String>, interrupt_receiver: Receiver<String>)
 
             let boot_unix_listener = socket::bind(&BOOT_SOCKET_PATH)?;
 
+            libqinit::system::begin_phase("data partition mount");
             if boot_selection == BootSelection::NetBoot {
                 mount_base_partitions(NetBootStatus::Pending)?;
             } else {
                 mount_base_partitions(NetBootStatus::None)?;
             }
+            libqinit::system::end_phase("data partition mount");
 
             let rotation_env_var_base = "SLINT_KMS_ROTATION=";
             let rotation_env_var;
@@ -155,23 +206,32 @@ fn init(interrupt_sender: Sender<String>, interrupt_receiver: Receiver<String>)
                 &boot_config.system.initial_screen_rotation
             ));
 
-            first_stage_info("Spawning second stage qinit binary");
+            if let Err(e) = libqinit::system::save_first_stage_boot_phases() {
+                first_stage_error(&format!("{}", &e));
+            }
+            if let Err(e) = libqinit::system::save_first_stage_filesystem_corruption() {
+                first_stage_error(&format!("{}", &e));
+            }
+
             fs::create_dir_all(&QINIT_LOG_DIR)?;
-            Command::new("/bin/sh")
-                .args(&[
-                    "-c",
-                    &format!(
-                        "env RUST_LOG_STYLE=always {} {} 2>&1 | tee -a {}",
-                        &rotation_env_var,
-                        &QINIT_PATH,
-                        &format!("{}/{}", &QINIT_LOG_DIR, &QINIT_LOG_FILE)
-                    ),
-                ])
-                .spawn()
-                .with_context(|| "Failed to spawn second stage qinit binary")?;
+            // pipefail makes the second stage's exit code that of the whole pipeline, rather than tee's
+            let _ = SECOND_STAGE_COMMAND.set(format!(
+                "set -o pipefail; env RUST_LOG_STYLE=always {} {} 2>&1 | tee -a {}",
+                &rotation_env_var,
+                &QINIT_PATH,
+                &format!("{}/{}", &QINIT_LOG_DIR, &QINIT_LOG_FILE)
+            ));
+            spawn_second_stage()?;
 
             first_stage_info("Waiting for status message from second stage qinit binary");
-            let status = from_bytes::<OverlayStatus>(socket::read(&boot_unix_listener)?.deref())?;
+            let status = loop {
+                let frame = socket::read(&boot_unix_listener)?;
+                if frame.first() == Some(&BOOT_SOCKET_INFO_FRAME) {
+                    first_stage_info(&String::from_utf8_lossy(&frame[1..]));
+                    continue;
+                }
+                break from_bytes::<OverlayStatus>(frame.deref())?;
+            };
 
             if status.ready {
                 first_stage_info("Ready for systemd initialization");
@@ -188,9 +248,14 @@ fn init(interrupt_sender: Sender<String>, interrupt_receiver: Receiver<String>)
         } else {
             // System initialization
             info!("(Second stage) qinit binary starting");
+            if let Err(e) = libqinit::system::load_first_stage_boot_phases() {
+                error!("{}", &e);
+            }
+            if let Err(e) = libqinit::system::load_first_stage_filesystem_corruption() {
+                error!("{}", &e);
+            }
             #[cfg(not(feature = "gui_only"))]
             {
-                sethostname("pinenote").with_context(|| "Failed to set device's hostname")?;
                 run_command("/sbin/ifconfig", &["lo", "up"])
                     .with_context(|| "Failed to set loopback network device up")?;
             }
@@ -210,29 +275,122 @@ fn init(interrupt_sender: Sender<String>, interrupt_receiver: Receiver<String>)
                 set_workdir("/").with_context(|| "Failed to set current directory to / (not in chroot)")?;
                 fs::create_dir_all(&libqinit::DEFAULT_MOUNTPOINT)
                     .with_context(|| "Failed to create default mountpoint's directory")?;
-
-                mount_modules()?;
-                let _ = mount_firmware(&pubkey);
+                // Base partitions were mounted by the first stage
+                libqinit::system::check_boot_partition_writable();
+                libqinit::system::record_wake_reason();
             }
 
-            // Read boot configuration
-            let (original_boot_config, boot_config_valid) = BootConfig::read()?;
+            // Firmware archive verification, waveform partition reading and boot configuration parsing do not depend on each other
+            libqinit::system::begin_phase("early boot");
+            let (original_boot_config, boot_config_valid) = thread::scope(|scope| -> Result<(BootConfig, bool)> {
+                #[cfg(not(feature = "gui_only"))]
+                let firmware_thread = scope.spawn(|| -> Result<()> {
+                    libqinit::system::begin_phase("firmware mount");
+                    mount_modules()?;
+                    let _ = mount_firmware(&pubkey);
+                    libqinit::system::end_phase("firmware mount");
+                    Ok(())
+                });
+                #[cfg(not(feature = "gui_only"))]
+                let waveform_thread = scope.spawn(|| -> Result<()> {
+                    libqinit::system::begin_phase("waveform backup");
+                    let result = eink::prepare_waveform_backup();
+                    libqinit::system::end_phase("waveform backup");
+                    result
+                });
+
+                // Nothing to run alongside in this mode
+                #[cfg(feature = "gui_only")]
+                let _ = scope;
+
+                libqinit::system::begin_phase("boot configuration read");
+                if let Err(e) = libqinit::boot_config::reconcile_pending_change() {
+                    error!("Failed to reconcile pending boot configuration change: {:?}", &e);
+                }
+                let boot_config_result = BootConfig::read();
+                libqinit::system::end_phase("boot configuration read");
+
+                #[cfg(not(feature = "gui_only"))]
+                {
+                    firmware_thread
+                        .join()
+                        .map_err(|_| anyhow::anyhow!("Firmware mount thread panicked"))??;
+                    // load_waveform() tries again and falls back to the generic waveform if needed
+                    if let Err(e) = waveform_thread
+                        .join()
+                        .map_err(|_| anyhow::anyhow!("Waveform backup thread panicked"))?
+                    {
+                        log::warn!("{:?}", &e);
+                    }
+                }
+
+                boot_config_result
+            })?;
+            libqinit::system::end_phase("early boot");
+            let sequential_ms: u64 = ["firmware mount", "waveform backup", "boot configuration read"]
+                .iter()
+                .filter_map(|name| libqinit::system::phase_duration_ms(&name))
+                .sum();
+            info!(
+                "Early boot steps took {} ms ({} ms if run sequentially)",
+                libqinit::system::phase_duration_ms("early boot").unwrap_or(0),
+                &sequential_ms
+            );
             info!("Original boot configuration: {:?}", &original_boot_config);
+            #[cfg(not(feature = "gui_only"))]
+            sethostname(&libqinit::system::hostname(&original_boot_config))
+                .with_context(|| "Failed to set device's hostname")?;
+            #[cfg(not(feature = "gui_only"))]
+            libqinit::system::check_disk_space(&original_boot_config);
             let mut boot_config = original_boot_config.clone();
+            #[cfg(not(feature = "gui_only"))]
+            if let Err(e) = rootfs::select_slot(&mut boot_config) {
+                error!("Failed to select root filesystem slot: {:?}", &e);
+            }
+            #[cfg(not(feature = "gui_only"))]
+            if let Some((estimate, boots_count)) = libqinit::system::estimate_boot_duration() {
+                let message = format!(
+                    "Estimated boot time: ~{} s (based on last {} boot{})",
+                    estimate.as_secs(),
+                    &boots_count,
+                    if boots_count == 1 { "" } else { "s" }
+                );
+                if let Err(e) = send_first_stage_info(&message) {
+                    error!("Failed to send boot time estimate to first stage: {}", &e);
+                }
+            }
+            if boot_config.system.device_id.is_none() {
+                let device_id = libqinit::system::generate_device_id()?;
+                info!("Assigning device ID '{}'", &device_id);
+                boot_config.system.device_id = Some(device_id);
+            }
 
             // Version strings
-            let version_string = generate_version_string(
+            let mut version_string = generate_version_string(
                 &mut boot_config,
                 &git_const::git_hash!()[0..12],
                 &kernel_commit,
             );
-            let short_version_string = generate_short_version_string(&kernel_commit, &kernel_version);
+            let short_version_string = generate_short_version_string(
+                &kernel_commit,
+                &kernel_version,
+                boot_config.system.device_id.as_deref(),
+            );
 
             #[cfg(not(feature = "gui_only"))]
             {
+                libqinit::system::begin_phase("waveform load");
                 eink::load_waveform()?;
+                libqinit::system::end_phase("waveform load");
+                libqinit::system::begin_phase("module load");
                 eink::load_modules()?;
+                libqinit::system::end_phase("module load");
+                eink::set_geometry(eink::Geometry::resolve(
+                    &original_boot_config.system.initial_screen_rotation,
+                ));
                 eink::setup_touchscreen(&mut boot_config)?;
+                eink::start_refresh_watchdog();
+                libqinit::input::probe_with_retry();
 
                 #[cfg(feature = "debug")]
                 if let Err(e) = debug::start_debug_framework(&pubkey, &mut boot_config) {
@@ -260,6 +418,11 @@ fn init(interrupt_sender: Sender<String>, interrupt_receiver: Receiver<String>)
                 println!();
             }
 
+            // Shown on the About page so that degraded display quality can be explained
+            if libqinit::eink::generic_waveform_in_use() {
+                version_string.push_str("\nRunning generic display waveform");
+            }
+
             // Setup GUI
             let mut systemd_targets_total = SYSTEMD_NO_TARGETS;
             #[cfg(not(feature = "gui_only"))]
@@ -270,6 +433,7 @@ fn init(interrupt_sender: Sender<String>, interrupt_receiver: Receiver<String>)
             }
             let display_progress_bar = systemd_targets_total != SYSTEMD_NO_TARGETS;
             let (progress_sender, progress_receiver): (Sender<f32>, Receiver<f32>) = channel();
+            let (boot_status_sender, boot_status_receiver): (Sender<libqinit::systemd::BootStatus>, Receiver<libqinit::systemd::BootStatus>) = channel();
             let (boot_sender, boot_receiver): (Sender<BootCommandForm>, Receiver<BootCommandForm>) =
                 channel();
             let (toast_sender, toast_receiver): (Sender<String>, Receiver<String>) = channel();
@@ -287,6 +451,7 @@ fn init(interrupt_sender: Sender<String>, interrupt_receiver: Receiver<String>)
             let (netboot_ready_sender, netboot_ready_receiver): (Sender<()>, Receiver<()>) = channel();
 
             let boot_config_mutex = Arc::new(Mutex::new(boot_config.clone()));
+            libqinit::system::begin_phase("GUI start");
             thread::spawn({
                 let boot_config_mutex = boot_config_mutex.clone();
                 let toast_sender = toast_sender.clone();
@@ -294,6 +459,7 @@ fn init(interrupt_sender: Sender<String>, interrupt_receiver: Receiver<String>)
                 move || {
                     gui::setup_gui(
                         progress_receiver,
+                        boot_status_receiver,
                         boot_sender,
                         login_credentials_sender,
                         splash_receiver,
@@ -319,6 +485,9 @@ fn init(interrupt_sender: Sender<String>, interrupt_receiver: Receiver<String>)
                     if #[cfg(feature = "debug")] {
                         if let Ok(()) = netboot::setup() {
                             mount_base_partitions(NetBootStatus::Available)?;
+                            if let Some(issue) = libqinit::system::check_boot_partition_writable() {
+                                libqinit::notifier::get().sticky_toast(&issue.notice());
+                            }
                             netboot_ready_sender.send(())?;
                         }
                     } else {
@@ -338,31 +507,43 @@ fn init(interrupt_sender: Sender<String>, interrupt_receiver: Receiver<String>)
             );
 
             // Check if we need to force a reboot here to apply configuration changes
-            let mut config_force_reboot = true;
-            if boot_config.rootfs.persistent_storage != original_boot_config.rootfs.persistent_storage {
+            // Whatever the GUI changed is committed from here on: it can no longer be undone
+            let session_changes = boot_config::session_changes();
+            boot_config::clear_session_changes();
+            for change in &session_changes {
+                info!("Committing change: {}", &change.label());
+            }
+            let config_force_reboot = session_changes.iter().any(|change| change.requires_reboot());
+            if config_force_reboot {
                 // It might be useful to recount the number of systemd targets
                 boot_config.rootfs.systemd_targets_total = None;
-            } else {
-                config_force_reboot = false;
             }
 
             if config_force_reboot {
                 if boot_command == BootCommand::NormalBoot {
                     boot_command = BootCommand::Reboot;
+                    #[cfg(not(feature = "gui_only"))]
+                    rootfs::offer_write_layer_migration(original_boot_config.rootfs.persistent_storage, boot_config.rootfs.persistent_storage);
                     toast_sender.send("Applying changes".to_string())?;
-                    BootConfig::write(&mut boot_config, false)?;
-                    std::thread::sleep(Duration::from_millis(gui::TOAST_DURATION_MILLIS as u64));
-
-                    shut_down(
-                        libquillcom::socket::PrimitiveShutDownType::Reboot,
-                        libqinit::system::PowerDownMode::Normal,
-                        Arc::new(AtomicBool::new(true)),
-                    )?;
+                    // Should the reboot fail, persistent storage goes back to what this boot actually runs with
+                    let mut previous_boot_config = boot_config.clone();
+                    previous_boot_config.rootfs.persistent_storage = original_boot_config.rootfs.persistent_storage;
+                    previous_boot_config.rootfs.systemd_targets_total = original_boot_config.rootfs.systemd_targets_total;
+                    BootConfigTransaction::stage(&previous_boot_config, &boot_config, "persistent storage toggle", true)?
+                        .perform(|| {
+                            std::thread::sleep(Duration::from_millis(gui::TOAST_DURATION_MILLIS as u64));
+                            shut_down(
+                                libquillcom::socket::PrimitiveShutDownType::Reboot,
+                                libqinit::system::PowerDownMode::Normal,
+                                Arc::new(AtomicBool::new(true)),
+                            )
+                        })?;
                 }
             } else {
                 // Trigger switch to boot splash page
                 if boot_command == BootCommand::NormalBoot {
                     progress_sender.send(0.0)?;
+                    let _ = boot_status_sender.send(libqinit::systemd::BootStatus::Phase(libqinit::systemd::BootPhase::MountingSystem));
                 }
             }
 
@@ -400,32 +581,100 @@ fn init(interrupt_sender: Sender<String>, interrupt_receiver: Receiver<String>)
             #[cfg(not(feature = "gui_only"))]
             {
                 // Resume boot
-                rootfs::setup(&pubkey, boot_config.rootfs.persistent_storage)?;
+                if let Some(zram) = &boot_config.system.zram {
+                    let algorithm = zram
+                        .compression_algorithm
+                        .as_deref()
+                        .unwrap_or(libqinit::system::DEFAULT_ZRAM_ALGORITHM);
+                    if let Err(e) = libqinit::system::setup_zram(zram.size_mb, &algorithm) {
+                        error!("Failed to set up zram swap: {:?}", &e);
+                    }
+                }
+                if let Err(e) = rootfs::recover_interrupted_update(&pubkey) {
+                    error!("Failed to recover interrupted install: {:?}", &e);
+                }
+                libqinit::system::begin_phase("rootfs setup");
+                if boot_config.rootfs.persistent_storage
+                    && let Err(e) = rootfs::check_write_layer()
+                {
+                    error!("Failed to check persistent write layer: {:?}", &e);
+                }
+                rootfs::setup(&pubkey, &boot_config.rootfs)?;
+                libqinit::system::end_phase("rootfs setup");
+
+                // The hostname may have been changed from the GUI since init
+                let hostname = libqinit::system::hostname(&boot_config);
+                if hostname != libqinit::system::hostname(&original_boot_config) {
+                    sethostname(&hostname).with_context(|| "Failed to set device's hostname")?;
+                }
+                if let Err(e) = rootfs::write_hostname(&hostname) {
+                    error!("{:?}", &e);
+                }
+                if let Err(e) = rootfs::write_localtime(&boot_config.system.timezone) {
+                    error!("{:?}", &e);
+                }
             }
 
-            // Socket used for binaries inside the chroot wishing to invoke a 'Fatal error' splash
+            #[cfg(not(feature = "gui_only"))]
+            let handoff_interrupt_sender = interrupt_sender.clone();
+
+            // Bound here, before the overlay is declared ready: errors received before the GUI is up wait in the early event buffer
             let qinit_socket_path = format!("{}/run/{}", &libqinit::OVERLAY_MOUNTPOINT, &QINIT_SOCKET);
-            std::thread::spawn(move || {
-                if let Ok(qinit_unix_listener) = socket::bind(&qinit_socket_path) {
-                    // This is a one-time call: any more fatal errors are useless since we already block the UI until the next boot
-                    if let Ok(qinit_unix_listener_socket) = socket::read(&qinit_unix_listener) {
-                        info!("Received request to show fatal error splash: proceeding");
-                        if let Ok(error_details) =
-                            from_bytes::<socket::ErrorDetails>(&qinit_unix_listener_socket)
-                        {
-                            let _ = interrupt_sender.send(error_details.error_reason);
-                            let _ = fs::remove_file(&qinit_socket_path);
-                        }
-                    }
-                }
-            });
+            if let Err(e) = rootfs_socket::listen_for_fatal_errors(&qinit_socket_path, &libqinit::notifier::EARLY_EVENTS, interrupt_sender) {
+                error!("{:?}", &e);
+            }
 
             #[cfg(not(feature = "gui_only"))]
             {
+                if !boot_config.flags.first_boot_done && !boot_config.flags.provisioning_done {
+                    libqinit::system::begin_phase("first boot setup");
+                    let failures = rootfs::run_first_boot_scripts(&pubkey);
+                    libqinit::system::end_phase("first boot setup");
+                    if !failures.is_empty() {
+                        let _ = toast_sender.send(format!(
+                            "Some first boot setup scripts failed:\n{}",
+                            failures.join("\n")
+                        ));
+                    }
+                    // Written back along with the rest of the boot configuration once systemd started
+                    boot_config.flags.provisioning_done = true;
+                }
+
+                // Before the overlay is declared ready: the first stage may exec /sbin/init as soon as it is
+                let handoff_baseline = systemd::handoff_baseline();
                 let overlay_status = to_allocvec(&OverlayStatus { ready: true })
                     .with_context(|| "Failed to create vector with boot command")?;
                 let _ = socket::write(&BOOT_SOCKET_PATH, &overlay_status)?;
 
+                // From now on, the first stage is about to exec /sbin/init: watch for a failed handoff instead of waiting forever
+                let handoff_finished = Arc::new(AtomicBool::new(false));
+                thread::spawn({
+                    let handoff_timeout = systemd::get_handoff_timeout(&boot_config);
+                    let handoff_finished = handoff_finished.clone();
+                    let toast_sender = toast_sender.clone();
+                    move || {
+                        if let Err(e) = handoff_baseline.and_then(|baseline| systemd::monitor_handoff(
+                            baseline,
+                            handoff_timeout,
+                            handoff_finished,
+                            handoff_interrupt_sender,
+                            toast_sender,
+                        )) {
+                            error!("Chroot handoff monitor failed: {}", &e);
+                        }
+                    }
+                });
+
+                if boot_config.rootfs.persistent_storage {
+                    let rootfs_config = boot_config.rootfs.clone();
+                    let toast_sender = toast_sender.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = rootfs::monitor_write_layer(&rootfs_config, toast_sender) {
+                            error!("Persistent write layer monitor failed: {}", &e);
+                        }
+                    });
+                }
+
                 thread::spawn(move || {
                     rootfs_socket::initialize(
                         login_credentials_receiver,
@@ -433,22 +682,46 @@ fn init(interrupt_sender: Sender<String>, interrupt_receiver: Receiver<String>)
                         splash_ready_receiver,
                         can_shut_down.clone(),
                         login_page_trigger_sender,
+                        // Debug builds keep the credentials around, so that the root filesystem can be restarted by hand without logging in again
+                        !cfg!(feature = "debug"),
                     )
                 });
 
+                libqinit::system::begin_phase("systemd wait");
+                let _ = boot_status_sender.send(systemd::BootStatus::Phase(systemd::BootPhase::StartingServices));
                 if display_progress_bar {
                     progress_sender.send(rootfs::ROOTFS_MOUNTED_PROGRESS_VALUE)?;
-                    systemd::wait_for_targets(&mut boot_config, systemd_targets_total, progress_sender)?;
+                    systemd::wait_for_targets(&mut boot_config, systemd_targets_total, progress_sender, boot_status_sender)?;
                 } else {
                     // Only runs on first boot or when boot configuration is cleared/corrupted
                     systemd::wait_and_count_targets(Some(&mut boot_config), Some(progress_sender), None)?;
                 }
+                handoff_finished.store(true, Ordering::SeqCst);
+                libqinit::system::end_phase("systemd wait");
+                if let Err(e) = libqinit::system::write_boot_timing_report() {
+                    error!("{}", &e);
+                }
+                if let Err(e) = libqinit::system::record_boot_duration() {
+                    error!("Failed to record boot duration: {}", &e);
+                }
 
                 // Wait until systemd startup has completed
                 let boot_command_form = boot_receiver.recv()?;
                 let (boot_command, can_shut_down) = handle_boot_command(boot_command_form);
                 info!("systemd startup complete");
-                if !boot_config_valid || boot_config != original_boot_config {
+                // Recovery keys may have been created and the GUI scale changed on the login page in the meantime
+                {
+                    let gui_boot_config = boot_config_mutex.lock().unwrap();
+                    boot_config.system.recovery_keys = gui_boot_config.system.recovery_keys.clone();
+                    boot_config.system.ui_scale = gui_boot_config.system.ui_scale;
+                }
+                // The attempt counter was already written at the start of this boot: resetting it always has to be written back
+                let mut boot_config_changed = !boot_config_valid || boot_config != original_boot_config;
+                if boot_command == BootCommand::BootFinished && boot_config.rootfs.boot_attempts > 0 {
+                    boot_config.rootfs.boot_attempts = 0;
+                    boot_config_changed = true;
+                }
+                if boot_config_changed {
                     BootConfig::write(&mut boot_config, false)?;
                 }
 
@@ -492,6 +765,16 @@ fn handle_boot_command(boot_command_form: BootCommandForm) -> (BootCommand, Arc<
     );
 }
 
+// The first stage prints these before the overlay is ready: it is all that headless users see until then
+#[cfg(all(not(feature = "init_wrapper"), not(feature = "gui_only")))]
+fn send_first_stage_info(message: &str) -> Result<()> {
+    let mut frame = vec![BOOT_SOCKET_INFO_FRAME];
+    frame.extend_from_slice(message.as_bytes());
+    let _ = socket::write(&BOOT_SOCKET_PATH, &frame)?;
+
+    Ok(())
+}
+
 cfg_if::cfg_if! {
     if #[cfg(feature = "init_wrapper")] {
         fn first_stage_info(message: &str) {
@@ -501,12 +784,61 @@ cfg_if::cfg_if! {
             error!("(First stage) {}", &message);
         }
 
+        fn spawn_second_stage() -> Result<()> {
+            first_stage_info("Spawning second stage qinit binary");
+            let command = SECOND_STAGE_COMMAND
+                .get()
+                .ok_or_else(|| anyhow::anyhow!("Second stage command is not set"))?;
+            let child = Command::new("/bin/sh")
+                .args(&["-c", &command])
+                .spawn()
+                .with_context(|| "Failed to spawn second stage qinit binary")?;
+            SECOND_STAGE_PID.store(child.id() as i32, Ordering::SeqCst);
+
+            Ok(())
+        }
+
+        // See exit_codes.rs: the second stage only exits when it failed without a GUI to show why
+        fn supervise_second_stage(status: i32) {
+            let exit_code = ExitCode::from_code(status);
+            first_stage_info(&format!(
+                "Second stage qinit binary exited with code {} ({})",
+                &status,
+                exit_code.description()
+            ));
+            let mut recovery = exit_code.recovery();
+            if recovery == Recovery::Retry
+                && SECOND_STAGE_RESTARTS.fetch_add(1, Ordering::SeqCst) >= MAX_SECOND_STAGE_RESTARTS
+            {
+                first_stage_error("Second stage qinit binary failed too many times");
+                recovery = Recovery::PowerOff;
+            }
+            match recovery {
+                Recovery::Nothing => {}
+                Recovery::Retry => {
+                    if let Err(e) = spawn_second_stage() {
+                        first_stage_error(&format!("{:?}", &e));
+                    }
+                }
+                // Nobody is able to tell what happened on screen: better not drain the battery in the meantime
+                Recovery::PowerOff => {
+                    first_stage_error("Powering off");
+                    if let Err(e) = Command::new(&POWER_OFF_BINARY_PATH).arg("-f").status() {
+                        first_stage_error(&format!("Failed to power off: {}", &e));
+                    }
+                }
+            }
+        }
+
         // Thanks, ChatGPT
         fn reap_zombies() {
             loop {
                 match waitpid(Pid::from_raw(-1), Some(WaitPidFlag::WNOHANG)) {
                     Ok(WaitStatus::Exited(pid, status)) => {
                         first_stage_info(&format!("Child {} exited with status {}", pid, status));
+                        if pid.as_raw() == SECOND_STAGE_PID.load(Ordering::SeqCst) {
+                            supervise_second_stage(status);
+                        }
                     }
                     Ok(WaitStatus::Signaled(pid, sig, _)) => {
                         first_stage_info(&format!("Child {} killed by signal {:?}", pid, sig));
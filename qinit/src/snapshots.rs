@@ -0,0 +1,303 @@
+use anyhow::{Context, Result};
+use libqinit::eink::{Geometry, ScreenRotation};
+use log::{error, info, warn};
+use slint::platform::software_renderer::{MinimalSoftwareWindow, RepaintBufferType};
+use slint::platform::{Platform, WindowAdapter};
+use slint::{ComponentHandle, PhysicalSize, PlatformError, SharedString};
+use std::fs;
+use std::io::BufWriter;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::gui::{AppWindow, DialogType, Page, QrCodePage};
+
+// Headless snapshot harness: drives the GUI through fixture states with the software renderer and compares the result against checked-in golden images
+// Usage: qinit --snapshots [OUTPUT_DIR] [--update-goldens]
+pub const SNAPSHOTS_ARG: &str = "--snapshots";
+const UPDATE_GOLDENS_ARG: &str = "--update-goldens";
+const DEFAULT_OUTPUT_DIR: &str = "target/snapshots";
+const GOLDENS_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/ui/snapshots");
+// PineNote panel, in the default (portrait) orientation
+const GEOMETRY: Geometry = Geometry::native(ScreenRotation::Cw270);
+const SCREEN_WIDTH: u32 = GEOMETRY.effective_size().0;
+const SCREEN_HEIGHT: u32 = GEOMETRY.effective_size().1;
+// Pixels whose luma differs by less than this are considered identical (anti-aliasing noise)
+const LUMA_TOLERANCE: u8 = 24;
+const MAX_DIFFERENT_PIXELS_RATIO: f64 = 0.002;
+
+// Adding a page to the suite only takes a new line here
+const SNAPSHOTS: &[(&str, fn(&AppWindow))] = &[
+    ("quill_boot", |gui| gui.set_page(Page::QuillBoot)),
+    ("wifi_list", show_wifi_list),
+    ("error", show_error),
+    ("user_login", show_user_login),
+    ("shut_down_splash", show_shut_down_splash),
+    ("timezone", show_timezone),
+    ("users", show_users),
+    ("logs", show_logs),
+    ("system_update", show_system_update),
+    ("factory_reset", |gui| gui.set_page(Page::FactoryReset)),
+    ("usb_storage", show_usb_storage),
+    ("touch_calibration", show_touch_calibration),
+    ("recovery_locked", |gui| gui.set_page(Page::RecoveryLocked)),
+];
+
+struct SnapshotPlatform;
+
+impl Platform for SnapshotPlatform {
+    fn create_window_adapter(&self) -> Result<Rc<dyn WindowAdapter>, PlatformError> {
+        Ok(MinimalSoftwareWindow::new(RepaintBufferType::NewBuffers))
+    }
+}
+
+fn string_model(strings: &[&str]) -> slint::ModelRc<SharedString> {
+    let strings: Vec<SharedString> = strings
+        .iter()
+        .map(|string| SharedString::from(*string))
+        .collect();
+
+    slint::ModelRc::new(slint::VecModel::from(strings))
+}
+
+fn show_wifi_list(gui: &AppWindow) {
+    gui.set_page(Page::QuillBoot);
+    gui.set_wifi_enabled(true);
+    gui.set_wifi_connected(true);
+    gui.set_wifi_connected_name(SharedString::from("Quill"));
+    gui.set_wifi_network_names(string_model(&[
+        "Quill",
+        "PineNote hotspot",
+        "Open café network",
+    ]));
+    gui.set_wifi_network_open_vec(slint::ModelRc::new(slint::VecModel::from(vec![
+        false, false, true,
+    ])));
+    gui.set_dialog(DialogType::WifiUI);
+}
+
+fn show_error(gui: &AppWindow) {
+    gui.set_short_version_string(SharedString::from("snapshot"));
+    gui.set_qr_code_page(QrCodePage::NotAvailable);
+    gui.set_error_reason(SharedString::from(
+        "Reason: Failed to mount root filesystem's SquashFS archive\nCaused by: No such file or directory (os error 2)",
+    ));
+    gui.set_page(Page::Error);
+}
+
+fn show_user_login(gui: &AppWindow) {
+    gui.set_default_user(SharedString::from("user"));
+    gui.set_page(Page::UserLogin);
+}
+
+fn show_shut_down_splash(gui: &AppWindow) {
+    gui.set_splash_wallpaper_text(SharedString::from("Powered off"));
+    gui.set_splash_wallpaper_date_time_information(SharedString::from("01/01"));
+    gui.set_page(Page::ShutDownSplash);
+}
+
+fn show_timezone(gui: &AppWindow) {
+    gui.set_current_timezone(SharedString::from("Europe/Paris"));
+    gui.set_timezone_names(string_model(&[
+        "America",
+        "America/Montreal",
+        "America/Vancouver",
+        "Europe",
+        "Europe/Paris",
+        "Europe/Warsaw",
+    ]));
+    gui.set_timezone_is_region(slint::ModelRc::new(slint::VecModel::from(vec![
+        true, false, false, true, false, false,
+    ])));
+    gui.set_page(Page::Timezone);
+}
+
+fn show_users(gui: &AppWindow) {
+    gui.set_users_names(string_model(&["user", "guest"]));
+    gui.set_users_encrypted(slint::ModelRc::new(slint::VecModel::from(vec![
+        true, false,
+    ])));
+    gui.set_users_editable(true);
+    gui.set_page(Page::Users);
+}
+
+fn show_logs(gui: &AppWindow) {
+    gui.set_logs_text(SharedString::from(
+        "[    0.000000] Booting Linux on physical CPU 0x0000000000 [0x412fd050]\n[    1.234567] rockchip-ebc fdec0000.ebc: Loaded waveform\n[    2.345678] mmc0: new HS200 MMC card at address 0001",
+    ));
+    gui.set_logs_pages_count(3);
+    gui.set_page(Page::Logs);
+}
+
+fn show_system_update(gui: &AppWindow) {
+    gui.set_system_update_names(string_model(&["quill-os-2026.10.squashfs"]));
+    gui.set_system_update_details(string_model(&["812 MB, signed"]));
+    gui.set_page(Page::SystemUpdate);
+}
+
+fn show_usb_storage(gui: &AppWindow) {
+    gui.set_shared_storage_device(SharedString::from("/dev/mmcblk0p9"));
+    gui.set_shared_storage_read_only(true);
+    gui.set_page(Page::UsbStorage);
+}
+
+fn show_touch_calibration(gui: &AppWindow) {
+    gui.set_touch_calibration_step(1);
+    gui.set_touch_calibration_message(SharedString::from("Tap the target"));
+    gui.set_page(Page::TouchCalibration);
+}
+
+fn render(setup: fn(&AppWindow)) -> Result<Vec<u8>> {
+    let gui = AppWindow::new()?;
+    gui.window()
+        .set_size(PhysicalSize::new(SCREEN_WIDTH, SCREEN_HEIGHT));
+    gui.set_startup_finished(true);
+    setup(&gui);
+    gui.show()?;
+    slint::platform::update_timers_and_animations();
+    let snapshot = gui
+        .window()
+        .take_snapshot()
+        .with_context(|| "Failed to take window snapshot")?;
+    gui.hide()?;
+
+    if snapshot.width() != SCREEN_WIDTH || snapshot.height() != SCREEN_HEIGHT {
+        return Err(anyhow::anyhow!(
+            "Unexpected snapshot size: {}x{}",
+            snapshot.width(),
+            snapshot.height()
+        ));
+    }
+
+    Ok(snapshot.as_bytes().to_vec())
+}
+
+fn write_png(path: &Path, rgba: &[u8]) -> Result<()> {
+    let file = fs::File::create(&path)
+        .with_context(|| format!("Failed to create '{}'", &path.display()))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), SCREEN_WIDTH, SCREEN_HEIGHT);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()?
+        .write_image_data(&rgba)
+        .with_context(|| format!("Failed to write '{}'", &path.display()))?;
+
+    Ok(())
+}
+
+fn read_png(path: &Path) -> Result<Vec<u8>> {
+    let file =
+        fs::File::open(&path).with_context(|| format!("Failed to open '{}'", &path.display()))?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::ALPHA);
+    let mut reader = decoder.read_info()?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer)?;
+    if info.width != SCREEN_WIDTH
+        || info.height != SCREEN_HEIGHT
+        || info.color_type != png::ColorType::Rgba
+        || info.bit_depth != png::BitDepth::Eight
+    {
+        return Err(anyhow::anyhow!(
+            "Golden image '{}' does not match the expected format",
+            &path.display()
+        ));
+    }
+    buffer.truncate(info.buffer_size());
+
+    Ok(buffer)
+}
+
+// The panel is greyscale: comparing luma is what is perceptually relevant
+fn luma(pixel: &[u8]) -> u8 {
+    ((pixel[0] as u32 * 299 + pixel[1] as u32 * 587 + pixel[2] as u32 * 114) / 1000) as u8
+}
+
+// Returns the ratio of pixels that differ, along with a diff image highlighting them in black
+fn compare(golden: &[u8], actual: &[u8]) -> (f64, Vec<u8>) {
+    let mut diff = Vec::with_capacity(actual.len());
+    let mut different_pixels = 0;
+    for (golden_pixel, actual_pixel) in golden.chunks_exact(4).zip(actual.chunks_exact(4)) {
+        if luma(&golden_pixel).abs_diff(luma(&actual_pixel)) > LUMA_TOLERANCE {
+            different_pixels += 1;
+            diff.extend_from_slice(&[0, 0, 0, 255]);
+        } else {
+            diff.extend_from_slice(&[235, 235, 235, 255]);
+        }
+    }
+
+    (
+        different_pixels as f64 / (SCREEN_WIDTH * SCREEN_HEIGHT) as f64,
+        diff,
+    )
+}
+
+fn run(output_dir: &str, update_goldens: bool) -> Result<bool> {
+    slint::platform::set_platform(Box::new(SnapshotPlatform))
+        .map_err(|e| anyhow::anyhow!("Failed to set snapshot platform: {}", e))?;
+    fs::create_dir_all(&output_dir)
+        .with_context(|| format!("Failed to create output directory '{}'", &output_dir))?;
+    if update_goldens {
+        fs::create_dir_all(&GOLDENS_DIR)
+            .with_context(|| format!("Failed to create goldens directory '{}'", &GOLDENS_DIR))?;
+    }
+
+    let mut success = true;
+    for (name, setup) in SNAPSHOTS {
+        let actual = render(*setup).with_context(|| format!("Failed to render '{}'", &name))?;
+        let file_name = format!("{}.png", &name);
+        write_png(&Path::new(&output_dir).join(&file_name), &actual)?;
+
+        let golden_path = Path::new(&GOLDENS_DIR).join(&file_name);
+        if update_goldens {
+            write_png(&golden_path, &actual)?;
+            info!("Updated golden image for '{}'", &name);
+            continue;
+        }
+        if !golden_path.exists() {
+            warn!(
+                "No golden image for '{}': run with {} to create it",
+                &name, &UPDATE_GOLDENS_ARG
+            );
+            success = false;
+            continue;
+        }
+
+        let (ratio, diff) = compare(&read_png(&golden_path)?, &actual);
+        if ratio > MAX_DIFFERENT_PIXELS_RATIO {
+            write_png(
+                &Path::new(&output_dir).join(format!("{}.diff.png", &name)),
+                &diff,
+            )?;
+            error!(
+                "Snapshot '{}' differs from its golden image ({:.3}% of pixels)",
+                &name,
+                ratio * 100.0
+            );
+            success = false;
+        } else {
+            info!("Snapshot '{}' matches its golden image", &name);
+        }
+    }
+
+    Ok(success)
+}
+
+pub fn main() -> i32 {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let update_goldens = args.iter().any(|arg| arg == UPDATE_GOLDENS_ARG);
+    let output_dir = args
+        .iter()
+        .find(|arg| !arg.starts_with("--"))
+        .map(|arg| arg.as_str())
+        .unwrap_or(DEFAULT_OUTPUT_DIR);
+
+    match run(&output_dir, update_goldens) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            error!("Snapshot harness failed: {:?}", &e);
+            2
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use anyhow::Result;
+use libqinit::boot_config::Language;
+use log::info;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+// The same catalog is bundled into the Slint UI at build time: strings shown from Rust are looked up here instead
+const FRENCH_CATALOG: &str = include_str!("../lang/fr/LC_MESSAGES/qinit.po");
+
+static CURRENT_LANGUAGE: Mutex<Language> = Mutex::new(Language::English);
+static FRENCH_MESSAGES: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut characters = text.chars();
+    while let Some(character) = characters.next() {
+        if character != '\\' {
+            unescaped.push(character);
+            continue;
+        }
+        match characters.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some(other) => unescaped.push(other),
+            None => {}
+        }
+    }
+
+    unescaped
+}
+
+// Only what the catalogs here use: msgctxt, msgid and msgstr, with strings possibly continued on the following lines
+fn parse_catalog(catalog: &str) -> HashMap<String, String> {
+    let mut messages = HashMap::new();
+    let mut msgid = String::new();
+    let mut msgstr = String::new();
+    let mut in_msgstr = false;
+    let mut flush = |msgid: &mut String, msgstr: &mut String| {
+        // Untranslated entries fall back to English
+        if !msgid.is_empty() && !msgstr.is_empty() {
+            messages.insert(msgid.clone(), msgstr.clone());
+        }
+        msgid.clear();
+        msgstr.clear();
+    };
+
+    for line in catalog.lines().map(str::trim) {
+        let (keyword, quoted) = match line.split_once(' ') {
+            Some((keyword, quoted)) if ["msgctxt", "msgid", "msgstr"].contains(&keyword) => {
+                (Some(keyword), quoted)
+            }
+            _ if line.starts_with('"') => (None, line),
+            _ => continue,
+        };
+        let quoted = quoted.trim();
+        let text = unescape(
+            quoted
+                .strip_prefix('"')
+                .and_then(|quoted| quoted.strip_suffix('"'))
+                .unwrap_or(quoted),
+        );
+        match keyword {
+            Some("msgctxt") => {
+                flush(&mut msgid, &mut msgstr);
+                in_msgstr = false;
+            }
+            Some("msgid") => {
+                if in_msgstr {
+                    flush(&mut msgid, &mut msgstr);
+                }
+                in_msgstr = false;
+                msgid = text;
+            }
+            Some(_) => {
+                in_msgstr = true;
+                msgstr = text;
+            }
+            None if in_msgstr => msgstr.push_str(&text),
+            None => msgid.push_str(&text),
+        }
+    }
+    flush(&mut msgid, &mut msgstr);
+
+    messages
+}
+
+fn catalog(language: Language) -> Option<&'static HashMap<String, String>> {
+    match language {
+        Language::English => None,
+        Language::French => Some(FRENCH_MESSAGES.get_or_init(|| parse_catalog(&FRENCH_CATALOG))),
+    }
+}
+
+// Takes effect right away, including for what the UI already shows: must be called once the window exists
+pub fn set_language(language: Language) -> Result<()> {
+    info!("Switching GUI language to '{}'", language.name());
+    slint::select_bundled_translation(language.code())
+        .map_err(|e| anyhow::anyhow!("Failed to switch GUI language: {}", &e))?;
+    *CURRENT_LANGUAGE.lock().unwrap() = language;
+
+    Ok(())
+}
+
+pub fn current_language() -> Language {
+    *CURRENT_LANGUAGE.lock().unwrap()
+}
+
+// English messages are the keys: anything missing from the catalog is shown as is
+pub fn tr(message: &str) -> String {
+    catalog(current_language())
+        .and_then(|messages| messages.get(message))
+        .cloned()
+        .unwrap_or_else(|| message.to_string())
+}
+
+// Same placeholders as @tr() in Slint, filled in order
+pub fn tr_format(message: &str, arguments: &[&str]) -> String {
+    let mut translated = tr(message);
+    for argument in arguments {
+        translated = translated.replacen("{}", argument, 1);
+    }
+
+    translated
+}
@@ -1,4 +1,4 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use clap::Parser;
 use libquillcom::socket;
 use postcard::{from_bytes, to_allocvec};
@@ -22,6 +22,10 @@ struct ExclusiveOptions {
 
     #[arg(long, short = 'l', group = "exclusive")]
     trigger_login_page_switch: bool,
+
+    // Asks for the login credentials twice: the second answer must be empty
+    #[arg(long, short = 'c', group = "exclusive")]
+    check_login_credentials_consumed: bool,
 }
 
 #[derive(Parser)]
@@ -41,9 +45,28 @@ struct Args {
     socket_path: String,
 }
 
+fn get_login_credentials(socket_path: &str) -> Result<Option<socket::LoginForm>> {
+    let vector = to_allocvec(&socket::CommandToQinit::GetLoginCredentials)?;
+    let reply = socket::write_and_read(&socket_path, &vector)?;
+    match from_bytes::<socket::AnswerFromQinit>(&reply)? {
+        socket::AnswerFromQinit::Login(credentials) => Ok(credentials),
+        _ => bail!("Unexpected answer to login credentials request"),
+    }
+}
+
 fn main() -> Result<()> {
     env_logger::init();
     let args = Args::parse();
+    if args.exclusive_options.check_login_credentials_consumed {
+        if get_login_credentials(&args.socket_path)?.is_none() {
+            bail!("No login credentials to begin with: log in first");
+        }
+        if get_login_credentials(&args.socket_path)?.is_some() {
+            bail!("Login credentials were served twice");
+        }
+        info!("Login credentials were only served once");
+        return Ok(());
+    }
     let vector;
     if args.exclusive_options.trigger_fatal_error {
         vector = to_allocvec(&socket::ErrorDetails {
@@ -66,8 +89,11 @@ fn main() -> Result<()> {
     }
 
     if args.exclusive_options.get_login_credentials {
-        let credentials = &from_bytes::<Option<socket::LoginForm>>(&reply)?;
-        if let Some(c) = credentials {
+        let credentials = match from_bytes::<socket::AnswerFromQinit>(&reply)? {
+            socket::AnswerFromQinit::Login(credentials) => credentials,
+            _ => bail!("Unexpected answer to login credentials request"),
+        };
+        if let Some(c) = &credentials {
             info!("Username: '{}'", c.username);
             info!("Password: '{}'", c.password);
         }